@@ -50,6 +50,11 @@ logoutLink.addEventListener("click", (e) => {
                     protectImg.src = "/assets/default.jpg";
                 }
             });
+        } else if (response.status === 403) {
+            // logged in, but without the scope the protected content needs
+            loginLink.style.display = "none";
+            logoutLink.style.display = "block";
+            protectImg.src = "/assets/default.jpg";
         } else {
             loginLink.style.display = "block";
             logoutLink.style.display = "none";
@@ -17,21 +17,27 @@ use std::env;
 
 use askama::Template;
 use axum::{
-    http::StatusCode,
     response::{Html, IntoResponse},
     routing::get,
     Json, Router,
 };
-use axum_extra::extract::CookieJar;
+use secrecy::Secret;
 use serde::Serialize;
 use tower_http::services::ServeDir;
 
+use scope::{RequireScope, RequiredScope};
+use token_verifier::AuthServiceTokenVerifier;
+
+mod scope;
+mod token_verifier;
+
 #[tokio::main]
 async fn main() {
     let app = Router::new()
         .nest_service("/assets", ServeDir::new("assets"))
         .route("/", get(root))
-        .route("/protected", get(protected));
+        .route("/protected", get(protected))
+        .with_state(token_verifier());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:42068").await.unwrap();
 
@@ -61,43 +67,39 @@ async fn root() -> impl IntoResponse {
     Html(template.render().unwrap())
 }
 
-async fn protected(jar: CookieJar) -> impl IntoResponse {
-    let jwt_cookie = match jar.get("jwt") {
-        Some(cookie) => cookie,
-        None => {
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
-    };
+/// Scope a user's token needs for the protected route, from a role or permission granted to them.
+struct ViewProtected;
 
-    let api_client = reqwest::Client::builder().build().unwrap();
+impl RequiredScope for ViewProtected {
+    const SCOPE: &'static str = "protected:read";
+}
 
-    let verify_token_body = serde_json::json!({
-        "token": &jwt_cookie.value(),
-    });
+async fn protected(scope: RequireScope<ViewProtected>) -> impl IntoResponse {
+    Json(ProtectedRouteResponse {
+        img_url: "https://img.icons8.com/?size=1000&id=1348&format=png&color=000000".to_owned(),
+        user_id: scope.0.sub,
+    })
+}
 
+/// Tokens are checked by the auth service, which only introspects them for services registered
+/// as confidential clients.
+fn token_verifier() -> AuthServiceTokenVerifier {
     let auth_hostname = env::var("AUTH_SERVICE_HOST_NAME").unwrap_or("0.0.0.0".to_owned());
-    let url = format!("http://{}:42069/verify-token", auth_hostname);
-
-    let response = match api_client.post(&url).json(&verify_token_body).send().await {
-        Ok(response) => response,
-        Err(_) => {
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
-    };
-
-    match response.status() {
-        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::BAD_REQUEST => {
-            StatusCode::UNAUTHORIZED.into_response()
-        }
-        reqwest::StatusCode::OK => Json(ProtectedRouteResponse {
-            img_url: "https://img.icons8.com/?size=1000&id=1348&format=png&color=000000".to_owned(),
-        })
-        .into_response(),
-        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    }
+    let client_id =
+        env::var("AUTH_SERVICE_CLIENT_ID").expect("AUTH_SERVICE_CLIENT_ID should be set.");
+    let client_secret =
+        env::var("AUTH_SERVICE_CLIENT_SECRET").expect("AUTH_SERVICE_CLIENT_SECRET should be set.");
+
+    AuthServiceTokenVerifier::new(
+        &format!("http://{}:42069", auth_hostname),
+        client_id,
+        Secret::new(client_secret),
+        reqwest::Client::new(),
+    )
 }
 
 #[derive(Serialize)]
 pub struct ProtectedRouteResponse {
     pub img_url: String,
+    pub user_id: Option<String>,
 }
@@ -0,0 +1,84 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::marker::PhantomData;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, StatusCode},
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+
+use crate::token_verifier::{AuthServiceTokenVerifier, IntrospectionResponse};
+
+/// Name of the cookie the auth service keeps a logged in user's token in.
+const JWT_COOKIE_NAME: &str = "jwt";
+
+/// A scope that routes can require, named by a type so it shows in the handler's signature.
+pub trait RequiredScope {
+    const SCOPE: &'static str;
+}
+
+/// Extractor that only lets a request through when the auth service says its token is active and
+/// carries the scope `S` names. The token is taken from a Bearer authorization header, or else
+/// from the auth cookie of a logged in user.
+pub struct RequireScope<S>(pub IntrospectionResponse, PhantomData<S>);
+
+#[async_trait]
+impl<S> FromRequestParts<AuthServiceTokenVerifier> for RequireScope<S>
+where
+    S: RequiredScope,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        verifier: &AuthServiceTokenVerifier,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers)
+            .or_else(|| {
+                CookieJar::from_headers(&parts.headers)
+                    .get(JWT_COOKIE_NAME)
+                    .map(|cookie| Secret::new(cookie.value().to_owned()))
+            })
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let introspection = verifier
+            .verify(&token)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let has_scope = introspection
+            .scope
+            .as_deref()
+            .is_some_and(|scope| scope.split(' ').any(|scope| scope == S::SCOPE));
+        if !has_scope {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(Self(introspection, PhantomData))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Secret::new(token.trim().to_owned()))
+}
@@ -0,0 +1,193 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+/// Verifies tokens by having the auth service introspect them through `/verify-token`. The
+/// service has to be registered as a confidential client, since introspection is only answered
+/// for those.
+#[derive(Clone)]
+pub struct AuthServiceTokenVerifier {
+    http_client: Client,
+    verify_token_url: Url,
+    client_id: String,
+    client_secret: Secret<String>,
+}
+
+impl AuthServiceTokenVerifier {
+    pub fn new(
+        base_url: &str,
+        client_id: String,
+        client_secret: Secret<String>,
+        http_client: Client,
+    ) -> Self {
+        // joined relative to a base ending in a slash, so that a path prefix the auth service is
+        // served under is kept
+        let base_url = format!("{}/", base_url.trim_end_matches('/'));
+        let verify_token_url = Url::parse(&base_url)
+            .and_then(|url| url.join("verify-token"))
+            .expect("Auth service URL should be valid.");
+        Self {
+            http_client,
+            verify_token_url,
+            client_id,
+            client_secret,
+        }
+    }
+
+    /// The introspection of the token when it is active, or `None` when the auth service says it
+    /// isn't.
+    pub async fn verify(
+        &self,
+        token: &Secret<String>,
+    ) -> Result<Option<IntrospectionResponse>, reqwest::Error> {
+        let body = serde_json::json!({
+            "token": token.expose_secret(),
+            "introspect": true,
+            "client_id": self.client_id,
+            "client_secret": self.client_secret.expose_secret(),
+        });
+
+        let introspection = self
+            .http_client
+            .post(self.verify_token_url.clone())
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<IntrospectionResponse>()
+            .await?;
+        Ok(introspection.active.then_some(introspection))
+    }
+}
+
+/// The part of the auth service's introspection response this service uses.
+#[derive(Deserialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    pub sub: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn verifier(base_url: &str) -> AuthServiceTokenVerifier {
+        AuthServiceTokenVerifier::new(
+            base_url,
+            "app-service".to_owned(),
+            Secret::new("app-secret".to_owned()),
+            Client::new(),
+        )
+    }
+
+    fn token() -> Secret<String> {
+        Secret::new("token".to_owned())
+    }
+
+    #[tokio::test]
+    async fn verify_returns_introspection_of_active_token() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/verify-token"))
+            .and(body_partial_json(serde_json::json!({
+                "token": "token",
+                "introspect": true,
+                "client_id": "app-service",
+                "client_secret": "app-secret",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "active": true,
+                "scope": "articles:read articles:write",
+                "token_type": "Bearer",
+                "exp": 1700000600,
+                "sub": "4c7b5bd2-2c3a-4a8e-9f0e-2f2f7c7a9d10",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let introspection = verifier(&mock_server.uri())
+            .verify(&token())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            introspection.sub.as_deref(),
+            Some("4c7b5bd2-2c3a-4a8e-9f0e-2f2f7c7a9d10")
+        );
+        assert_eq!(
+            introspection.scope.as_deref(),
+            Some("articles:read articles:write")
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_keeps_path_prefix_of_auth_service_url() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/auth/verify-token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "active": false })),
+            )
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for base_url in ["/auth", "/auth/"] {
+            let base_url = format!("{}{}", mock_server.uri(), base_url);
+            let result = verifier(&base_url).verify(&token()).await;
+            assert!(matches!(result, Ok(None)));
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_rejects_inactive_token() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/verify-token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "active": false })),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let result = verifier(&mock_server.uri()).verify(&token()).await;
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn verify_fails_when_service_is_not_authenticated() {
+        let mock_server = MockServer::start().await;
+
+        // wrong client credentials are a misconfiguration, not a bad token
+        Mock::given(method("POST"))
+            .and(path("/verify-token"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let result = verifier(&mock_server.uri()).verify(&token()).await;
+        assert!(result.is_err());
+    }
+}
@@ -45,6 +45,17 @@ signupLoginLink.addEventListener("click", (e) => {
   signupSection.style.display = "none";
 });
 
+// Apps that log users in through /oauth/authorize send them here first, and get them back once
+// they are logged in. Nothing else is followed, so the page can't be used to redirect elsewhere.
+function returnToAuthorization() {
+  const returnTo = new URLSearchParams(window.location.search).get("return_to");
+  if (returnTo !== null && returnTo.startsWith("/oauth/authorize?")) {
+    window.location.assign(returnTo);
+    return true;
+  }
+  return false;
+}
+
 // -----------------------------------------------------
 
 const loginForm = document.getElementById("login-form");
@@ -81,7 +92,9 @@ loginButton.addEventListener("click", (e) => {
       loginForm.email.value = "";
       loginForm.password.value = "";
       loginErrAlter.style.display = "none";
-      alert("You have successfully logged in.");
+      if (!returnToAuthorization()) {
+        alert("You have successfully logged in.");
+      }
     } else {
       response.json().then((data) => {
         let error_msg = data.error;
@@ -160,6 +173,9 @@ TwoFAButton.addEventListener("click", (e) => {
       TwoFAForm.email_code.value = "";
       TwoFAForm.login_attempt_id.value = "";
       TwoFAErrAlter.style.display = "none";
+      if (returnToAuthorization()) {
+        return;
+      }
       alert("You have successfully logged in.");
       loginSection.style.display = "block";
       twoFASection.style.display = "none";
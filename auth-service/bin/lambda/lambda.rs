@@ -16,36 +16,87 @@
 
 use auth_service::{
     app_state::AppState,
-    domain::Email,
+    domain::{Email, PasswordPolicy},
     services::{
-        data_stores::{HashmapTwoFACodeStore, HashmapUserStore, HashsetBannedTokenStore},
+        data_stores::{
+            HashmapAuthorizationCodeStore, HashmapEmailCooldownStore, HashmapExternalIdentityStore,
+            HashmapFailedLoginStore, HashmapOAuthClientStore, HashmapOAuthStateStore,
+            HashmapPermissionStore, HashmapRateLimitStore, HashmapRecoveryCodeStore,
+            HashmapRefreshTokenStore, HashmapSessionStore, HashmapTwoFACodeStore, HashmapUserStore,
+            HashmapWebauthnChallengeStore, HashmapWebauthnCredentialStore, HashsetBannedTokenStore,
+        },
         //mock_email_client::MockEmailClient,
+        file_breached_password_source::FileBreachedPasswordSource,
+        hibp_breached_password_source::HibpBreachedPasswordSource,
+        oidc_client::OidcClient,
         postmark_email_client::PostmarkEmailClient,
     },
-    utils::constants::{prod, POSTMARK_AUTH_TOKEN},
+    utils::constants::{
+        prod, BREACHED_PASSWORDS_PATH, OAUTH_CLIENT_REGISTRATION_TOKEN, OAUTH_PROVIDERS,
+        PASSWORD_BREACH_CHECK, PASSWORD_HASHER, PASSWORD_POLICY, POSTMARK_AUTH_TOKEN, RATE_LIMITS,
+        ROLES,
+    },
     Application,
 };
-use axum::Router;
+use axum::{extract::ConnectInfo, Router};
 use http::Request as HttpRequest;
-use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use lambda_http::{
+    request::RequestContext, run, service_fn, Body, Error, Request, RequestExt, Response,
+};
 use reqwest::Client;
 use secrecy::Secret;
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 use tower::ServiceExt;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     // init app state
-    let user_store = Arc::new(RwLock::new(HashmapUserStore::default()));
+    let password_hasher = Arc::new(PASSWORD_HASHER.clone());
+    let user_store = Arc::new(RwLock::new(HashmapUserStore::new(password_hasher.clone())));
     let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
     let two_fa_code_store = Arc::new(RwLock::new(HashmapTwoFACodeStore::default()));
+    let refresh_token_store = Arc::new(RwLock::new(HashmapRefreshTokenStore::default()));
+    let session_store = Arc::new(RwLock::new(HashmapSessionStore::default()));
+    let recovery_code_store = Arc::new(RwLock::new(HashmapRecoveryCodeStore::new(password_hasher)));
+    let webauthn_credential_store =
+        Arc::new(RwLock::new(HashmapWebauthnCredentialStore::default()));
+    let webauthn_challenge_store = Arc::new(RwLock::new(HashmapWebauthnChallengeStore::default()));
+    let email_cooldown_store = Arc::new(RwLock::new(HashmapEmailCooldownStore::default()));
+    let failed_login_store = Arc::new(RwLock::new(HashmapFailedLoginStore::default()));
+    let rate_limit_store = Arc::new(RwLock::new(HashmapRateLimitStore::default()));
+    let oauth_state_store = Arc::new(RwLock::new(HashmapOAuthStateStore::default()));
+    let external_identity_store = Arc::new(RwLock::new(HashmapExternalIdentityStore::default()));
+    let oauth_client_store = Arc::new(RwLock::new(HashmapOAuthClientStore::default()));
+    let authorization_code_store = Arc::new(RwLock::new(HashmapAuthorizationCodeStore::default()));
+    let permission_store = Arc::new(RwLock::new(HashmapPermissionStore::default()));
     let email_client = Arc::new(configure_postmark_email_client());
     let app_state = AppState::new(
         user_store,
         banned_token_store,
         two_fa_code_store,
+        refresh_token_store,
+        session_store,
+        recovery_code_store,
+        webauthn_credential_store,
+        webauthn_challenge_store,
+        email_cooldown_store,
+        failed_login_store,
+        rate_limit_store,
+        oauth_state_store,
+        external_identity_store,
+        oauth_client_store,
+        authorization_code_store,
+        permission_store,
+        *RATE_LIMITS,
+        configure_password_policy(),
+        Arc::new(ROLES.clone()),
+        OAUTH_CLIENT_REGISTRATION_TOKEN.clone(),
         email_client,
+        Arc::new(configure_oidc_client()),
     );
 
     let app = Application::build(app_state, prod::APP_ADDRESS)
@@ -62,7 +113,11 @@ async fn main() -> Result<(), Error> {
 
 async fn handle_lambda_event(router: Router, event: Request) -> Result<Response<Body>, Error> {
     // convert Lambda request to Axum request
-    let (parts, body) = event.into_parts();
+    let source_ip = source_ip(&event);
+    let (mut parts, body) = event.into_parts();
+    if let Some(ip) = source_ip {
+        parts.extensions.insert(ConnectInfo(SocketAddr::new(ip, 0)));
+    }
 
     let http_body = match body {
         Body::Empty => axum::body::Body::empty(),
@@ -94,6 +149,23 @@ async fn handle_lambda_event(router: Router, event: Request) -> Result<Response<
     Ok(builder.body(lambda_body)?)
 }
 
+/// The address the API gateway saw the request come from. Client supplied X-Forwarded-For
+/// entries are ignored, only the hop appended by a load balancer is trusted.
+fn source_ip(event: &Request) -> Option<IpAddr> {
+    let source_ip = match event.request_context_ref()? {
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.clone(),
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.clone(),
+        RequestContext::WebSocket(context) => context.identity.source_ip.clone(),
+        _ => event
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(str::to_owned),
+    };
+    source_ip?.trim().parse().ok()
+}
+
 fn configure_postmark_email_client() -> PostmarkEmailClient {
     let http_client = Client::builder()
         .timeout(prod::email_client::TIMEOUT)
@@ -107,3 +179,41 @@ fn configure_postmark_email_client() -> PostmarkEmailClient {
         http_client,
     )
 }
+
+fn configure_password_policy() -> PasswordPolicy {
+    if !*PASSWORD_BREACH_CHECK {
+        return PASSWORD_POLICY.clone();
+    }
+
+    match &*BREACHED_PASSWORDS_PATH {
+        Some(path) => {
+            let breached_passwords = FileBreachedPasswordSource::load(path)
+                .expect("BREACHED_PASSWORDS_PATH should be a file of SHA-1 hashes.");
+            PASSWORD_POLICY
+                .clone()
+                .with_breached_passwords(Arc::new(breached_passwords))
+        }
+        None => {
+            let http_client = Client::builder()
+                .timeout(prod::breached_passwords::TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client");
+            let breached_passwords = HibpBreachedPasswordSource::new(
+                prod::breached_passwords::BASE_URL.to_owned(),
+                http_client,
+            );
+            PASSWORD_POLICY
+                .clone()
+                .with_breached_passwords(Arc::new(breached_passwords))
+        }
+    }
+}
+
+fn configure_oidc_client() -> OidcClient {
+    let http_client = Client::builder()
+        .timeout(prod::oauth::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    OidcClient::new(OAUTH_PROVIDERS.clone(), http_client)
+}
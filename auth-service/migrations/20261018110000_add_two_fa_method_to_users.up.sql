@@ -0,0 +1,24 @@
+-- Copyright 2024 Ibrahim Mbaziira
+
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+
+--     http://www.apache.org/licenses/LICENSE-2.0
+
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- migrate:up
+-- Record which second factor a user has instead of only whether they have one
+ALTER TABLE users ADD COLUMN two_fa_method TEXT NOT NULL DEFAULT 'none'
+    CHECK (two_fa_method IN ('none', 'email', 'totp'));
+UPDATE users SET two_fa_method = 'email' WHERE require_2fa;
+ALTER TABLE users DROP COLUMN require_2fa;
+-- Base32 secret of the user's authenticator app
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+-- Time step of the last accepted TOTP code, so that no code is accepted twice
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
@@ -0,0 +1,18 @@
+-- Copyright 2024 Ibrahim Mbaziira
+
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+
+--     http://www.apache.org/licenses/LICENSE-2.0
+
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- migrate:up
+-- Set while an account is pending deletion, to when it will be deleted for good
+ALTER TABLE users ADD COLUMN purge_at TIMESTAMPTZ;
+CREATE INDEX users_purge_at_idx ON users (purge_at) WHERE purge_at IS NOT NULL;
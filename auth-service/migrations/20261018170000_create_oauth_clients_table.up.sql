@@ -0,0 +1,25 @@
+-- Copyright 2024 Ibrahim Mbaziira
+
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+
+--     http://www.apache.org/licenses/LICENSE-2.0
+
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- migrate:up
+-- Applications registered to get tokens from the authorization server
+CREATE TABLE IF NOT EXISTS oauth_clients(
+   id UUID NOT NULL PRIMARY KEY,
+   name TEXT NOT NULL,
+   secret_hash TEXT,
+   redirect_uris TEXT[] NOT NULL,
+   grant_types TEXT[] NOT NULL,
+   scope TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,30 @@
+-- Copyright 2024 Ibrahim Mbaziira
+
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+
+--     http://www.apache.org/licenses/LICENSE-2.0
+
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- migrate:up
+-- Roles granted to users, which bring the permissions configured for them
+CREATE TABLE IF NOT EXISTS user_roles(
+   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+   role TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   PRIMARY KEY (user_id, role)
+);
+
+-- Permissions granted to users directly
+CREATE TABLE IF NOT EXISTS user_permissions(
+   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+   permission TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   PRIMARY KEY (user_id, permission)
+);
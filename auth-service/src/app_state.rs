@@ -14,35 +14,113 @@
    limitations under the License.
 */
 
-use crate::domain::{BannedTokenStore, EmailClient, TwoFACodeStore, UserStore};
+use crate::domain::{
+    AuthorizationCodeStore, BannedTokenStore, EmailClient, EmailCooldownStore,
+    ExternalIdentityStore, FailedLoginStore, OAuthClientStore, OAuthStateStore, PasswordPolicy,
+    PermissionStore, RateLimitStore, RecoveryCodeStore, RefreshTokenStore, Roles, SessionStore,
+    TwoFACodeStore, UserStore, WebauthnChallengeStore, WebauthnCredentialStore,
+};
+use crate::services::oidc_client::OidcClient;
+use crate::utils::rate_limit::RateLimits;
+use secrecy::Secret;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub type UserStoreType = Arc<RwLock<dyn UserStore + Send + Sync>>;
 pub type BannedTokenStoreType = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
 pub type TwoFACodeStoreType = Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>;
+pub type RefreshTokenStoreType = Arc<RwLock<dyn RefreshTokenStore + Send + Sync>>;
+pub type SessionStoreType = Arc<RwLock<dyn SessionStore + Send + Sync>>;
+pub type RecoveryCodeStoreType = Arc<RwLock<dyn RecoveryCodeStore + Send + Sync>>;
+pub type WebauthnCredentialStoreType = Arc<RwLock<dyn WebauthnCredentialStore + Send + Sync>>;
+pub type WebauthnChallengeStoreType = Arc<RwLock<dyn WebauthnChallengeStore + Send + Sync>>;
+pub type EmailCooldownStoreType = Arc<RwLock<dyn EmailCooldownStore + Send + Sync>>;
+pub type FailedLoginStoreType = Arc<RwLock<dyn FailedLoginStore + Send + Sync>>;
+pub type RateLimitStoreType = Arc<RwLock<dyn RateLimitStore + Send + Sync>>;
+pub type OAuthStateStoreType = Arc<RwLock<dyn OAuthStateStore + Send + Sync>>;
+pub type ExternalIdentityStoreType = Arc<RwLock<dyn ExternalIdentityStore + Send + Sync>>;
+pub type OAuthClientStoreType = Arc<RwLock<dyn OAuthClientStore + Send + Sync>>;
+pub type AuthorizationCodeStoreType = Arc<RwLock<dyn AuthorizationCodeStore + Send + Sync>>;
+pub type PermissionStoreType = Arc<RwLock<dyn PermissionStore + Send + Sync>>;
 pub type EmailClientType = Arc<dyn EmailClient + Send + Sync>;
+pub type OidcClientType = Arc<OidcClient>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub user_store: UserStoreType,
     pub banned_token_store: BannedTokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
+    pub refresh_token_store: RefreshTokenStoreType,
+    pub session_store: SessionStoreType,
+    pub recovery_code_store: RecoveryCodeStoreType,
+    pub webauthn_credential_store: WebauthnCredentialStoreType,
+    pub webauthn_challenge_store: WebauthnChallengeStoreType,
+    pub email_cooldown_store: EmailCooldownStoreType,
+    pub failed_login_store: FailedLoginStoreType,
+    pub rate_limit_store: RateLimitStoreType,
+    pub oauth_state_store: OAuthStateStoreType,
+    pub external_identity_store: ExternalIdentityStoreType,
+    pub oauth_client_store: OAuthClientStoreType,
+    pub authorization_code_store: AuthorizationCodeStoreType,
+    pub permission_store: PermissionStoreType,
+    pub rate_limits: RateLimits,
+    pub password_policy: PasswordPolicy,
+    pub roles: Arc<Roles>,
+    /// Token that OAuth clients are registered with, registration is closed without one.
+    pub client_registration_token: Option<Secret<String>>,
     pub email_client: EmailClientType,
+    pub oidc_client: OidcClientType,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         user_store: UserStoreType,
         banned_token_store: BannedTokenStoreType,
         two_fa_code_store: TwoFACodeStoreType,
+        refresh_token_store: RefreshTokenStoreType,
+        session_store: SessionStoreType,
+        recovery_code_store: RecoveryCodeStoreType,
+        webauthn_credential_store: WebauthnCredentialStoreType,
+        webauthn_challenge_store: WebauthnChallengeStoreType,
+        email_cooldown_store: EmailCooldownStoreType,
+        failed_login_store: FailedLoginStoreType,
+        rate_limit_store: RateLimitStoreType,
+        oauth_state_store: OAuthStateStoreType,
+        external_identity_store: ExternalIdentityStoreType,
+        oauth_client_store: OAuthClientStoreType,
+        authorization_code_store: AuthorizationCodeStoreType,
+        permission_store: PermissionStoreType,
+        rate_limits: RateLimits,
+        password_policy: PasswordPolicy,
+        roles: Arc<Roles>,
+        client_registration_token: Option<Secret<String>>,
         email_client: EmailClientType,
+        oidc_client: OidcClientType,
     ) -> Self {
         Self {
             user_store,
             banned_token_store,
             two_fa_code_store,
+            refresh_token_store,
+            session_store,
+            recovery_code_store,
+            webauthn_credential_store,
+            webauthn_challenge_store,
+            email_cooldown_store,
+            failed_login_store,
+            rate_limit_store,
+            oauth_state_store,
+            external_identity_store,
+            oauth_client_store,
+            authorization_code_store,
+            permission_store,
+            rate_limits,
+            password_policy,
+            roles,
+            client_registration_token,
             email_client,
+            oidc_client,
         }
     }
 }
@@ -0,0 +1,466 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::eyre::{eyre, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use ring::digest::{digest, SHA256};
+use secrecy::{ExposeSecret, Secret};
+use std::{collections::BTreeSet, fmt, hash::Hash};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::{decode_base64url, oauth::random_base64url, PkceVerifier, SessionId, UserId};
+
+const CLIENT_SECRET_LENGTH: usize = 48;
+const MAX_CLIENT_NAME_LENGTH: usize = 100;
+const AUTHORIZATION_CODE_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(Uuid);
+
+impl ClientId {
+    pub fn parse(id: &str) -> Result<Self> {
+        let id = Uuid::parse_str(id).map_err(|_| eyre!("Invalid client id"))?;
+        Ok(Self(id))
+    }
+}
+
+impl Default for ClientId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for ClientId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl AsRef<Uuid> for ClientId {
+    fn as_ref(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Secret a confidential client authenticates with. It is only shown once, at registration.
+#[derive(Debug, Clone)]
+pub struct ClientSecret(Secret<String>);
+
+impl ClientSecret {
+    pub fn parse(secret: Secret<String>) -> Result<Self> {
+        if secret.expose_secret().is_empty() {
+            return Err(eyre!("Invalid client secret"));
+        }
+        Ok(Self(secret))
+    }
+
+    /// Secrets are long random strings, so unlike passwords a fast hash is enough to store them.
+    pub fn hash(&self) -> String {
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, self.0.expose_secret().as_bytes()))
+    }
+}
+
+impl Default for ClientSecret {
+    fn default() -> Self {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(CLIENT_SECRET_LENGTH)
+            .map(char::from)
+            .collect();
+        Self(Secret::new(secret))
+    }
+}
+
+impl AsRef<Secret<String>> for ClientSecret {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrantType {
+    AuthorizationCode,
+    ClientCredentials,
+}
+
+impl GrantType {
+    pub fn parse(grant_type: &str) -> Result<Self> {
+        match grant_type {
+            "authorization_code" => Ok(Self::AuthorizationCode),
+            "client_credentials" => Ok(Self::ClientCredentials),
+            _ => Err(eyre!("Unsupported grant type")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AuthorizationCode => "authorization_code",
+            Self::ClientCredentials => "client_credentials",
+        }
+    }
+}
+
+/// A set of scopes, written space separated as in RFC 6749.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scopes(BTreeSet<String>);
+
+impl Scopes {
+    pub fn parse(scope: &str) -> Result<Self> {
+        let mut scopes = BTreeSet::new();
+        for token in scope.split(' ').filter(|token| !token.is_empty()) {
+            if !is_scope_token(token) {
+                return Err(eyre!("Invalid scope: {:?}", token));
+            }
+            scopes.insert(token.to_owned());
+        }
+        Ok(Self(scopes))
+    }
+
+    pub fn contains(&self, scope: &str) -> bool {
+        self.0.contains(scope)
+    }
+
+    pub fn is_subset(&self, other: &Scopes) -> bool {
+        self.0.is_subset(&other.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Scopes {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        Self(iter.into_iter().map(str::to_owned).collect())
+    }
+}
+
+/// Printable ASCII except the space, double quote and backslash (RFC 6749 section 3.3).
+pub(super) fn is_scope_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|c| c == 0x21 || (0x23..=0x5b).contains(&c) || (0x5d..=0x7e).contains(&c))
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scopes: Vec<&str> = self.iter().collect();
+        f.write_str(&scopes.join(" "))
+    }
+}
+
+/// An application registered to get tokens from this service. Public clients, like single page
+/// and mobile apps, can't keep a secret, so they only get the authorization code flow, where PKCE
+/// stands in for the secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthClient {
+    pub id: ClientId,
+    pub name: String,
+    /// Hash of the client secret, `None` for public clients.
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<GrantType>,
+    /// The most a token issued to the client can be scoped to.
+    pub scopes: Scopes,
+}
+
+impl OAuthClient {
+    /// Validates the metadata of a new client. Confidential clients also get their secret, which
+    /// isn't kept anywhere but in the hash.
+    pub fn new(
+        name: String,
+        redirect_uris: Vec<String>,
+        grant_types: Vec<GrantType>,
+        scopes: Scopes,
+        confidential: bool,
+    ) -> Result<(Self, Option<ClientSecret>)> {
+        if name.trim().is_empty() || name.chars().count() > MAX_CLIENT_NAME_LENGTH {
+            return Err(eyre!("Invalid client name"));
+        }
+        if grant_types.is_empty() {
+            return Err(eyre!("A client needs at least one grant type"));
+        }
+        if grant_types.contains(&GrantType::ClientCredentials) && !confidential {
+            return Err(eyre!(
+                "Public clients can't use the client credentials grant"
+            ));
+        }
+        if grant_types.contains(&GrantType::AuthorizationCode) && redirect_uris.is_empty() {
+            return Err(eyre!("The authorization code grant needs a redirect URI"));
+        }
+        for redirect_uri in &redirect_uris {
+            validate_redirect_uri(redirect_uri)?;
+        }
+
+        let mut deduplicated_grant_types = Vec::new();
+        for grant_type in grant_types {
+            if !deduplicated_grant_types.contains(&grant_type) {
+                deduplicated_grant_types.push(grant_type);
+            }
+        }
+
+        let secret = confidential.then(ClientSecret::default);
+        let client = Self {
+            id: ClientId::default(),
+            name: name.trim().to_owned(),
+            secret_hash: secret.as_ref().map(ClientSecret::hash),
+            redirect_uris,
+            grant_types: deduplicated_grant_types,
+            scopes,
+        };
+        Ok((client, secret))
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    pub fn allows_grant(&self, grant_type: GrantType) -> bool {
+        self.grant_types.contains(&grant_type)
+    }
+
+    /// Redirect URIs are compared exactly, so that a code can't be sent anywhere else on the
+    /// client's domain.
+    pub fn has_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+
+    pub fn verify_secret(&self, secret: &ClientSecret) -> bool {
+        match &self.secret_hash {
+            Some(hash) => hash.as_bytes().ct_eq(secret.hash().as_bytes()).into(),
+            None => false,
+        }
+    }
+}
+
+/// Redirect URIs have to be absolute and without a fragment (RFC 6749 section 3.1.2). Plain HTTP
+/// is only accepted on the loopback interface, for apps running on the user's machine.
+pub fn validate_redirect_uri(redirect_uri: &str) -> Result<()> {
+    let url = Url::parse(redirect_uri).map_err(|_| eyre!("Invalid redirect URI"))?;
+    if url.fragment().is_some() {
+        return Err(eyre!("Redirect URIs can't have a fragment"));
+    }
+    if url.scheme() == "http"
+        && !matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+    {
+        return Err(eyre!("Redirect URIs have to use HTTPS"));
+    }
+    Ok(())
+}
+
+/// Single use code the authorization endpoint redirects back to the client with.
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode(Secret<String>);
+
+impl PartialEq for AuthorizationCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl Hash for AuthorizationCode {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
+impl Eq for AuthorizationCode {}
+
+impl AuthorizationCode {
+    pub fn parse(code: Secret<String>) -> Result<Self> {
+        let bytes = decode_base64url(code.expose_secret())?;
+        if bytes.len() != AUTHORIZATION_CODE_LENGTH {
+            return Err(eyre!("Invalid authorization code"));
+        }
+        Ok(Self(code))
+    }
+}
+
+impl Default for AuthorizationCode {
+    fn default() -> Self {
+        Self(Secret::new(random_base64url(AUTHORIZATION_CODE_LENGTH)))
+    }
+}
+
+impl AsRef<Secret<String>> for AuthorizationCode {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+/// What a code was issued for, kept until the client exchanges it for tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizationGrant {
+    pub client_id: ClientId,
+    pub redirect_uri: String,
+    pub user_id: UserId,
+    /// The login the user approved the request from. Tokens issued for the code belong to it, so
+    /// logging out or revoking the session revokes them too.
+    pub session_id: SessionId,
+    pub scopes: Scopes,
+    /// S256 PKCE challenge from the authorization request.
+    pub code_challenge: String,
+    /// Echoed back in the ID token.
+    pub nonce: Option<String>,
+}
+
+impl AuthorizationGrant {
+    pub fn verify_code_verifier(&self, code_verifier: &PkceVerifier) -> bool {
+        self.code_challenge
+            .as_bytes()
+            .ct_eq(code_verifier.challenge().as_bytes())
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(grant_types: Vec<GrantType>, confidential: bool) -> Result<OAuthClient> {
+        OAuthClient::new(
+            "App".to_owned(),
+            vec!["https://app.example.com/callback".to_owned()],
+            grant_types,
+            Scopes::parse("openid email").unwrap(),
+            confidential,
+        )
+        .map(|(client, _)| client)
+    }
+
+    #[test]
+    fn parse_scopes() {
+        let scopes = Scopes::parse("openid  email openid").unwrap();
+        assert_eq!(scopes.to_string(), "email openid");
+        assert!(scopes.contains("openid"));
+        assert!(Scopes::parse("").unwrap().is_empty());
+        assert!(Scopes::parse("read\"write").is_err());
+        assert!(Scopes::parse("read\\write").is_err());
+
+        assert!(Scopes::parse("email").unwrap().is_subset(&scopes));
+        assert!(!Scopes::parse("email profile").unwrap().is_subset(&scopes));
+    }
+
+    #[test]
+    fn parse_grant_types() {
+        for grant_type in [GrantType::AuthorizationCode, GrantType::ClientCredentials] {
+            assert_eq!(GrantType::parse(grant_type.as_str()).unwrap(), grant_type);
+        }
+        assert!(GrantType::parse("password").is_err());
+    }
+
+    #[test]
+    fn verify_client_secret() {
+        let (client, secret) = OAuthClient::new(
+            "App".to_owned(),
+            vec![],
+            vec![GrantType::ClientCredentials],
+            Scopes::default(),
+            true,
+        )
+        .unwrap();
+        let secret = secret.unwrap();
+
+        assert!(client.is_confidential());
+        assert!(client.verify_secret(&secret));
+        assert!(!client.verify_secret(&ClientSecret::default()));
+    }
+
+    #[test]
+    fn public_clients_have_no_secret() {
+        let (client, secret) = OAuthClient::new(
+            "App".to_owned(),
+            vec!["https://app.example.com/callback".to_owned()],
+            vec![GrantType::AuthorizationCode],
+            Scopes::default(),
+            false,
+        )
+        .unwrap();
+
+        assert!(secret.is_none());
+        assert!(!client.is_confidential());
+        assert!(!client.verify_secret(&ClientSecret::default()));
+    }
+
+    #[test]
+    fn reject_invalid_client_metadata() {
+        assert!(client(vec![], true).is_err());
+        assert!(client(vec![GrantType::ClientCredentials], false).is_err());
+
+        let redirect_uri = |uri: &str| {
+            OAuthClient::new(
+                "App".to_owned(),
+                vec![uri.to_owned()],
+                vec![GrantType::AuthorizationCode],
+                Scopes::default(),
+                false,
+            )
+        };
+        assert!(redirect_uri("/callback").is_err());
+        assert!(redirect_uri("https://app.example.com/callback#top").is_err());
+        assert!(redirect_uri("http://app.example.com/callback").is_err());
+        assert!(redirect_uri("http://127.0.0.1:8080/callback").is_ok());
+        assert!(redirect_uri("com.example.app:/callback").is_ok());
+    }
+
+    #[test]
+    fn redirect_uris_match_exactly() {
+        let client = client(vec![GrantType::AuthorizationCode], true).unwrap();
+        assert!(client.has_redirect_uri("https://app.example.com/callback"));
+        assert!(!client.has_redirect_uri("https://app.example.com/callback/"));
+        assert!(!client.has_redirect_uri("https://app.example.com/callback?next=/"));
+    }
+
+    #[test]
+    fn parse_authorization_code_round_trip() {
+        let code = AuthorizationCode::default();
+        assert_eq!(
+            AuthorizationCode::parse(code.as_ref().clone()).unwrap(),
+            code
+        );
+        assert!(AuthorizationCode::parse(Secret::new("code".to_owned())).is_err());
+    }
+
+    #[test]
+    fn verify_code_verifier() {
+        let code_verifier = PkceVerifier::default();
+        let grant = AuthorizationGrant {
+            client_id: ClientId::default(),
+            redirect_uri: "https://app.example.com/callback".to_owned(),
+            user_id: UserId::default(),
+            session_id: SessionId::default(),
+            scopes: Scopes::default(),
+            code_challenge: code_verifier.challenge(),
+            nonce: None,
+        };
+
+        assert!(grant.verify_code_verifier(&code_verifier));
+        assert!(!grant.verify_code_verifier(&PkceVerifier::default()));
+    }
+}
@@ -0,0 +1,27 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::Result;
+
+/// Where breached passwords are looked up. Lookups follow the k-anonymity model of the Have I Been
+/// Pwned range API: only the first `BREACHED_HASH_PREFIX_LENGTH` hex characters of the password's
+/// SHA-1 hash are handed over, so the source never learns which password is being checked.
+#[async_trait::async_trait]
+pub trait BreachedPasswordSource {
+    /// The rest of every breached hash starting with `prefix`, as uppercase hex.
+    async fn hash_suffixes(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+pub const BREACHED_HASH_PREFIX_LENGTH: usize = 5;
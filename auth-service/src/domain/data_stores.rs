@@ -14,18 +14,63 @@
    limitations under the License.
 */
 
-use super::{Email, Password, User};
+use super::{
+    AuthorizationCode, AuthorizationGrant, ClientId, CredentialId, Email, ExistingPassword,
+    ExternalIdentity, Grants, OAuthClient, OAuthFlow, OAuthState, Password, Permission,
+    RecoveryCode, Role, Session, SessionId, TotpSecret, TwoFAMethod, User, UserId,
+    WebauthnCeremony, WebauthnChallenge, WebauthnCredential,
+};
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{eyre, Report, Result};
-use rand::Rng;
+use rand::{distributions::Alphanumeric, Rng};
 use secrecy::{ExposeSecret, Secret};
+use std::hash::Hash;
 use thiserror::Error;
+use webauthn_rs::prelude::Passkey;
 
 #[async_trait::async_trait]
 pub trait UserStore {
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError>;
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError>;
-    async fn validate_user(&self, email: &Email, password: &Password)
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError>;
+    /// Checks the password, and replaces its hash if it was made with outdated settings or from
+    /// the password as typed rather than normalized.
+    async fn validate_user(
+        &self,
+        email: &Email,
+        password: &ExistingPassword,
+    ) -> Result<(), UserStoreError>;
+    async fn set_totp_secret(
+        &mut self,
+        id: &UserId,
+        secret: TotpSecret,
+    ) -> Result<(), UserStoreError>;
+    async fn set_two_fa_method(
+        &mut self,
+        id: &UserId,
+        method: TwoFAMethod,
+    ) -> Result<(), UserStoreError>;
+    /// Turns 2FA off and forgets the authenticator app secret, so turning TOTP back on needs a new
+    /// enrollment.
+    async fn disable_two_fa(&mut self, id: &UserId) -> Result<(), UserStoreError>;
+    /// Records the time step of an accepted TOTP code. Fails with `InvalidCredentials` if a code
+    /// from that step or a later one was already accepted, so that every code works only once.
+    async fn use_totp_step(&mut self, id: &UserId, step: u64) -> Result<(), UserStoreError>;
+    async fn set_password(&mut self, id: &UserId, password: Password)
         -> Result<(), UserStoreError>;
+    async fn set_verified(&mut self, id: &UserId) -> Result<(), UserStoreError>;
+    /// Marks the account for deletion. It is kept until `purge_at`, so it can still be restored.
+    async fn mark_for_deletion(
+        &mut self,
+        id: &UserId,
+        purge_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError>;
+    async fn restore_user(&mut self, id: &UserId) -> Result<(), UserStoreError>;
+    /// Deletes every account whose `purge_at` has passed and returns them, so that the data kept
+    /// elsewhere can be purged too.
+    async fn delete_due_users(&mut self, now: DateTime<Utc>) -> Result<Vec<User>, UserStoreError>;
+    /// How many users have a password hash that will be replaced at their next login.
+    async fn count_outdated_password_hashes(&self) -> Result<usize, UserStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -52,11 +97,12 @@ impl PartialEq for UserStoreError {
     }
 }
 
-
 #[async_trait::async_trait]
 pub trait BannedTokenStore {
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError>;
-    async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError>;
+    /// Bans the token and returns whether it wasn't banned already, so that banning a single-use
+    /// token is what uses it up.
+    async fn add_token(&mut self, jti: String) -> Result<bool, BannedTokenStoreError>;
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -65,36 +111,521 @@ pub enum BannedTokenStoreError {
     UnexpectedError(#[source] Report),
 }
 
+#[async_trait::async_trait]
+pub trait RefreshTokenStore {
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        data: RefreshTokenData,
+    ) -> Result<(), RefreshTokenStoreError>;
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError>;
+    /// Marks the token as used and returns its data as it was before, in one atomic step so two
+    /// concurrent refreshes can't both see it unused.
+    async fn use_token(
+        &mut self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError>;
+    async fn revoke_family(
+        &mut self,
+        family_id: &RefreshTokenFamilyId,
+    ) -> Result<(), RefreshTokenStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum RefreshTokenStoreError {
+    #[error("Refresh token not found")]
+    TokenNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for RefreshTokenStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::TokenNotFound, Self::TokenNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait SessionStore {
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError>;
+    /// Records activity on a live session and returns it.
+    async fn touch_session(&mut self, id: &SessionId) -> Result<Session, SessionStoreError>;
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError>;
+    async fn remove_session(
+        &mut self,
+        user_id: &UserId,
+        id: &SessionId,
+    ) -> Result<(), SessionStoreError>;
+    async fn remove_all_sessions(&mut self, user_id: &UserId) -> Result<(), SessionStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum SessionStoreError {
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for SessionStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::SessionNotFound, Self::SessionNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait WebauthnCredentialStore {
+    async fn add_credential(
+        &mut self,
+        credential: WebauthnCredential,
+    ) -> Result<(), WebauthnCredentialStoreError>;
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<WebauthnCredential, WebauthnCredentialStoreError>;
+    async fn get_credentials(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<WebauthnCredential>, WebauthnCredentialStoreError>;
+    /// Stores the counter and backup state a login moved the passkey on to.
+    async fn update_passkey(
+        &mut self,
+        id: &CredentialId,
+        passkey: Passkey,
+    ) -> Result<(), WebauthnCredentialStoreError>;
+    async fn remove_credentials(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), WebauthnCredentialStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebauthnCredentialStoreError {
+    #[error("Credential already exists")]
+    CredentialAlreadyExists,
+    #[error("Credential not found")]
+    CredentialNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebauthnCredentialStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CredentialAlreadyExists, Self::CredentialAlreadyExists)
+                | (Self::CredentialNotFound, Self::CredentialNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait WebauthnChallengeStore {
+    async fn add_challenge(
+        &mut self,
+        challenge: WebauthnChallenge,
+        ceremony: WebauthnCeremony,
+    ) -> Result<(), WebauthnChallengeStoreError>;
+    /// Removes the challenge so that every challenge is answered at most once.
+    async fn take_challenge(
+        &mut self,
+        challenge: &WebauthnChallenge,
+    ) -> Result<WebauthnCeremony, WebauthnChallengeStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebauthnChallengeStoreError {
+    #[error("Challenge not found")]
+    ChallengeNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebauthnChallengeStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::ChallengeNotFound, Self::ChallengeNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait OAuthStateStore {
+    async fn add_flow(
+        &mut self,
+        state: OAuthState,
+        flow: OAuthFlow,
+    ) -> Result<(), OAuthStateStoreError>;
+    /// Removes the state so that every authorization response is accepted at most once.
+    async fn take_flow(&mut self, state: &OAuthState) -> Result<OAuthFlow, OAuthStateStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum OAuthStateStoreError {
+    #[error("State not found")]
+    StateNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for OAuthStateStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::StateNotFound, Self::StateNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait ExternalIdentityStore {
+    async fn add_identity(
+        &mut self,
+        identity: ExternalIdentity,
+    ) -> Result<(), ExternalIdentityStoreError>;
+    async fn get_user_id(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UserId, ExternalIdentityStoreError>;
+    async fn remove_identities(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), ExternalIdentityStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum ExternalIdentityStoreError {
+    #[error("Identity already linked")]
+    IdentityAlreadyLinked,
+    #[error("Identity not found")]
+    IdentityNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for ExternalIdentityStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::IdentityAlreadyLinked, Self::IdentityAlreadyLinked)
+                | (Self::IdentityNotFound, Self::IdentityNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait OAuthClientStore {
+    async fn add_client(&mut self, client: OAuthClient) -> Result<(), OAuthClientStoreError>;
+    async fn get_client(&self, id: &ClientId) -> Result<OAuthClient, OAuthClientStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum OAuthClientStoreError {
+    #[error("Client already exists")]
+    ClientAlreadyExists,
+    #[error("Client not found")]
+    ClientNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for OAuthClientStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::ClientAlreadyExists, Self::ClientAlreadyExists)
+                | (Self::ClientNotFound, Self::ClientNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait AuthorizationCodeStore {
+    async fn add_code(
+        &mut self,
+        code: AuthorizationCode,
+        grant: AuthorizationGrant,
+    ) -> Result<(), AuthorizationCodeStoreError>;
+    /// Removes the code so that it can be exchanged for tokens only once.
+    async fn take_code(
+        &mut self,
+        code: &AuthorizationCode,
+    ) -> Result<AuthorizationGrant, AuthorizationCodeStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum AuthorizationCodeStoreError {
+    #[error("Authorization code not found")]
+    CodeNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for AuthorizationCodeStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CodeNotFound, Self::CodeNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+/// Roles and permissions granted to each user. Granting what a user already has is not an error.
+#[async_trait::async_trait]
+pub trait PermissionStore {
+    async fn grant_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError>;
+    async fn revoke_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError>;
+    async fn grant_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError>;
+    async fn revoke_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError>;
+    async fn get_grants(&self, user_id: &UserId) -> Result<Grants, PermissionStoreError>;
+    async fn remove_grants(&mut self, user_id: &UserId) -> Result<(), PermissionStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum PermissionStoreError {
+    #[error("Grant not found")]
+    GrantNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for PermissionStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::GrantNotFound, Self::GrantNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait RecoveryCodeStore {
+    /// Replaces every code the user has left with a new set.
+    async fn replace_codes(
+        &mut self,
+        user_id: &UserId,
+        codes: Vec<RecoveryCode>,
+    ) -> Result<(), RecoveryCodeStoreError>;
+    /// Removes the code if it is one of the user's, so that it works only once.
+    async fn use_code(
+        &mut self,
+        user_id: &UserId,
+        code: &RecoveryCode,
+    ) -> Result<(), RecoveryCodeStoreError>;
+    async fn count_codes(&self, user_id: &UserId) -> Result<usize, RecoveryCodeStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum RecoveryCodeStoreError {
+    #[error("Invalid recovery code")]
+    InvalidRecoveryCode,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for RecoveryCodeStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::InvalidRecoveryCode, Self::InvalidRecoveryCode)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait EmailCooldownStore {
+    /// Starts the cooldown for an address, unless one is still running. Callers only send an
+    /// email when this succeeds.
+    async fn start_cooldown(&mut self, email: &Email) -> Result<(), EmailCooldownStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum EmailCooldownStoreError {
+    #[error("Cooldown is still running")]
+    CooldownActive,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for EmailCooldownStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CooldownActive, Self::CooldownActive)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait FailedLoginStore {
+    async fn get_failures(
+        &self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError>;
+    /// Counts an attempt as failed before its password is checked, and returns the record as it
+    /// was before, in one step so that parallel attempts each see the ones before them. Records
+    /// are forgotten once `FAILED_LOGIN_TTL_SECONDS` pass without another attempt.
+    async fn reserve_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError>;
+    /// Takes back an attempt counted by `reserve_attempt`, which returned `previous`. The last
+    /// failure goes back to `previous`'s too, unless another attempt has been counted since.
+    async fn refund_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+        previous: &FailedLogins,
+    ) -> Result<(), FailedLoginStoreError>;
+    async fn clear_failures(&mut self, key: &FailedLoginKey) -> Result<(), FailedLoginStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum FailedLoginStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for FailedLoginStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait RateLimitStore {
+    /// Takes a token from the key's bucket, which holds `limit.requests` tokens and refills over
+    /// `limit.per_seconds`.
+    async fn take_token(&mut self, key: &str, limit: &RateLimit)
+        -> Result<(), RateLimitStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitStoreError {
+    /// Carries the seconds until the bucket has a token again.
+    #[error("Rate limit exceeded")]
+    LimitExceeded(u64),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for RateLimitStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::LimitExceeded(a), Self::LimitExceeded(b)) => a == b,
+            _ => matches!(
+                (self, other),
+                (Self::UnexpectedError(_), Self::UnexpectedError(_))
+            ),
+        }
+    }
+}
+
+/// Wrong guesses a 2FA code survives. After that it is thrown away and the user has to log in
+/// again.
+pub const MAX_TWO_FA_CODE_GUESSES: u32 = 5;
+
+/// Codes one user can be issued within `TWO_FA_CODE_WINDOW_SECONDS`, so that `/login` can't be
+/// used to flood their inbox.
+pub const MAX_TWO_FA_CODES_PER_WINDOW: u32 = 5;
+pub const TWO_FA_CODE_WINDOW_SECONDS: i64 = 600;
+
+/// Login attempts one user can have in progress at once, e.g. on a phone and a laptop. Starting
+/// another drops the oldest.
+pub const MAX_LIVE_TWO_FA_CODES: usize = 3;
+
+/// How long a login attempt's code can be used.
+pub const TWO_FA_CODE_TTL_SECONDS: i64 = 600;
+
 #[async_trait::async_trait]
 pub trait TwoFACodeStore {
+    /// Stores the code for a new login attempt next to the user's others, unless
+    /// `MAX_TWO_FA_CODES_PER_WINDOW` have been issued already.
     async fn add_code(
         &mut self,
         email: Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError>;
+    /// Ends every login attempt the user has in progress.
+    async fn remove_codes(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError>;
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(Email, TwoFACode), TwoFACodeStoreError>;
+    /// Counts a wrong guess at the attempt's code and returns how many are left. The code is
+    /// removed once none are.
+    async fn record_wrong_guess(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<u32, TwoFACodeStoreError>;
 }
 
 #[derive(Debug, Error)]
 pub enum TwoFACodeStoreError {
     #[error("Login Attempt ID not found")]
     LoginAttemptIdNotFound,
+    /// Carries the seconds until another code may be issued.
+    #[error("Too many 2FA codes issued")]
+    TooManyCodes(u64),
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
 
 impl PartialEq for TwoFACodeStoreError {
     fn eq(&self, other: &Self) -> bool {
-        matches!(
-            (self, other),
-            (Self::LoginAttemptIdNotFound, Self::LoginAttemptIdNotFound)
-                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
-        )
+        match (self, other) {
+            (Self::TooManyCodes(a), Self::TooManyCodes(b)) => a == b,
+            _ => matches!(
+                (self, other),
+                (Self::LoginAttemptIdNotFound, Self::LoginAttemptIdNotFound)
+                    | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+            ),
+        }
     }
 }
 
@@ -107,6 +638,14 @@ impl PartialEq for LoginAttemptId {
     }
 }
 
+impl Hash for LoginAttemptId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
+impl Eq for LoginAttemptId {}
+
 impl LoginAttemptId {
     pub fn parse(id: Secret<String>) -> Result<Self> {
         let id = uuid::Uuid::parse_str(id.expose_secret())
@@ -161,10 +700,140 @@ impl AsRef<Secret<String>> for TwoFACode {
     }
 }
 
+const REFRESH_TOKEN_LENGTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct RefreshToken(Secret<String>);
+
+impl PartialEq for RefreshToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl RefreshToken {
+    pub fn parse(token: Secret<String>) -> Result<Self> {
+        let value = token.expose_secret();
+        if value.len() == REFRESH_TOKEN_LENGTH && value.chars().all(|c| c.is_ascii_alphanumeric()) {
+            Ok(Self(token))
+        } else {
+            Err(eyre!("Invalid refresh token"))
+        }
+    }
+}
+
+impl Default for RefreshToken {
+    fn default() -> Self {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(REFRESH_TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        Self(Secret::new(token))
+    }
+}
+
+impl AsRef<Secret<String>> for RefreshToken {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+/// Identifies a chain of refresh tokens issued from a single login. Every rotation stays in the
+/// same family, so a replayed token can revoke all of its descendants at once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RefreshTokenFamilyId(String);
+
+impl RefreshTokenFamilyId {
+    pub fn parse(id: String) -> Result<Self> {
+        let id =
+            uuid::Uuid::parse_str(&id).map_err(|_| eyre!("Invalid refresh token family id"))?;
+        Ok(Self(id.to_string()))
+    }
+}
+
+impl Default for RefreshTokenFamilyId {
+    fn default() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+impl AsRef<str> for RefreshTokenFamilyId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefreshTokenData {
+    pub user_id: UserId,
+    pub session_id: SessionId,
+    pub family_id: RefreshTokenFamilyId,
+    pub used: bool,
+}
+
+impl RefreshTokenData {
+    pub fn new(user_id: UserId, session_id: SessionId, family_id: RefreshTokenFamilyId) -> Self {
+        Self {
+            user_id,
+            session_id,
+            family_id,
+            used: false,
+        }
+    }
+}
+
+/// What failed logins are counted against. An account and the address a login came from are
+/// throttled separately, so spreading guesses over many accounts or many addresses doesn't help.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FailedLoginKey {
+    Account(Email),
+    Ip(String),
+}
+
+/// The failed logins of a key since its record was last cleared or expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FailedLogins {
+    pub count: u32,
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+/// How many requests a rate limited key can make in a period. Bursts of up to `requests` are
+/// fine, after that requests are let through as fast as the bucket refills.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per_seconds: u32,
+}
+
+impl RateLimit {
+    /// Parses limits written as `<requests>/<seconds>`, e.g. `10/60`.
+    pub fn parse(limit: &str) -> Result<Self> {
+        let (requests, per_seconds) = limit
+            .split_once('/')
+            .ok_or_else(|| eyre!("Rate limit should look like <requests>/<seconds>"))?;
+        let rate_limit = Self {
+            requests: requests.trim().parse()?,
+            per_seconds: per_seconds.trim().parse()?,
+        };
+        if rate_limit.requests == 0 || rate_limit.per_seconds == 0 {
+            return Err(eyre!(
+                "Rate limit should have non-zero requests and seconds"
+            ));
+        }
+        Ok(rate_limit)
+    }
+
+    /// Tokens added back to a bucket per second.
+    pub fn refill_rate(&self) -> f64 {
+        self.requests as f64 / self.per_seconds as f64
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::TwoFACode;
-    use secrecy::Secret;
+    use super::{RateLimit, RefreshToken, TwoFACode};
+    use secrecy::{ExposeSecret, Secret};
 
     #[test]
     fn accept_full_generated_two_fa_code_range() {
@@ -177,4 +846,40 @@ mod tests {
         assert!(TwoFACode::parse(Secret::new("99999".to_string())).is_err());
         assert!(TwoFACode::parse(Secret::new("1000000".to_string())).is_err());
     }
+
+    #[test]
+    fn accept_generated_refresh_token() {
+        let token = RefreshToken::default();
+        assert!(RefreshToken::parse(token.as_ref().clone()).is_ok());
+        assert_ne!(
+            token.as_ref().expose_secret(),
+            RefreshToken::default().as_ref().expose_secret()
+        );
+    }
+
+    #[test]
+    fn reject_malformed_refresh_tokens() {
+        assert!(RefreshToken::parse(Secret::new("".to_string())).is_err());
+        assert!(RefreshToken::parse(Secret::new("short".to_string())).is_err());
+        assert!(RefreshToken::parse(Secret::new("-".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn parse_rate_limits() {
+        assert_eq!(
+            RateLimit::parse("10/60").unwrap(),
+            RateLimit {
+                requests: 10,
+                per_seconds: 60
+            }
+        );
+        assert_eq!(RateLimit::parse(" 5 / 1 ").unwrap().refill_rate(), 5.0);
+    }
+
+    #[test]
+    fn reject_malformed_rate_limits() {
+        for limit in ["", "10", "10/", "/60", "0/60", "10/0", "-1/60", "ten/60"] {
+            assert!(RateLimit::parse(limit).is_err(), "accepted {:?}", limit);
+        }
+    }
 }
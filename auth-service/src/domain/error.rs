@@ -17,6 +17,8 @@
 use color_eyre::eyre::Report;
 use thiserror::Error;
 
+use super::PasswordPolicyError;
+
 #[derive(Debug, Error)]
 pub enum AuthAPIError {
     #[error("User already exists")]
@@ -29,6 +31,99 @@ pub enum AuthAPIError {
     MissingToken,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("TOTP already enabled")]
+    TotpAlreadyEnabled,
+    #[error("TOTP enrollment not found")]
+    TotpEnrollmentNotFound,
+    #[error("2FA already enabled")]
+    TwoFAAlreadyEnabled,
+    #[error("2FA not enabled")]
+    TwoFANotEnabled,
+    #[error("Passkey already registered")]
+    PasskeyAlreadyRegistered,
+    #[error("Email not verified")]
+    EmailNotVerified,
+    #[error("Verification email sent recently")]
+    VerificationEmailCooldown,
+    #[error("Account pending deletion")]
+    AccountPendingDeletion,
+    #[error("OAuth provider not found")]
+    OAuthProviderNotFound,
+    #[error("Invalid client")]
+    InvalidClient,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Unknown role")]
+    UnknownRole,
+    #[error("Invalid permission")]
+    InvalidPermission,
+    #[error("Grant not found")]
+    GrantNotFound,
+    #[error("Insufficient scope")]
+    InsufficientScope,
+    /// Carries the seconds to wait before trying again.
+    #[error("Too many attempts")]
+    TooManyAttempts(u64),
+    /// Carries the seconds to wait before trying again.
+    #[error("Too many requests")]
+    TooManyRequests(u64),
+    #[error("Weak password")]
+    WeakPassword(#[source] PasswordPolicyError),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<PasswordPolicyError> for AuthAPIError {
+    fn from(e: PasswordPolicyError) -> Self {
+        match e {
+            PasswordPolicyError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+            e => AuthAPIError::WeakPassword(e),
+        }
+    }
+}
+
+/// Errors of the OAuth endpoints, which clients expect as the error codes of RFC 6749 section 5.2
+/// and RFC 7591 section 3.2.2 rather than the messages the rest of the API answers with.
+#[derive(Debug, Error)]
+pub enum OAuthError {
+    /// Carries a description of what is wrong with the request.
+    #[error("Invalid request")]
+    InvalidRequest(&'static str),
+    #[error("Invalid client")]
+    InvalidClient,
+    #[error("Invalid grant")]
+    InvalidGrant,
+    #[error("Unauthorized client")]
+    UnauthorizedClient,
+    #[error("Unsupported grant type")]
+    UnsupportedGrantType,
+    #[error("Invalid scope")]
+    InvalidScope,
+    #[error("Invalid token")]
+    InvalidToken,
+    #[error("Invalid redirect URI")]
+    InvalidRedirectUri(#[source] Report),
+    #[error("Invalid client metadata")]
+    InvalidClientMetadata(#[source] Report),
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
+
+impl OAuthError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::InvalidToken => "invalid_token",
+            Self::InvalidRedirectUri(_) => "invalid_redirect_uri",
+            Self::InvalidClientMetadata(_) => "invalid_client_metadata",
+            Self::UnexpectedError(_) => "server_error",
+        }
+    }
+}
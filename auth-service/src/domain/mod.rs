@@ -14,16 +14,34 @@
    limitations under the License.
 */
 
+pub mod authorization;
+pub mod breached_password_source;
 pub mod data_stores;
 pub mod email;
 pub mod email_client;
 pub mod error;
+pub mod oauth;
 pub mod password;
+pub mod password_hasher;
+pub mod password_policy;
+pub mod role;
+pub mod session;
+pub mod two_fa;
 pub mod user;
+pub mod webauthn;
 
+pub use authorization::*;
+pub use breached_password_source::*;
 pub use data_stores::*;
 pub use email::*;
 pub use email_client::*;
 pub use error::*;
+pub use oauth::*;
 pub use password::*;
+pub use password_hasher::*;
+pub use password_policy::*;
+pub use role::*;
+pub use session::*;
+pub use two_fa::*;
 pub use user::*;
+pub use webauthn::*;
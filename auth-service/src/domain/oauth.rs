@@ -0,0 +1,295 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::eyre::{eyre, Context, Result};
+use rand::RngCore;
+use ring::digest::{digest, SHA256};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::{decode_base64url, UserId};
+
+const OAUTH_STATE_LENGTH: usize = 32;
+const OAUTH_NONCE_LENGTH: usize = 32;
+const PKCE_VERIFIER_LENGTH: usize = 32;
+pub const OPENID_SCOPE: &str = "openid";
+pub const EMAIL_SCOPE: &str = "email";
+
+/// An OpenID Connect provider users can log in with. The endpoints are the ones its discovery
+/// document lists, and `redirect_uri` is the callback registered with it for this service.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthProvider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Secret<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub redirect_uri: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![OPENID_SCOPE.to_owned(), "email".to_owned()]
+}
+
+/// The providers users can log in with, by name. The name is the one in the login routes, so it
+/// is limited to characters that need no escaping in a path.
+#[derive(Debug, Clone, Default)]
+pub struct OAuthProviders {
+    providers: HashMap<String, OAuthProvider>,
+}
+
+impl OAuthProviders {
+    pub fn new(providers: Vec<OAuthProvider>) -> Result<Self> {
+        let mut by_name = HashMap::new();
+        for provider in providers {
+            let valid_name = !provider.name.is_empty()
+                && provider
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(eyre!("invalid OAuth provider name: {:?}", provider.name));
+            }
+            // without the openid scope the provider answers with plain OAuth 2.0 and no ID token
+            if !provider.scopes.iter().any(|scope| scope == OPENID_SCOPE) {
+                return Err(eyre!(
+                    "OAuth provider {} has to request the openid scope",
+                    provider.name
+                ));
+            }
+            if by_name.contains_key(&provider.name) {
+                return Err(eyre!("duplicate OAuth provider: {}", provider.name));
+            }
+            by_name.insert(provider.name.clone(), provider);
+        }
+        Ok(Self { providers: by_name })
+    }
+
+    /// Builds the registry from a JSON document with a `providers` list.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: OAuthProvidersConfig =
+            serde_json::from_str(json).wrap_err("failed to parse OAuth providers")?;
+        Self::new(config.providers)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OAuthProvider> {
+        self.providers.get(name)
+    }
+}
+
+#[derive(Deserialize)]
+struct OAuthProvidersConfig {
+    providers: Vec<OAuthProvider>,
+}
+
+pub(super) fn random_base64url(length: usize) -> String {
+    let mut bytes = vec![0u8; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Random value an authorization request carries and the provider hands back to the callback,
+/// tying the response to the login this service started.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OAuthState(String);
+
+impl OAuthState {
+    pub fn parse(state: String) -> Result<Self> {
+        let bytes = decode_base64url(&state)?;
+        if bytes.len() != OAUTH_STATE_LENGTH {
+            return Err(eyre!("Invalid OAuth state"));
+        }
+        Ok(Self(URL_SAFE_NO_PAD.encode(bytes)))
+    }
+}
+
+impl Default for OAuthState {
+    fn default() -> Self {
+        Self(random_base64url(OAUTH_STATE_LENGTH))
+    }
+}
+
+impl AsRef<str> for OAuthState {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// PKCE code verifier (RFC 7636). Only its hash goes into the authorization request, and the
+/// verifier itself only to the token endpoint, so an intercepted code is useless on its own.
+#[derive(Debug, Clone)]
+pub struct PkceVerifier(Secret<String>);
+
+impl PartialEq for PkceVerifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl PkceVerifier {
+    pub fn parse(verifier: Secret<String>) -> Result<Self> {
+        let value = verifier.expose_secret();
+        let valid = (43..=128).contains(&value.len())
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+        if !valid {
+            return Err(eyre!("Invalid PKCE code verifier"));
+        }
+        Ok(Self(verifier))
+    }
+
+    /// The S256 code challenge sent in the authorization request.
+    pub fn challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, self.0.expose_secret().as_bytes()))
+    }
+}
+
+impl Default for PkceVerifier {
+    fn default() -> Self {
+        Self(Secret::new(random_base64url(PKCE_VERIFIER_LENGTH)))
+    }
+}
+
+impl AsRef<Secret<String>> for PkceVerifier {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+/// What is kept about a login between sending the user to the provider and the callback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthFlow {
+    pub provider: String,
+    pub code_verifier: PkceVerifier,
+    /// Echoed back in the ID token, so a token issued for another login can't be replayed.
+    pub nonce: String,
+}
+
+impl OAuthFlow {
+    pub fn new(provider: String) -> Self {
+        Self {
+            provider,
+            code_verifier: PkceVerifier::default(),
+            nonce: random_base64url(OAUTH_NONCE_LENGTH),
+        }
+    }
+}
+
+/// An account at a provider, linked to a user. `subject` is the provider's `sub` claim, which
+/// unlike the email address never changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: UserId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider_json(name: &str, scopes: &str) -> String {
+        format!(
+            r#"{{
+                "name": "{}",
+                "issuer": "https://accounts.example.com",
+                "client_id": "client",
+                "client_secret": "secret",
+                "authorization_endpoint": "https://accounts.example.com/authorize",
+                "token_endpoint": "https://accounts.example.com/token",
+                "jwks_uri": "https://accounts.example.com/jwks",
+                "redirect_uri": "https://auth.example.com/oauth/{}/callback"
+                {}
+            }}"#,
+            name, name, scopes
+        )
+    }
+
+    #[test]
+    fn load_providers_from_json() {
+        let json = format!(
+            r#"{{ "providers": [{}, {}] }}"#,
+            provider_json("google", ""),
+            provider_json("okta", r#", "scopes": ["openid", "email", "profile"]"#)
+        );
+        let providers = OAuthProviders::from_json(&json).unwrap();
+
+        let google = providers.get("google").unwrap();
+        assert_eq!(google.scopes, vec!["openid", "email"]);
+        assert_eq!(google.client_secret.expose_secret(), "secret");
+        assert_eq!(providers.get("okta").unwrap().scopes.len(), 3);
+        assert!(providers.get("github").is_none());
+    }
+
+    #[test]
+    fn reject_invalid_provider_configs() {
+        let duplicate = format!(
+            r#"{{ "providers": [{}, {}] }}"#,
+            provider_json("google", ""),
+            provider_json("google", "")
+        );
+        assert!(OAuthProviders::from_json(&duplicate).is_err());
+
+        let no_openid = format!(
+            r#"{{ "providers": [{}] }}"#,
+            provider_json("google", r#", "scopes": ["email"]"#)
+        );
+        assert!(OAuthProviders::from_json(&no_openid).is_err());
+
+        let bad_name = format!(r#"{{ "providers": [{}] }}"#, provider_json("a/b", ""));
+        assert!(OAuthProviders::from_json(&bad_name).is_err());
+    }
+
+    #[test]
+    fn parse_state_round_trip() {
+        let state = OAuthState::default();
+        assert_eq!(OAuthState::parse(state.as_ref().to_owned()).unwrap(), state);
+        assert_ne!(OAuthState::default(), state);
+        assert!(OAuthState::parse("".to_owned()).is_err());
+        assert!(OAuthState::parse(URL_SAFE_NO_PAD.encode([0u8; 16])).is_err());
+    }
+
+    #[test]
+    fn pkce_challenge_matches_rfc_7636_example() {
+        // Appendix B of RFC 7636
+        let verifier = PkceVerifier::parse(Secret::new(
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_owned(),
+        ))
+        .unwrap();
+        assert_eq!(
+            verifier.challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn reject_malformed_pkce_verifiers() {
+        assert!(PkceVerifier::parse(Secret::new("short".to_owned())).is_err());
+        assert!(PkceVerifier::parse(Secret::new("a".repeat(129))).is_err());
+        assert!(PkceVerifier::parse(Secret::new(format!("{}!", "a".repeat(43)))).is_err());
+        let verifier = PkceVerifier::default();
+        assert_eq!(
+            PkceVerifier::parse(verifier.as_ref().clone()).unwrap(),
+            verifier
+        );
+    }
+}
@@ -0,0 +1,58 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::Result;
+use secrecy::Secret;
+use std::sync::Arc;
+
+use super::ExistingPassword;
+
+/// Turns passwords, and other secrets that are only ever compared, into salted hashes that are
+/// safe to store.
+#[async_trait::async_trait]
+pub trait PasswordHasher {
+    async fn hash(&self, password: Secret<String>) -> Result<Secret<String>>;
+    /// Fails if `candidate` isn't the password `hash` was made from.
+    async fn verify(&self, hash: Secret<String>, candidate: Secret<String>) -> Result<()>;
+    /// Whether `hash` was made with another algorithm or parameters than new hashes are, and
+    /// should be replaced the next time the password is known.
+    fn needs_rehash(&self, hash: &Secret<String>) -> bool;
+    /// How every hash made with the current settings starts, up to the salt. Hashes that start
+    /// otherwise need a rehash, so stores can count them without loading them.
+    fn current_prefix(&self) -> String;
+
+    /// Fails unless `password` matches `hash` in one of the forms it may have been hashed in.
+    /// Otherwise says whether the hash should be replaced with one of the normalized password,
+    /// because it was made from the password as typed or with outdated settings.
+    async fn verify_existing(
+        &self,
+        hash: &Secret<String>,
+        password: &ExistingPassword,
+    ) -> Result<bool> {
+        let normalized = self
+            .verify(hash.clone(), password.normalized.as_ref().clone())
+            .await;
+        match (normalized, &password.as_typed) {
+            (Ok(()), _) => Ok(self.needs_rehash(hash)),
+            (Err(_), Some(as_typed)) => {
+                self.verify(hash.clone(), as_typed.as_ref().clone()).await?;
+                Ok(true)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+pub type PasswordHasherType = Arc<dyn PasswordHasher + Send + Sync>;
@@ -0,0 +1,396 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Report, Result};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use secrecy::{ExposeSecret, Secret};
+use std::sync::Arc;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+use super::{BreachedPasswordSource, Email, Password, BREACHED_HASH_PREFIX_LENGTH};
+
+pub const DEFAULT_MIN_PASSWORD_LENGTH: usize = 8;
+pub const DEFAULT_MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Passwords that are among the first tried by anyone guessing, kept in the binary so the check
+/// works without any source of breached passwords.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Passwords within this many edits of the email, or of the part before the `@`, are too easy to
+/// guess for anyone who knows the address.
+const MAX_EMAIL_DISTANCE: usize = 2;
+
+pub type BreachedPasswordSourceType = Arc<dyn BreachedPasswordSource + Send + Sync>;
+
+/// How passwords are normalized before they're checked and hashed, so that the same password
+/// typed on different keyboards or platforms still matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    None,
+    Nfc,
+    Nfkc,
+}
+
+impl Normalization {
+    pub fn parse(normalization: &str) -> Result<Self> {
+        match normalization.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "nfc" => Ok(Self::Nfc),
+            "nfkc" => Ok(Self::Nfkc),
+            _ => Err(eyre!("Unknown Unicode normalization: {}", normalization)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PasswordPolicyError {
+    #[error("Password must be at least {0} characters")]
+    TooShort(usize),
+    #[error("Password must be at most {0} characters")]
+    TooLong(usize),
+    #[error("Password is too common")]
+    TooCommon,
+    #[error("Password has appeared in a data breach")]
+    Breached,
+    #[error("Password is too similar to the email address")]
+    TooSimilarToEmail,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PasswordPolicyError {
+    /// The rule that was broken, for clients that show their own messages.
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Self::TooShort(_) => "min_length",
+            Self::TooLong(_) => "max_length",
+            Self::TooCommon => "common_password",
+            Self::Breached => "breached_password",
+            Self::TooSimilarToEmail => "similar_to_email",
+            Self::UnexpectedError(_) => "unexpected",
+        }
+    }
+}
+
+impl PartialEq for PasswordPolicyError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::TooShort(a), Self::TooShort(b)) | (Self::TooLong(a), Self::TooLong(b)) => a == b,
+            _ => matches!(
+                (self, other),
+                (Self::TooCommon, Self::TooCommon)
+                    | (Self::Breached, Self::Breached)
+                    | (Self::TooSimilarToEmail, Self::TooSimilarToEmail)
+                    | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+            ),
+        }
+    }
+}
+
+/// A password the user already has, normalized the way passwords are set now, and as typed when
+/// that differs. Passwords set before normalization was turned on, or changed, were hashed as
+/// typed, and only match that way until they are hashed again.
+#[derive(Debug, Clone)]
+pub struct ExistingPassword {
+    pub normalized: Password,
+    pub as_typed: Option<Password>,
+}
+
+impl From<Password> for ExistingPassword {
+    fn from(password: Password) -> Self {
+        Self {
+            normalized: password,
+            as_typed: None,
+        }
+    }
+}
+
+/// The rules new passwords have to follow. Passwords users already have aren't checked against
+/// them, so tightening the policy never locks anyone out.
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub normalization: Normalization,
+    pub breached_passwords: Option<BreachedPasswordSourceType>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: DEFAULT_MIN_PASSWORD_LENGTH,
+            max_length: DEFAULT_MAX_PASSWORD_LENGTH,
+            normalization: Normalization::Nfkc,
+            breached_passwords: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn with_breached_passwords(self, source: BreachedPasswordSourceType) -> Self {
+        Self {
+            breached_passwords: Some(source),
+            ..self
+        }
+    }
+
+    /// Parses a password the user already has, e.g. at login.
+    pub fn parse(&self, password: Secret<String>) -> Result<ExistingPassword> {
+        let normalized = self.normalize(password.clone());
+        let as_typed = (normalized.expose_secret() != password.expose_secret())
+            .then(|| Password::parse(password).ok())
+            .flatten();
+        Ok(ExistingPassword {
+            normalized: Password::parse(normalized)?,
+            as_typed,
+        })
+    }
+
+    /// Parses a password the user is setting, which has to follow every rule.
+    pub async fn parse_new(
+        &self,
+        password: Secret<String>,
+        email: &Email,
+    ) -> Result<Password, PasswordPolicyError> {
+        let password = self.normalize(password);
+        let length = password.expose_secret().chars().count();
+        if length < self.min_length {
+            return Err(PasswordPolicyError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(PasswordPolicyError::TooLong(self.max_length));
+        }
+        if is_common(&password) {
+            return Err(PasswordPolicyError::TooCommon);
+        }
+        if is_similar_to_email(&password, email) {
+            return Err(PasswordPolicyError::TooSimilarToEmail);
+        }
+        if let Some(source) = &self.breached_passwords {
+            if is_breached(&password, source.as_ref()).await? {
+                return Err(PasswordPolicyError::Breached);
+            }
+        }
+
+        // the policy's minimum can't go below what `Password` itself accepts
+        Password::parse(password).map_err(|_| PasswordPolicyError::TooShort(self.min_length))
+    }
+
+    fn normalize(&self, password: Secret<String>) -> Secret<String> {
+        let password = password.expose_secret();
+        Secret::new(match self.normalization {
+            Normalization::None => password.to_owned(),
+            Normalization::Nfc => password.nfc().collect(),
+            Normalization::Nfkc => password.nfkc().collect(),
+        })
+    }
+}
+
+fn is_common(password: &Secret<String>) -> bool {
+    let password = password.expose_secret().to_lowercase();
+    COMMON_PASSWORDS.lines().any(|common| common == password)
+}
+
+fn is_similar_to_email(password: &Secret<String>, email: &Email) -> bool {
+    let password = password.expose_secret().to_lowercase();
+    let email = email.as_ref().expose_secret().to_lowercase();
+    let local_part = email.split('@').next().unwrap_or_default();
+
+    password.contains(&email)
+        || (local_part.chars().count() >= 3 && password.contains(local_part))
+        || edit_distance(&password, &email) <= MAX_EMAIL_DISTANCE
+        || edit_distance(&password, local_part) <= MAX_EMAIL_DISTANCE
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[tracing::instrument(name = "Check breached passwords", skip_all)]
+async fn is_breached(
+    password: &Secret<String>,
+    source: &(dyn BreachedPasswordSource + Send + Sync),
+) -> Result<bool, PasswordPolicyError> {
+    let hash: String = digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        password.expose_secret().as_bytes(),
+    )
+    .as_ref()
+    .iter()
+    .map(|byte| format!("{:02X}", byte))
+    .collect();
+    let (prefix, suffix) = hash.split_at(BREACHED_HASH_PREFIX_LENGTH);
+
+    let suffixes = source
+        .hash_suffixes(prefix)
+        .await
+        .map_err(PasswordPolicyError::UnexpectedError)?;
+    Ok(suffixes
+        .iter()
+        .any(|breached| breached.eq_ignore_ascii_case(suffix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticSource(Vec<String>);
+
+    #[async_trait::async_trait]
+    impl BreachedPasswordSource for StaticSource {
+        async fn hash_suffixes(&self, prefix: &str) -> Result<Vec<String>> {
+            assert_eq!(prefix.len(), BREACHED_HASH_PREFIX_LENGTH);
+            Ok(self.0.clone())
+        }
+    }
+
+    fn email() -> Email {
+        Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap()
+    }
+
+    async fn check(policy: &PasswordPolicy, password: &str) -> Result<(), PasswordPolicyError> {
+        policy
+            .parse_new(Secret::new(password.to_owned()), &email())
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn enforce_length_limits() {
+        let policy = PasswordPolicy {
+            min_length: 10,
+            max_length: 12,
+            ..PasswordPolicy::default()
+        };
+        assert_eq!(
+            check(&policy, "tr0ub4dor").await,
+            Err(PasswordPolicyError::TooShort(10))
+        );
+        assert_eq!(
+            check(&policy, "tr0ub4dor&3xyz").await,
+            Err(PasswordPolicyError::TooLong(12))
+        );
+        assert!(check(&policy, "tr0ub4dor&3").await.is_ok());
+        // length is counted in characters, not bytes
+        assert!(check(&policy, "ünïcödé✓pässwörd").await.is_err());
+        assert!(check(&policy, "ünïcödé✓päss").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reject_common_passwords() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            check(&policy, "Password123").await,
+            Err(PasswordPolicyError::TooCommon)
+        );
+        assert_eq!(
+            check(&policy, "qwertyuiop").await,
+            Err(PasswordPolicyError::TooCommon)
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_passwords_similar_to_email() {
+        let policy = PasswordPolicy::default();
+        for password in [
+            "ibrahim@umbrella.corp",
+            "Ibrahim2024!",
+            "ibrahim12",
+            "ibrahim@umbrella.cop",
+        ] {
+            assert_eq!(
+                check(&policy, password).await,
+                Err(PasswordPolicyError::TooSimilarToEmail),
+                "accepted {:?}",
+                password
+            );
+        }
+        assert!(check(&policy, "notSoSecure").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reject_breached_passwords() {
+        let hash: String = digest(&SHA1_FOR_LEGACY_USE_ONLY, b"notSoSecure")
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let source = StaticSource(vec![hash[BREACHED_HASH_PREFIX_LENGTH..].to_lowercase()]);
+        let policy = PasswordPolicy::default().with_breached_passwords(Arc::new(source));
+
+        assert_eq!(
+            check(&policy, "notSoSecure").await,
+            Err(PasswordPolicyError::Breached)
+        );
+        assert!(check(&policy, "notSoSecure1").await.is_ok());
+    }
+
+    #[test]
+    fn normalize_compatible_characters() {
+        let policy = PasswordPolicy::default();
+        // a full-width "Ａ" and a decomposed "é" become their plain forms
+        let password = policy
+            .parse(Secret::new("Ａbcde\u{301}fghi".to_owned()))
+            .unwrap();
+        assert_eq!(password.normalized.as_ref().expose_secret(), "Abcdéfghi");
+        // kept for a hash made from the password as it was typed
+        assert_eq!(
+            password.as_typed.unwrap().as_ref().expose_secret(),
+            "Ａbcde\u{301}fghi"
+        );
+
+        let password = policy.parse(Secret::new("Abcdefghi".to_owned())).unwrap();
+        assert!(password.as_typed.is_none());
+
+        let policy = PasswordPolicy {
+            normalization: Normalization::None,
+            ..PasswordPolicy::default()
+        };
+        let password = policy
+            .parse(Secret::new("Ａbcde\u{301}fghi".to_owned()))
+            .unwrap();
+        assert_eq!(
+            password.normalized.as_ref().expose_secret(),
+            "Ａbcde\u{301}fghi"
+        );
+        assert!(password.as_typed.is_none());
+    }
+
+    #[test]
+    fn parse_normalization() {
+        assert_eq!(Normalization::parse("NFKC").unwrap(), Normalization::Nfkc);
+        assert_eq!(Normalization::parse("none").unwrap(), Normalization::None);
+        assert!(Normalization::parse("nfd").is_err());
+    }
+
+    #[test]
+    fn count_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("ibrahim", "ibrahim"), 0);
+    }
+}
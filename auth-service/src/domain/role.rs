@@ -0,0 +1,226 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+
+use super::{authorization::is_scope_token, Scopes};
+
+const MAX_ROLE_LENGTH: usize = 64;
+const MAX_PERMISSION_LENGTH: usize = 128;
+/// The role that manages everyone else's roles. It always exists, whatever is configured.
+pub const ADMIN_ROLE: &str = "admin";
+/// The scope the admin API requires, which the admin role brings.
+pub const MANAGE_ROLES_SCOPE: &str = "roles:manage";
+
+/// Name of a role, as it appears in the admin API's paths.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Role(String);
+
+impl Role {
+    pub fn parse(role: &str) -> Result<Self> {
+        let valid = !role.is_empty()
+            && role.len() <= MAX_ROLE_LENGTH
+            && role
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(eyre!("Invalid role: {:?}", role));
+        }
+        Ok(Self(role.to_owned()))
+    }
+}
+
+impl AsRef<str> for Role {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Something a user may do. Permissions are carried in tokens as scopes, so they follow the same
+/// rules, e.g. `articles:write`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Permission(String);
+
+impl Permission {
+    pub fn parse(permission: &str) -> Result<Self> {
+        if permission.len() > MAX_PERMISSION_LENGTH || !is_scope_token(permission) {
+            return Err(eyre!("Invalid permission: {:?}", permission));
+        }
+        Ok(Self(permission.to_owned()))
+    }
+}
+
+impl AsRef<str> for Permission {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The roles and permissions granted to a user.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Grants {
+    pub roles: BTreeSet<Role>,
+    pub permissions: BTreeSet<Permission>,
+}
+
+/// The roles users can be granted, each with the permissions it brings. Roles are configured
+/// rather than stored, so what a role means is reviewed like any other change.
+#[derive(Debug, Clone)]
+pub struct Roles {
+    roles: HashMap<Role, BTreeSet<Permission>>,
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        Self::new(Vec::new()).expect("the admin role is valid")
+    }
+}
+
+impl Roles {
+    pub fn new(definitions: Vec<RoleDefinition>) -> Result<Self> {
+        let mut roles = HashMap::new();
+        for definition in definitions {
+            let role = Role::parse(&definition.name)?;
+            let permissions = definition
+                .permissions
+                .iter()
+                .map(|permission| Permission::parse(permission))
+                .collect::<Result<BTreeSet<_>>>()?;
+            if roles.insert(role, permissions).is_some() {
+                return Err(eyre!("duplicate role: {}", definition.name));
+            }
+        }
+
+        // the admin role may be given more permissions, but never loses the one to manage roles
+        roles
+            .entry(Role(ADMIN_ROLE.to_owned()))
+            .or_default()
+            .insert(Permission(MANAGE_ROLES_SCOPE.to_owned()));
+        Ok(Self { roles })
+    }
+
+    /// Builds the roles from a JSON document with a `roles` list.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: RolesConfig = serde_json::from_str(json).wrap_err("failed to parse roles")?;
+        Self::new(config.roles)
+    }
+
+    pub fn contains(&self, role: &Role) -> bool {
+        self.roles.contains_key(role)
+    }
+
+    /// The scopes a user with `grants` gets in their tokens: the permissions granted directly and
+    /// those of their roles. A role that is no longer configured brings nothing.
+    pub fn effective_scopes(&self, grants: &Grants) -> Scopes {
+        let from_roles = grants
+            .roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .flatten();
+        grants
+            .permissions
+            .iter()
+            .chain(from_roles)
+            .map(Permission::as_ref)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoleDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RolesConfig {
+    roles: Vec<RoleDefinition>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grants(roles: &[&str], permissions: &[&str]) -> Grants {
+        Grants {
+            roles: roles
+                .iter()
+                .map(|role| Role::parse(role).unwrap())
+                .collect(),
+            permissions: permissions
+                .iter()
+                .map(|permission| Permission::parse(permission).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn load_roles_from_json() {
+        let roles = Roles::from_json(
+            r#"{ "roles": [
+                { "name": "editor", "permissions": ["articles:read", "articles:write"] },
+                { "name": "admin", "permissions": ["articles:delete"] }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert!(roles.contains(&Role::parse("editor").unwrap()));
+        assert!(!roles.contains(&Role::parse("viewer").unwrap()));
+        let scopes = roles.effective_scopes(&grants(&["admin"], &[]));
+        assert_eq!(scopes.to_string(), "articles:delete roles:manage");
+    }
+
+    #[test]
+    fn admin_role_exists_without_config() {
+        let roles = Roles::default();
+        let scopes = roles.effective_scopes(&grants(&["admin"], &[]));
+        assert!(scopes.contains(MANAGE_ROLES_SCOPE));
+    }
+
+    #[test]
+    fn effective_scopes_combine_roles_and_permissions() {
+        let roles = Roles::new(vec![RoleDefinition {
+            name: "editor".to_owned(),
+            permissions: vec!["articles:write".to_owned()],
+        }])
+        .unwrap();
+
+        let scopes = roles.effective_scopes(&grants(
+            &["editor", "removed-role"],
+            &["articles:write", "reports:read"],
+        ));
+        assert_eq!(scopes.to_string(), "articles:write reports:read");
+        assert!(roles.effective_scopes(&Grants::default()).is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_roles_and_permissions() {
+        assert!(Role::parse("").is_err());
+        assert!(Role::parse("Editor").is_err());
+        assert!(Role::parse("a/b").is_err());
+        assert!(Role::parse(&"a".repeat(65)).is_err());
+        assert!(Permission::parse("").is_err());
+        assert!(Permission::parse("articles write").is_err());
+        assert!(Permission::parse("articles\\write").is_err());
+
+        let duplicate = r#"{ "roles": [{ "name": "editor" }, { "name": "editor" }] }"#;
+        assert!(Roles::from_json(duplicate).is_err());
+        let bad_permission = r#"{ "roles": [{ "name": "editor", "permissions": [""] }] }"#;
+        assert!(Roles::from_json(bad_permission).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+
+use super::UserId;
+
+/// A single login on one device. Every access and refresh token issued for the login carries the
+/// session id, so removing the session signs that device out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: SessionId,
+    pub user_id: UserId,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub fn new(user_id: UserId, user_agent: Option<String>, ip: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: SessionId::default(),
+            user_id,
+            user_agent,
+            ip,
+            created_at: now,
+            last_seen_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionId(String);
+
+impl SessionId {
+    pub fn parse(id: String) -> Result<Self> {
+        let id = uuid::Uuid::parse_str(&id).map_err(|_| eyre!("Invalid session id"))?;
+        Ok(Self(id.to_string()))
+    }
+}
+
+impl Default for SessionId {
+    fn default() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+impl AsRef<str> for SessionId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
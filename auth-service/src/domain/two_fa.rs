@@ -0,0 +1,214 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Result};
+use rand::{Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+
+/// How a user proves their second factor after a correct password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TwoFAMethod {
+    #[default]
+    None,
+    /// A six digit code mailed to the user for every login.
+    Email,
+    /// An RFC 6238 code from an authenticator app.
+    Totp,
+}
+
+impl TwoFAMethod {
+    pub fn parse(method: &str) -> Result<Self> {
+        match method {
+            "none" => Ok(Self::None),
+            "email" => Ok(Self::Email),
+            "totp" => Ok(Self::Totp),
+            _ => Err(eyre!("Invalid 2FA method")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Email => "email",
+            Self::Totp => "totp",
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::None
+    }
+}
+
+// RFC 4226 recommends a 160 bit shared secret and requires at least 128 bits.
+const TOTP_SECRET_LENGTH: usize = 20;
+const TOTP_SECRET_MIN_LENGTH: usize = 16;
+
+/// Shared secret of an authenticator app, kept base32 encoded as apps expect it.
+#[derive(Debug, Clone)]
+pub struct TotpSecret(Secret<String>);
+
+impl PartialEq for TotpSecret {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl TotpSecret {
+    pub fn parse(secret: Secret<String>) -> Result<Self> {
+        let bytes = totp_rs::Secret::Encoded(secret.expose_secret().to_owned())
+            .to_bytes()
+            .map_err(|_| eyre!("Invalid TOTP secret"))?;
+        if bytes.len() < TOTP_SECRET_MIN_LENGTH {
+            return Err(eyre!("TOTP secret is too short"));
+        }
+        Ok(Self(secret))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        totp_rs::Secret::Encoded(self.0.expose_secret().to_owned())
+            .to_bytes()
+            .map_err(|_| eyre!("Invalid TOTP secret"))
+    }
+}
+
+impl Default for TotpSecret {
+    fn default() -> Self {
+        let mut bytes = [0u8; TOTP_SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(Secret::new(
+            totp_rs::Secret::Raw(bytes.to_vec())
+                .to_encoded()
+                .to_string(),
+        ))
+    }
+}
+
+impl AsRef<Secret<String>> for TotpSecret {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+/// How many recovery codes a user gets each time the set is generated.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// Ten characters from an alphabet without look-alikes such as 0/o and 1/l carry about 49 bits.
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// Single-use code that stands in for the second factor, shown as `xxxxx-xxxxx`.
+#[derive(Debug, Clone)]
+pub struct RecoveryCode(Secret<String>);
+
+impl PartialEq for RecoveryCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl RecoveryCode {
+    /// Accepts codes typed in any case, with or without the separator.
+    pub fn parse(code: Secret<String>) -> Result<Self> {
+        let code: String = code
+            .expose_secret()
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if code.len() != RECOVERY_CODE_LENGTH
+            || !code.bytes().all(|c| RECOVERY_CODE_ALPHABET.contains(&c))
+        {
+            return Err(eyre!("Invalid recovery code"));
+        }
+        Ok(Self::from_normalized(&code))
+    }
+
+    fn from_normalized(code: &str) -> Self {
+        let (head, tail) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+        Self(Secret::new(format!("{}-{}", head, tail)))
+    }
+}
+
+impl Default for RecoveryCode {
+    fn default() -> Self {
+        let mut rng = rand::thread_rng();
+        let code: String = (0..RECOVERY_CODE_LENGTH)
+            .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+            .collect();
+        Self::from_normalized(&code)
+    }
+}
+
+impl AsRef<Secret<String>> for RecoveryCode {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecoveryCode, TotpSecret, TwoFAMethod};
+    use secrecy::{ExposeSecret, Secret};
+
+    #[test]
+    fn parse_round_trips_two_fa_methods() {
+        for method in [TwoFAMethod::None, TwoFAMethod::Email, TwoFAMethod::Totp] {
+            assert_eq!(TwoFAMethod::parse(method.as_str()).unwrap(), method);
+        }
+        assert!(TwoFAMethod::parse("sms").is_err());
+    }
+
+    #[test]
+    fn parse_round_trips_generated_totp_secret() {
+        let secret = TotpSecret::default();
+        assert_eq!(TotpSecret::parse(secret.as_ref().clone()).unwrap(), secret);
+        assert_eq!(secret.to_bytes().unwrap().len(), 20);
+        assert_ne!(TotpSecret::default(), secret);
+    }
+
+    #[test]
+    fn reject_malformed_totp_secrets() {
+        assert!(TotpSecret::parse(Secret::new("".to_owned())).is_err());
+        assert!(TotpSecret::parse(Secret::new("not base32!".to_owned())).is_err());
+        // valid base32, but only 80 bits
+        assert!(TotpSecret::parse(Secret::new("JBSWY3DPEHPK3PXP".to_owned())).is_err());
+    }
+
+    #[test]
+    fn parse_round_trips_generated_recovery_code() {
+        let code = RecoveryCode::default();
+        assert_eq!(code.as_ref().expose_secret().len(), 11);
+        assert_eq!(RecoveryCode::parse(code.as_ref().clone()).unwrap(), code);
+        assert_ne!(RecoveryCode::default(), code);
+    }
+
+    #[test]
+    fn parse_normalizes_recovery_codes() {
+        let expected = RecoveryCode::parse(Secret::new("abcde-fghjk".to_owned())).unwrap();
+        for code in ["ABCDE-FGHJK", "abcdefghjk", " abcde fghjk "] {
+            assert_eq!(
+                RecoveryCode::parse(Secret::new(code.to_owned())).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn reject_malformed_recovery_codes() {
+        for code in ["", "abcde-fghj", "abcde-fghjkm", "abcde-fghi1", "123456"] {
+            assert!(RecoveryCode::parse(Secret::new(code.to_owned())).is_err());
+        }
+    }
+}
@@ -14,21 +14,91 @@
    limitations under the License.
 */
 
-use super::{Email, Password};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use std::fmt;
+use uuid::Uuid;
+
+use super::{Email, Password, TotpSecret, TwoFAMethod};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct User {
+    pub id: UserId,
     pub email: Email,
     pub password: Password,
-    pub require_2fa: bool,
+    pub two_fa_method: TwoFAMethod,
+    /// Set once TOTP enrollment starts; only trusted after `two_fa_method` becomes `Totp`.
+    pub totp_secret: Option<TotpSecret>,
+    /// Whether the user has followed the link emailed at signup. Logging in requires it.
+    pub verified: bool,
+    /// Set while the account is pending deletion, to when it will be deleted for good.
+    pub purge_at: Option<DateTime<Utc>>,
 }
 
 impl User {
-    pub fn new(email: Email, password: Password, requires_2fa: bool) -> Self {
+    pub fn new(email: Email, password: Password, two_fa_method: TwoFAMethod) -> Self {
         Self {
+            id: UserId::default(),
             email,
             password,
-            require_2fa: requires_2fa,
+            two_fa_method,
+            totp_secret: None,
+            verified: false,
+            purge_at: None,
         }
     }
 }
+
+/// Stable identifier for a user. Unlike the email it never changes, so it is what tokens carry as
+/// their subject and what other records should be keyed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserId(Uuid);
+
+impl UserId {
+    pub fn parse(id: &str) -> Result<Self> {
+        let id = Uuid::parse_str(id).map_err(|_| eyre!("Invalid user id"))?;
+        Ok(Self(id))
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for UserId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl AsRef<Uuid> for UserId {
+    fn as_ref(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserId;
+
+    #[test]
+    fn parse_round_trips_generated_user_id() {
+        let id = UserId::default();
+        assert_eq!(UserId::parse(&id.to_string()).unwrap(), id);
+        assert_ne!(UserId::default(), id);
+    }
+
+    #[test]
+    fn reject_malformed_user_ids() {
+        assert!(UserId::parse("").is_err());
+        assert!(UserId::parse("ap@0xfrait.com").is_err());
+    }
+}
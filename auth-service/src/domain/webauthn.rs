@@ -0,0 +1,149 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use base64::{
+    alphabet,
+    engine::{
+        general_purpose::URL_SAFE_NO_PAD, DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig,
+    },
+    Engine,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use webauthn_rs::prelude::{
+    DiscoverableAuthentication, Passkey, PasskeyAuthentication, PasskeyRegistration,
+};
+
+use super::UserId;
+
+// Browsers send base64url without padding, but some client libraries keep it.
+const URL_SAFE_ANY_PAD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub fn decode_base64url(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_ANY_PAD
+        .decode(value)
+        .map_err(|_| eyre!("Invalid base64url value"))
+}
+
+/// Authenticator-chosen id of a passkey, at most 1023 bytes per the WebAuthn spec.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CredentialId(Vec<u8>);
+
+impl CredentialId {
+    pub fn parse(id: Vec<u8>) -> Result<Self> {
+        if id.is_empty() || id.len() > 1023 {
+            return Err(eyre!("Invalid credential id"));
+        }
+        Ok(Self(id))
+    }
+
+    pub fn to_base64url(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.0)
+    }
+}
+
+impl AsRef<[u8]> for CredentialId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A registered passkey. `passkey` holds the public key and counter webauthn-rs checks logins
+/// against.
+#[derive(Debug, Clone)]
+pub struct WebauthnCredential {
+    pub id: CredentialId,
+    pub user_id: UserId,
+    pub passkey: Passkey,
+    pub created_at: DateTime<Utc>,
+}
+
+const WEBAUTHN_CHALLENGE_LENGTH: usize = 32;
+
+/// Challenge webauthn-rs issued for a ceremony, kept base64url encoded as it appears in the client
+/// data.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WebauthnChallenge(String);
+
+impl WebauthnChallenge {
+    pub fn parse(challenge: String) -> Result<Self> {
+        Self::from_bytes(&decode_base64url(&challenge)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != WEBAUTHN_CHALLENGE_LENGTH {
+            return Err(eyre!("Invalid WebAuthn challenge"));
+        }
+        Ok(Self(URL_SAFE_NO_PAD.encode(bytes)))
+    }
+}
+
+impl AsRef<str> for WebauthnChallenge {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// What an outstanding challenge was issued for, along with the webauthn-rs state needed to finish
+/// the ceremony, so it can't be replayed in another one.
+#[derive(Debug, Clone)]
+pub enum WebauthnCeremony {
+    Registration {
+        user_id: UserId,
+        state: PasskeyRegistration,
+    },
+    /// Started from the email address of an account with passkeys.
+    Authentication {
+        user_id: UserId,
+        state: PasskeyAuthentication,
+    },
+    /// Started without an email, or with one of an account without passkeys. `user_id` is only
+    /// known in the latter case.
+    DiscoverableAuthentication {
+        user_id: Option<UserId>,
+        state: DiscoverableAuthentication,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_padded_challenge() {
+        let challenge = URL_SAFE_NO_PAD.encode([7u8; 32]);
+        assert_eq!(
+            WebauthnChallenge::parse(format!("{}=", challenge)).unwrap(),
+            WebauthnChallenge::parse(challenge).unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_malformed_challenges() {
+        assert!(WebauthnChallenge::parse("".to_owned()).is_err());
+        assert!(WebauthnChallenge::parse("not base64!".to_owned()).is_err());
+        assert!(WebauthnChallenge::parse(URL_SAFE_NO_PAD.encode([0u8; 16])).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_credential_ids() {
+        assert!(CredentialId::parse(vec![]).is_err());
+        assert!(CredentialId::parse(vec![0; 1024]).is_err());
+        assert!(CredentialId::parse(vec![0; 16]).is_ok());
+    }
+}
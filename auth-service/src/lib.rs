@@ -15,20 +15,28 @@
 */
 
 use axum::{
-    http::{Method, StatusCode},
+    extract::connect_info::{ConnectInfo, IntoMakeServiceWithConnectInfo},
+    http::{
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+        HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, AddExtension},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{delete, get, post, put},
     serve::Serve,
     Json, Router,
 };
-use domain::AuthAPIError;
+use domain::{AuthAPIError, OAuthError};
 use redis::{Client, RedisResult};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
-use utils::tracing::{make_span_with_request_id, on_request, on_response};
+use utils::{
+    rate_limit::rate_limit,
+    tracing::{make_span_with_request_id, on_request, on_response},
+};
 
 pub mod app_state;
 pub mod domain;
@@ -37,11 +45,23 @@ pub mod services;
 pub mod utils;
 
 use crate::app_state::AppState;
-use routes::{login, logout, signup, verify_2fa, verify_token};
+use routes::{
+    authorize, change_password, confirm_password_reset, confirm_totp, count_recovery_codes,
+    delete_account, disable_2fa, enable_2fa, enroll_totp, get_grants, grant_permission, grant_role,
+    introspect, jwks, list_sessions, login, logout, oauth_callback, oauth_start,
+    openid_configuration, refresh, regenerate_recovery_codes, register_client,
+    request_password_reset, resend_verification_email, restore_account, revoke_all_sessions,
+    revoke_permission, revoke_role, revoke_session, signup, token, verify_2fa, verify_email,
+    verify_token, webauthn_login_finish, webauthn_login_start, webauthn_register_finish,
+    webauthn_register_start,
+};
 
 // The Application struct encapsulates application logic
 pub struct Application {
-    pub server: Serve<Router, Router>,
+    pub server: Serve<
+        IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+        AddExtension<Router, ConnectInfo<SocketAddr>>,
+    >,
     // expose address as a public field, so it's accessible in tests
     pub address: String,
     pub router: Router,
@@ -56,7 +76,7 @@ impl Application {
         ];
 
         let cors = CorsLayer::new()
-            .allow_methods([Method::GET, Method::POST])
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
             .allow_credentials(true)
             .allow_origin(allowed_origins);
 
@@ -65,8 +85,54 @@ impl Application {
             .route("/signup", post(signup))
             .route("/login", post(login))
             .route("/logout", post(logout))
+            .route("/refresh", post(refresh))
             .route("/verify-2fa", post(verify_2fa))
+            .route("/password-reset/request", post(request_password_reset))
+            .route("/password-reset/confirm", post(confirm_password_reset))
+            .route("/change-password", post(change_password))
+            .route("/account", delete(delete_account))
+            .route("/account/restore", post(restore_account))
+            .route("/verify-email", get(verify_email))
+            .route("/verify-email/resend", post(resend_verification_email))
+            .route("/enable-2fa", post(enable_2fa))
+            .route("/disable-2fa", post(disable_2fa))
+            .route("/enroll-totp", post(enroll_totp))
+            .route("/confirm-totp", post(confirm_totp))
+            .route(
+                "/recovery-codes",
+                get(count_recovery_codes).post(regenerate_recovery_codes),
+            )
             .route("/verify-token", post(verify_token))
+            .route("/webauthn/register/start", post(webauthn_register_start))
+            .route("/webauthn/register/finish", post(webauthn_register_finish))
+            .route("/webauthn/login/start", post(webauthn_login_start))
+            .route("/webauthn/login/finish", post(webauthn_login_finish))
+            .route("/sessions", get(list_sessions).delete(revoke_all_sessions))
+            .route("/sessions/:id", delete(revoke_session))
+            .route("/oauth/:provider/start", get(oauth_start))
+            .route("/oauth/:provider/callback", get(oauth_callback))
+            .route("/oauth/authorize", get(authorize))
+            .route("/oauth/token", post(token))
+            .route("/oauth/introspect", post(introspect))
+            .route("/oauth/register", post(register_client))
+            .route("/admin/users/:id/grants", get(get_grants))
+            .route(
+                "/admin/users/:id/roles/:role",
+                put(grant_role).delete(revoke_role),
+            )
+            .route(
+                "/admin/users/:id/permissions/:permission",
+                put(grant_permission).delete(revoke_permission),
+            )
+            .route("/.well-known/jwks.json", get(jwks))
+            .route(
+                "/.well-known/openid-configuration",
+                get(openid_configuration),
+            )
+            .layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                rate_limit,
+            ))
             .with_state(app_state)
             .layer(cors)
             .layer(
@@ -78,7 +144,12 @@ impl Application {
 
         let listener = tokio::net::TcpListener::bind(address).await?;
         let address = listener.local_addr()?.to_string();
-        let server = axum::serve(listener, router.clone());
+        let server = axum::serve(
+            listener,
+            router
+                .clone()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
         let app = Application {
             server,
@@ -97,12 +168,27 @@ impl Application {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ErrorResponse {
     pub error: String,
+    /// Set when a new password breaks the password policy, naming the rule it broke.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
 }
 
 impl IntoResponse for AuthAPIError {
     fn into_response(self) -> Response {
         log_error_chain(&self);
 
+        let retry_after = match &self {
+            AuthAPIError::TooManyAttempts(seconds) | AuthAPIError::TooManyRequests(seconds) => {
+                Some(*seconds)
+            }
+            _ => None,
+        };
+        // password policy errors say which rule was broken, and by how much
+        let (rule, policy_message) = match &self {
+            AuthAPIError::WeakPassword(e) => (Some(e.rule().to_string()), Some(e.to_string())),
+            _ => (None, None),
+        };
+
         let (status, error_message) = match self {
             AuthAPIError::UserAlreadyExists => (StatusCode::CONFLICT, "User already exists"),
             AuthAPIError::InvalidCredentials => (StatusCode::BAD_REQUEST, "Invalid credentials"),
@@ -114,13 +200,99 @@ impl IntoResponse for AuthAPIError {
             }
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing auth token"),
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid auth token"),
+            AuthAPIError::SessionNotFound => (StatusCode::NOT_FOUND, "Session not found"),
+            AuthAPIError::TotpAlreadyEnabled => (StatusCode::CONFLICT, "TOTP already enabled"),
+            AuthAPIError::TotpEnrollmentNotFound => {
+                (StatusCode::NOT_FOUND, "TOTP enrollment not found")
+            }
+            AuthAPIError::TwoFAAlreadyEnabled => (StatusCode::CONFLICT, "2FA already enabled"),
+            AuthAPIError::TwoFANotEnabled => (StatusCode::CONFLICT, "2FA not enabled"),
+            AuthAPIError::PasskeyAlreadyRegistered => {
+                (StatusCode::CONFLICT, "Passkey already registered")
+            }
+            AuthAPIError::EmailNotVerified => (StatusCode::FORBIDDEN, "Email not verified"),
+            AuthAPIError::VerificationEmailCooldown => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Verification email sent recently",
+            ),
+            AuthAPIError::AccountPendingDeletion => {
+                (StatusCode::FORBIDDEN, "Account pending deletion")
+            }
+            AuthAPIError::OAuthProviderNotFound => {
+                (StatusCode::NOT_FOUND, "OAuth provider not found")
+            }
+            AuthAPIError::InvalidClient => (StatusCode::UNAUTHORIZED, "Invalid client credentials"),
+            AuthAPIError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+            AuthAPIError::UnknownRole => (StatusCode::BAD_REQUEST, "Unknown role"),
+            AuthAPIError::InvalidPermission => (StatusCode::BAD_REQUEST, "Invalid permission"),
+            AuthAPIError::GrantNotFound => (StatusCode::NOT_FOUND, "Grant not found"),
+            AuthAPIError::InsufficientScope => (StatusCode::FORBIDDEN, "Insufficient scope"),
+            AuthAPIError::TooManyAttempts(_) => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many attempts")
+            }
+            AuthAPIError::TooManyRequests(_) => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+            }
+            AuthAPIError::WeakPassword(_) => (StatusCode::BAD_REQUEST, "Weak password"),
         };
 
         let body = Json(ErrorResponse {
-            error: error_message.to_string(),
+            error: policy_message.unwrap_or_else(|| error_message.to_string()),
+            rule,
+        });
+
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OAuthErrorResponse {
+    pub error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        log_error_chain(&self);
+
+        let status = match self {
+            OAuthError::InvalidClient | OAuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            OAuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let error_description = match &self {
+            OAuthError::InvalidRequest(description) => Some(description.to_string()),
+            OAuthError::InvalidRedirectUri(e) | OAuthError::InvalidClientMetadata(e) => {
+                Some(e.to_string())
+            }
+            _ => None,
+        };
+        // RFC 6749 section 5.2 and RFC 6750 section 3 ask for the scheme a client has to use
+        let authenticate = match self {
+            OAuthError::InvalidClient => Some("Basic"),
+            OAuthError::InvalidToken => Some("Bearer"),
+            _ => None,
+        };
+
+        let body = Json(OAuthErrorResponse {
+            error: self.code().to_owned(),
+            error_description,
         });
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(scheme) = authenticate {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static(scheme));
+        }
+        response
     }
 }
 
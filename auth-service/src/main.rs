@@ -16,37 +16,67 @@
 
 use auth_service::{
     app_state::AppState,
-    domain::Email,
+    domain::{Email, PasswordPolicy},
     get_postgres_pool, get_redis_client,
     services::{
+        account_purge::run_account_purge,
         data_stores::{
-            // HashmapTwoFACodeStore, HashmapUserStore, HashsetBannedTokenStore,
+            // HashmapAuthorizationCodeStore, HashmapEmailCooldownStore,
+            // HashmapExternalIdentityStore, HashmapFailedLoginStore, HashmapOAuthClientStore,
+            // HashmapOAuthStateStore, HashmapPermissionStore, HashmapRateLimitStore,
+            // HashmapRecoveryCodeStore, HashmapRefreshTokenStore, HashmapSessionStore,
+            // HashmapTwoFACodeStore, HashmapUserStore, HashmapWebauthnChallengeStore,
+            // HashmapWebauthnCredentialStore, HashsetBannedTokenStore,
+            PostgresExternalIdentityStore,
+            PostgresOAuthClientStore,
+            PostgresPermissionStore,
+            PostgresRecoveryCodeStore,
             PostgresUserStore,
+            PostgresWebauthnCredentialStore,
+            RedisAuthorizationCodeStore,
             RedisBannedTokenStore,
+            RedisEmailCooldownStore,
+            RedisFailedLoginStore,
+            RedisOAuthStateStore,
+            RedisRateLimitStore,
+            RedisRefreshTokenStore,
+            RedisSessionStore,
             RedisTwoFACodeStore,
+            RedisWebauthnChallengeStore,
         },
+        file_breached_password_source::FileBreachedPasswordSource,
+        hibp_breached_password_source::HibpBreachedPasswordSource,
         //mock_email_client::MockEmailClient,
+        oidc_client::OidcClient,
+        password_hash_report::run_password_hash_report,
         postmark_email_client::PostmarkEmailClient,
     },
     utils::{
-        constants::{prod, DATABASE_URL, JWT_SECRET, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME},
+        constants::{
+            prod, BREACHED_PASSWORDS_PATH, DATABASE_URL, JWT_ISSUER, JWT_KEYRING,
+            OAUTH_CLIENT_REGISTRATION_TOKEN, OAUTH_PROVIDERS, PASSWORD_BREACH_CHECK,
+            PASSWORD_HASHER, PASSWORD_POLICY, POSTMARK_AUTH_TOKEN, RATE_LIMITS, REDIS_HOST_NAME,
+            ROLES,
+        },
         tracing::init_tracing,
     },
     Application,
 };
-use color_eyre;
+use metrics_exporter_prometheus::PrometheusBuilder;
 use reqwest::Client;
 use secrecy::Secret;
 use sqlx::PgPool;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() {
     color_eyre::install().expect("Failed to install color_eyre");
     init_tracing().expect("Failed to initialize tracing");
+    configure_metrics();
 
-    lazy_static::initialize(&JWT_SECRET);
+    lazy_static::initialize(&JWT_KEYRING);
+    lazy_static::initialize(&JWT_ISSUER);
 
     let pg_pool = configure_postgresql().await;
     let redis_connection = configure_redis().await;
@@ -55,21 +85,97 @@ async fn main() {
     // let user_store = Arc::new(RwLock::new(HashmapUserStore::default()));
     // let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
     // let two_fa_code_store = Arc::new(RwLock::new(HashmapTwoFACodeStore::default()));
+    // let refresh_token_store = Arc::new(RwLock::new(HashmapRefreshTokenStore::default()));
+    // let session_store = Arc::new(RwLock::new(HashmapSessionStore::default()));
+    // let recovery_code_store = Arc::new(RwLock::new(HashmapRecoveryCodeStore::default()));
+    // let webauthn_credential_store = Arc::new(RwLock::new(HashmapWebauthnCredentialStore::default()));
+    // let webauthn_challenge_store = Arc::new(RwLock::new(HashmapWebauthnChallengeStore::default()));
+    // let email_cooldown_store = Arc::new(RwLock::new(HashmapEmailCooldownStore::default()));
+    // let failed_login_store = Arc::new(RwLock::new(HashmapFailedLoginStore::default()));
+    // let rate_limit_store = Arc::new(RwLock::new(HashmapRateLimitStore::default()));
+    // let oauth_state_store = Arc::new(RwLock::new(HashmapOAuthStateStore::default()));
+    // let external_identity_store = Arc::new(RwLock::new(HashmapExternalIdentityStore::default()));
+    // let oauth_client_store = Arc::new(RwLock::new(HashmapOAuthClientStore::default()));
+    // let authorization_code_store = Arc::new(RwLock::new(HashmapAuthorizationCodeStore::default()));
+    // let permission_store = Arc::new(RwLock::new(HashmapPermissionStore::default()));
 
     // use persistent storage
-    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
+    let password_hasher = Arc::new(PASSWORD_HASHER.clone());
+    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(
+        pg_pool.clone(),
+        password_hasher.clone(),
+    )));
     let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(
         redis_connection.clone(),
     )));
-    let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_connection)));
+    let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+        redis_connection.clone(),
+    )));
+    let refresh_token_store = Arc::new(RwLock::new(RedisRefreshTokenStore::new(
+        redis_connection.clone(),
+    )));
+    let session_store = Arc::new(RwLock::new(RedisSessionStore::new(
+        redis_connection.clone(),
+    )));
+    let recovery_code_store = Arc::new(RwLock::new(PostgresRecoveryCodeStore::new(
+        pg_pool.clone(),
+        password_hasher,
+    )));
+    let webauthn_credential_store = Arc::new(RwLock::new(PostgresWebauthnCredentialStore::new(
+        pg_pool.clone(),
+    )));
+    let external_identity_store = Arc::new(RwLock::new(PostgresExternalIdentityStore::new(
+        pg_pool.clone(),
+    )));
+    let oauth_client_store = Arc::new(RwLock::new(PostgresOAuthClientStore::new(pg_pool.clone())));
+    let permission_store = Arc::new(RwLock::new(PostgresPermissionStore::new(pg_pool)));
+    let webauthn_challenge_store = Arc::new(RwLock::new(RedisWebauthnChallengeStore::new(
+        redis_connection.clone(),
+    )));
+    let email_cooldown_store = Arc::new(RwLock::new(RedisEmailCooldownStore::new(
+        redis_connection.clone(),
+    )));
+    let failed_login_store = Arc::new(RwLock::new(RedisFailedLoginStore::new(
+        redis_connection.clone(),
+    )));
+    let rate_limit_store = Arc::new(RwLock::new(RedisRateLimitStore::new(
+        redis_connection.clone(),
+    )));
+    let oauth_state_store = Arc::new(RwLock::new(RedisOAuthStateStore::new(
+        redis_connection.clone(),
+    )));
+    let authorization_code_store = Arc::new(RwLock::new(RedisAuthorizationCodeStore::new(
+        redis_connection,
+    )));
 
     let email_client = Arc::new(configure_postmark_email_client());
     let app_state = AppState::new(
         user_store,
         banned_token_store,
         two_fa_code_store,
+        refresh_token_store,
+        session_store,
+        recovery_code_store,
+        webauthn_credential_store,
+        webauthn_challenge_store,
+        email_cooldown_store,
+        failed_login_store,
+        rate_limit_store,
+        oauth_state_store,
+        external_identity_store,
+        oauth_client_store,
+        authorization_code_store,
+        permission_store,
+        *RATE_LIMITS,
+        configure_password_policy(),
+        Arc::new(ROLES.clone()),
+        OAUTH_CLIENT_REGISTRATION_TOKEN.clone(),
         email_client,
+        Arc::new(configure_oidc_client()),
     );
+    tokio::spawn(run_account_purge(app_state.clone()));
+    tokio::spawn(run_password_hash_report(app_state.clone()));
+
     let svc = Application::build(app_state, prod::APP_ADDRESS)
         .await
         .expect("failed to build service");
@@ -98,6 +204,17 @@ async fn configure_redis() -> redis::aio::MultiplexedConnection {
         .expect("Failed to get Redis connection")
 }
 
+/// Serves the service's metrics, such as `outdated_password_hashes`, for Prometheus to scrape.
+fn configure_metrics() {
+    let address: SocketAddr = prod::METRICS_ADDRESS
+        .parse()
+        .expect("METRICS_ADDRESS should be a socket address");
+    PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install()
+        .expect("Failed to install Prometheus exporter");
+}
+
 fn configure_postmark_email_client() -> PostmarkEmailClient {
     let http_client = Client::builder()
         .timeout(prod::email_client::TIMEOUT)
@@ -111,3 +228,41 @@ fn configure_postmark_email_client() -> PostmarkEmailClient {
         http_client,
     )
 }
+
+fn configure_password_policy() -> PasswordPolicy {
+    if !*PASSWORD_BREACH_CHECK {
+        return PASSWORD_POLICY.clone();
+    }
+
+    match &*BREACHED_PASSWORDS_PATH {
+        Some(path) => {
+            let breached_passwords = FileBreachedPasswordSource::load(path)
+                .expect("BREACHED_PASSWORDS_PATH should be a file of SHA-1 hashes.");
+            PASSWORD_POLICY
+                .clone()
+                .with_breached_passwords(Arc::new(breached_passwords))
+        }
+        None => {
+            let http_client = Client::builder()
+                .timeout(prod::breached_passwords::TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client");
+            let breached_passwords = HibpBreachedPasswordSource::new(
+                prod::breached_passwords::BASE_URL.to_owned(),
+                http_client,
+            );
+            PASSWORD_POLICY
+                .clone()
+                .with_breached_passwords(Arc::new(breached_passwords))
+        }
+    }
+}
+
+fn configure_oidc_client() -> OidcClient {
+    let http_client = Client::builder()
+        .timeout(prod::oauth::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    OidcClient::new(OAUTH_PROVIDERS.clone(), http_client)
+}
@@ -0,0 +1,189 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::{cookie, CookieJar};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, User},
+    utils::{
+        auth::current_user,
+        client::ClientInfo,
+        constants::{
+            ACCOUNT_DELETION_GRACE_PERIOD_SECONDS, JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME,
+        },
+    },
+};
+
+use super::{login::verify_password, two_fa::require_second_factor};
+
+/// Schedules the logged in user's account for deletion and logs out every device. Users with 2FA
+/// get a 206 with a login attempt the first time, and repeat the request with its code.
+#[tracing::instrument(name = "Delete account", skip_all)]
+pub async fn delete_account(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<DeleteAccountRequest>,
+) -> (CookieJar, Result<Response, AuthAPIError>) {
+    let user = match current_user(&jar, &state).await {
+        Ok(user) => user,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    if let Err(e) = verify_password(&user.email, request.password, &client, &state).await {
+        return (jar, Err(e));
+    }
+
+    if user.two_fa_method.is_enabled() {
+        match require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state)
+            .await
+        {
+            Ok(Some(response)) => return (jar, Ok(response)),
+            Ok(None) => {}
+            Err(e) => return (jar, Err(e)),
+        }
+    }
+
+    let purge_at = Utc::now() + Duration::seconds(*ACCOUNT_DELETION_GRACE_PERIOD_SECONDS);
+    if let Err(e) = state
+        .user_store
+        .write()
+        .await
+        .mark_for_deletion(&user.id, purge_at)
+        .await
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+
+    if let Err(e) = state
+        .session_store
+        .write()
+        .await
+        .remove_all_sessions(&user.id)
+        .await
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+
+    send_deletion_email(&user, purge_at, &state).await;
+
+    let jar = jar
+        .remove(cookie::Cookie::from(JWT_COOKIE_NAME))
+        .remove(cookie::Cookie::from(REFRESH_TOKEN_COOKIE_NAME));
+    let response = Json(DeleteAccountResponse {
+        message: "Account scheduled for deletion".to_owned(),
+        purge_at: purge_at.to_rfc3339(),
+    });
+    (jar, Ok((StatusCode::ACCEPTED, response).into_response()))
+}
+
+/// Cancels a pending deletion. The account's sessions were revoked when deletion was asked for,
+/// so this takes the credentials instead of a cookie, throttled like a login. Users with 2FA get a
+/// 206 with a login attempt the first time, and repeat the request with its code.
+#[tracing::instrument(name = "Restore account", skip_all)]
+pub async fn restore_account(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(request): Json<RestoreAccountRequest>,
+) -> Result<Response, AuthAPIError> {
+    let email = Email::parse(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    verify_password(&email, request.password, &client, &state).await?;
+
+    let user = state
+        .user_store
+        .read()
+        .await
+        .get_user(&email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    if user.purge_at.is_none() {
+        return Ok(StatusCode::OK.into_response());
+    }
+
+    if user.two_fa_method.is_enabled() {
+        if let Some(response) =
+            require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state)
+                .await?
+        {
+            return Ok(response);
+        }
+    }
+
+    state
+        .user_store
+        .write()
+        .await
+        .restore_user(&user.id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn send_deletion_email(user: &User, purge_at: DateTime<Utc>, state: &AppState) {
+    let content = format!(
+        "Your account will be deleted for good on {}. If you change your mind, or this wasn't \
+         you, restore it before then.",
+        purge_at.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    // the deletion is scheduled either way, so a failed notification is only logged
+    if let Err(e) = state
+        .email_client
+        .send_email(&user.email, "Your account will be deleted", &content)
+        .await
+    {
+        tracing::error!("failed to send account deletion notification: {:?}", e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: Secret<String>,
+    /// Both 2FA fields are only needed when 2FA is enabled, and come from a previous 206.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteAccountResponse {
+    pub message: String,
+    /// When the account will be deleted for good, in RFC 3339.
+    #[serde(rename = "purgeAt")]
+    pub purge_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct RestoreAccountRequest {
+    pub email: Secret<String>,
+    pub password: Secret<String>,
+    /// Both 2FA fields are only needed when 2FA is enabled, and come from a previous 206.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
@@ -0,0 +1,168 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Permission, PermissionStoreError, Role, UserId, UserStoreError},
+    utils::scope::{ManageRoles, RequireScope},
+};
+
+#[tracing::instrument(name = "Get grants", skip_all)]
+pub async fn get_grants(
+    _: RequireScope<ManageRoles>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = find_user(&id, &state).await?;
+
+    let grants = state
+        .permission_store
+        .read()
+        .await
+        .get_grants(&user_id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    let scope = state.roles.effective_scopes(&grants).to_string();
+
+    Ok((
+        StatusCode::OK,
+        Json(GrantsResponse {
+            roles: grants
+                .roles
+                .iter()
+                .map(|role| role.as_ref().to_owned())
+                .collect(),
+            permissions: grants
+                .permissions
+                .iter()
+                .map(|permission| permission.as_ref().to_owned())
+                .collect(),
+            scope,
+        }),
+    ))
+}
+
+/// Only roles that are configured can be granted, so a typo doesn't go unnoticed.
+#[tracing::instrument(name = "Grant role", skip_all)]
+pub async fn grant_role(
+    _: RequireScope<ManageRoles>,
+    State(state): State<AppState>,
+    Path((id, role)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let role = Role::parse(&role).map_err(|_| AuthAPIError::UnknownRole)?;
+    if !state.roles.contains(&role) {
+        return Err(AuthAPIError::UnknownRole);
+    }
+    let user_id = find_user(&id, &state).await?;
+
+    let result = state
+        .permission_store
+        .write()
+        .await
+        .grant_role(&user_id, &role)
+        .await;
+    changed(result)
+}
+
+/// Roles that are no longer configured can still be revoked, to clean them up.
+#[tracing::instrument(name = "Revoke role", skip_all)]
+pub async fn revoke_role(
+    _: RequireScope<ManageRoles>,
+    State(state): State<AppState>,
+    Path((id, role)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let role = Role::parse(&role).map_err(|_| AuthAPIError::GrantNotFound)?;
+    let user_id = find_user(&id, &state).await?;
+
+    let result = state
+        .permission_store
+        .write()
+        .await
+        .revoke_role(&user_id, &role)
+        .await;
+    changed(result)
+}
+
+#[tracing::instrument(name = "Grant permission", skip_all)]
+pub async fn grant_permission(
+    _: RequireScope<ManageRoles>,
+    State(state): State<AppState>,
+    Path((id, permission)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let permission = Permission::parse(&permission).map_err(|_| AuthAPIError::InvalidPermission)?;
+    let user_id = find_user(&id, &state).await?;
+
+    let result = state
+        .permission_store
+        .write()
+        .await
+        .grant_permission(&user_id, &permission)
+        .await;
+    changed(result)
+}
+
+#[tracing::instrument(name = "Revoke permission", skip_all)]
+pub async fn revoke_permission(
+    _: RequireScope<ManageRoles>,
+    State(state): State<AppState>,
+    Path((id, permission)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let permission = Permission::parse(&permission).map_err(|_| AuthAPIError::GrantNotFound)?;
+    let user_id = find_user(&id, &state).await?;
+
+    let result = state
+        .permission_store
+        .write()
+        .await
+        .revoke_permission(&user_id, &permission)
+        .await;
+    changed(result)
+}
+
+async fn find_user(id: &str, state: &AppState) -> Result<UserId, AuthAPIError> {
+    let user_id = UserId::parse(id).map_err(|_| AuthAPIError::UserNotFound)?;
+
+    match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(_) => Ok(user_id),
+        Err(UserStoreError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => Err(AuthAPIError::UserNotFound),
+    }
+}
+
+/// Tokens that are out already keep their scopes until they expire, new ones get the change.
+fn changed(result: Result<(), PermissionStoreError>) -> Result<StatusCode, AuthAPIError> {
+    match result {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(PermissionStoreError::GrantNotFound) => Err(AuthAPIError::GrantNotFound),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GrantsResponse {
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    /// The scopes the user's tokens carry, from both the roles and the permissions.
+    pub scope: String,
+}
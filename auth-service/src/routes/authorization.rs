@@ -0,0 +1,481 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::{
+        header::{CACHE_CONTROL, PRAGMA},
+        HeaderMap, Uri,
+    },
+    response::{IntoResponse, Redirect, Response},
+    Form, Json,
+};
+use axum_extra::extract::CookieJar;
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        decode_base64url, AuthAPIError, AuthorizationCode, AuthorizationCodeStoreError,
+        AuthorizationGrant, ClientId, GrantType, OAuthClient, OAuthClientStoreError, OAuthError,
+        PkceVerifier, Scopes, SessionStoreError, UserStoreError, EMAIL_SCOPE, OPENID_SCOPE,
+    },
+    utils::{
+        auth::{
+            effective_scopes, generate_access_token, validate_auth_cookie, validate_token, Claims,
+            TokenValidationError, TOKEN_TTL_SECONDS,
+        },
+        authorization::{
+            authenticate_client, authenticate_introspecting_client, generate_id_token,
+            requested_scopes,
+        },
+        constants::AUTH_SERVICE_URL,
+    },
+};
+
+const CODE_CHALLENGE_LENGTH: usize = 43;
+const TOKEN_TYPE: &str = "Bearer";
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeRequest {
+    response_type: Option<String>,
+    client_id: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    nonce: Option<String>,
+    prompt: Option<String>,
+}
+
+/// The authorization endpoint (RFC 6749 section 4.1.1). Users who aren't logged in are sent to the
+/// login page, which comes back here once they are, so logging in and 2FA work the same as they
+/// do everywhere else. Clients are our own apps, so there is no consent step.
+#[tracing::instrument(name = "Authorize", skip_all)]
+pub async fn authorize(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    jar: CookieJar,
+    Query(request): Query<AuthorizeRequest>,
+) -> Result<Response, OAuthError> {
+    // until the client and its redirect URI are known, there is nowhere safe to send errors
+    let client_id = request
+        .client_id
+        .as_deref()
+        .ok_or(OAuthError::InvalidRequest("client_id is missing"))?;
+    let client = get_client(client_id, &state).await?;
+    let redirect_uri = request
+        .redirect_uri
+        .clone()
+        .filter(|redirect_uri| client.has_redirect_uri(redirect_uri))
+        .ok_or(OAuthError::InvalidRequest(
+            "redirect_uri is not registered for the client",
+        ))?;
+    let redirect = ClientRedirect {
+        redirect_uri,
+        state: request.state.clone(),
+    };
+
+    if request.response_type.as_deref() != Some("code") {
+        return redirect.error("unsupported_response_type");
+    }
+    if !client.allows_grant(GrantType::AuthorizationCode) {
+        return redirect.error("unauthorized_client");
+    }
+    // PKCE is required of every client, not just public ones
+    let code_challenge = match (
+        request.code_challenge,
+        request.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some("S256")) if is_code_challenge(&challenge) => challenge,
+        _ => return redirect.error("invalid_request"),
+    };
+    let Ok(scopes) = requested_scopes(request.scope.as_deref(), &client) else {
+        return redirect.error("invalid_scope");
+    };
+
+    let claims = match validate_auth_cookie(&jar, &state).await {
+        Ok(claims) => claims,
+        Err(AuthAPIError::UnexpectedError(e)) => return Err(OAuthError::UnexpectedError(e)),
+        Err(_) if request.prompt.as_deref() == Some("none") => {
+            return redirect.error("login_required")
+        }
+        Err(_) => return login_redirect(&uri),
+    };
+    let (Ok(user_id), Ok(session_id)) = (claims.user_id(), claims.session_id()) else {
+        return login_redirect(&uri);
+    };
+
+    match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(user) if user.purge_at.is_none() => {}
+        Ok(_) | Err(UserStoreError::UserNotFound) => return redirect.error("access_denied"),
+        Err(e) => return Err(OAuthError::UnexpectedError(e.into())),
+    }
+
+    let code = AuthorizationCode::default();
+    let grant = AuthorizationGrant {
+        client_id: client.id,
+        redirect_uri: redirect.redirect_uri.clone(),
+        user_id,
+        session_id,
+        scopes,
+        code_challenge,
+        nonce: request.nonce,
+    };
+    state
+        .authorization_code_store
+        .write()
+        .await
+        .add_code(code.clone(), grant)
+        .await
+        .map_err(|e| OAuthError::UnexpectedError(e.into()))?;
+
+    redirect.send(&[("code", code.as_ref().expose_secret())])
+}
+
+async fn get_client(client_id: &str, state: &AppState) -> Result<OAuthClient, OAuthError> {
+    let client_id =
+        ClientId::parse(client_id).map_err(|_| OAuthError::InvalidRequest("unknown client_id"))?;
+    match state
+        .oauth_client_store
+        .read()
+        .await
+        .get_client(&client_id)
+        .await
+    {
+        Ok(client) => Ok(client),
+        Err(OAuthClientStoreError::ClientNotFound) => {
+            Err(OAuthError::InvalidRequest("unknown client_id"))
+        }
+        Err(e) => Err(OAuthError::UnexpectedError(e.into())),
+    }
+}
+
+/// An S256 challenge is the base64url encoded SHA-256 of the verifier, without padding.
+fn is_code_challenge(challenge: &str) -> bool {
+    challenge.len() == CODE_CHALLENGE_LENGTH
+        && decode_base64url(challenge).is_ok_and(|bytes| bytes.len() == 32)
+}
+
+/// Sends the user to the login page, which brings them back to `return_to` once logged in.
+fn login_redirect(uri: &Uri) -> Result<Response, OAuthError> {
+    let mut login_page = Url::parse(&format!("{}/", *AUTH_SERVICE_URL))
+        .map_err(|e| OAuthError::UnexpectedError(e.into()))?;
+    login_page
+        .query_pairs_mut()
+        .append_pair("return_to", &uri.to_string());
+    Ok(Redirect::to(login_page.as_str()).into_response())
+}
+
+/// Where the authorization endpoint sends the user back to, echoing the client's state.
+struct ClientRedirect {
+    redirect_uri: String,
+    state: Option<String>,
+}
+
+impl ClientRedirect {
+    fn send(&self, params: &[(&str, &str)]) -> Result<Response, OAuthError> {
+        let mut url =
+            Url::parse(&self.redirect_uri).map_err(|e| OAuthError::UnexpectedError(e.into()))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.extend_pairs(params);
+            if let Some(state) = &self.state {
+                query.append_pair("state", state);
+            }
+        }
+        Ok(Redirect::to(url.as_str()).into_response())
+    }
+
+    /// Errors once the redirect URI is trusted go back to the client (RFC 6749 section 4.1.2.1).
+    fn error(&self, error: &str) -> Result<Response, OAuthError> {
+        self.send(&[("error", error)])
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    grant_type: Option<String>,
+    code: Option<Secret<String>>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<Secret<String>>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// The token endpoint (RFC 6749 section 3.2), for the authorization code and client credentials
+/// grants.
+#[tracing::instrument(name = "Token", skip_all)]
+pub async fn token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(request): Form<TokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(
+        &headers,
+        request.client_id.clone(),
+        request.client_secret.clone(),
+        &state,
+    )
+    .await?;
+
+    let grant_type = request
+        .grant_type
+        .as_deref()
+        .ok_or(OAuthError::InvalidRequest("grant_type is missing"))?;
+    let grant_type = GrantType::parse(grant_type).map_err(|_| OAuthError::UnsupportedGrantType)?;
+    if !client.allows_grant(grant_type) {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+
+    let response = match grant_type {
+        GrantType::AuthorizationCode => exchange_code(&client, request, &state).await?,
+        GrantType::ClientCredentials => issue_client_token(&client, request)?,
+    };
+
+    // tokens must not end up in a cache (RFC 6749 section 5.1)
+    Ok((
+        [(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")],
+        Json(response),
+    ))
+}
+
+async fn exchange_code(
+    client: &OAuthClient,
+    request: TokenRequest,
+    state: &AppState,
+) -> Result<TokenResponse, OAuthError> {
+    let code = request
+        .code
+        .ok_or(OAuthError::InvalidRequest("code is missing"))?;
+    let code_verifier = request
+        .code_verifier
+        .ok_or(OAuthError::InvalidRequest("code_verifier is missing"))?;
+    let code = AuthorizationCode::parse(code).map_err(|_| OAuthError::InvalidGrant)?;
+
+    let grant = match state
+        .authorization_code_store
+        .write()
+        .await
+        .take_code(&code)
+        .await
+    {
+        Ok(grant) => grant,
+        Err(AuthorizationCodeStoreError::CodeNotFound) => return Err(OAuthError::InvalidGrant),
+        Err(e) => return Err(OAuthError::UnexpectedError(e.into())),
+    };
+
+    // the code is used up either way, so a stolen one can't be retried with other guesses
+    let code_verifier = PkceVerifier::parse(code_verifier).map_err(|_| OAuthError::InvalidGrant)?;
+    if grant.client_id != client.id
+        || request.redirect_uri.as_deref() != Some(grant.redirect_uri.as_str())
+        || !grant.verify_code_verifier(&code_verifier)
+    {
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    // the user may have logged out or been deleted since approving the request
+    match state
+        .session_store
+        .write()
+        .await
+        .touch_session(&grant.session_id)
+        .await
+    {
+        Ok(session) if session.user_id == grant.user_id => {}
+        Ok(_) | Err(SessionStoreError::SessionNotFound) => return Err(OAuthError::InvalidGrant),
+        Err(e) => return Err(OAuthError::UnexpectedError(e.into())),
+    }
+    let user = match state
+        .user_store
+        .read()
+        .await
+        .get_user_by_id(&grant.user_id)
+        .await
+    {
+        Ok(user) if user.purge_at.is_none() => user,
+        Ok(_) | Err(UserStoreError::UserNotFound) => return Err(OAuthError::InvalidGrant),
+        Err(e) => return Err(OAuthError::UnexpectedError(e.into())),
+    };
+
+    // a client registered for a permission only gets it for users who have been granted it
+    let user_scopes = effective_scopes(&user.id, state)
+        .await
+        .map_err(OAuthError::UnexpectedError)?;
+    let scopes: Scopes = grant
+        .scopes
+        .iter()
+        .filter(|scope| {
+            *scope == OPENID_SCOPE || *scope == EMAIL_SCOPE || user_scopes.contains(scope)
+        })
+        .collect();
+
+    let access_token = generate_access_token(
+        user.id.to_string(),
+        Some(&grant.session_id),
+        &client.id,
+        &scopes,
+    )
+    .map_err(OAuthError::UnexpectedError)?;
+    let id_token = if scopes.contains(OPENID_SCOPE) {
+        let id_token = generate_id_token(&user, &client.id, &scopes, grant.nonce)
+            .map_err(OAuthError::UnexpectedError)?;
+        Some(id_token.expose_secret().to_owned())
+    } else {
+        None
+    };
+
+    Ok(TokenResponse {
+        access_token: access_token.expose_secret().to_owned(),
+        token_type: TOKEN_TYPE.to_owned(),
+        expires_in: TOKEN_TTL_SECONDS,
+        scope: (!scopes.is_empty()).then(|| scopes.to_string()),
+        id_token,
+    })
+}
+
+/// Issues a token that acts for the client itself, for calls between services.
+fn issue_client_token(
+    client: &OAuthClient,
+    request: TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    if !client.is_confidential() {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let scopes = requested_scopes(request.scope.as_deref(), client)?;
+    // there is no user to identify
+    if scopes.contains(OPENID_SCOPE) {
+        return Err(OAuthError::InvalidScope);
+    }
+
+    let access_token = generate_access_token(client.id.to_string(), None, &client.id, &scopes)
+        .map_err(OAuthError::UnexpectedError)?;
+
+    Ok(TokenResponse {
+        access_token: access_token.expose_secret().to_owned(),
+        token_type: TOKEN_TYPE.to_owned(),
+        expires_in: TOKEN_TTL_SECONDS,
+        scope: (!scopes.is_empty()).then(|| scopes.to_string()),
+        id_token: None,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectionRequest {
+    token: Option<Secret<String>>,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+}
+
+/// What RFC 7662 section 2.2 says about a token. Everything but `active` is left out for a token
+/// that isn't.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+}
+
+impl From<Claims> for IntrospectionResponse {
+    fn from(claims: Claims) -> Self {
+        Self {
+            active: true,
+            scope: claims.scope,
+            client_id: claims.client_id,
+            token_type: Some(TOKEN_TYPE.to_owned()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            nbf: Some(claims.nbf),
+            sub: Some(claims.sub),
+            aud: Some(claims.aud),
+            iss: Some(claims.iss),
+            jti: Some(claims.jti),
+            sid: claims.sid,
+        }
+    }
+}
+
+/// The introspection endpoint (RFC 7662), for confidential clients to check the tokens they are
+/// called with.
+#[tracing::instrument(name = "Introspect", skip_all)]
+pub async fn introspect(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(request): Form<IntrospectionRequest>,
+) -> Result<Json<IntrospectionResponse>, OAuthError> {
+    authenticate_introspecting_client(
+        &headers,
+        request.client_id.clone(),
+        request.client_secret.clone(),
+        &state,
+    )
+    .await?;
+
+    let token = request
+        .token
+        .ok_or(OAuthError::InvalidRequest("token is missing"))?;
+    match validate_token(
+        &token,
+        state.banned_token_store.clone(),
+        state.session_store.clone(),
+    )
+    .await
+    {
+        Ok(claims) => Ok(Json(claims.into())),
+        Err(TokenValidationError::InvalidToken(_)) => Ok(Json(IntrospectionResponse::inactive())),
+        Err(TokenValidationError::UnexpectedError(e)) => Err(OAuthError::UnexpectedError(e)),
+    }
+}
@@ -0,0 +1,107 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, SessionStoreError, UserStoreError},
+    utils::{auth::validate_auth_cookie, client::ClientInfo},
+};
+
+use super::login::verify_password;
+
+/// Changes the logged in user's password. The current password is asked for again so that a
+/// stolen cookie alone can't lock the owner out, and checked like a login so it can't be guessed.
+#[tracing::instrument(name = "Change password", skip_all)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let claims = validate_auth_cookie(&jar, &state).await?;
+    let user_id = claims.user_id()?;
+
+    let user = match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(user) => user,
+        Err(UserStoreError::UnexpectedError(e)) => return Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => return Err(AuthAPIError::InvalidToken),
+    };
+
+    verify_password(&user.email, request.current_password, &client, &state).await?;
+
+    let new_password = state
+        .password_policy
+        .parse_new(request.new_password, &user.email)
+        .await?;
+
+    state
+        .user_store
+        .write()
+        .await
+        .set_password(&user.id, new_password)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    if request.revoke_other_sessions {
+        let mut session_store = state.session_store.write().await;
+        let sessions = session_store
+            .get_sessions(&user.id)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+        for session in sessions
+            .iter()
+            .filter(|session| claims.sid.as_deref() != Some(session.id.as_ref()))
+        {
+            match session_store.remove_session(&user.id, &session.id).await {
+                // it may have been logged out in the meantime
+                Ok(()) | Err(SessionStoreError::SessionNotFound) => {}
+                Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+            }
+        }
+    }
+
+    // the password has changed either way, so a failed notification is only logged
+    if let Err(e) = state
+        .email_client
+        .send_email(
+            &user.email,
+            "Your password was changed",
+            "The password of your account was just changed. If this wasn't you, reset your \
+             password right away and check the devices that are logged in to your account.",
+        )
+        .await
+    {
+        tracing::error!("failed to send password change notification: {:?}", e);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    #[serde(rename = "currentPassword")]
+    pub current_password: Secret<String>,
+    #[serde(rename = "newPassword")]
+    pub new_password: Secret<String>,
+    /// Logs out every device except the one making the request.
+    #[serde(rename = "revokeOtherSessions", default)]
+    pub revoke_other_sessions: bool,
+}
@@ -0,0 +1,132 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use color_eyre::eyre::eyre;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{validate_redirect_uri, GrantType, OAuthClient, OAuthError, Scopes},
+    utils::authorization::check_registration_token,
+};
+
+const CLIENT_SECRET_BASIC: &str = "client_secret_basic";
+const CLIENT_SECRET_POST: &str = "client_secret_post";
+const NO_CLIENT_AUTHENTICATION: &str = "none";
+
+/// Client metadata as RFC 7591 section 2 names it. Only what this service uses is read.
+#[derive(Debug, Deserialize)]
+pub struct RegisterClientRequest {
+    pub client_name: String,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    #[serde(default = "default_grant_types")]
+    pub grant_types: Vec<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default = "default_token_endpoint_auth_method")]
+    pub token_endpoint_auth_method: String,
+}
+
+fn default_grant_types() -> Vec<String> {
+    vec![GrantType::AuthorizationCode.as_str().to_owned()]
+}
+
+fn default_token_endpoint_auth_method() -> String {
+    CLIENT_SECRET_BASIC.to_owned()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterClientResponse {
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub client_name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub scope: String,
+    pub token_endpoint_auth_method: String,
+}
+
+/// Registers a client (RFC 7591). The client secret is only ever shown in this response.
+#[tracing::instrument(name = "Register OAuth client", skip_all)]
+pub async fn register_client(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RegisterClientRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    check_registration_token(&headers, &state)?;
+
+    let confidential = match request.token_endpoint_auth_method.as_str() {
+        CLIENT_SECRET_BASIC | CLIENT_SECRET_POST => true,
+        NO_CLIENT_AUTHENTICATION => false,
+        _ => {
+            return Err(OAuthError::InvalidClientMetadata(eyre!(
+                "Unsupported token_endpoint_auth_method"
+            )))
+        }
+    };
+    for redirect_uri in &request.redirect_uris {
+        validate_redirect_uri(redirect_uri).map_err(OAuthError::InvalidRedirectUri)?;
+    }
+    let grant_types = request
+        .grant_types
+        .iter()
+        .map(|grant_type| GrantType::parse(grant_type))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(OAuthError::InvalidClientMetadata)?;
+    let scopes = Scopes::parse(request.scope.as_deref().unwrap_or_default())
+        .map_err(OAuthError::InvalidClientMetadata)?;
+
+    let (client, secret) = OAuthClient::new(
+        request.client_name,
+        request.redirect_uris,
+        grant_types,
+        scopes,
+        confidential,
+    )
+    .map_err(OAuthError::InvalidClientMetadata)?;
+
+    // ids are random, so a clash is as unexpected as any other store failure
+    state
+        .oauth_client_store
+        .write()
+        .await
+        .add_client(client.clone())
+        .await
+        .map_err(|e| OAuthError::UnexpectedError(e.into()))?;
+
+    let response = RegisterClientResponse {
+        client_id: client.id.to_string(),
+        client_secret: secret.map(|secret| secret.as_ref().expose_secret().to_owned()),
+        client_name: client.name,
+        redirect_uris: client.redirect_uris,
+        grant_types: client
+            .grant_types
+            .iter()
+            .map(|grant_type| grant_type.as_str().to_owned())
+            .collect(),
+        scope: client.scopes.to_string(),
+        token_endpoint_auth_method: request.token_endpoint_auth_method,
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
@@ -21,36 +21,37 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, Password, TwoFACode, UserStoreError},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        AuthAPIError, Email, LoginAttemptId, TwoFACode, TwoFACodeStoreError, TwoFAMethod, UserId,
+        UserStoreError,
+    },
+    utils::{
+        auth::{create_session, effective_scopes, generate_auth_cookie, generate_refresh_cookie},
+        client::ClientInfo,
+        login_throttle::{
+            clear_failed_logins, failed_login_keys, refund_login_attempt, report_failed_login,
+            reserve_login_attempt,
+        },
+    },
 };
 
 #[tracing::instrument(name = "Login", skip_all)]
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     jar: CookieJar,
     Json(request): Json<LoginRequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
-    let password = match Password::parse(request.password) {
-        Ok(password) => password,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
-    };
-
     let email = match Email::parse(request.email) {
         Ok(email) => email,
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let user_store = &state.user_store.read().await;
-
-    if let Err(e) = user_store.validate_user(&email, &password).await {
-        return match e {
-            UserStoreError::UnexpectedError(e) => (jar, Err(AuthAPIError::UnexpectedError(e))),
-            _ => (jar, Err(AuthAPIError::IncorrectCredentials)),
-        };
+    if let Err(e) = verify_password(&email, request.password, &client, &state).await {
+        return (jar, Err(e));
     }
 
-    let user = match user_store.get_user(&email).await {
+    let user = match state.user_store.read().await.get_user(&email).await {
         Ok(user) => user,
         Err(UserStoreError::UnexpectedError(e)) => {
             return (jar, Err(AuthAPIError::UnexpectedError(e)))
@@ -58,64 +59,153 @@ pub async fn login(
         Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
     };
 
-    match user.require_2fa {
-        true => handle_2fa(&user.email, &state, jar).await,
-        false => handle_no_2fa(&user.email, jar).await,
+    // checked after the password so that it says nothing about accounts the caller can't log into
+    if !user.verified {
+        return (jar, Err(AuthAPIError::EmailNotVerified));
+    }
+    if user.purge_at.is_some() {
+        return (jar, Err(AuthAPIError::AccountPendingDeletion));
+    }
+
+    match user.two_fa_method {
+        TwoFAMethod::None => handle_no_2fa(&user.id, client, &state, jar).await,
+        method => handle_2fa(&user.email, method, &state, jar).await,
+    }
+}
+
+/// Checks the user's password, counting failures against the login backoff. Routes that ask for
+/// the password again before a sensitive change use this too, so they can't be used to guess it.
+pub(super) async fn verify_password(
+    email: &Email,
+    password: Secret<String>,
+    client: &ClientInfo,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let password = state
+        .password_policy
+        .parse(password)
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let failed_login_keys = failed_login_keys(email, client);
+    let previous = reserve_login_attempt(&failed_login_keys, state).await?;
+
+    let result = state
+        .user_store
+        .read()
+        .await
+        .validate_user(email, &password)
+        .await;
+    match result {
+        Ok(()) => clear_failed_logins(&failed_login_keys, &previous, state).await,
+        Err(UserStoreError::UnexpectedError(e)) => {
+            refund_login_attempt(&failed_login_keys, &previous, state).await?;
+            Err(AuthAPIError::UnexpectedError(e))
+        }
+        Err(_) => {
+            report_failed_login(&failed_login_keys, &previous);
+            Err(AuthAPIError::IncorrectCredentials)
+        }
     }
 }
 
 #[tracing::instrument(name = "Handle 2FA flow", skip_all)]
-async fn handle_2fa(
+pub(super) async fn handle_2fa(
     email: &Email,
+    method: TwoFAMethod,
     state: &AppState,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
+    match start_2fa(email, method, state).await {
+        Ok(response) => (
+            jar,
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                Json(LoginResponse::TwoFactorAuth(response)),
+            )),
+        ),
+        Err(e) => (jar, Err(e)),
+    }
+}
+
+/// Starts a login attempt that has to be completed with the second factor, and emails the code
+/// to users of email 2FA. Routes that ask for 2FA again before a sensitive change use this too.
+pub(super) async fn start_2fa(
+    email: &Email,
+    method: TwoFAMethod,
+    state: &AppState,
+) -> Result<TwoFactorAuthResponse, AuthAPIError> {
     let login_attempt_id = LoginAttemptId::default();
     let two_fa_code = TwoFACode::default();
 
-    if let Err(e) = state
+    state
         .two_fa_code_store
         .write()
         .await
         .add_code(email.clone(), login_attempt_id.clone(), two_fa_code.clone())
         .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
-    }
-
-    if let Err(e) = state
-        .email_client
-        .send_email(email, "2FA Code", two_fa_code.as_ref().expose_secret())
-        .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+        .map_err(|e| match e {
+            TwoFACodeStoreError::TooManyCodes(seconds) => AuthAPIError::TooManyAttempts(seconds),
+            e => AuthAPIError::UnexpectedError(e.into()),
+        })?;
+
+    // TOTP users read their code from an authenticator app, the stored code is never sent
+    if method == TwoFAMethod::Email {
+        state
+            .email_client
+            .send_email(email, "2FA Code", two_fa_code.as_ref().expose_secret())
+            .await
+            .map_err(AuthAPIError::UnexpectedError)?;
     }
 
-    let response = Json(LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
+    Ok(TwoFactorAuthResponse {
         message: "2FA required".to_owned(),
         login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
-    }));
-
-    (jar, Ok((StatusCode::PARTIAL_CONTENT, response)))
+        two_fa_method: method.as_str().to_owned(),
+    })
 }
 
+/// Logs the user in by setting the auth and refresh cookies. Other ways of logging in end here
+/// too once they have identified the user.
 #[tracing::instrument(name = "Handle non-2FA flow", skip_all)]
-async fn handle_no_2fa(
-    email: &Email,
+pub(super) async fn handle_no_2fa(
+    user_id: &UserId,
+    client: ClientInfo,
+    state: &AppState,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
-    let auth_cookie = match generate_auth_cookie(email) {
+    let session_id = match create_session(user_id, client, state.session_store.clone()).await {
+        Ok(session_id) => session_id,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+
+    let scopes = match effective_scopes(user_id, state).await {
+        Ok(scopes) => scopes,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+
+    let auth_cookie = match generate_auth_cookie(user_id, &session_id, &scopes) {
+        Ok(cookie) => cookie,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+
+    let refresh_cookie = match generate_refresh_cookie(
+        user_id,
+        &session_id,
+        state.refresh_token_store.clone(),
+    )
+    .await
+    {
         Ok(cookie) => cookie,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
 
-    let updated_jar = jar.add(auth_cookie);
+    let updated_jar = jar.add(auth_cookie).add(refresh_cookie);
     (
         updated_jar,
         Ok((StatusCode::OK, Json(LoginResponse::RegularAuth))),
@@ -140,4 +230,6 @@ pub struct TwoFactorAuthResponse {
     pub message: String,
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: String,
+    #[serde(rename = "2FAMethod")]
+    pub two_fa_method: String,
 }
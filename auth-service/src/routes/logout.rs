@@ -19,10 +19,10 @@ use secrecy::Secret;
 
 use crate::{
     app_state::AppState,
-    domain::AuthAPIError,
+    domain::{AuthAPIError, RefreshToken, RefreshTokenStoreError, SessionStoreError},
     utils::{
-        auth::{validate_token, TokenValidationError},
-        constants::JWT_COOKIE_NAME,
+        auth::validate_auth_cookie,
+        constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
     },
 };
 
@@ -31,31 +31,55 @@ pub async fn logout(
     State(state): State<AppState>,
     jar: CookieJar,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
-    let cookie = match jar.get(JWT_COOKIE_NAME) {
-        Some(cookie) => cookie,
-        None => return (jar, Err(AuthAPIError::MissingToken)),
-    };
-
-    let token = Secret::new(cookie.value().to_owned());
-    let _ = match validate_token(&token, state.banned_token_store.clone()).await {
+    let claims = match validate_auth_cookie(&jar, &state).await {
         Ok(claims) => claims,
-        Err(TokenValidationError::UnexpectedError(e)) => {
-            return (jar, Err(AuthAPIError::UnexpectedError(e)))
-        }
-        Err(TokenValidationError::InvalidToken(_)) => return (jar, Err(AuthAPIError::InvalidToken)),
+        Err(e) => return (jar, Err(e)),
     };
 
     if let Err(e) = state
         .banned_token_store
         .write()
         .await
-        .add_token(token.to_owned())
+        .add_token(claims.jti.clone())
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
 
-    let jar = jar.remove(cookie::Cookie::from(JWT_COOKIE_NAME));
+    // claims were just validated, so both ids are well formed
+    if let (Ok(user_id), Ok(session_id)) = (claims.user_id(), claims.session_id()) {
+        match state
+            .session_store
+            .write()
+            .await
+            .remove_session(&user_id, &session_id)
+            .await
+        {
+            Ok(()) | Err(SessionStoreError::SessionNotFound) => {}
+            Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+        }
+    }
+
+    if let Some(refresh_cookie) = jar.get(REFRESH_TOKEN_COOKIE_NAME) {
+        if let Ok(refresh_token) =
+            RefreshToken::parse(Secret::new(refresh_cookie.value().to_owned()))
+        {
+            let mut refresh_token_store = state.refresh_token_store.write().await;
+            match refresh_token_store.get_token(&refresh_token).await {
+                Ok(data) => {
+                    if let Err(e) = refresh_token_store.revoke_family(&data.family_id).await {
+                        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+                    }
+                }
+                Err(RefreshTokenStoreError::TokenNotFound) => {}
+                Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+            }
+        }
+    }
+
+    let jar = jar
+        .remove(cookie::Cookie::from(JWT_COOKIE_NAME))
+        .remove(cookie::Cookie::from(REFRESH_TOKEN_COOKIE_NAME));
 
     (jar, Ok(StatusCode::OK))
 }
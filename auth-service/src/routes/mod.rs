@@ -13,14 +13,46 @@
    See the License for the specific language governing permissions and
    limitations under the License.
 */
+mod account;
+mod admin;
+mod authorization;
+mod change_password;
+mod client_registration;
+mod jwks;
 mod login;
 mod logout;
+mod oauth;
+mod openid_configuration;
+mod password_reset;
+mod recovery_codes;
+mod refresh;
+mod sessions;
 mod signup;
+mod totp;
+mod two_fa;
 mod verify_2fa;
+mod verify_email;
 mod verify_token;
+mod webauthn;
 
+pub use account::*;
+pub use admin::*;
+pub use authorization::*;
+pub use change_password::*;
+pub use client_registration::*;
+pub use jwks::*;
 pub use login::*;
 pub use logout::*;
+pub use oauth::*;
+pub use openid_configuration::*;
+pub use password_reset::*;
+pub use recovery_codes::*;
+pub use refresh::*;
+pub use sessions::*;
 pub use signup::*;
+pub use totp::*;
+pub use two_fa::*;
 pub use verify_2fa::*;
+pub use verify_email::*;
 pub use verify_token::*;
+pub use webauthn::*;
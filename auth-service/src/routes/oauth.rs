@@ -0,0 +1,285 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::Secret;
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Email, ExternalIdentity, ExternalIdentityStoreError, OAuthFlow,
+        OAuthProvider, OAuthState, OAuthStateStoreError, Password, TwoFAMethod, User,
+        UserStoreError,
+    },
+    services::oidc_client::{IdTokenClaims, OidcError, OAUTH_STATE_TTL_SECONDS},
+    utils::{client::ClientInfo, constants::OAUTH_STATE_COOKIE_NAME},
+};
+
+use super::login::{handle_2fa, handle_no_2fa};
+
+/// Accounts created by logging in with a provider get a random password nobody knows. Their
+/// owners can still set one with a password reset.
+const UNUSABLE_PASSWORD_LENGTH: usize = 64;
+
+#[derive(Deserialize)]
+pub struct OAuthCallbackQuery {
+    code: Option<Secret<String>>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Sends the user to the provider to log in. The state also goes into a cookie, so that the
+/// callback only completes the login in the browser that started it.
+#[tracing::instrument(name = "Start OAuth login", skip_all)]
+pub async fn oauth_start(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let provider = state
+        .oidc_client
+        .provider(&provider)
+        .ok_or(AuthAPIError::OAuthProviderNotFound)?;
+
+    let oauth_state = OAuthState::default();
+    let flow = OAuthFlow::new(provider.name.clone());
+    let url = state
+        .oidc_client
+        .authorization_url(provider, &oauth_state, &flow)
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    state
+        .oauth_state_store
+        .write()
+        .await
+        .add_flow(oauth_state.clone(), flow)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let jar = jar.add(create_state_cookie(&oauth_state));
+    Ok((jar, Redirect::to(url.as_str())))
+}
+
+/// Completes the login the provider redirected back from, the same way `/login` does.
+#[tracing::instrument(name = "OAuth callback", skip_all)]
+pub async fn oauth_callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let state_cookie = jar
+        .get(OAUTH_STATE_COOKIE_NAME)
+        .map(|cookie| cookie.value().to_owned());
+    let jar = jar.remove(Cookie::from(OAUTH_STATE_COOKIE_NAME));
+
+    let user = match authenticate(&provider, state_cookie, query, &state).await {
+        Ok(user) => user,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    if user.purge_at.is_some() {
+        return (jar, Err(AuthAPIError::AccountPendingDeletion));
+    }
+
+    match user.two_fa_method {
+        TwoFAMethod::None => handle_no_2fa(&user.id, client, &state, jar).await,
+        method => handle_2fa(&user.email, method, &state, jar).await,
+    }
+}
+
+async fn authenticate(
+    provider: &str,
+    state_cookie: Option<String>,
+    query: OAuthCallbackQuery,
+    state: &AppState,
+) -> Result<User, AuthAPIError> {
+    let provider = state
+        .oidc_client
+        .provider(provider)
+        .ok_or(AuthAPIError::OAuthProviderNotFound)?;
+
+    // the user turned the login down, or the provider couldn't complete it
+    if let Some(error) = query.error {
+        tracing::debug!(provider = %provider.name, error = %error, "Provider returned an error");
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+    let (Some(code), Some(returned_state)) = (query.code, query.state) else {
+        return Err(AuthAPIError::InvalidCredentials);
+    };
+
+    // a state from another browser means someone is trying to log this one into their account
+    if state_cookie.as_deref() != Some(returned_state.as_str()) {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+    let oauth_state =
+        OAuthState::parse(returned_state).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let flow = take_flow(&oauth_state, state).await?;
+    if flow.provider != provider.name {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    let id_token = state
+        .oidc_client
+        .exchange_code(provider, &code, &flow)
+        .await
+        .map_err(oidc_error)?;
+    let claims = state
+        .oidc_client
+        .validate_id_token(provider, &id_token, &flow)
+        .await
+        .map_err(oidc_error)?;
+
+    find_or_link_user(provider, claims, state).await
+}
+
+/// Finds the user the provider account is linked to. A provider account seen for the first time
+/// is linked to the user with its email address, or to a new user, but only once the provider has
+/// verified the address. Otherwise anyone could claim an address at a provider and take over the
+/// account registered with it.
+async fn find_or_link_user(
+    provider: &OAuthProvider,
+    claims: IdTokenClaims,
+    state: &AppState,
+) -> Result<User, AuthAPIError> {
+    let linked_user_id = state
+        .external_identity_store
+        .read()
+        .await
+        .get_user_id(&provider.name, &claims.sub)
+        .await;
+
+    match linked_user_id {
+        Ok(user_id) => {
+            return match state.user_store.read().await.get_user_by_id(&user_id).await {
+                Ok(user) => Ok(user),
+                Err(UserStoreError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+                Err(_) => Err(AuthAPIError::IncorrectCredentials),
+            }
+        }
+        Err(ExternalIdentityStoreError::IdentityNotFound) => {}
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    let email = match (claims.email, claims.email_verified) {
+        (Some(email), true) => {
+            Email::parse(Secret::new(email)).map_err(|_| AuthAPIError::IncorrectCredentials)?
+        }
+        _ => return Err(AuthAPIError::EmailNotVerified),
+    };
+
+    let user = {
+        let mut user_store = state.user_store.write().await;
+        match user_store.get_user(&email).await {
+            Ok(user) if user.verified => user,
+            // whoever signed up with the address never proved it was theirs, so their password
+            // must not open the account once the address's real owner has linked it
+            Ok(mut user) => {
+                user_store
+                    .set_password(&user.id, unusable_password()?)
+                    .await
+                    .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+                user_store
+                    .set_verified(&user.id)
+                    .await
+                    .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+                user.verified = true;
+                user
+            }
+            Err(UserStoreError::UserNotFound) => {
+                let mut user = User::new(email, unusable_password()?, TwoFAMethod::None);
+                user.verified = true;
+                user_store
+                    .add_user(user.clone())
+                    .await
+                    .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+                user
+            }
+            Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+        }
+    };
+
+    let identity = ExternalIdentity {
+        provider: provider.name.clone(),
+        subject: claims.sub,
+        user_id: user.id,
+    };
+    state
+        .external_identity_store
+        .write()
+        .await
+        .add_identity(identity)
+        .await
+        .map_err(|e| match e {
+            // a concurrent callback for the same provider account got there first
+            ExternalIdentityStoreError::IdentityAlreadyLinked => AuthAPIError::IncorrectCredentials,
+            e => AuthAPIError::UnexpectedError(e.into()),
+        })?;
+
+    Ok(user)
+}
+
+async fn take_flow(oauth_state: &OAuthState, state: &AppState) -> Result<OAuthFlow, AuthAPIError> {
+    match state
+        .oauth_state_store
+        .write()
+        .await
+        .take_flow(oauth_state)
+        .await
+    {
+        Ok(flow) => Ok(flow),
+        Err(OAuthStateStoreError::StateNotFound) => Err(AuthAPIError::IncorrectCredentials),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+fn oidc_error(e: OidcError) -> AuthAPIError {
+    match e {
+        OidcError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        e => {
+            tracing::debug!("Rejected OAuth login: {:?}", e);
+            AuthAPIError::IncorrectCredentials
+        }
+    }
+}
+
+fn create_state_cookie(oauth_state: &OAuthState) -> Cookie<'static> {
+    // Lax, since the provider's redirect back is a cross-site navigation
+    Cookie::build((OAUTH_STATE_COOKIE_NAME, oauth_state.as_ref().to_owned()))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(OAUTH_STATE_TTL_SECONDS))
+        .build()
+}
+
+fn unusable_password() -> Result<Password, AuthAPIError> {
+    let password: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(UNUSABLE_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect();
+    Password::parse(Secret::new(password)).map_err(AuthAPIError::UnexpectedError)
+}
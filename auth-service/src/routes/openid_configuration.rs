@@ -0,0 +1,79 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::Json;
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{GrantType, OPENID_SCOPE},
+    utils::{
+        authorization::openid_enabled,
+        constants::{AUTH_SERVICE_URL, JWT_ISSUER, JWT_KEYRING},
+    },
+};
+
+/// The discovery document (OpenID Connect Discovery section 3), so clients can find the
+/// endpoints and keys instead of configuring each of them. The openid scope and ID token signing
+/// algorithm are only listed when ID tokens are issued.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub introspection_endpoint: String,
+    pub registration_endpoint: String,
+    pub jwks_uri: String,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<Algorithm>,
+    pub subject_types_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+}
+
+#[tracing::instrument(name = "OpenID configuration", skip_all)]
+pub async fn openid_configuration() -> Json<OpenIdConfiguration> {
+    let endpoint = |path: &str| format!("{}{}", *AUTH_SERVICE_URL, path);
+    let mut scopes_supported = vec!["email".to_owned()];
+    let mut id_token_signing_alg_values_supported = vec![];
+    if openid_enabled() {
+        scopes_supported.insert(0, OPENID_SCOPE.to_owned());
+        id_token_signing_alg_values_supported.push(JWT_KEYRING.active().algorithm());
+    }
+    Json(OpenIdConfiguration {
+        issuer: JWT_ISSUER.to_owned(),
+        authorization_endpoint: endpoint("/oauth/authorize"),
+        token_endpoint: endpoint("/oauth/token"),
+        introspection_endpoint: endpoint("/oauth/introspect"),
+        registration_endpoint: endpoint("/oauth/register"),
+        jwks_uri: endpoint("/.well-known/jwks.json"),
+        response_types_supported: vec!["code".to_owned()],
+        grant_types_supported: vec![
+            GrantType::AuthorizationCode.as_str().to_owned(),
+            GrantType::ClientCredentials.as_str().to_owned(),
+        ],
+        code_challenge_methods_supported: vec!["S256".to_owned()],
+        token_endpoint_auth_methods_supported: vec![
+            "client_secret_basic".to_owned(),
+            "client_secret_post".to_owned(),
+            "none".to_owned(),
+        ],
+        id_token_signing_alg_values_supported,
+        subject_types_supported: vec!["public".to_owned()],
+        scopes_supported,
+    })
+}
@@ -0,0 +1,155 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use color_eyre::eyre::Result;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, EmailClient, User, UserId, UserStoreError},
+    utils::{
+        auth::TokenValidationError,
+        password_reset::{
+            generate_password_reset_token, password_reset_link, validate_password_reset_token,
+        },
+    },
+};
+
+/// Emails a password reset link if an account exists for the address. The response is the same
+/// either way so it can't be used to find out who has an account.
+#[tracing::instrument(name = "Request password reset", skip_all)]
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(request): Json<PasswordResetRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let user = match state.user_store.read().await.get_user(&email).await {
+        Ok(user) => Some(user),
+        Err(UserStoreError::UserNotFound) => None,
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+
+    // sending takes long enough to be measured, so it happens off the request path
+    if let Some(user) = user {
+        let email_client = state.email_client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_password_reset_email(&user, email_client.as_ref()).await {
+                tracing::error!("failed to send password reset email: {:?}", e);
+            }
+        });
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(PasswordResetResponse {
+            message: "If an account exists for this email, a reset link has been sent".to_owned(),
+        }),
+    ))
+}
+
+/// Sets a new password with a token from a reset link. The token only works once, and every
+/// existing session of the user is revoked.
+#[tracing::instrument(name = "Confirm password reset", skip_all)]
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(request): Json<ConfirmPasswordResetRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let claims = validate_password_reset_token(&request.token, state.banned_token_store.clone())
+        .await
+        .map_err(|e| match e {
+            TokenValidationError::InvalidToken(_) => AuthAPIError::InvalidToken,
+            TokenValidationError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        })?;
+    let user_id = UserId::parse(&claims.sub).map_err(|_| AuthAPIError::InvalidToken)?;
+
+    let user = match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    // a weak password leaves the token unused, so the user can pick another one
+    let password = state
+        .password_policy
+        .parse_new(request.password, &user.email)
+        .await?;
+
+    // banning the token uses it up, so of two concurrent requests with it only one gets past here
+    let newly_banned = state
+        .banned_token_store
+        .write()
+        .await
+        .add_token(claims.jti)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    if !newly_banned {
+        return Err(AuthAPIError::InvalidToken);
+    }
+
+    match state
+        .user_store
+        .write()
+        .await
+        .set_password(&user_id, password)
+        .await
+    {
+        Ok(()) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    state
+        .session_store
+        .write()
+        .await
+        .remove_all_sessions(&user_id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(StatusCode::OK)
+}
+
+async fn send_password_reset_email(
+    user: &User,
+    email_client: &(dyn EmailClient + Send + Sync),
+) -> Result<()> {
+    let token = generate_password_reset_token(&user.id)?;
+    let link = password_reset_link(&token)?;
+    let content = format!(
+        "Use this link to choose a new password, it expires in 10 minutes: {}",
+        link
+    );
+    email_client
+        .send_email(&user.email, "Reset your password", &content)
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmPasswordResetRequest {
+    pub token: Secret<String>,
+    pub password: Secret<String>,
+}
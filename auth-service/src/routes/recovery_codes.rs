@@ -0,0 +1,124 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, RecoveryCode, UserId, RECOVERY_CODE_COUNT},
+    utils::auth::current_user,
+};
+
+use super::two_fa::require_second_factor;
+
+/// Replaces the logged in user's recovery codes, after a fresh check of their current second
+/// factor. Codes left from the previous set stop working.
+#[tracing::instrument(name = "Regenerate recovery codes", skip_all)]
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<RegenerateRecoveryCodesRequest>,
+) -> Result<Response, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    if !user.two_fa_method.is_enabled() {
+        return Err(AuthAPIError::TwoFANotEnabled);
+    }
+
+    if let Some(response) =
+        require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state).await?
+    {
+        return Ok(response);
+    }
+
+    let recovery_codes = issue_recovery_codes(&user.id, &state).await?;
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodesResponse { recovery_codes }),
+    )
+        .into_response())
+}
+
+#[tracing::instrument(name = "Count recovery codes", skip_all)]
+pub async fn count_recovery_codes(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    let remaining = state
+        .recovery_code_store
+        .read()
+        .await
+        .count_codes(&user.id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodeCountResponse { remaining }),
+    ))
+}
+
+/// Generates a new set of recovery codes for the user. The plain codes are only ever returned
+/// from here, the store keeps hashes.
+pub(super) async fn issue_recovery_codes(
+    user_id: &UserId,
+    state: &AppState,
+) -> Result<Vec<String>, AuthAPIError> {
+    let codes: Vec<RecoveryCode> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| RecoveryCode::default())
+        .collect();
+    let plain_codes = codes
+        .iter()
+        .map(|code| code.as_ref().expose_secret().to_owned())
+        .collect();
+
+    state
+        .recovery_code_store
+        .write()
+        .await
+        .replace_codes(user_id, codes)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(plain_codes)
+}
+
+#[derive(Deserialize)]
+pub struct RegenerateRecoveryCodesRequest {
+    /// Both fields come from a previous 206, and are left out to start the check.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodeCountResponse {
+    pub remaining: usize,
+}
@@ -0,0 +1,116 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, RefreshToken, RefreshTokenData, RefreshTokenStoreError, SessionStoreError,
+    },
+    utils::{
+        auth::{create_refresh_cookie, effective_scopes, generate_auth_cookie},
+        constants::REFRESH_TOKEN_COOKIE_NAME,
+    },
+};
+
+#[tracing::instrument(name = "Refresh", skip_all)]
+pub async fn refresh(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let cookie = match jar.get(REFRESH_TOKEN_COOKIE_NAME) {
+        Some(cookie) => cookie,
+        None => return (jar, Err(AuthAPIError::MissingToken)),
+    };
+
+    let token = match RefreshToken::parse(Secret::new(cookie.value().to_owned())) {
+        Ok(token) => token,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let mut refresh_token_store = state.refresh_token_store.write().await;
+    let data = match refresh_token_store.use_token(&token).await {
+        Ok(data) => data,
+        Err(RefreshTokenStoreError::TokenNotFound) => {
+            return (jar, Err(AuthAPIError::InvalidToken))
+        }
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
+    // a rotated token should never come back, so treat a replay as a stolen session
+    if data.used {
+        tracing::warn!("refresh token reuse detected, revoking session");
+        if let Err(e) = refresh_token_store.revoke_family(&data.family_id).await {
+            return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+        }
+        if let Err(e) = state
+            .session_store
+            .write()
+            .await
+            .remove_session(&data.user_id, &data.session_id)
+            .await
+        {
+            if e != SessionStoreError::SessionNotFound {
+                return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+            }
+        }
+        return (jar, Err(AuthAPIError::InvalidToken));
+    }
+
+    match state
+        .session_store
+        .write()
+        .await
+        .touch_session(&data.session_id)
+        .await
+    {
+        Ok(_) => {}
+        Err(SessionStoreError::SessionNotFound) => {
+            if let Err(e) = refresh_token_store.revoke_family(&data.family_id).await {
+                return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+            }
+            return (jar, Err(AuthAPIError::InvalidToken));
+        }
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    }
+
+    let new_token = RefreshToken::default();
+    let new_data = RefreshTokenData::new(data.user_id, data.session_id.clone(), data.family_id);
+    if let Err(e) = refresh_token_store
+        .add_token(new_token.clone(), new_data)
+        .await
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+    drop(refresh_token_store);
+
+    // grants may have changed since the last token, so the new one carries the current scopes
+    let scopes = match effective_scopes(&data.user_id, &state).await {
+        Ok(scopes) => scopes,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+
+    let auth_cookie = match generate_auth_cookie(&data.user_id, &data.session_id, &scopes) {
+        Ok(cookie) => cookie,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+
+    let updated_jar = jar.add(auth_cookie).add(create_refresh_cookie(&new_token));
+
+    (updated_jar, Ok(StatusCode::OK))
+}
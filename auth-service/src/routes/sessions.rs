@@ -0,0 +1,145 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::{cookie, CookieJar};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Session, SessionId, SessionStoreError},
+    utils::{
+        auth::validate_auth_cookie,
+        constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    },
+};
+
+#[tracing::instrument(name = "List sessions", skip_all)]
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let claims = validate_auth_cookie(&jar, &state).await?;
+    let user_id = claims.user_id()?;
+    let session_id = claims.session_id()?;
+
+    let sessions = state
+        .session_store
+        .read()
+        .await
+        .get_sessions(&user_id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionResponse::new(session, session_id.as_ref()))
+        .collect();
+
+    Ok((StatusCode::OK, Json(ListSessionsResponse { sessions })))
+}
+
+#[tracing::instrument(name = "Revoke session", skip_all)]
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let claims = validate_auth_cookie(&jar, &state).await?;
+    let user_id = claims.user_id()?;
+    let session_id = SessionId::parse(id).map_err(|_| AuthAPIError::SessionNotFound)?;
+
+    match state
+        .session_store
+        .write()
+        .await
+        .remove_session(&user_id, &session_id)
+        .await
+    {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(SessionStoreError::SessionNotFound) => Err(AuthAPIError::SessionNotFound),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[tracing::instrument(name = "Revoke all sessions", skip_all)]
+pub async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let claims = match validate_auth_cookie(&jar, &state).await {
+        Ok(claims) => claims,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    let user_id = match claims.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    if let Err(e) = state
+        .session_store
+        .write()
+        .await
+        .remove_all_sessions(&user_id)
+        .await
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+
+    // the current session is gone too, so sign this device out
+    let jar = jar
+        .remove(cookie::Cookie::from(JWT_COOKIE_NAME))
+        .remove(cookie::Cookie::from(REFRESH_TOKEN_COOKIE_NAME));
+
+    (jar, Ok(StatusCode::NO_CONTENT))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListSessionsResponse {
+    pub sessions: Vec<SessionResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionResponse {
+    pub id: String,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: String,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session: Session, current_session_id: &str) -> Self {
+        Self {
+            current: session.id.as_ref() == current_session_id,
+            id: session.id.as_ref().to_owned(),
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at.to_rfc3339(),
+            last_seen_at: session.last_seen_at.to_rfc3339(),
+        }
+    }
+}
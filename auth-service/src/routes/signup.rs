@@ -20,9 +20,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, Password, User},
+    domain::{AuthAPIError, Email, EmailCooldownStoreError, TwoFAMethod, User},
 };
 
+use super::{recovery_codes::issue_recovery_codes, verify_email::send_verification_email};
+
 #[derive(Deserialize)]
 pub struct SignupRequest {
     pub email: Secret<String>,
@@ -34,6 +36,13 @@ pub struct SignupRequest {
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct SignupResponse {
     pub message: String,
+    /// Only present when 2FA was turned on at signup.
+    #[serde(
+        rename = "recoveryCodes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_codes: Option<Vec<String>>,
 }
 
 #[tracing::instrument(name = "Signup", skip_all)]
@@ -43,22 +52,55 @@ pub async fn signup(
 ) -> Result<impl IntoResponse, AuthAPIError> {
     let email =
         Email::parse(request.email.clone()).map_err(|_| AuthAPIError::InvalidCredentials)?;
-    let password =
-        Password::parse(request.password.clone()).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let password = state
+        .password_policy
+        .parse_new(request.password.clone(), &email)
+        .await?;
+
+    // authenticator apps are enrolled after signup, so only email codes can be asked for here
+    let two_fa_method = match request.require_2fa {
+        true => TwoFAMethod::Email,
+        false => TwoFAMethod::None,
+    };
+    let user = User::new(email.clone(), password, two_fa_method);
+    let user_id = user.id;
+
+    {
+        let mut user_store = state.user_store.write().await;
 
-    let user = User::new(email, password, request.require_2fa);
-    let mut user_store = state.user_store.write().await;
+        if user_store.get_user(&user.email).await.is_ok() {
+            return Err(AuthAPIError::UserAlreadyExists);
+        }
 
-    if user_store.get_user(&user.email).await.is_ok() {
-        return Err(AuthAPIError::UserAlreadyExists);
+        if let Err(e) = user_store.add_user(user).await {
+            return Err(AuthAPIError::UnexpectedError(e.into()));
+        }
     }
 
-    if let Err(e) = user_store.add_user(user).await {
-        return Err(AuthAPIError::UnexpectedError(e.into()));
+    // a resend right after signup would only duplicate this email
+    if let Err(EmailCooldownStoreError::UnexpectedError(e)) = state
+        .email_cooldown_store
+        .write()
+        .await
+        .start_cooldown(&email)
+        .await
+    {
+        return Err(AuthAPIError::UnexpectedError(e));
     }
 
+    // the account exists either way, and the link can be sent again
+    if let Err(e) = send_verification_email(&user_id, &email, state.email_client.as_ref()).await {
+        tracing::error!("failed to send verification email: {:?}", e);
+    }
+
+    let recovery_codes = match two_fa_method.is_enabled() {
+        true => Some(issue_recovery_codes(&user_id, &state).await?),
+        false => None,
+    };
+
     let response = Json(SignupResponse {
         message: "User created successfully!".to_owned(),
+        recovery_codes,
     });
 
     Ok((StatusCode::CREATED, response))
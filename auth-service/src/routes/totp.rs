@@ -0,0 +1,164 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, TotpSecret, TwoFAMethod, UserStoreError},
+    utils::{
+        auth::current_user,
+        client::ClientInfo,
+        totp::{provisioning_uri, verify_totp_code},
+    },
+};
+
+use super::{
+    login::verify_password, recovery_codes::issue_recovery_codes, two_fa::require_second_factor,
+};
+
+/// Starts TOTP enrollment for the logged in user. The secret only replaces the user's current 2FA
+/// method once a code from it is confirmed, so an abandoned enrollment changes nothing.
+#[tracing::instrument(name = "Enroll TOTP", skip_all)]
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    if user.two_fa_method == TwoFAMethod::Totp {
+        return Err(AuthAPIError::TotpAlreadyEnabled);
+    }
+
+    let secret = TotpSecret::default();
+    let otpauth_uri =
+        provisioning_uri(&secret, &user.email).map_err(AuthAPIError::UnexpectedError)?;
+
+    state
+        .user_store
+        .write()
+        .await
+        .set_totp_secret(&user.id, secret.clone())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let response = Json(EnrollTotpResponse {
+        secret: secret.as_ref().expose_secret().to_owned(),
+        otpauth_uri,
+    });
+
+    Ok((StatusCode::OK, response))
+}
+
+/// Switches the logged in user to TOTP once their password and a code from the enrolled secret
+/// check out. Users who already have 2FA get a 206 first, and repeat the request with a code from
+/// their current method.
+#[tracing::instrument(name = "Confirm TOTP", skip_all)]
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<ConfirmTotpRequest>,
+) -> Result<Response, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    if user.two_fa_method == TwoFAMethod::Totp {
+        return Err(AuthAPIError::TotpAlreadyEnabled);
+    }
+    // enrollment only needs the session, so the password keeps a stolen one from binding its app
+    verify_password(&user.email, request.password, &client, &state).await?;
+
+    let secret = user
+        .totp_secret
+        .as_ref()
+        .ok_or(AuthAPIError::TotpEnrollmentNotFound)?;
+
+    let step = verify_totp_code(secret, &request.two_fa_code)
+        .map_err(AuthAPIError::UnexpectedError)?
+        .ok_or(AuthAPIError::IncorrectCredentials)?;
+
+    if user.two_fa_method.is_enabled() {
+        if let Some(response) = require_second_factor(
+            &user,
+            request.login_attempt_id,
+            request.current_two_fa_code,
+            &state,
+        )
+        .await?
+        {
+            return Ok(response);
+        }
+    }
+
+    // only recorded now, because users answering the 206 repeat the same code
+    {
+        let mut user_store = state.user_store.write().await;
+        match user_store.use_totp_step(&user.id, step).await {
+            Ok(()) => {}
+            Err(UserStoreError::InvalidCredentials) => {
+                return Err(AuthAPIError::IncorrectCredentials)
+            }
+            Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+        }
+        user_store
+            .set_two_fa_method(&user.id, TwoFAMethod::Totp)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+
+    // users switching over from emailed codes keep the recovery codes they already have
+    let recovery_codes = match user.two_fa_method.is_enabled() {
+        true => None,
+        false => Some(issue_recovery_codes(&user.id, &state).await?),
+    };
+
+    Ok((StatusCode::OK, Json(ConfirmTotpResponse { recovery_codes })).into_response())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrollTotpResponse {
+    pub secret: String,
+    #[serde(rename = "otpauthUri")]
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmTotpResponse {
+    /// Only present when TOTP turned 2FA on.
+    #[serde(
+        rename = "recoveryCodes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub password: Secret<String>,
+    #[serde(rename = "2FACode")]
+    pub two_fa_code: Secret<String>,
+    /// Both fields come from a previous 206, and are only needed if 2FA is already on.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "current2FACode", default)]
+    pub current_two_fa_code: Option<Secret<String>>,
+}
@@ -0,0 +1,161 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, LoginAttemptId, TwoFAMethod, User},
+    utils::{auth::current_user, client::ClientInfo},
+};
+
+use super::{
+    login::{start_2fa, verify_password},
+    recovery_codes::{issue_recovery_codes, RecoveryCodesResponse},
+    verify_2fa::{check_second_factor, SecondFactor},
+};
+
+/// Turns on emailed 2FA codes for the logged in user, who has to give their password again. The
+/// first request emails a code and answers 206, and 2FA is only turned on once the request is
+/// repeated with that code.
+#[tracing::instrument(name = "Enable 2FA", skip_all)]
+pub async fn enable_2fa(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<Enable2FARequest>,
+) -> Result<Response, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    if user.two_fa_method.is_enabled() {
+        return Err(AuthAPIError::TwoFAAlreadyEnabled);
+    }
+    verify_password(&user.email, request.password, &client, &state).await?;
+
+    // the code is checked the way it will be once emailed codes are on
+    let user = User {
+        two_fa_method: TwoFAMethod::Email,
+        ..user
+    };
+    if let Some(response) =
+        require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state).await?
+    {
+        return Ok(response);
+    }
+
+    state
+        .user_store
+        .write()
+        .await
+        .set_two_fa_method(&user.id, TwoFAMethod::Email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let recovery_codes = issue_recovery_codes(&user.id, &state).await?;
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodesResponse { recovery_codes }),
+    )
+        .into_response())
+}
+
+/// Turns 2FA off for the logged in user, after a fresh check of their current second factor. The
+/// user's recovery codes and authenticator app secret are dropped with it.
+#[tracing::instrument(name = "Disable 2FA", skip_all)]
+pub async fn disable_2fa(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<Toggle2FARequest>,
+) -> Result<Response, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    if !user.two_fa_method.is_enabled() {
+        return Err(AuthAPIError::TwoFANotEnabled);
+    }
+
+    if let Some(response) =
+        require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state).await?
+    {
+        return Ok(response);
+    }
+
+    state
+        .user_store
+        .write()
+        .await
+        .disable_two_fa(&user.id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    state
+        .recovery_code_store
+        .write()
+        .await
+        .replace_codes(&user.id, Vec::new())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Checks the code from a previous 206. Without one, starts a login attempt for the user's method
+/// and returns the 206 to send instead.
+pub(super) async fn require_second_factor(
+    user: &User,
+    login_attempt_id: Option<Secret<String>>,
+    two_fa_code: Option<Secret<String>>,
+    state: &AppState,
+) -> Result<Option<Response>, AuthAPIError> {
+    let (login_attempt_id, two_fa_code) = match (login_attempt_id, two_fa_code) {
+        (Some(login_attempt_id), Some(two_fa_code)) => (login_attempt_id, two_fa_code),
+        _ => {
+            let response = start_2fa(&user.email, user.two_fa_method, state).await?;
+            return Ok(Some(
+                (StatusCode::PARTIAL_CONTENT, Json(response)).into_response(),
+            ));
+        }
+    };
+
+    let login_attempt_id =
+        LoginAttemptId::parse(login_attempt_id).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let second_factor = SecondFactor::parse(two_fa_code)?;
+    check_second_factor(user, &login_attempt_id, second_factor, state).await?;
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+pub struct Enable2FARequest {
+    pub password: Secret<String>,
+    /// Both fields come from a previous 206, and are left out to start the check.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct Toggle2FARequest {
+    /// Both fields come from a previous 206, and are left out to start the check.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
@@ -21,12 +21,18 @@ use subtle::ConstantTimeEq;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        AuthAPIError, Email, LoginAttemptId, RecoveryCode, RecoveryCodeStoreError, TwoFACode,
+        TwoFAMethod, User, UserStoreError,
+    },
+    utils::{client::ClientInfo, totp::verify_totp_code},
 };
 
+use super::login::handle_no_2fa;
+
 pub async fn verify_2fa(
     State(state): State<AppState>,
+    client: ClientInfo,
     jar: CookieJar,
     Json(request): Json<Verify2FARequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
@@ -40,46 +46,102 @@ pub async fn verify_2fa(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let two_fa_code = match TwoFACode::parse(request.two_fa_code) {
-        Ok(two_fa_code) => two_fa_code,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+    let second_factor = match SecondFactor::parse(request.two_fa_code) {
+        Ok(second_factor) => second_factor,
+        Err(e) => return (jar, Err(e)),
     };
 
-    let mut two_fa_code_store = state.two_fa_code_store.write().await;
-    let code_tuple = match two_fa_code_store.get_code(&email).await {
-        Ok(code_tuple) => code_tuple,
+    let user = match state.user_store.read().await.get_user(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UnexpectedError(e)) => {
+            return (jar, Err(AuthAPIError::UnexpectedError(e)))
+        }
         Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
     };
 
-    let id_matches: bool = code_tuple
-        .0
-        .as_ref()
-        .expose_secret()
-        .as_bytes()
-        .ct_eq(login_attempt_id.as_ref().expose_secret().as_bytes())
-        .into();
-    let code_matches: bool = code_tuple
-        .1
-        .as_ref()
-        .expose_secret()
-        .as_bytes()
-        .ct_eq(two_fa_code.as_ref().expose_secret().as_bytes())
-        .into();
-    if !(id_matches & code_matches) {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
+    if let Err(e) = check_second_factor(&user, &login_attempt_id, second_factor, &state).await {
+        return (jar, Err(e));
     }
 
-    if let Err(e) = two_fa_code_store.remove_code(&email).await {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    handle_no_2fa(&user.id, client, &state, jar).await
+}
+
+/// Checks the second factor for a login attempt started by `start_2fa`, and ends the attempt when
+/// it matches. Routes that ask for 2FA again before a sensitive change use this as well.
+pub(super) async fn check_second_factor(
+    user: &User,
+    login_attempt_id: &LoginAttemptId,
+    second_factor: SecondFactor,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let mut two_fa_code_store = state.two_fa_code_store.write().await;
+    let code_tuple = two_fa_code_store
+        .get_code(login_attempt_id)
+        .await
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+
+    // the attempt has to belong to the user it's being completed for
+    if code_tuple.0 != user.email {
+        return Err(AuthAPIError::IncorrectCredentials);
     }
 
-    let cookie = match generate_auth_cookie(&email) {
-        Ok(cookie) => cookie,
-        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    let code_matches = match (second_factor, user.two_fa_method, &user.totp_secret) {
+        (SecondFactor::RecoveryCode(recovery_code), _, _) => match state
+            .recovery_code_store
+            .write()
+            .await
+            .use_code(&user.id, &recovery_code)
+            .await
+        {
+            Ok(()) => true,
+            Err(RecoveryCodeStoreError::InvalidRecoveryCode) => false,
+            Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+        },
+        (SecondFactor::Code(code), TwoFAMethod::Email, _) => match TwoFACode::parse(code) {
+            Ok(two_fa_code) => code_tuple
+                .1
+                .as_ref()
+                .expose_secret()
+                .as_bytes()
+                .ct_eq(two_fa_code.as_ref().expose_secret().as_bytes())
+                .into(),
+            Err(_) => false,
+        },
+        (SecondFactor::Code(code), TwoFAMethod::Totp, Some(secret)) => {
+            match verify_totp_code(secret, &code).map_err(AuthAPIError::UnexpectedError)? {
+                // a code that was already accepted once counts as a wrong guess
+                Some(step) => match state
+                    .user_store
+                    .write()
+                    .await
+                    .use_totp_step(&user.id, step)
+                    .await
+                {
+                    Ok(()) => true,
+                    Err(UserStoreError::InvalidCredentials) => false,
+                    Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+                },
+                None => false,
+            }
+        }
+        _ => false,
     };
+    if !code_matches {
+        // the attempt's code is thrown away once it has been guessed at too often
+        let guesses_left = two_fa_code_store
+            .record_wrong_guess(login_attempt_id)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        if guesses_left == 0 {
+            tracing::warn!("2FA code invalidated after too many wrong guesses");
+        }
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
 
-    let updated_jar = jar.add(cookie);
-    (updated_jar, Ok(()))
+    two_fa_code_store
+        .remove_code(login_attempt_id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))
 }
 
 #[derive(Debug, Deserialize)]
@@ -87,6 +149,30 @@ pub struct Verify2FARequest {
     pub email: Secret<String>,
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: Secret<String>,
+    /// The emailed or TOTP code, or one of the user's recovery codes.
     #[serde(rename = "2FACode")]
     pub two_fa_code: Secret<String>,
 }
+
+pub(super) enum SecondFactor {
+    Code(Secret<String>),
+    RecoveryCode(RecoveryCode),
+}
+
+impl SecondFactor {
+    /// Both methods use six digit codes, anything else has to be a recovery code. Callers parse
+    /// before any lookups so a malformed code is always a 400.
+    pub(super) fn parse(two_fa_code: Secret<String>) -> Result<Self, AuthAPIError> {
+        if is_six_digit_code(&two_fa_code) {
+            return Ok(Self::Code(two_fa_code));
+        }
+        RecoveryCode::parse(two_fa_code)
+            .map(Self::RecoveryCode)
+            .map_err(|_| AuthAPIError::InvalidCredentials)
+    }
+}
+
+fn is_six_digit_code(code: &Secret<String>) -> bool {
+    let code = code.expose_secret();
+    code.len() == 6 && code.chars().all(|c| c.is_ascii_digit())
+}
@@ -0,0 +1,142 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use color_eyre::eyre::Result;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, EmailClient, EmailCooldownStoreError, UserId, UserStoreError},
+    utils::{
+        auth::TokenValidationError,
+        email_verification::{
+            email_verification_link, generate_email_verification_token,
+            validate_email_verification_token,
+        },
+    },
+};
+
+/// Marks the user's email as verified. This is where the emailed link points, so it is a GET.
+#[tracing::instrument(name = "Verify email", skip_all)]
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let claims = validate_email_verification_token(&query.token).map_err(|e| match e {
+        TokenValidationError::InvalidToken(_) => AuthAPIError::InvalidToken,
+        TokenValidationError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+    })?;
+    let user_id = UserId::parse(&claims.sub).map_err(|_| AuthAPIError::InvalidToken)?;
+
+    match state.user_store.write().await.set_verified(&user_id).await {
+        Ok(()) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(VerifyEmailResponse {
+            message: "Email verified".to_owned(),
+        }),
+    ))
+}
+
+/// Sends another verification link to an unverified account. The response is the same for
+/// every address so it can't be used to find out who has an account.
+#[tracing::instrument(name = "Resend verification email", skip_all)]
+pub async fn resend_verification_email(
+    State(state): State<AppState>,
+    Json(request): Json<ResendVerificationEmailRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // the cooldown runs for unknown addresses too, for the same reason
+    match state
+        .email_cooldown_store
+        .write()
+        .await
+        .start_cooldown(&email)
+        .await
+    {
+        Ok(()) => {}
+        Err(EmailCooldownStoreError::CooldownActive) => {
+            return Err(AuthAPIError::VerificationEmailCooldown)
+        }
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    let user = match state.user_store.read().await.get_user(&email).await {
+        Ok(user) if !user.verified => Some(user),
+        Ok(_) | Err(UserStoreError::UserNotFound) => None,
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+
+    // sending takes long enough to be measured, so it happens off the request path
+    if let Some(user) = user {
+        let email_client = state.email_client.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                send_verification_email(&user.id, &user.email, email_client.as_ref()).await
+            {
+                tracing::error!("failed to send verification email: {:?}", e);
+            }
+        });
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(VerifyEmailResponse {
+            message:
+                "If this email belongs to an unverified account, a verification link has been sent"
+                    .to_owned(),
+        }),
+    ))
+}
+
+pub(super) async fn send_verification_email(
+    user_id: &UserId,
+    email: &Email,
+    email_client: &(dyn EmailClient + Send + Sync),
+) -> Result<()> {
+    let token = generate_email_verification_token(user_id)?;
+    let link = email_verification_link(&token)?;
+    let content = format!("Use this link to verify your email address: {}", link);
+    email_client
+        .send_email(email, "Verify your email address", &content)
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: Secret<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationEmailRequest {
+    pub email: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyEmailResponse {
+    pub message: String,
+}
@@ -13,28 +13,87 @@
    See the License for the specific language governing permissions and
    limitations under the License.
 */
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use secrecy::Secret;
 use serde::Deserialize;
 
 use crate::{
     app_state::AppState,
-    domain::AuthAPIError,
-    utils::auth::{validate_token, TokenValidationError},
+    domain::{AuthAPIError, OAuthError},
+    utils::{
+        auth::{validate_token, TokenValidationError},
+        authorization::authenticate_introspecting_client,
+    },
 };
 
+use super::IntrospectionResponse;
+
+/// Answers with a bare status by default. With `introspect` set, it answers with what RFC 7662
+/// says about the token instead, and a token that isn't valid is `{"active": false}` rather than
+/// a 401. Services authenticate with their OAuth client credentials, in a Basic header or in the
+/// body, and are rejected if those are wrong. Introspection needs them, as on `/oauth/introspect`.
+#[tracing::instrument(name = "Verify token", skip_all)]
 pub async fn verify_token(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<VerifyTokenRequest>,
-) -> Result<StatusCode, AuthAPIError> {
-    match validate_token(&request.token, state.banned_token_store.clone()).await {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(TokenValidationError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
-        Err(TokenValidationError::InvalidToken(_)) => Err(AuthAPIError::InvalidToken),
+) -> Result<Response, AuthAPIError> {
+    let has_credentials = headers.contains_key(AUTHORIZATION) || request.client_id.is_some();
+    if request.introspect && !has_credentials {
+        return Err(AuthAPIError::InvalidClient);
+    }
+    if has_credentials {
+        authenticate_service(&headers, &request, &state).await?;
+    }
+
+    let result = validate_token(
+        &request.token,
+        state.banned_token_store.clone(),
+        state.session_store.clone(),
+    )
+    .await;
+
+    match (result, request.introspect) {
+        (Ok(claims), true) => Ok(Json(IntrospectionResponse::from(claims)).into_response()),
+        (Ok(_), false) => Ok(StatusCode::OK.into_response()),
+        (Err(TokenValidationError::InvalidToken(_)), true) => {
+            Ok(Json(IntrospectionResponse::inactive()).into_response())
+        }
+        (Err(TokenValidationError::InvalidToken(_)), false) => Err(AuthAPIError::InvalidToken),
+        (Err(TokenValidationError::UnexpectedError(e)), _) => Err(AuthAPIError::UnexpectedError(e)),
+    }
+}
+
+async fn authenticate_service(
+    headers: &HeaderMap,
+    request: &VerifyTokenRequest,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    match authenticate_introspecting_client(
+        headers,
+        request.client_id.clone(),
+        request.client_secret.clone(),
+        state,
+    )
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(OAuthError::InvalidClient) => Err(AuthAPIError::InvalidClient),
+        Err(OAuthError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => Err(AuthAPIError::InvalidCredentials),
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct VerifyTokenRequest {
     token: Secret<String>,
+    #[serde(default)]
+    introspect: bool,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
 }
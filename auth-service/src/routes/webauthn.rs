@@ -0,0 +1,398 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use webauthn_rs::prelude::{
+    DiscoverableKey, Passkey, PublicKeyCredential, RegisterPublicKeyCredential, WebauthnError,
+};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, CredentialId, Email, UserId, UserStoreError, WebauthnCeremony,
+        WebauthnChallenge, WebauthnChallengeStoreError, WebauthnCredential,
+        WebauthnCredentialStoreError,
+    },
+    utils::{
+        auth::current_user,
+        client::ClientInfo,
+        webauthn::{answered_challenge, WEBAUTHN},
+    },
+};
+
+use super::{
+    login::{handle_no_2fa, verify_password},
+    two_fa::require_second_factor,
+};
+
+const PUBLIC_KEY_CREDENTIAL_TYPE: &str = "public-key";
+
+/// Returns the options the browser passes to `navigator.credentials.create()` to register a
+/// passkey for the logged in user.
+#[tracing::instrument(name = "Start WebAuthn registration", skip_all)]
+pub async fn webauthn_register_start(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    let exclude_credentials = passkeys(&user.id, &state)
+        .await?
+        .iter()
+        .map(|passkey| passkey.cred_id().clone())
+        .collect();
+
+    let email = user.email.as_ref().expose_secret();
+    let (options, registration) = WEBAUTHN
+        .start_passkey_registration(*user.id.as_ref(), email, email, Some(exclude_credentials))
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    store_ceremony(
+        &options.public_key.challenge,
+        WebauthnCeremony::Registration {
+            user_id: user.id,
+            state: registration,
+        },
+        &state,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(options)))
+}
+
+/// Stores the passkey created from the registration options. A passkey logs the user in without
+/// their password or second factor, so both are asked for again first. Users with 2FA get a 206
+/// with a login attempt the first time, and repeat the request with its code.
+#[tracing::instrument(name = "Finish WebAuthn registration", skip_all)]
+pub async fn webauthn_register_finish(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<RegisterFinishRequest>,
+) -> Result<Response, AuthAPIError> {
+    let user = current_user(&jar, &state).await?;
+    verify_password(&user.email, request.password, &client, &state).await?;
+    if user.two_fa_method.is_enabled() {
+        if let Some(response) =
+            require_second_factor(&user, request.login_attempt_id, request.two_fa_code, &state)
+                .await?
+        {
+            return Ok(response);
+        }
+    }
+
+    let credential = request.credential;
+    if credential.type_ != PUBLIC_KEY_CREDENTIAL_TYPE {
+        return Err(AuthAPIError::InvalidCredentials);
+    }
+
+    let challenge = answered_challenge(&credential.response.client_data_json)
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    let registration = match take_challenge(&challenge, &state).await? {
+        WebauthnCeremony::Registration {
+            user_id,
+            state: registration,
+        } if user_id == user.id => registration,
+        _ => return Err(AuthAPIError::IncorrectCredentials),
+    };
+
+    // webauthn-rs rejects passkeys from the exclude list with a misleading error, so registered
+    // ones are caught first
+    let credential_id = CredentialId::parse(credential.raw_id.to_vec())
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    match state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credential(&credential_id)
+        .await
+    {
+        Ok(_) => return Err(AuthAPIError::PasskeyAlreadyRegistered),
+        Err(WebauthnCredentialStoreError::CredentialNotFound) => {}
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    let passkey = WEBAUTHN
+        .finish_passkey_registration(&credential, &registration)
+        .map_err(|e| {
+            tracing::debug!("Rejected registration: {:?}", e);
+            AuthAPIError::IncorrectCredentials
+        })?;
+    if passkey.cred_id().as_slice() != credential_id.as_ref() {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    let credential = WebauthnCredential {
+        id: credential_id,
+        user_id: user.id,
+        passkey,
+        created_at: Utc::now(),
+    };
+    match state
+        .webauthn_credential_store
+        .write()
+        .await
+        .add_credential(credential)
+        .await
+    {
+        Ok(()) => Ok(StatusCode::CREATED.into_response()),
+        Err(WebauthnCredentialStoreError::CredentialAlreadyExists) => {
+            Err(AuthAPIError::PasskeyAlreadyRegistered)
+        }
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+/// Returns the options the browser passes to `navigator.credentials.get()`. Without an email the
+/// authenticator offers whichever discoverable passkeys it holds for this site. An unknown email
+/// gets the same options, so the response doesn't reveal whether an account exists.
+#[tracing::instrument(name = "Start WebAuthn login", skip_all)]
+pub async fn webauthn_login_start(
+    State(state): State<AppState>,
+    Json(request): Json<WebauthnLoginStartRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = match request.email {
+        Some(email) => {
+            let email = Email::parse(email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+            match state.user_store.read().await.get_user(&email).await {
+                Ok(user) => Some(user.id),
+                Err(UserStoreError::UnexpectedError(e)) => {
+                    return Err(AuthAPIError::UnexpectedError(e))
+                }
+                Err(_) => None,
+            }
+        }
+        None => None,
+    };
+
+    let passkeys = match &user_id {
+        Some(user_id) => passkeys(user_id, &state).await?,
+        None => vec![],
+    };
+    let (options, ceremony) = match user_id {
+        Some(user_id) if !passkeys.is_empty() => {
+            let (options, authentication) = WEBAUTHN
+                .start_passkey_authentication(&passkeys)
+                .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+            let ceremony = WebauthnCeremony::Authentication {
+                user_id,
+                state: authentication,
+            };
+            (options, ceremony)
+        }
+        _ => {
+            let (options, authentication) = WEBAUTHN
+                .start_discoverable_authentication()
+                .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+            let ceremony = WebauthnCeremony::DiscoverableAuthentication {
+                user_id,
+                state: authentication,
+            };
+            (options, ceremony)
+        }
+    };
+    store_ceremony(&options.public_key.challenge, ceremony, &state).await?;
+
+    Ok((StatusCode::OK, Json(options)))
+}
+
+/// Logs the user in with a passkey. The authenticator verified the user with a PIN or biometric,
+/// so no further 2FA step is needed.
+#[tracing::instrument(name = "Finish WebAuthn login", skip_all)]
+pub async fn webauthn_login_finish(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(request): Json<PublicKeyCredential>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let user_id = match verify_login(&state, request).await {
+        Ok(user_id) => user_id,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(user) if !user.verified => return (jar, Err(AuthAPIError::EmailNotVerified)),
+        Ok(user) if user.purge_at.is_some() => {
+            return (jar, Err(AuthAPIError::AccountPendingDeletion))
+        }
+        Ok(_) => {}
+        Err(UserStoreError::UnexpectedError(e)) => {
+            return (jar, Err(AuthAPIError::UnexpectedError(e)))
+        }
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    }
+
+    handle_no_2fa(&user_id, client, &state, jar).await
+}
+
+async fn verify_login(
+    state: &AppState,
+    credential: PublicKeyCredential,
+) -> Result<UserId, AuthAPIError> {
+    if credential.type_ != PUBLIC_KEY_CREDENTIAL_TYPE {
+        return Err(AuthAPIError::InvalidCredentials);
+    }
+
+    let credential_id = CredentialId::parse(credential.raw_id.to_vec())
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let challenge = answered_challenge(&credential.response.client_data_json)
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    let ceremony = take_challenge(&challenge, state).await?;
+
+    let stored = match state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credential(&credential_id)
+        .await
+    {
+        Ok(stored) => stored,
+        Err(WebauthnCredentialStoreError::UnexpectedError(e)) => {
+            return Err(AuthAPIError::UnexpectedError(e))
+        }
+        Err(_) => return Err(AuthAPIError::IncorrectCredentials),
+    };
+
+    // the passkey has to belong to the account the login started for, and to the account the
+    // authenticator says it belongs to
+    let result = match ceremony {
+        WebauthnCeremony::Authentication {
+            user_id,
+            state: authentication,
+        } if user_id == stored.user_id => {
+            WEBAUTHN.finish_passkey_authentication(&credential, &authentication)
+        }
+        WebauthnCeremony::DiscoverableAuthentication {
+            user_id,
+            state: authentication,
+        } if user_id.is_none_or(|user_id| user_id == stored.user_id) => {
+            let (user_handle, _) = WEBAUTHN
+                .identify_discoverable_authentication(&credential)
+                .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+            if user_handle != *stored.user_id.as_ref() {
+                return Err(AuthAPIError::IncorrectCredentials);
+            }
+            WEBAUTHN.finish_discoverable_authentication(
+                &credential,
+                authentication,
+                &[DiscoverableKey::from(&stored.passkey)],
+            )
+        }
+        _ => return Err(AuthAPIError::IncorrectCredentials),
+    };
+
+    let result = match result {
+        Ok(result) => result,
+        Err(WebauthnError::CredentialPossibleCompromise) => {
+            tracing::warn!(
+                user_id = %stored.user_id,
+                credential_id = stored.id.to_base64url(),
+                "Passkey sign count did not increase, the authenticator may have been cloned"
+            );
+            return Err(AuthAPIError::IncorrectCredentials);
+        }
+        Err(e) => {
+            tracing::debug!("Rejected assertion: {:?}", e);
+            return Err(AuthAPIError::IncorrectCredentials);
+        }
+    };
+
+    let mut passkey = stored.passkey;
+    if passkey.update_credential(&result) == Some(true) {
+        state
+            .webauthn_credential_store
+            .write()
+            .await
+            .update_passkey(&stored.id, passkey)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+
+    Ok(stored.user_id)
+}
+
+async fn store_ceremony(
+    challenge: &[u8],
+    ceremony: WebauthnCeremony,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let challenge =
+        WebauthnChallenge::from_bytes(challenge).map_err(AuthAPIError::UnexpectedError)?;
+    state
+        .webauthn_challenge_store
+        .write()
+        .await
+        .add_challenge(challenge, ceremony)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))
+}
+
+async fn take_challenge(
+    challenge: &WebauthnChallenge,
+    state: &AppState,
+) -> Result<WebauthnCeremony, AuthAPIError> {
+    match state
+        .webauthn_challenge_store
+        .write()
+        .await
+        .take_challenge(challenge)
+        .await
+    {
+        Ok(ceremony) => Ok(ceremony),
+        Err(WebauthnChallengeStoreError::ChallengeNotFound) => {
+            Err(AuthAPIError::IncorrectCredentials)
+        }
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+async fn passkeys(user_id: &UserId, state: &AppState) -> Result<Vec<Passkey>, AuthAPIError> {
+    let credentials = state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credentials(user_id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(credentials
+        .into_iter()
+        .map(|credential| credential.passkey)
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterFinishRequest {
+    pub credential: RegisterPublicKeyCredential,
+    pub password: Secret<String>,
+    /// Both 2FA fields are only needed when 2FA is enabled, and come from a previous 206.
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(rename = "2FACode", default)]
+    pub two_fa_code: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebauthnLoginStartRequest {
+    #[serde(default)]
+    pub email: Option<Secret<String>>,
+}
@@ -0,0 +1,98 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use std::time::Duration;
+
+use crate::{app_state::AppState, domain::User};
+
+/// How often the purge job looks for accounts whose grace period is over.
+pub const ACCOUNT_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Runs `purge_deleted_accounts` every `ACCOUNT_PURGE_INTERVAL`, for as long as the service runs.
+pub async fn run_account_purge(state: AppState) {
+    let mut interval = tokio::time::interval(ACCOUNT_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_deleted_accounts(&state, Utc::now()).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("purged {} deleted accounts", count),
+            Err(e) => tracing::error!("failed to purge deleted accounts: {:?}", e),
+        }
+    }
+}
+
+/// Deletes the accounts whose grace period was over by `now`, along with everything else kept
+/// about them, and returns how many there were.
+#[tracing::instrument(name = "Purge deleted accounts", skip_all)]
+pub async fn purge_deleted_accounts(state: &AppState, now: DateTime<Utc>) -> Result<usize> {
+    let users = state.user_store.write().await.delete_due_users(now).await?;
+
+    // the accounts are gone already, so one failure shouldn't keep the others' data around
+    for user in &users {
+        if let Err(e) = purge_user_data(user, state).await {
+            tracing::error!("failed to purge data of user {}: {:?}", user.id, e);
+        }
+    }
+
+    Ok(users.len())
+}
+
+async fn purge_user_data(user: &User, state: &AppState) -> Result<()> {
+    state
+        .session_store
+        .write()
+        .await
+        .remove_all_sessions(&user.id)
+        .await?;
+
+    state
+        .two_fa_code_store
+        .write()
+        .await
+        .remove_codes(&user.email)
+        .await?;
+
+    state
+        .recovery_code_store
+        .write()
+        .await
+        .replace_codes(&user.id, Vec::new())
+        .await?;
+
+    state
+        .webauthn_credential_store
+        .write()
+        .await
+        .remove_credentials(&user.id)
+        .await?;
+
+    state
+        .external_identity_store
+        .write()
+        .await
+        .remove_identities(&user.id)
+        .await?;
+
+    state
+        .permission_store
+        .write()
+        .await
+        .remove_grants(&user.id)
+        .await?;
+
+    Ok(())
+}
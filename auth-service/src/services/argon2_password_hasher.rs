@@ -0,0 +1,247 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash,
+    PasswordHasher as Argon2Hasher, Version,
+};
+use color_eyre::eyre::{ensure, Context, Result};
+use pbkdf2::Pbkdf2;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    domain::PasswordHasher,
+    utils::constants::{
+        DEFAULT_PASSWORD_HASH_ITERATIONS, DEFAULT_PASSWORD_HASH_MEMORY_KIB,
+        DEFAULT_PASSWORD_HASH_PARALLELISM,
+    },
+};
+
+/// Hashes with Argon2id. Hashing runs on the blocking thread pool, as it is slow on purpose.
+///
+/// Hashes imported from the old system, bcrypt or PBKDF2 in PHC format, still verify, but always
+/// need a rehash.
+#[derive(Debug, Clone)]
+pub struct Argon2PasswordHasher {
+    params: Params,
+}
+
+impl Argon2PasswordHasher {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .wrap_err("invalid Argon2 parameters")?;
+        Ok(Self { params })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl Default for Argon2PasswordHasher {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_PASSWORD_HASH_MEMORY_KIB,
+            DEFAULT_PASSWORD_HASH_ITERATIONS,
+            DEFAULT_PASSWORD_HASH_PARALLELISM,
+        )
+        .expect("default Argon2 parameters should be valid")
+    }
+}
+
+#[async_trait::async_trait]
+impl PasswordHasher for Argon2PasswordHasher {
+    #[tracing::instrument(name = "Computing password hash", skip_all)]
+    async fn hash(&self, password: Secret<String>) -> Result<Secret<String>> {
+        let argon2 = self.argon2();
+        let current_span: tracing::Span = tracing::Span::current();
+        let result = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| {
+                let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
+                let password_hash = argon2
+                    .hash_password(password.expose_secret().as_bytes(), &salt)?
+                    .to_string();
+
+                Ok(Secret::new(password_hash))
+            })
+        })
+        .await;
+
+        result?
+    }
+
+    #[tracing::instrument(name = "Verify password hash", skip_all)]
+    async fn verify(&self, hash: Secret<String>, candidate: Secret<String>) -> Result<()> {
+        // the parameters a hash was made with are read from the hash itself
+        let argon2 = self.argon2();
+        let current_span: tracing::Span = tracing::Span::current();
+        let result = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| {
+                if is_bcrypt(&hash) {
+                    let matches = bcrypt::verify(candidate.expose_secret(), hash.expose_secret())?;
+                    ensure!(matches, "failed to verify password hash");
+                    return Ok(());
+                }
+
+                let expected_password_hash: PasswordHash<'_> =
+                    PasswordHash::new(hash.expose_secret())?;
+
+                expected_password_hash
+                    .verify_password(&[&argon2, &Pbkdf2], candidate.expose_secret())
+                    .wrap_err("failed to verify password hash")
+            })
+        })
+        .await;
+
+        result?
+    }
+
+    fn needs_rehash(&self, hash: &Secret<String>) -> bool {
+        let Ok(hash) = PasswordHash::new(hash.expose_secret()) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&hash) else {
+            return true;
+        };
+
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+
+    fn current_prefix(&self) -> String {
+        format!(
+            "${}$v={}$m={},t={},p={}$",
+            Algorithm::Argon2id.ident(),
+            Version::V0x13 as u32,
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost()
+        )
+    }
+}
+
+/// bcrypt hashes predate the PHC string format, and are told apart by their prefix instead.
+fn is_bcrypt(hash: &Secret<String>) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.expose_secret().starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_only_the_hashed_password() {
+        let hasher = Argon2PasswordHasher::default();
+        let hash = hasher
+            .hash(Secret::new("$3curedZ".to_owned()))
+            .await
+            .unwrap();
+        assert_ne!(hash.expose_secret(), "$3curedZ");
+
+        let result = hasher
+            .verify(hash.clone(), Secret::new("$3curedZ".to_owned()))
+            .await;
+        assert!(result.is_ok());
+
+        let result = hasher
+            .verify(hash, Secret::new("incorrectPassword".to_owned()))
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn hash_with_configured_parameters() {
+        let hasher = Argon2PasswordHasher::new(8192, 3, 2).unwrap();
+        let hash = hasher
+            .hash(Secret::new("$3curedZ".to_owned()))
+            .await
+            .unwrap();
+        assert!(hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=8192,t=3,p=2$"));
+
+        // hashes made with other parameters still verify
+        let result = Argon2PasswordHasher::default()
+            .verify(hash, Secret::new("$3curedZ".to_owned()))
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_legacy_hashes() {
+        let hasher = Argon2PasswordHasher::default();
+        let bcrypt_hash = bcrypt::hash("$3curedZ", 4).unwrap();
+        let pbkdf2_hash = Pbkdf2
+            .hash_password(b"$3curedZ", &SaltString::generate(&mut rand::thread_rng()))
+            .unwrap()
+            .to_string();
+
+        for hash in [bcrypt_hash, pbkdf2_hash] {
+            let result = hasher
+                .verify(
+                    Secret::new(hash.clone()),
+                    Secret::new("$3curedZ".to_owned()),
+                )
+                .await;
+            assert!(result.is_ok(), "rejected {}", hash);
+
+            let result = hasher
+                .verify(
+                    Secret::new(hash.clone()),
+                    Secret::new("incorrectPassword".to_owned()),
+                )
+                .await;
+            assert!(result.is_err(), "accepted {}", hash);
+
+            assert!(hasher.needs_rehash(&Secret::new(hash)));
+        }
+    }
+
+    #[tokio::test]
+    async fn rehash_when_parameters_change() {
+        let hasher = Argon2PasswordHasher::default();
+        let hash = hasher
+            .hash(Secret::new("$3curedZ".to_owned()))
+            .await
+            .unwrap();
+        assert!(!hasher.needs_rehash(&hash));
+
+        let stronger_hasher = Argon2PasswordHasher::new(19456, 2, 1).unwrap();
+        assert!(stronger_hasher.needs_rehash(&hash));
+
+        // outdated hashes can be told apart by their prefix too
+        assert!(hash.expose_secret().starts_with(&hasher.current_prefix()));
+        assert!(!hash
+            .expose_secret()
+            .starts_with(&stronger_hasher.current_prefix()));
+
+        // argon2i and argon2d hashes are upgraded to argon2id
+        let argon2i_hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, hasher.params.clone())
+            .hash_password(b"$3curedZ", &SaltString::generate(&mut rand::thread_rng()))
+            .unwrap()
+            .to_string();
+        assert!(hasher.needs_rehash(&Secret::new(argon2i_hash)));
+    }
+
+    #[test]
+    fn reject_invalid_parameters() {
+        assert!(Argon2PasswordHasher::new(15000, 0, 1).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::{
+    domain::{
+        data_stores::{AuthorizationCodeStore, AuthorizationCodeStoreError},
+        AuthorizationCode, AuthorizationGrant,
+    },
+    utils::authorization::AUTHORIZATION_CODE_TTL_SECONDS,
+};
+
+#[derive(Default)]
+pub struct HashmapAuthorizationCodeStore {
+    codes: HashMap<AuthorizationCode, (AuthorizationGrant, DateTime<Utc>)>,
+}
+
+#[async_trait::async_trait]
+impl AuthorizationCodeStore for HashmapAuthorizationCodeStore {
+    async fn add_code(
+        &mut self,
+        code: AuthorizationCode,
+        grant: AuthorizationGrant,
+    ) -> Result<(), AuthorizationCodeStoreError> {
+        let expires_at = Utc::now() + Duration::seconds(AUTHORIZATION_CODE_TTL_SECONDS);
+        self.codes.insert(code, (grant, expires_at));
+        Ok(())
+    }
+
+    async fn take_code(
+        &mut self,
+        code: &AuthorizationCode,
+    ) -> Result<AuthorizationGrant, AuthorizationCodeStoreError> {
+        match self.codes.remove(code) {
+            Some((grant, expires_at)) if expires_at > Utc::now() => Ok(grant),
+            _ => Err(AuthorizationCodeStoreError::CodeNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{ClientId, PkceVerifier, Scopes, SessionId, UserId};
+
+    fn grant() -> AuthorizationGrant {
+        AuthorizationGrant {
+            client_id: ClientId::default(),
+            redirect_uri: "https://app.example.com/callback".to_owned(),
+            user_id: UserId::default(),
+            session_id: SessionId::default(),
+            scopes: Scopes::parse("openid").unwrap(),
+            code_challenge: PkceVerifier::default().challenge(),
+            nonce: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_take_code_once() {
+        let mut store = HashmapAuthorizationCodeStore::default();
+        let code = AuthorizationCode::default();
+        let grant = grant();
+
+        let result = store.add_code(code.clone(), grant.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(store.take_code(&code).await, Ok(grant));
+        assert_eq!(
+            store.take_code(&code).await,
+            Err(AuthorizationCodeStoreError::CodeNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_expired_code() {
+        let mut store = HashmapAuthorizationCodeStore::default();
+        let code = AuthorizationCode::default();
+        store
+            .codes
+            .insert(code.clone(), (grant(), Utc::now() - Duration::seconds(1)));
+
+        assert_eq!(
+            store.take_code(&code).await,
+            Err(AuthorizationCodeStoreError::CodeNotFound)
+        );
+    }
+}
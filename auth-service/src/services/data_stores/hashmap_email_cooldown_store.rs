@@ -0,0 +1,85 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::{
+    domain::{
+        data_stores::{EmailCooldownStore, EmailCooldownStoreError},
+        Email,
+    },
+    utils::email_verification::VERIFICATION_EMAIL_COOLDOWN_SECONDS,
+};
+
+#[derive(Default)]
+pub struct HashmapEmailCooldownStore {
+    cooldowns: HashMap<Email, DateTime<Utc>>,
+}
+
+#[async_trait::async_trait]
+impl EmailCooldownStore for HashmapEmailCooldownStore {
+    async fn start_cooldown(&mut self, email: &Email) -> Result<(), EmailCooldownStoreError> {
+        let now = Utc::now();
+        if let Some(ends_at) = self.cooldowns.get(email) {
+            if *ends_at > now {
+                return Err(EmailCooldownStoreError::CooldownActive);
+            }
+        }
+
+        let ends_at = now + Duration::seconds(VERIFICATION_EMAIL_COOLDOWN_SECONDS);
+        self.cooldowns.insert(email.clone(), ends_at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email(address: &str) -> Email {
+        Email::parse(Secret::new(address.to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_start_cooldown_once_per_address() {
+        let mut store = HashmapEmailCooldownStore::default();
+
+        assert_eq!(
+            store.start_cooldown(&email("ibrahim@umbrella.corp")).await,
+            Ok(())
+        );
+        assert_eq!(
+            store.start_cooldown(&email("ibrahim@umbrella.corp")).await,
+            Err(EmailCooldownStoreError::CooldownActive)
+        );
+        assert_eq!(
+            store.start_cooldown(&email("i@umbrella.corp")).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restart_expired_cooldown() {
+        let mut store = HashmapEmailCooldownStore::default();
+        let email = email("ibrahim@umbrella.corp");
+        store
+            .cooldowns
+            .insert(email.clone(), Utc::now() - Duration::seconds(1));
+
+        assert_eq!(store.start_cooldown(&email).await, Ok(()));
+    }
+}
@@ -0,0 +1,136 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_stores::{ExternalIdentityStore, ExternalIdentityStoreError},
+    ExternalIdentity, UserId,
+};
+
+/// Linked identities keyed by provider and subject.
+#[derive(Default)]
+pub struct HashmapExternalIdentityStore {
+    identities: HashMap<(String, String), UserId>,
+}
+
+#[async_trait::async_trait]
+impl ExternalIdentityStore for HashmapExternalIdentityStore {
+    async fn add_identity(
+        &mut self,
+        identity: ExternalIdentity,
+    ) -> Result<(), ExternalIdentityStoreError> {
+        let key = (identity.provider, identity.subject);
+        if self.identities.contains_key(&key) {
+            return Err(ExternalIdentityStoreError::IdentityAlreadyLinked);
+        }
+        self.identities.insert(key, identity.user_id);
+        Ok(())
+    }
+
+    async fn get_user_id(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UserId, ExternalIdentityStoreError> {
+        self.identities
+            .get(&(provider.to_owned(), subject.to_owned()))
+            .copied()
+            .ok_or(ExternalIdentityStoreError::IdentityNotFound)
+    }
+
+    async fn remove_identities(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), ExternalIdentityStoreError> {
+        self.identities.retain(|_, linked| linked != user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(provider: &str, subject: &str, user_id: UserId) -> ExternalIdentity {
+        ExternalIdentity {
+            provider: provider.to_owned(),
+            subject: subject.to_owned(),
+            user_id,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_identity() {
+        let mut store = HashmapExternalIdentityStore::default();
+        let user_id = UserId::default();
+
+        let result = store.add_identity(identity("google", "123", user_id)).await;
+        assert!(result.is_ok());
+        assert_eq!(store.get_user_id("google", "123").await, Ok(user_id));
+        assert_eq!(
+            store.get_user_id("okta", "123").await,
+            Err(ExternalIdentityStoreError::IdentityNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_identity_linked_twice() {
+        let mut store = HashmapExternalIdentityStore::default();
+        store
+            .add_identity(identity("google", "123", UserId::default()))
+            .await
+            .unwrap();
+
+        let result = store
+            .add_identity(identity("google", "123", UserId::default()))
+            .await;
+        assert_eq!(
+            result,
+            Err(ExternalIdentityStoreError::IdentityAlreadyLinked)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_identities() {
+        let mut store = HashmapExternalIdentityStore::default();
+        let user_id = UserId::default();
+        let other_user_id = UserId::default();
+        store
+            .add_identity(identity("google", "123", user_id))
+            .await
+            .unwrap();
+        store
+            .add_identity(identity("okta", "456", user_id))
+            .await
+            .unwrap();
+        store
+            .add_identity(identity("google", "789", other_user_id))
+            .await
+            .unwrap();
+
+        let result = store.remove_identities(&user_id).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            store.get_user_id("google", "123").await,
+            Err(ExternalIdentityStoreError::IdentityNotFound)
+        );
+        assert_eq!(
+            store.get_user_id("okta", "456").await,
+            Err(ExternalIdentityStoreError::IdentityNotFound)
+        );
+        assert_eq!(store.get_user_id("google", "789").await, Ok(other_user_id));
+    }
+}
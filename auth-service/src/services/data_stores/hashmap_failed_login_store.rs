@@ -0,0 +1,187 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+
+use crate::{
+    domain::data_stores::{FailedLoginKey, FailedLoginStore, FailedLoginStoreError, FailedLogins},
+    utils::login_throttle::FAILED_LOGIN_TTL_SECONDS,
+};
+
+#[derive(Default)]
+pub struct HashmapFailedLoginStore {
+    failures: HashMap<FailedLoginKey, FailedLogins>,
+}
+
+impl HashmapFailedLoginStore {
+    /// The key's record, or an empty one once it has expired.
+    fn current(&self, key: &FailedLoginKey) -> FailedLogins {
+        let expires_after = Utc::now() - Duration::seconds(FAILED_LOGIN_TTL_SECONDS);
+        match self.failures.get(key) {
+            Some(failures)
+                if failures
+                    .last_failure_at
+                    .is_some_and(|last_failure_at| last_failure_at > expires_after) =>
+            {
+                *failures
+            }
+            _ => FailedLogins::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FailedLoginStore for HashmapFailedLoginStore {
+    async fn get_failures(
+        &self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError> {
+        Ok(self.current(key))
+    }
+
+    async fn reserve_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError> {
+        let previous = self.current(key);
+        let failures = FailedLogins {
+            count: previous.count + 1,
+            last_failure_at: Some(Utc::now()),
+        };
+        self.failures.insert(key.clone(), failures);
+        Ok(previous)
+    }
+
+    async fn refund_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+        previous: &FailedLogins,
+    ) -> Result<(), FailedLoginStoreError> {
+        let current = self.current(key);
+        if current.count == 0 {
+            return Ok(());
+        }
+
+        let last_failure_at = if current.count == previous.count + 1 {
+            previous.last_failure_at
+        } else {
+            current.last_failure_at
+        };
+        match last_failure_at {
+            Some(_) => {
+                self.failures.insert(
+                    key.clone(),
+                    FailedLogins {
+                        count: current.count - 1,
+                        last_failure_at,
+                    },
+                );
+            }
+            None => {
+                self.failures.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn clear_failures(&mut self, key: &FailedLoginKey) -> Result<(), FailedLoginStoreError> {
+        self.failures.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Email;
+    use secrecy::Secret;
+
+    fn account() -> FailedLoginKey {
+        FailedLoginKey::Account(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reserve_and_clear_failures() {
+        let mut store = HashmapFailedLoginStore::default();
+        let key = account();
+        let ip = FailedLoginKey::Ip("203.0.113.7".to_owned());
+
+        assert_eq!(store.get_failures(&key).await.unwrap().count, 0);
+        assert_eq!(
+            store.reserve_attempt(&key).await,
+            Ok(FailedLogins::default())
+        );
+        let previous = store.reserve_attempt(&key).await.unwrap();
+        assert_eq!(previous.count, 1);
+        let failures = store.get_failures(&key).await.unwrap();
+        assert_eq!(failures.count, 2);
+        assert!(failures.last_failure_at.is_some());
+        assert_eq!(store.get_failures(&ip).await.unwrap().count, 0);
+
+        assert!(store.clear_failures(&key).await.is_ok());
+        assert_eq!(store.get_failures(&key).await, Ok(FailedLogins::default()));
+    }
+
+    #[tokio::test]
+    async fn test_refund_attempt() {
+        let mut store = HashmapFailedLoginStore::default();
+        let key = account();
+        let earlier = FailedLogins {
+            count: 4,
+            last_failure_at: Some(Utc::now() - Duration::seconds(30)),
+        };
+        store.failures.insert(key.clone(), earlier);
+
+        // a refund puts the record back as it was
+        let previous = store.reserve_attempt(&key).await.unwrap();
+        assert_eq!(previous, earlier);
+        store.refund_attempt(&key, &previous).await.unwrap();
+        assert_eq!(store.get_failures(&key).await, Ok(earlier));
+
+        // unless another attempt was counted in the meantime, which keeps its time
+        let first = store.reserve_attempt(&key).await.unwrap();
+        store.reserve_attempt(&key).await.unwrap();
+        store.refund_attempt(&key, &first).await.unwrap();
+        let failures = store.get_failures(&key).await.unwrap();
+        assert_eq!(failures.count, 5);
+        assert_ne!(failures.last_failure_at, earlier.last_failure_at);
+
+        // refunding the only attempt on a key leaves nothing behind
+        let ip = FailedLoginKey::Ip("203.0.113.7".to_owned());
+        let previous = store.reserve_attempt(&ip).await.unwrap();
+        store.refund_attempt(&ip, &previous).await.unwrap();
+        assert_eq!(store.get_failures(&ip).await, Ok(FailedLogins::default()));
+    }
+
+    #[tokio::test]
+    async fn test_forget_expired_failures() {
+        let mut store = HashmapFailedLoginStore::default();
+        let key = account();
+        store.failures.insert(
+            key.clone(),
+            FailedLogins {
+                count: 5,
+                last_failure_at: Some(Utc::now() - Duration::seconds(FAILED_LOGIN_TTL_SECONDS + 1)),
+            },
+        );
+
+        assert_eq!(store.get_failures(&key).await.unwrap().count, 0);
+        assert_eq!(store.reserve_attempt(&key).await.unwrap().count, 0);
+        assert_eq!(store.get_failures(&key).await.unwrap().count, 1);
+    }
+}
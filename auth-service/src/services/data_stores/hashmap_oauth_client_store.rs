@@ -0,0 +1,94 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_stores::{OAuthClientStore, OAuthClientStoreError},
+    ClientId, OAuthClient,
+};
+
+#[derive(Default)]
+pub struct HashmapOAuthClientStore {
+    clients: HashMap<ClientId, OAuthClient>,
+}
+
+#[async_trait::async_trait]
+impl OAuthClientStore for HashmapOAuthClientStore {
+    async fn add_client(&mut self, client: OAuthClient) -> Result<(), OAuthClientStoreError> {
+        if self.clients.contains_key(&client.id) {
+            return Err(OAuthClientStoreError::ClientAlreadyExists);
+        }
+        self.clients.insert(client.id, client);
+        Ok(())
+    }
+
+    async fn get_client(&self, id: &ClientId) -> Result<OAuthClient, OAuthClientStoreError> {
+        self.clients
+            .get(id)
+            .cloned()
+            .ok_or(OAuthClientStoreError::ClientNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{GrantType, Scopes};
+
+    fn client() -> OAuthClient {
+        let (client, _) = OAuthClient::new(
+            "App".to_owned(),
+            vec![],
+            vec![GrantType::ClientCredentials],
+            Scopes::default(),
+            true,
+        )
+        .unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_client() {
+        let mut store = HashmapOAuthClientStore::default();
+        let client = client();
+
+        let result = store.add_client(client.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(store.get_client(&client.id).await, Ok(client));
+    }
+
+    #[tokio::test]
+    async fn test_add_duplicate_client() {
+        let mut store = HashmapOAuthClientStore::default();
+        let client = client();
+
+        let result = store.add_client(client.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            store.add_client(client).await,
+            Err(OAuthClientStoreError::ClientAlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_unknown_client() {
+        let store = HashmapOAuthClientStore::default();
+        assert_eq!(
+            store.get_client(&ClientId::default()).await,
+            Err(OAuthClientStoreError::ClientNotFound)
+        );
+    }
+}
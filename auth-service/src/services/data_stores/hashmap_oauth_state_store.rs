@@ -0,0 +1,88 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::{
+    domain::{
+        data_stores::{OAuthStateStore, OAuthStateStoreError},
+        OAuthFlow, OAuthState,
+    },
+    services::oidc_client::OAUTH_STATE_TTL_SECONDS,
+};
+
+#[derive(Default)]
+pub struct HashmapOAuthStateStore {
+    flows: HashMap<OAuthState, (OAuthFlow, DateTime<Utc>)>,
+}
+
+#[async_trait::async_trait]
+impl OAuthStateStore for HashmapOAuthStateStore {
+    async fn add_flow(
+        &mut self,
+        state: OAuthState,
+        flow: OAuthFlow,
+    ) -> Result<(), OAuthStateStoreError> {
+        let expires_at = Utc::now() + Duration::seconds(OAUTH_STATE_TTL_SECONDS);
+        self.flows.insert(state, (flow, expires_at));
+        Ok(())
+    }
+
+    async fn take_flow(&mut self, state: &OAuthState) -> Result<OAuthFlow, OAuthStateStoreError> {
+        match self.flows.remove(state) {
+            Some((flow, expires_at)) if expires_at > Utc::now() => Ok(flow),
+            _ => Err(OAuthStateStoreError::StateNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_take_flow_once() {
+        let mut store = HashmapOAuthStateStore::default();
+        let state = OAuthState::default();
+        let flow = OAuthFlow::new("google".to_owned());
+
+        let result = store.add_flow(state.clone(), flow.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(store.take_flow(&state).await, Ok(flow));
+        assert_eq!(
+            store.take_flow(&state).await,
+            Err(OAuthStateStoreError::StateNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_expired_flow() {
+        let mut store = HashmapOAuthStateStore::default();
+        let state = OAuthState::default();
+        store.flows.insert(
+            state.clone(),
+            (
+                OAuthFlow::new("google".to_owned()),
+                Utc::now() - Duration::seconds(1),
+            ),
+        );
+
+        assert_eq!(
+            store.take_flow(&state).await,
+            Err(OAuthStateStoreError::StateNotFound)
+        );
+    }
+}
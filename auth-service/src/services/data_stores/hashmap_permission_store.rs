@@ -0,0 +1,163 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_stores::{PermissionStore, PermissionStoreError},
+    Grants, Permission, Role, UserId,
+};
+
+#[derive(Default)]
+pub struct HashmapPermissionStore {
+    grants: HashMap<UserId, Grants>,
+}
+
+#[async_trait::async_trait]
+impl PermissionStore for HashmapPermissionStore {
+    async fn grant_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError> {
+        self.grants
+            .entry(*user_id)
+            .or_default()
+            .roles
+            .insert(role.clone());
+        Ok(())
+    }
+
+    async fn revoke_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError> {
+        let removed = self
+            .grants
+            .get_mut(user_id)
+            .is_some_and(|grants| grants.roles.remove(role));
+        if !removed {
+            return Err(PermissionStoreError::GrantNotFound);
+        }
+        Ok(())
+    }
+
+    async fn grant_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError> {
+        self.grants
+            .entry(*user_id)
+            .or_default()
+            .permissions
+            .insert(permission.clone());
+        Ok(())
+    }
+
+    async fn revoke_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError> {
+        let removed = self
+            .grants
+            .get_mut(user_id)
+            .is_some_and(|grants| grants.permissions.remove(permission));
+        if !removed {
+            return Err(PermissionStoreError::GrantNotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_grants(&self, user_id: &UserId) -> Result<Grants, PermissionStoreError> {
+        Ok(self.grants.get(user_id).cloned().unwrap_or_default())
+    }
+
+    async fn remove_grants(&mut self, user_id: &UserId) -> Result<(), PermissionStoreError> {
+        self.grants.remove(user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grant_and_revoke_role() {
+        let mut store = HashmapPermissionStore::default();
+        let user_id = UserId::default();
+        let role = Role::parse("editor").unwrap();
+
+        store.grant_role(&user_id, &role).await.unwrap();
+        // granting twice is fine
+        store.grant_role(&user_id, &role).await.unwrap();
+        let grants = store.get_grants(&user_id).await.unwrap();
+        assert_eq!(grants.roles.len(), 1);
+        assert!(grants.roles.contains(&role));
+
+        assert_eq!(store.revoke_role(&user_id, &role).await, Ok(()));
+        assert_eq!(
+            store.revoke_role(&user_id, &role).await,
+            Err(PermissionStoreError::GrantNotFound)
+        );
+        assert_eq!(store.get_grants(&user_id).await, Ok(Grants::default()));
+    }
+
+    #[tokio::test]
+    async fn test_grant_and_revoke_permission() {
+        let mut store = HashmapPermissionStore::default();
+        let user_id = UserId::default();
+        let permission = Permission::parse("articles:write").unwrap();
+
+        assert_eq!(
+            store.revoke_permission(&user_id, &permission).await,
+            Err(PermissionStoreError::GrantNotFound)
+        );
+        store.grant_permission(&user_id, &permission).await.unwrap();
+        let grants = store.get_grants(&user_id).await.unwrap();
+        assert!(grants.permissions.contains(&permission));
+        assert!(grants.roles.is_empty());
+
+        assert_eq!(store.revoke_permission(&user_id, &permission).await, Ok(()));
+        assert!(store
+            .get_grants(&user_id)
+            .await
+            .unwrap()
+            .permissions
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_grants() {
+        let mut store = HashmapPermissionStore::default();
+        let user_id = UserId::default();
+        let other_user_id = UserId::default();
+        let role = Role::parse("editor").unwrap();
+        store.grant_role(&user_id, &role).await.unwrap();
+        store.grant_role(&other_user_id, &role).await.unwrap();
+
+        store.remove_grants(&user_id).await.unwrap();
+        assert_eq!(store.get_grants(&user_id).await, Ok(Grants::default()));
+        assert!(store
+            .get_grants(&other_user_id)
+            .await
+            .unwrap()
+            .roles
+            .contains(&role));
+    }
+}
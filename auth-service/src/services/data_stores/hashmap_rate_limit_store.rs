@@ -0,0 +1,110 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::domain::data_stores::{RateLimit, RateLimitStore, RateLimitStoreError};
+
+/// Token buckets kept in memory. Each replica counts on its own, so the limits only hold for the
+/// whole service when it runs as a single instance.
+#[derive(Default)]
+pub struct HashmapRateLimitStore {
+    buckets: HashMap<String, Bucket>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for HashmapRateLimitStore {
+    async fn take_token(
+        &mut self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<(), RateLimitStoreError> {
+        let now = Utc::now();
+        let capacity = limit.requests as f64;
+        let bucket = self.buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+
+        let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_rate()).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = ((1.0 - bucket.tokens) / limit.refill_rate()).ceil() as u64;
+            return Err(RateLimitStoreError::LimitExceeded(wait.max(1)));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    const LIMIT: RateLimit = RateLimit {
+        requests: 3,
+        per_seconds: 30,
+    };
+
+    #[tokio::test]
+    async fn test_take_tokens_until_bucket_is_empty() {
+        let mut store = HashmapRateLimitStore::default();
+        for _ in 0..LIMIT.requests {
+            assert!(store.take_token("login:ip:127.0.0.1", &LIMIT).await.is_ok());
+        }
+        // one token comes back every 10 seconds
+        assert_eq!(
+            store.take_token("login:ip:127.0.0.1", &LIMIT).await,
+            Err(RateLimitStoreError::LimitExceeded(10))
+        );
+        // other keys have buckets of their own
+        assert!(store.take_token("login:ip:10.0.0.1", &LIMIT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refill_bucket_over_time() {
+        let mut store = HashmapRateLimitStore::default();
+        store.buckets.insert(
+            "signup:ip:127.0.0.1".to_owned(),
+            Bucket {
+                tokens: 0.0,
+                updated_at: Utc::now() - Duration::seconds(20),
+            },
+        );
+
+        assert!(store
+            .take_token("signup:ip:127.0.0.1", &LIMIT)
+            .await
+            .is_ok());
+        assert!(store
+            .take_token("signup:ip:127.0.0.1", &LIMIT)
+            .await
+            .is_ok());
+        assert!(matches!(
+            store.take_token("signup:ip:127.0.0.1", &LIMIT).await,
+            Err(RateLimitStoreError::LimitExceeded(_))
+        ));
+    }
+}
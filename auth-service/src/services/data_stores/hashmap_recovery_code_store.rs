@@ -0,0 +1,161 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    domain::{
+        data_stores::{RecoveryCodeStore, RecoveryCodeStoreError},
+        PasswordHasherType, RecoveryCode, UserId,
+    },
+    services::argon2_password_hasher::Argon2PasswordHasher,
+};
+
+/// Keeps only hashes of the codes, like the PostgreSQL store.
+pub struct HashmapRecoveryCodeStore {
+    codes: HashMap<UserId, Vec<Secret<String>>>,
+    password_hasher: PasswordHasherType,
+}
+
+impl HashmapRecoveryCodeStore {
+    pub fn new(password_hasher: PasswordHasherType) -> Self {
+        Self {
+            codes: HashMap::new(),
+            password_hasher,
+        }
+    }
+}
+
+impl Default for HashmapRecoveryCodeStore {
+    fn default() -> Self {
+        Self::new(Arc::new(Argon2PasswordHasher::default()))
+    }
+}
+
+#[async_trait::async_trait]
+impl RecoveryCodeStore for HashmapRecoveryCodeStore {
+    async fn replace_codes(
+        &mut self,
+        user_id: &UserId,
+        codes: Vec<RecoveryCode>,
+    ) -> Result<(), RecoveryCodeStoreError> {
+        let mut code_hashes = Vec::with_capacity(codes.len());
+        for code in codes {
+            let code_hash = self
+                .password_hasher
+                .hash(code.as_ref().to_owned())
+                .await
+                .map_err(RecoveryCodeStoreError::UnexpectedError)?;
+            code_hashes.push(code_hash);
+        }
+        self.codes.insert(*user_id, code_hashes);
+        Ok(())
+    }
+
+    async fn use_code(
+        &mut self,
+        user_id: &UserId,
+        code: &RecoveryCode,
+    ) -> Result<(), RecoveryCodeStoreError> {
+        let code_hashes = self
+            .codes
+            .get_mut(user_id)
+            .ok_or(RecoveryCodeStoreError::InvalidRecoveryCode)?;
+
+        let mut matching_index = None;
+        for (i, code_hash) in code_hashes.iter().enumerate() {
+            let matches = self
+                .password_hasher
+                .verify(
+                    Secret::new(code_hash.expose_secret().to_owned()),
+                    code.as_ref().to_owned(),
+                )
+                .await
+                .is_ok();
+            if matches {
+                matching_index = Some(i);
+                break;
+            }
+        }
+
+        match matching_index {
+            Some(i) => {
+                code_hashes.remove(i);
+                Ok(())
+            }
+            None => Err(RecoveryCodeStoreError::InvalidRecoveryCode),
+        }
+    }
+
+    async fn count_codes(&self, user_id: &UserId) -> Result<usize, RecoveryCodeStoreError> {
+        Ok(self.codes.get(user_id).map_or(0, Vec::len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replace_and_count_codes() {
+        let mut store = HashmapRecoveryCodeStore::default();
+        let user_id = UserId::default();
+        assert_eq!(store.count_codes(&user_id).await, Ok(0));
+
+        let result = store
+            .replace_codes(&user_id, vec![RecoveryCode::default(); 3])
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(store.count_codes(&user_id).await, Ok(3));
+
+        // a new set replaces whatever was left
+        let result = store
+            .replace_codes(&user_id, vec![RecoveryCode::default()])
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(store.count_codes(&user_id).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_use_code_once() {
+        let mut store = HashmapRecoveryCodeStore::default();
+        let user_id = UserId::default();
+        let codes = vec![RecoveryCode::default(), RecoveryCode::default()];
+        store.replace_codes(&user_id, codes.clone()).await.unwrap();
+
+        let result = store.use_code(&user_id, &codes[1]).await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(store.count_codes(&user_id).await, Ok(1));
+
+        let result = store.use_code(&user_id, &codes[1]).await;
+        assert_eq!(result, Err(RecoveryCodeStoreError::InvalidRecoveryCode));
+    }
+
+    #[tokio::test]
+    async fn test_use_code_of_other_user() {
+        let mut store = HashmapRecoveryCodeStore::default();
+        let code = RecoveryCode::default();
+        store
+            .replace_codes(&UserId::default(), vec![code.clone()])
+            .await
+            .unwrap();
+
+        let result = store.use_code(&UserId::default(), &code).await;
+        assert_eq!(result, Err(RecoveryCodeStoreError::InvalidRecoveryCode));
+    }
+}
@@ -0,0 +1,160 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use secrecy::ExposeSecret;
+use std::collections::HashMap;
+
+use crate::domain::data_stores::{
+    RefreshToken, RefreshTokenData, RefreshTokenFamilyId, RefreshTokenStore, RefreshTokenStoreError,
+};
+
+#[derive(Default)]
+pub struct HashmapRefreshTokenStore {
+    tokens: HashMap<String, RefreshTokenData>,
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenStore for HashmapRefreshTokenStore {
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        data: RefreshTokenData,
+    ) -> Result<(), RefreshTokenStoreError> {
+        self.tokens
+            .insert(token.as_ref().expose_secret().to_owned(), data);
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError> {
+        match self.tokens.get(token.as_ref().expose_secret()) {
+            Some(data) => Ok(data.clone()),
+            None => Err(RefreshTokenStoreError::TokenNotFound),
+        }
+    }
+
+    async fn use_token(
+        &mut self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError> {
+        match self.tokens.get_mut(token.as_ref().expose_secret()) {
+            Some(data) => {
+                let previous = data.clone();
+                data.used = true;
+                Ok(previous)
+            }
+            None => Err(RefreshTokenStoreError::TokenNotFound),
+        }
+    }
+
+    async fn revoke_family(
+        &mut self,
+        family_id: &RefreshTokenFamilyId,
+    ) -> Result<(), RefreshTokenStoreError> {
+        self.tokens.retain(|_, data| data.family_id != *family_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{SessionId, UserId};
+
+    fn token_data(family_id: &RefreshTokenFamilyId) -> RefreshTokenData {
+        RefreshTokenData::new(UserId::default(), SessionId::default(), family_id.clone())
+    }
+
+    #[tokio::test]
+    async fn test_add_token() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token = RefreshToken::default();
+        let data = token_data(&RefreshTokenFamilyId::default());
+
+        let result = store.add_token(token.clone(), data.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            store.tokens.get(token.as_ref().expose_secret()),
+            Some(&data)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token = RefreshToken::default();
+        let data = token_data(&RefreshTokenFamilyId::default());
+        store
+            .tokens
+            .insert(token.as_ref().expose_secret().to_owned(), data.clone());
+
+        assert_eq!(store.get_token(&token).await, Ok(data));
+        assert_eq!(
+            store.get_token(&RefreshToken::default()).await,
+            Err(RefreshTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_use_token() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let token = RefreshToken::default();
+        let data = token_data(&RefreshTokenFamilyId::default());
+        store
+            .tokens
+            .insert(token.as_ref().expose_secret().to_owned(), data);
+
+        assert!(!store.use_token(&token).await.unwrap().used);
+        assert!(store.get_token(&token).await.unwrap().used);
+        assert!(store.use_token(&token).await.unwrap().used);
+        assert_eq!(
+            store.use_token(&RefreshToken::default()).await,
+            Err(RefreshTokenStoreError::TokenNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoke_family() {
+        let mut store = HashmapRefreshTokenStore::default();
+        let family_id = RefreshTokenFamilyId::default();
+        let first = RefreshToken::default();
+        let second = RefreshToken::default();
+        let unrelated = RefreshToken::default();
+        store
+            .add_token(first.clone(), token_data(&family_id))
+            .await
+            .unwrap();
+        store
+            .add_token(second.clone(), token_data(&family_id))
+            .await
+            .unwrap();
+        store
+            .add_token(
+                unrelated.clone(),
+                token_data(&RefreshTokenFamilyId::default()),
+            )
+            .await
+            .unwrap();
+
+        let result = store.revoke_family(&family_id).await;
+        assert!(result.is_ok());
+        assert!(store.get_token(&first).await.is_err());
+        assert!(store.get_token(&second).await.is_err());
+        assert!(store.get_token(&unrelated).await.is_ok());
+    }
+}
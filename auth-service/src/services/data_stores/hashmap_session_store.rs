@@ -0,0 +1,149 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use chrono::Utc;
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_stores::{SessionStore, SessionStoreError},
+    Session, SessionId, UserId,
+};
+
+#[derive(Default)]
+pub struct HashmapSessionStore {
+    sessions: HashMap<SessionId, Session>,
+}
+
+#[async_trait::async_trait]
+impl SessionStore for HashmapSessionStore {
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError> {
+        self.sessions.insert(session.id.clone(), session);
+        Ok(())
+    }
+
+    async fn touch_session(&mut self, id: &SessionId) -> Result<Session, SessionStoreError> {
+        match self.sessions.get_mut(id) {
+            Some(session) => {
+                session.last_seen_at = Utc::now();
+                Ok(session.clone())
+            }
+            None => Err(SessionStoreError::SessionNotFound),
+        }
+    }
+
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        Ok(self
+            .sessions
+            .values()
+            .filter(|session| session.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_session(
+        &mut self,
+        user_id: &UserId,
+        id: &SessionId,
+    ) -> Result<(), SessionStoreError> {
+        match self.sessions.get(id) {
+            Some(session) if session.user_id == *user_id => {
+                self.sessions.remove(id);
+                Ok(())
+            }
+            _ => Err(SessionStoreError::SessionNotFound),
+        }
+    }
+
+    async fn remove_all_sessions(&mut self, user_id: &UserId) -> Result<(), SessionStoreError> {
+        self.sessions
+            .retain(|_, session| session.user_id != *user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(user_id: UserId) -> Session {
+        Session::new(
+            user_id,
+            Some("Mozilla/5.0".to_owned()),
+            Some("127.0.0.1".to_owned()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_add_and_touch_session() {
+        let mut store = HashmapSessionStore::default();
+        let session = session(UserId::default());
+
+        let result = store.add_session(session.clone()).await;
+        assert!(result.is_ok());
+
+        let touched = store.touch_session(&session.id).await.unwrap();
+        assert_eq!(touched.id, session.id);
+        assert!(touched.last_seen_at >= session.last_seen_at);
+
+        let result = store.touch_session(&SessionId::default()).await;
+        assert_eq!(result, Err(SessionStoreError::SessionNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_get_sessions_only_returns_users_sessions() {
+        let mut store = HashmapSessionStore::default();
+        let user_id = UserId::default();
+        store.add_session(session(user_id)).await.unwrap();
+        store.add_session(session(user_id)).await.unwrap();
+        store.add_session(session(UserId::default())).await.unwrap();
+
+        let sessions = store.get_sessions(&user_id).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|session| session.user_id == user_id));
+    }
+
+    #[tokio::test]
+    async fn test_remove_session() {
+        let mut store = HashmapSessionStore::default();
+        let session = session(UserId::default());
+        store.add_session(session.clone()).await.unwrap();
+
+        // another user cannot remove the session
+        let result = store.remove_session(&UserId::default(), &session.id).await;
+        assert_eq!(result, Err(SessionStoreError::SessionNotFound));
+
+        let result = store.remove_session(&session.user_id, &session.id).await;
+        assert!(result.is_ok());
+
+        let result = store.touch_session(&session.id).await;
+        assert_eq!(result, Err(SessionStoreError::SessionNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_remove_all_sessions() {
+        let mut store = HashmapSessionStore::default();
+        let user_id = UserId::default();
+        let other = session(UserId::default());
+        store.add_session(session(user_id)).await.unwrap();
+        store.add_session(session(user_id)).await.unwrap();
+        store.add_session(other.clone()).await.unwrap();
+
+        let result = store.remove_all_sessions(&user_id).await;
+        assert!(result.is_ok());
+        assert!(store.get_sessions(&user_id).await.unwrap().is_empty());
+        assert!(store.touch_session(&other.id).await.is_ok());
+    }
+}
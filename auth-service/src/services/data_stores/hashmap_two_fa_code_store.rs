@@ -15,14 +15,38 @@
 */
 
 use crate::domain::{
-    data_stores::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+    data_stores::{
+        LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, MAX_LIVE_TWO_FA_CODES,
+        MAX_TWO_FA_CODES_PER_WINDOW, MAX_TWO_FA_CODE_GUESSES, TWO_FA_CODE_TTL_SECONDS,
+        TWO_FA_CODE_WINDOW_SECONDS,
+    },
     email::Email,
 };
+use chrono::{DateTime, Duration, Utc};
 use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct HashmapTwoFACodeStore {
-    codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
+    codes: HashMap<LoginAttemptId, TwoFAEntry>,
+    /// Codes issued to each user in the current window, and when the window ends.
+    issued: HashMap<Email, (u32, DateTime<Utc>)>,
+}
+
+#[derive(Debug, PartialEq)]
+struct TwoFAEntry {
+    email: Email,
+    code: TwoFACode,
+    wrong_guesses: u32,
+    expires_at: DateTime<Utc>,
+}
+
+impl HashmapTwoFACodeStore {
+    /// The attempt's entry, unless it has expired.
+    fn live_entry(&self, login_attempt_id: &LoginAttemptId) -> Option<&TwoFAEntry> {
+        self.codes
+            .get(login_attempt_id)
+            .filter(|entry| entry.expires_at > Utc::now())
+    }
 }
 
 #[async_trait::async_trait]
@@ -33,26 +57,90 @@ impl TwoFACodeStore for HashmapTwoFACodeStore {
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        self.codes.insert(email, (login_attempt_id, code));
+        let now = Utc::now();
+        let window = self
+            .issued
+            .entry(email.clone())
+            .or_insert((0, now + Duration::seconds(TWO_FA_CODE_WINDOW_SECONDS)));
+        if window.1 <= now {
+            *window = (0, now + Duration::seconds(TWO_FA_CODE_WINDOW_SECONDS));
+        }
+        if window.0 >= MAX_TWO_FA_CODES_PER_WINDOW {
+            let retry_after = (window.1 - now).num_seconds().max(1);
+            return Err(TwoFACodeStoreError::TooManyCodes(retry_after as u64));
+        }
+        window.0 += 1;
+
+        // expired attempts go first, then the oldest ones until the new one fits
+        self.codes.retain(|_, entry| entry.expires_at > now);
+        let mut live: Vec<_> = self
+            .codes
+            .iter()
+            .filter(|(_, entry)| entry.email == email)
+            .map(|(id, entry)| (entry.expires_at, id.clone()))
+            .collect();
+        live.sort_by_key(|(expires_at, _)| *expires_at);
+        let excess = (live.len() + 1).saturating_sub(MAX_LIVE_TWO_FA_CODES);
+        for (_, id) in live.into_iter().take(excess) {
+            self.codes.remove(&id);
+        }
+
+        self.codes.insert(
+            login_attempt_id,
+            TwoFAEntry {
+                email,
+                code,
+                wrong_guesses: 0,
+                expires_at: now + Duration::seconds(TWO_FA_CODE_TTL_SECONDS),
+            },
+        );
         Ok(())
     }
 
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        match self.codes.remove(email) {
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        match self.codes.remove(login_attempt_id) {
             Some(_) => Ok(()),
             None => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
         }
     }
 
+    async fn remove_codes(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        self.codes.retain(|_, entry| &entry.email != email);
+        Ok(())
+    }
+
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        match self.codes.get(email) {
-            Some(value) => Ok(value.clone()),
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(Email, TwoFACode), TwoFACodeStoreError> {
+        match self.live_entry(login_attempt_id) {
+            Some(entry) => Ok((entry.email.clone(), entry.code.clone())),
             None => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
         }
     }
+
+    async fn record_wrong_guess(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<u32, TwoFACodeStoreError> {
+        if self.live_entry(login_attempt_id).is_none() {
+            return Err(TwoFACodeStoreError::LoginAttemptIdNotFound);
+        }
+
+        let entry = self
+            .codes
+            .get_mut(login_attempt_id)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        entry.wrong_guesses += 1;
+        let guesses_left = MAX_TWO_FA_CODE_GUESSES.saturating_sub(entry.wrong_guesses);
+        if guesses_left == 0 {
+            self.remove_code(login_attempt_id).await?;
+        }
+        Ok(guesses_left)
+    }
 }
 
 #[cfg(test)]
@@ -60,59 +148,159 @@ mod tests {
     use super::*;
     use secrecy::Secret;
 
+    fn email() -> Email {
+        Email::parse(Secret::new("m@umbrella.corp".to_owned())).unwrap()
+    }
+
     #[tokio::test]
     async fn test_add_code() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(Secret::new("m@umbrella.corp".to_owned())).unwrap();
         let login_attempt_id = LoginAttemptId::default();
         let code = TwoFACode::default();
         let result = store
-            .add_code(email.clone(), login_attempt_id.clone(), code.clone())
+            .add_code(email(), login_attempt_id.clone(), code.clone())
             .await;
         assert!(result.is_ok());
-        assert_eq!(store.codes.get(&email), Some(&(login_attempt_id, code)));
+        let entry = store.codes.get(&login_attempt_id).unwrap();
+        assert_eq!(entry.email, email());
+        assert_eq!(entry.code, code);
     }
 
     #[tokio::test]
     async fn test_remove_code() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(Secret::new("m@umbrella.corp".to_string())).unwrap();
         let login_attempt_id = LoginAttemptId::default();
-        let code = TwoFACode::default();
         store
-            .codes
-            .insert(email.clone(), (login_attempt_id.clone(), code.clone()));
+            .add_code(email(), login_attempt_id.clone(), TwoFACode::default())
+            .await
+            .unwrap();
 
-        let result = store.remove_code(&email).await;
+        let result = store.remove_code(&login_attempt_id).await;
         assert!(result.is_ok());
-        assert_eq!(store.codes.get(&email), None);
+        assert_eq!(store.codes.get(&login_attempt_id), None);
     }
 
     #[tokio::test]
     async fn test_get_code() {
         let mut store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(Secret::new("m@umbrella.corp".to_string())).unwrap();
         let login_attempt_id = LoginAttemptId::default();
         let code = TwoFACode::default();
         store
-            .codes
-            .insert(email.clone(), (login_attempt_id.clone(), code.clone()));
+            .add_code(email(), login_attempt_id.clone(), code.clone())
+            .await
+            .unwrap();
 
-        let result = store.get_code(&email).await;
+        let result = store.get_code(&login_attempt_id).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), (login_attempt_id, code));
+        assert_eq!(result.unwrap(), (email(), code));
     }
 
     #[tokio::test]
     async fn test_get_code_not_found() {
         let store = HashmapTwoFACodeStore::default();
-        let email = Email::parse(Secret::new("m@umbrella.corp".to_string())).unwrap();
 
-        let result = store.get_code(&email).await;
+        let result = store.get_code(&LoginAttemptId::default()).await;
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
             TwoFACodeStoreError::LoginAttemptIdNotFound
         );
     }
+
+    #[tokio::test]
+    async fn test_get_code_expired() {
+        let mut store = HashmapTwoFACodeStore::default();
+        let login_attempt_id = LoginAttemptId::default();
+        store
+            .add_code(email(), login_attempt_id.clone(), TwoFACode::default())
+            .await
+            .unwrap();
+        store.codes.get_mut(&login_attempt_id).unwrap().expires_at =
+            Utc::now() - Duration::seconds(1);
+
+        assert_eq!(
+            store.get_code(&login_attempt_id).await.unwrap_err(),
+            TwoFACodeStoreError::LoginAttemptIdNotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_concurrent_attempts() {
+        let mut store = HashmapTwoFACodeStore::default();
+        let attempts: Vec<_> = (0..=MAX_LIVE_TWO_FA_CODES)
+            .map(|_| LoginAttemptId::default())
+            .collect();
+        for login_attempt_id in &attempts {
+            store
+                .add_code(email(), login_attempt_id.clone(), TwoFACode::default())
+                .await
+                .unwrap();
+        }
+
+        // only the oldest attempt gave way to the newest
+        assert!(store.get_code(&attempts[0]).await.is_err());
+        for login_attempt_id in &attempts[1..] {
+            assert!(store.get_code(login_attempt_id).await.is_ok());
+        }
+
+        assert!(store.remove_codes(&email()).await.is_ok());
+        assert!(store.codes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_code_after_max_wrong_guesses() {
+        let mut store = HashmapTwoFACodeStore::default();
+        let login_attempt_id = LoginAttemptId::default();
+        let other_attempt_id = LoginAttemptId::default();
+        for id in [&login_attempt_id, &other_attempt_id] {
+            store
+                .add_code(email(), id.clone(), TwoFACode::default())
+                .await
+                .unwrap();
+        }
+
+        for guesses_left in (0..MAX_TWO_FA_CODE_GUESSES).rev() {
+            assert_eq!(
+                store.record_wrong_guess(&login_attempt_id).await,
+                Ok(guesses_left)
+            );
+        }
+        assert_eq!(
+            store.get_code(&login_attempt_id).await.unwrap_err(),
+            TwoFACodeStoreError::LoginAttemptIdNotFound
+        );
+        assert_eq!(
+            store.record_wrong_guess(&login_attempt_id).await,
+            Err(TwoFACodeStoreError::LoginAttemptIdNotFound)
+        );
+
+        // the user's other attempt keeps every guess
+        assert_eq!(
+            store.record_wrong_guess(&other_attempt_id).await,
+            Ok(MAX_TWO_FA_CODE_GUESSES - 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit_codes_per_window() {
+        let mut store = HashmapTwoFACodeStore::default();
+
+        for _ in 0..MAX_TWO_FA_CODES_PER_WINDOW {
+            let result = store
+                .add_code(email(), LoginAttemptId::default(), TwoFACode::default())
+                .await;
+            assert!(result.is_ok());
+        }
+        let result = store
+            .add_code(email(), LoginAttemptId::default(), TwoFACode::default())
+            .await;
+        assert!(matches!(result, Err(TwoFACodeStoreError::TooManyCodes(_))));
+
+        // the count starts over once the window has passed
+        store.issued.get_mut(&email()).unwrap().1 = Utc::now() - Duration::seconds(1);
+        let result = store
+            .add_code(email(), LoginAttemptId::default(), TwoFACode::default())
+            .await;
+        assert!(result.is_ok());
+    }
 }
@@ -14,62 +14,246 @@
    limitations under the License.
 */
 
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
 
-use crate::domain::{Email, Password, User, UserStore, UserStoreError};
+use crate::{
+    domain::{
+        Email, ExistingPassword, Password, PasswordHasherType, TotpSecret, TwoFAMethod, User,
+        UserId, UserStore, UserStoreError,
+    },
+    services::argon2_password_hasher::Argon2PasswordHasher,
+};
 
-#[derive(Default)]
+/// Keeps users with a hash of their password in place of the password, like the PostgreSQL store.
+/// The users sit behind their own lock, so that `validate_user` can upgrade a hash.
 pub struct HashmapUserStore {
-    users: HashMap<Email, User>,
+    users: RwLock<HashMap<Email, User>>,
+    totp_steps: HashMap<UserId, u64>,
+    password_hasher: PasswordHasherType,
+}
+
+impl HashmapUserStore {
+    pub fn new(password_hasher: PasswordHasherType) -> Self {
+        Self {
+            users: RwLock::new(HashMap::new()),
+            totp_steps: HashMap::new(),
+            password_hasher,
+        }
+    }
+}
+
+impl Default for HashmapUserStore {
+    fn default() -> Self {
+        Self::new(Arc::new(Argon2PasswordHasher::default()))
+    }
 }
 
 #[async_trait::async_trait]
 impl UserStore for HashmapUserStore {
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        if self.users.contains_key(&user.email) {
+    async fn add_user(&mut self, mut user: User) -> Result<(), UserStoreError> {
+        if self.users.get_mut().contains_key(&user.email) {
             return Err(UserStoreError::UserAlreadyExists);
         }
-        self.users.insert(user.email.clone(), user);
+        user.password = self.hash_password(&user.password).await?;
+        self.users.get_mut().insert(user.email.clone(), user);
         Ok(())
     }
 
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
-        match self.users.get(email) {
+        match self.users.read().await.get(email) {
             Some(user) => Ok(user.clone()),
             None => Err(UserStoreError::UserNotFound),
         }
     }
 
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        self.users
+            .read()
+            .await
+            .values()
+            .find(|user| user.id == *id)
+            .cloned()
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
     async fn validate_user(
         &self,
         email: &Email,
-        password: &Password,
+        password: &ExistingPassword,
     ) -> Result<(), UserStoreError> {
-        match self.users.get(email) {
-            Some(user) => {
-                if user.password.eq(password) {
-                    Ok(())
-                } else {
-                    Err(UserStoreError::InvalidCredentials)
+        let password_hash = self
+            .users
+            .read()
+            .await
+            .get(email)
+            .map(|user| user.password.clone())
+            .ok_or(UserStoreError::UserNotFound)?;
+        let needs_rehash = self
+            .password_hasher
+            .verify_existing(password_hash.as_ref(), password)
+            .await
+            .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        if needs_rehash {
+            // the login goes ahead either way, and the hash is upgraded at the next one instead
+            match self.hash_password(&password.normalized).await {
+                Ok(new_hash) => {
+                    let mut users = self.users.write().await;
+                    // unless the password was changed in the meantime
+                    if let Some(user) = users
+                        .get_mut(email)
+                        .filter(|user| user.password == password_hash)
+                    {
+                        user.password = new_hash;
+                        tracing::info!("upgraded password hash of user {}", user.id);
+                    }
                 }
+                Err(e) => tracing::error!("failed to upgrade password hash: {:?}", e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_totp_secret(
+        &mut self,
+        id: &UserId,
+        secret: TotpSecret,
+    ) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.totp_secret = Some(secret);
+        self.totp_steps.remove(id);
+        Ok(())
+    }
+
+    async fn set_two_fa_method(
+        &mut self,
+        id: &UserId,
+        method: TwoFAMethod,
+    ) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.two_fa_method = method;
+        Ok(())
+    }
+
+    async fn disable_two_fa(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.two_fa_method = TwoFAMethod::None;
+        user.totp_secret = None;
+        self.totp_steps.remove(id);
+        Ok(())
+    }
+
+    async fn use_totp_step(&mut self, id: &UserId, step: u64) -> Result<(), UserStoreError> {
+        self.get_user_by_id_mut(id)?;
+        match self.totp_steps.get(id) {
+            Some(last_step) if *last_step >= step => Err(UserStoreError::InvalidCredentials),
+            _ => {
+                self.totp_steps.insert(*id, step);
+                Ok(())
             }
-            None => Err(UserStoreError::UserNotFound),
         }
     }
+
+    async fn set_password(
+        &mut self,
+        id: &UserId,
+        password: Password,
+    ) -> Result<(), UserStoreError> {
+        let password_hash = self.hash_password(&password).await?;
+        let user = self.get_user_by_id_mut(id)?;
+        user.password = password_hash;
+        Ok(())
+    }
+
+    async fn set_verified(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.verified = true;
+        Ok(())
+    }
+
+    async fn mark_for_deletion(
+        &mut self,
+        id: &UserId,
+        purge_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.purge_at = Some(purge_at);
+        Ok(())
+    }
+
+    async fn restore_user(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let user = self.get_user_by_id_mut(id)?;
+        user.purge_at = None;
+        Ok(())
+    }
+
+    async fn delete_due_users(&mut self, now: DateTime<Utc>) -> Result<Vec<User>, UserStoreError> {
+        let users = self.users.get_mut();
+        let due_emails: Vec<Email> = users
+            .values()
+            .filter(|user| user.purge_at.is_some_and(|purge_at| purge_at <= now))
+            .map(|user| user.email.clone())
+            .collect();
+
+        let due_users: Vec<User> = due_emails
+            .iter()
+            .filter_map(|email| users.remove(email))
+            .collect();
+        for user in &due_users {
+            self.totp_steps.remove(&user.id);
+        }
+        Ok(due_users)
+    }
+
+    async fn count_outdated_password_hashes(&self) -> Result<usize, UserStoreError> {
+        Ok(self
+            .users
+            .read()
+            .await
+            .values()
+            .filter(|user| self.password_hasher.needs_rehash(user.password.as_ref()))
+            .count())
+    }
+}
+
+impl HashmapUserStore {
+    async fn hash_password(&self, password: &Password) -> Result<Password, UserStoreError> {
+        let password_hash = self
+            .password_hasher
+            .hash(password.as_ref().to_owned())
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+        Password::parse(password_hash).map_err(UserStoreError::UnexpectedError)
+    }
+
+    fn get_user_by_id_mut(&mut self, id: &UserId) -> Result<&mut User, UserStoreError> {
+        self.users
+            .get_mut()
+            .values_mut()
+            .find(|user| user.id == *id)
+            .ok_or(UserStoreError::UserNotFound)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use secrecy::Secret;
+    use crate::domain::{PasswordHasher, PasswordPolicy};
+    use secrecy::{ExposeSecret, Secret};
 
     #[tokio::test]
     async fn test_add_user() {
         let mut user_store = HashmapUserStore::default();
         let user = User {
+            id: UserId::default(),
             email: Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
             password: Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
-            require_2fa: false,
+            two_fa_method: TwoFAMethod::None,
+            totp_secret: None,
+            verified: false,
+            purge_at: None,
         };
 
         // add a new user
@@ -87,13 +271,20 @@ mod tests {
         let email = Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap();
 
         let user = User {
+            id: UserId::default(),
             email: email.clone(),
             password: Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
-            require_2fa: false,
+            two_fa_method: TwoFAMethod::None,
+            totp_secret: None,
+            verified: false,
+            purge_at: None,
         };
 
         // get existing user
-        user_store.users.insert(email.clone(), user.clone());
+        user_store
+            .users
+            .get_mut()
+            .insert(email.clone(), user.clone());
         let result = user_store.get_user(&email).await;
         assert_eq!(result, Ok(user));
 
@@ -105,36 +296,354 @@ mod tests {
         assert_eq!(result, Err(UserStoreError::UserNotFound));
     }
 
+    #[tokio::test]
+    async fn test_get_user_by_id() {
+        let mut user_store = HashmapUserStore::default();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+
+        // get existing user
+        let stored = user_store.get_user_by_id(&user.id).await.unwrap();
+        assert_eq!(stored.id, user.id);
+        assert_eq!(stored.email, user.email);
+
+        // get non existing user
+        let result = user_store.get_user_by_id(&UserId::default()).await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
     #[tokio::test]
     async fn test_validate_user() {
         let mut user_store = HashmapUserStore::default();
         let email = Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap();
         let password = Password::parse(Secret::new("$3curedZ".to_owned())).unwrap();
 
-        let user = User {
-            email: email.clone(),
-            password: password.clone(),
-            require_2fa: false,
-        };
+        let user = User::new(email.clone(), password.clone(), TwoFAMethod::None);
 
         // validate a user that exists with correct password
-        user_store.users.insert(email.clone(), user.clone());
-        let result = user_store.validate_user(&email, &password).await;
+        user_store.add_user(user).await.unwrap();
+        let result = user_store
+            .validate_user(&email, &password.clone().into())
+            .await;
         assert_eq!(result, Ok(()));
 
         //  validate a user that exists with incorrect password
         let wrong_password = Password::parse(Secret::new("incorrectPassword".to_owned())).unwrap();
-        let result = user_store.validate_user(&email, &wrong_password).await;
+        let result = user_store
+            .validate_user(&email, &wrong_password.clone().into())
+            .await;
         assert_eq!(result, Err(UserStoreError::InvalidCredentials));
 
         //  validate a user that doesn't exist
         let result = user_store
             .validate_user(
                 &Email::parse(Secret::new("i@umbrella.corp".to_string())).unwrap(),
-                &password,
+                &password.into(),
             )
             .await;
 
         assert_eq!(result, Err(UserStoreError::UserNotFound));
     }
+
+    #[tokio::test]
+    async fn test_store_only_password_hash() {
+        let mut user_store = HashmapUserStore::default();
+        let password = Password::parse(Secret::new("$3curedZ".to_owned())).unwrap();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            password.clone(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+
+        let stored = user_store.get_user_by_id(&user.id).await.unwrap();
+        assert_ne!(stored.password, password);
+        assert!(stored
+            .password
+            .as_ref()
+            .expose_secret()
+            .starts_with("$argon2id$"));
+
+        // a new password is hashed too
+        let new_password = Password::parse(Secret::new("n3wP4ssword".to_owned())).unwrap();
+        user_store
+            .set_password(&user.id, new_password.clone())
+            .await
+            .unwrap();
+        let stored = user_store.get_user_by_id(&user.id).await.unwrap();
+        assert_ne!(stored.password, new_password);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_outdated_password_hash() {
+        let mut user_store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("$3curedZ".to_owned())).unwrap();
+
+        // a user whose hash was made before the parameters were raised
+        let outdated_hash = Argon2PasswordHasher::new(8192, 1, 1)
+            .unwrap()
+            .hash(password.as_ref().to_owned())
+            .await
+            .unwrap();
+        let user = User::new(
+            email.clone(),
+            Password::parse(outdated_hash).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store
+            .users
+            .get_mut()
+            .insert(email.clone(), user.clone());
+        assert_eq!(user_store.count_outdated_password_hashes().await, Ok(1));
+
+        // a wrong password doesn't touch the hash
+        let wrong_password = Password::parse(Secret::new("incorrectPassword".to_owned())).unwrap();
+        let result = user_store
+            .validate_user(&email, &wrong_password.clone().into())
+            .await;
+        assert_eq!(result, Err(UserStoreError::InvalidCredentials));
+        assert_eq!(user_store.count_outdated_password_hashes().await, Ok(1));
+
+        let result = user_store
+            .validate_user(&email, &password.clone().into())
+            .await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(user_store.count_outdated_password_hashes().await, Ok(0));
+        let stored = user_store.get_user(&email).await.unwrap();
+        assert_ne!(stored.password, user.password);
+
+        // the new hash still works
+        let result = user_store
+            .validate_user(&email, &password.clone().into())
+            .await;
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_password_hash_made_as_typed() {
+        let mut user_store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap();
+
+        // a user whose password was hashed before it was normalized
+        let as_typed = Password::parse(Secret::new("ﬁreﬂy$3curedZ".to_owned())).unwrap();
+        let user = User::new(email.clone(), as_typed, TwoFAMethod::None);
+        user_store.add_user(user.clone()).await.unwrap();
+
+        let password = PasswordPolicy::default()
+            .parse(Secret::new("ﬁreﬂy$3curedZ".to_owned()))
+            .unwrap();
+        assert!(password.as_typed.is_some());
+        let result = user_store.validate_user(&email, &password).await;
+        assert_eq!(result, Ok(()));
+        let stored = user_store.get_user(&email).await.unwrap();
+        assert_ne!(stored.password, user.password);
+
+        // the new hash is of the normalized password
+        let normalized = Password::parse(Secret::new("firefly$3curedZ".to_owned())).unwrap();
+        let result = user_store.validate_user(&email, &normalized.into()).await;
+        assert_eq!(result, Ok(()));
+        let result = user_store.validate_user(&email, &password).await;
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_set_totp_secret_and_two_fa_method() {
+        let mut user_store = HashmapUserStore::default();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+
+        let secret = TotpSecret::default();
+        let result = user_store.set_totp_secret(&user.id, secret.clone()).await;
+        assert!(result.is_ok());
+        let result = user_store
+            .set_two_fa_method(&user.id, TwoFAMethod::Totp)
+            .await;
+        assert!(result.is_ok());
+
+        let stored = user_store.get_user_by_id(&user.id).await.unwrap();
+        assert_eq!(stored.totp_secret, Some(secret));
+        assert_eq!(stored.two_fa_method, TwoFAMethod::Totp);
+
+        // update a user that doesn't exist
+        let result = user_store
+            .set_two_fa_method(&UserId::default(), TwoFAMethod::Email)
+            .await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_disable_two_fa() {
+        let mut user_store = HashmapUserStore::default();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+        user_store
+            .set_totp_secret(&user.id, TotpSecret::default())
+            .await
+            .unwrap();
+        user_store
+            .set_two_fa_method(&user.id, TwoFAMethod::Totp)
+            .await
+            .unwrap();
+
+        let result = user_store.disable_two_fa(&user.id).await;
+        assert!(result.is_ok());
+
+        let stored = user_store.get_user_by_id(&user.id).await.unwrap();
+        assert_eq!(stored.two_fa_method, TwoFAMethod::None);
+        assert_eq!(stored.totp_secret, None);
+
+        // update a user that doesn't exist
+        let result = user_store.disable_two_fa(&UserId::default()).await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_use_totp_step() {
+        let mut user_store = HashmapUserStore::default();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+
+        assert_eq!(user_store.use_totp_step(&user.id, 10).await, Ok(()));
+        // the same step again, or an earlier one, is a replayed code
+        let result = user_store.use_totp_step(&user.id, 10).await;
+        assert_eq!(result, Err(UserStoreError::InvalidCredentials));
+        let result = user_store.use_totp_step(&user.id, 9).await;
+        assert_eq!(result, Err(UserStoreError::InvalidCredentials));
+        assert_eq!(user_store.use_totp_step(&user.id, 11).await, Ok(()));
+
+        // a new secret starts over
+        user_store
+            .set_totp_secret(&user.id, TotpSecret::default())
+            .await
+            .unwrap();
+        assert_eq!(user_store.use_totp_step(&user.id, 11).await, Ok(()));
+
+        let result = user_store.use_totp_step(&UserId::default(), 12).await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_set_password() {
+        let mut user_store = HashmapUserStore::default();
+        let old_password = Password::parse(Secret::new("$3curedZ".to_owned())).unwrap();
+        let new_password = Password::parse(Secret::new("n3wP4ssword".to_owned())).unwrap();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            old_password.clone(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+
+        let result = user_store
+            .set_password(&user.id, new_password.clone())
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(
+            user_store
+                .validate_user(&user.email, &new_password.clone().into())
+                .await,
+            Ok(())
+        );
+        assert_eq!(
+            user_store
+                .validate_user(&user.email, &old_password.clone().into())
+                .await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+
+        // update a user that doesn't exist
+        let result = user_store
+            .set_password(&UserId::default(), new_password)
+            .await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_set_verified() {
+        let mut user_store = HashmapUserStore::default();
+        let user = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            Password::parse(Secret::new("$3curedZ".to_owned())).unwrap(),
+            TwoFAMethod::None,
+        );
+        user_store.add_user(user.clone()).await.unwrap();
+        assert!(!user_store.get_user_by_id(&user.id).await.unwrap().verified);
+
+        let result = user_store.set_verified(&user.id).await;
+        assert!(result.is_ok());
+        assert!(user_store.get_user_by_id(&user.id).await.unwrap().verified);
+
+        // update a user that doesn't exist
+        let result = user_store.set_verified(&UserId::default()).await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_delete_due_users() {
+        let mut user_store = HashmapUserStore::default();
+        let password = Password::parse(Secret::new("$3curedZ".to_owned())).unwrap();
+        let due = User::new(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+            password.clone(),
+            TwoFAMethod::None,
+        );
+        let restored = User::new(
+            Email::parse(Secret::new("i@umbrella.corp".to_owned())).unwrap(),
+            password,
+            TwoFAMethod::None,
+        );
+        user_store.add_user(due.clone()).await.unwrap();
+        user_store.add_user(restored.clone()).await.unwrap();
+
+        let now = Utc::now();
+        for user in [&due, &restored] {
+            let result = user_store.mark_for_deletion(&user.id, now).await;
+            assert!(result.is_ok());
+        }
+        assert_eq!(user_store.restore_user(&restored.id).await, Ok(()));
+
+        // nothing is due before the purge time
+        let deleted = user_store
+            .delete_due_users(now - chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(deleted.is_empty());
+
+        let deleted = user_store.delete_due_users(now).await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, due.id);
+        assert_eq!(
+            user_store.get_user_by_id(&due.id).await,
+            Err(UserStoreError::UserNotFound)
+        );
+        assert_eq!(
+            user_store
+                .get_user_by_id(&restored.id)
+                .await
+                .unwrap()
+                .purge_at,
+            None
+        );
+
+        // update a user that doesn't exist
+        let result = user_store.restore_user(&UserId::default()).await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+    }
 }
@@ -0,0 +1,116 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::{
+    domain::{
+        data_stores::{WebauthnChallengeStore, WebauthnChallengeStoreError},
+        WebauthnCeremony, WebauthnChallenge,
+    },
+    utils::webauthn::WEBAUTHN_CHALLENGE_TTL_SECONDS,
+};
+
+#[derive(Default)]
+pub struct HashmapWebauthnChallengeStore {
+    challenges: HashMap<WebauthnChallenge, (WebauthnCeremony, DateTime<Utc>)>,
+}
+
+#[async_trait::async_trait]
+impl WebauthnChallengeStore for HashmapWebauthnChallengeStore {
+    async fn add_challenge(
+        &mut self,
+        challenge: WebauthnChallenge,
+        ceremony: WebauthnCeremony,
+    ) -> Result<(), WebauthnChallengeStoreError> {
+        let expires_at = Utc::now() + Duration::seconds(WEBAUTHN_CHALLENGE_TTL_SECONDS);
+        self.challenges.insert(challenge, (ceremony, expires_at));
+        Ok(())
+    }
+
+    async fn take_challenge(
+        &mut self,
+        challenge: &WebauthnChallenge,
+    ) -> Result<WebauthnCeremony, WebauthnChallengeStoreError> {
+        match self.challenges.remove(challenge) {
+            Some((ceremony, expires_at)) if expires_at > Utc::now() => Ok(ceremony),
+            _ => Err(WebauthnChallengeStoreError::ChallengeNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{domain::UserId, utils::webauthn::WEBAUTHN};
+
+    fn challenge() -> WebauthnChallenge {
+        let mut bytes = [0u8; 32];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+        WebauthnChallenge::from_bytes(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_take_challenge_once() {
+        let mut store = HashmapWebauthnChallengeStore::default();
+        let challenge = challenge();
+        let user_id = UserId::default();
+        let (_, registration) = WEBAUTHN
+            .start_passkey_registration(*user_id.as_ref(), "user", "user", None)
+            .unwrap();
+
+        let result = store
+            .add_challenge(
+                challenge.clone(),
+                WebauthnCeremony::Registration {
+                    user_id,
+                    state: registration,
+                },
+            )
+            .await;
+        assert!(result.is_ok());
+        assert!(matches!(
+            store.take_challenge(&challenge).await,
+            Ok(WebauthnCeremony::Registration { user_id: id, .. }) if id == user_id
+        ));
+        assert_eq!(
+            store.take_challenge(&challenge).await.unwrap_err(),
+            WebauthnChallengeStoreError::ChallengeNotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_expired_challenge() {
+        let mut store = HashmapWebauthnChallengeStore::default();
+        let challenge = challenge();
+        let (_, authentication) = WEBAUTHN.start_discoverable_authentication().unwrap();
+        store.challenges.insert(
+            challenge.clone(),
+            (
+                WebauthnCeremony::DiscoverableAuthentication {
+                    user_id: None,
+                    state: authentication,
+                },
+                Utc::now() - Duration::seconds(1),
+            ),
+        );
+
+        assert_eq!(
+            store.take_challenge(&challenge).await.unwrap_err(),
+            WebauthnChallengeStoreError::ChallengeNotFound
+        );
+    }
+}
@@ -0,0 +1,219 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::collections::HashMap;
+use webauthn_rs::prelude::Passkey;
+
+use crate::domain::{
+    data_stores::{WebauthnCredentialStore, WebauthnCredentialStoreError},
+    CredentialId, UserId, WebauthnCredential,
+};
+
+#[derive(Default)]
+pub struct HashmapWebauthnCredentialStore {
+    credentials: HashMap<CredentialId, WebauthnCredential>,
+}
+
+#[async_trait::async_trait]
+impl WebauthnCredentialStore for HashmapWebauthnCredentialStore {
+    async fn add_credential(
+        &mut self,
+        credential: WebauthnCredential,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        if self.credentials.contains_key(&credential.id) {
+            return Err(WebauthnCredentialStoreError::CredentialAlreadyExists);
+        }
+        self.credentials.insert(credential.id.clone(), credential);
+        Ok(())
+    }
+
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<WebauthnCredential, WebauthnCredentialStoreError> {
+        self.credentials
+            .get(id)
+            .cloned()
+            .ok_or(WebauthnCredentialStoreError::CredentialNotFound)
+    }
+
+    async fn get_credentials(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<WebauthnCredential>, WebauthnCredentialStoreError> {
+        Ok(self
+            .credentials
+            .values()
+            .filter(|credential| credential.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_passkey(
+        &mut self,
+        id: &CredentialId,
+        passkey: Passkey,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        match self.credentials.get_mut(id) {
+            Some(credential) => {
+                credential.passkey = passkey;
+                Ok(())
+            }
+            None => Err(WebauthnCredentialStoreError::CredentialNotFound),
+        }
+    }
+
+    async fn remove_credentials(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        self.credentials
+            .retain(|_, credential| credential.user_id != *user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+
+    // A passkey as webauthn-rs serializes it after a registration without attestation.
+    fn passkey(id: &CredentialId, counter: u32) -> Passkey {
+        serde_json::from_value(serde_json::json!({
+            "cred": {
+                "cred_id": URL_SAFE_NO_PAD.encode(id),
+                "cred": {
+                    "type_": "ES256",
+                    "key": {
+                        "EC_EC2": {
+                            "curve": "SECP256R1",
+                            "x": "BkbAJgeQjq9u8AlJBm0y9LkvHFL7XhawF5Zmq3klBgo",
+                            "y": "lQRAFyn_dvM0c_q5IKkTmEwKV5DVwcqzp5vJrDwc7gs",
+                        },
+                    },
+                },
+                "counter": counter,
+                "transports": null,
+                "user_verified": true,
+                "backup_eligible": false,
+                "backup_state": false,
+                "registration_policy": "required",
+                "extensions": {
+                    "cred_protect": "Ignored",
+                    "hmac_create_secret": "NotRequested",
+                    "appid": "NotRequested",
+                    "cred_props": "Ignored",
+                },
+                "attestation": { "data": "None", "metadata": "None" },
+                "attestation_format": "none",
+            },
+        }))
+        .unwrap()
+    }
+
+    fn credential(user_id: UserId) -> WebauthnCredential {
+        let mut id = vec![0u8; 16];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut id);
+        let id = CredentialId::parse(id).unwrap();
+        WebauthnCredential {
+            passkey: passkey(&id, 0),
+            id,
+            user_id,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_credential() {
+        let mut store = HashmapWebauthnCredentialStore::default();
+        let credential = credential(UserId::default());
+
+        let result = store.add_credential(credential.clone()).await;
+        assert!(result.is_ok());
+        let stored = store.get_credential(&credential.id).await.unwrap();
+        assert_eq!(stored.id, credential.id);
+        assert_eq!(stored.user_id, credential.user_id);
+
+        // test duplicate entries
+        let result = store.add_credential(credential).await;
+        assert_eq!(
+            result.unwrap_err(),
+            WebauthnCredentialStoreError::CredentialAlreadyExists
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_credentials() {
+        let mut store = HashmapWebauthnCredentialStore::default();
+        let user_id = UserId::default();
+        store.add_credential(credential(user_id)).await.unwrap();
+        store.add_credential(credential(user_id)).await.unwrap();
+        store
+            .add_credential(credential(UserId::default()))
+            .await
+            .unwrap();
+
+        let credentials = store.get_credentials(&user_id).await.unwrap();
+        assert_eq!(credentials.len(), 2);
+        assert!(credentials
+            .iter()
+            .all(|credential| credential.user_id == user_id));
+    }
+
+    #[tokio::test]
+    async fn test_update_passkey() {
+        let mut store = HashmapWebauthnCredentialStore::default();
+        let credential = credential(UserId::default());
+        store.add_credential(credential.clone()).await.unwrap();
+
+        let result = store
+            .update_passkey(&credential.id, passkey(&credential.id, 7))
+            .await;
+        assert!(result.is_ok());
+        let stored = store.get_credential(&credential.id).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&stored.passkey).unwrap()["cred"]["counter"],
+            7
+        );
+
+        let unknown = CredentialId::parse(vec![1; 16]).unwrap();
+        assert_eq!(
+            store
+                .update_passkey(&unknown, passkey(&unknown, 1))
+                .await
+                .unwrap_err(),
+            WebauthnCredentialStoreError::CredentialNotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_credentials() {
+        let mut store = HashmapWebauthnCredentialStore::default();
+        let user_id = UserId::default();
+        let other_credential = credential(UserId::default());
+        store.add_credential(credential(user_id)).await.unwrap();
+        store
+            .add_credential(other_credential.clone())
+            .await
+            .unwrap();
+
+        let result = store.remove_credentials(&user_id).await;
+        assert!(result.is_ok());
+        assert!(store.get_credentials(&user_id).await.unwrap().is_empty());
+        assert!(store.get_credential(&other_credential.id).await.is_ok());
+    }
+}
@@ -14,25 +14,23 @@
    limitations under the License.
 */
 
-use secrecy::{ExposeSecret, Secret};
 use std::collections::HashSet;
 
 use crate::domain::data_stores::{BannedTokenStore, BannedTokenStoreError};
 
 #[derive(Default)]
 pub struct HashsetBannedTokenStore {
-    tokens: HashSet<String>,
+    jtis: HashSet<String>,
 }
 
 #[async_trait::async_trait]
 impl BannedTokenStore for HashsetBannedTokenStore {
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
-        self.tokens.insert(token.expose_secret().to_owned());
-        Ok(())
+    async fn add_token(&mut self, jti: String) -> Result<bool, BannedTokenStoreError> {
+        Ok(self.jtis.insert(jti))
     }
 
-    async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError> {
-        Ok(self.tokens.contains(token.expose_secret()))
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        Ok(self.jtis.contains(jti))
     }
 }
 
@@ -43,19 +41,24 @@ mod tests {
     #[tokio::test]
     async fn test_add_token() {
         let mut store = HashsetBannedTokenStore::default();
-        let token = Secret::new("the_token".to_owned());
-        let result = store.add_token(token.clone()).await;
+        let jti = uuid::Uuid::new_v4().to_string();
+        let result = store.add_token(jti.clone()).await;
 
-        assert!(result.is_ok());
-        assert!(store.tokens.contains(token.expose_secret()))
+        assert!(result.unwrap());
+        assert!(store.jtis.contains(&jti));
+
+        // banning it again tells the caller it was used up already
+        let result = store.add_token(jti).await;
+        assert!(!result.unwrap());
     }
 
     #[tokio::test]
     async fn test_contains_token() {
         let mut store = HashsetBannedTokenStore::default();
-        let token = Secret::new("the_token".to_owned());
-        store.tokens.insert(token.expose_secret().to_owned());
-        let result = store.contains_token(&token).await;
+        let jti = uuid::Uuid::new_v4().to_string();
+        store.jtis.insert(jti.clone());
+        let result = store.contains_token(&jti).await;
         assert!(result.unwrap());
+        assert!(!store.contains_token("another_jti").await.unwrap());
     }
 }
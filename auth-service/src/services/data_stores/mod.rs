@@ -14,16 +14,68 @@
    limitations under the License.
 */
 
+mod hashmap_authorization_code_store;
+mod hashmap_email_cooldown_store;
+mod hashmap_external_identity_store;
+mod hashmap_failed_login_store;
+mod hashmap_oauth_client_store;
+mod hashmap_oauth_state_store;
+mod hashmap_permission_store;
+mod hashmap_rate_limit_store;
+mod hashmap_recovery_code_store;
+mod hashmap_refresh_token_store;
+mod hashmap_session_store;
 mod hashmap_two_fa_code_store;
 mod hashmap_user_store;
+mod hashmap_webauthn_challenge_store;
+mod hashmap_webauthn_credential_store;
 mod hashset_banned_token_store;
+mod postgres_external_identity_store;
+mod postgres_oauth_client_store;
+mod postgres_permission_store;
+mod postgres_recovery_code_store;
 mod postgres_user_store;
+mod postgres_webauthn_credential_store;
+mod redis_authorization_code_store;
 mod redis_banned_token_store;
+mod redis_email_cooldown_store;
+mod redis_failed_login_store;
+mod redis_oauth_state_store;
+mod redis_rate_limit_store;
+mod redis_refresh_token_store;
+mod redis_session_store;
 mod redis_two_fa_code_store;
+mod redis_webauthn_challenge_store;
 
+pub use hashmap_authorization_code_store::*;
+pub use hashmap_email_cooldown_store::*;
+pub use hashmap_external_identity_store::*;
+pub use hashmap_failed_login_store::*;
+pub use hashmap_oauth_client_store::*;
+pub use hashmap_oauth_state_store::*;
+pub use hashmap_permission_store::*;
+pub use hashmap_rate_limit_store::*;
+pub use hashmap_recovery_code_store::*;
+pub use hashmap_refresh_token_store::*;
+pub use hashmap_session_store::*;
 pub use hashmap_two_fa_code_store::*;
 pub use hashmap_user_store::*;
+pub use hashmap_webauthn_challenge_store::*;
+pub use hashmap_webauthn_credential_store::*;
 pub use hashset_banned_token_store::*;
+pub use postgres_external_identity_store::*;
+pub use postgres_oauth_client_store::*;
+pub use postgres_permission_store::*;
+pub use postgres_recovery_code_store::*;
 pub use postgres_user_store::*;
+pub use postgres_webauthn_credential_store::*;
+pub use redis_authorization_code_store::*;
 pub use redis_banned_token_store::*;
+pub use redis_email_cooldown_store::*;
+pub use redis_failed_login_store::*;
+pub use redis_oauth_state_store::*;
+pub use redis_rate_limit_store::*;
+pub use redis_refresh_token_store::*;
+pub use redis_session_store::*;
 pub use redis_two_fa_code_store::*;
+pub use redis_webauthn_challenge_store::*;
@@ -0,0 +1,96 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::domain::{
+    data_stores::{ExternalIdentityStore, ExternalIdentityStoreError},
+    ExternalIdentity, UserId,
+};
+use sqlx::PgPool;
+
+pub struct PostgresExternalIdentityStore {
+    pool: PgPool,
+}
+
+impl PostgresExternalIdentityStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl ExternalIdentityStore for PostgresExternalIdentityStore {
+    #[tracing::instrument(name = "Adding external identity to PostgreSQL", skip_all)]
+    async fn add_identity(
+        &mut self,
+        identity: ExternalIdentity,
+    ) -> Result<(), ExternalIdentityStoreError> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO external_identities (provider, subject, user_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (provider, subject) DO NOTHING
+            "#,
+            identity.provider,
+            identity.subject,
+            identity.user_id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| ExternalIdentityStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(ExternalIdentityStoreError::IdentityAlreadyLinked);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving external identity from PostgreSQL", skip_all)]
+    async fn get_user_id(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UserId, ExternalIdentityStoreError> {
+        sqlx::query!(
+            r#"
+            SELECT user_id FROM external_identities WHERE provider = $1 AND subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ExternalIdentityStoreError::UnexpectedError(e.into()))?
+        .map(|row| row.user_id.into())
+        .ok_or(ExternalIdentityStoreError::IdentityNotFound)
+    }
+
+    #[tracing::instrument(name = "Removing external identities from PostgreSQL", skip_all)]
+    async fn remove_identities(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), ExternalIdentityStoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM external_identities WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| ExternalIdentityStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::domain::{
+    data_stores::{OAuthClientStore, OAuthClientStoreError},
+    ClientId, GrantType, OAuthClient, Scopes,
+};
+use color_eyre::eyre::Result;
+use sqlx::PgPool;
+
+pub struct PostgresOAuthClientStore {
+    pool: PgPool,
+}
+
+impl PostgresOAuthClientStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl OAuthClientStore for PostgresOAuthClientStore {
+    #[tracing::instrument(name = "Adding OAuth client to PostgreSQL", skip_all)]
+    async fn add_client(&mut self, client: OAuthClient) -> Result<(), OAuthClientStoreError> {
+        let grant_types: Vec<String> = client
+            .grant_types
+            .iter()
+            .map(|grant_type| grant_type.as_str().to_owned())
+            .collect();
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO oauth_clients (id, name, secret_hash, redirect_uris, grant_types, scope)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+            client.id.as_ref(),
+            client.name,
+            client.secret_hash,
+            &client.redirect_uris,
+            &grant_types,
+            client.scopes.to_string()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| OAuthClientStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(OAuthClientStoreError::ClientAlreadyExists);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving OAuth client from PostgreSQL", skip_all)]
+    async fn get_client(&self, id: &ClientId) -> Result<OAuthClient, OAuthClientStoreError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, name, secret_hash, redirect_uris, grant_types, scope
+            FROM oauth_clients WHERE id = $1
+            "#,
+            id.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| OAuthClientStoreError::UnexpectedError(e.into()))?
+        .ok_or(OAuthClientStoreError::ClientNotFound)?;
+
+        let grant_types = row
+            .grant_types
+            .iter()
+            .map(|grant_type| GrantType::parse(grant_type))
+            .collect::<Result<Vec<_>>>()
+            .map_err(OAuthClientStoreError::UnexpectedError)?;
+        let scopes = Scopes::parse(&row.scope).map_err(OAuthClientStoreError::UnexpectedError)?;
+
+        Ok(OAuthClient {
+            id: row.id.into(),
+            name: row.name,
+            secret_hash: row.secret_hash,
+            redirect_uris: row.redirect_uris,
+            grant_types,
+            scopes,
+        })
+    }
+}
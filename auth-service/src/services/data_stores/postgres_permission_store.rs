@@ -0,0 +1,181 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::domain::{
+    data_stores::{PermissionStore, PermissionStoreError},
+    Grants, Permission, Role, UserId,
+};
+use sqlx::PgPool;
+
+pub struct PostgresPermissionStore {
+    pool: PgPool,
+}
+
+impl PostgresPermissionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl PermissionStore for PostgresPermissionStore {
+    #[tracing::instrument(name = "Granting role in PostgreSQL", skip_all)]
+    async fn grant_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_roles (user_id, role)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, role) DO NOTHING
+            "#,
+            user_id.as_ref(),
+            role.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Revoking role in PostgreSQL", skip_all)]
+    async fn revoke_role(
+        &mut self,
+        user_id: &UserId,
+        role: &Role,
+    ) -> Result<(), PermissionStoreError> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM user_roles WHERE user_id = $1 AND role = $2
+            "#,
+            user_id.as_ref(),
+            role.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(PermissionStoreError::GrantNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Granting permission in PostgreSQL", skip_all)]
+    async fn grant_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_permissions (user_id, permission)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, permission) DO NOTHING
+            "#,
+            user_id.as_ref(),
+            permission.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Revoking permission in PostgreSQL", skip_all)]
+    async fn revoke_permission(
+        &mut self,
+        user_id: &UserId,
+        permission: &Permission,
+    ) -> Result<(), PermissionStoreError> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM user_permissions WHERE user_id = $1 AND permission = $2
+            "#,
+            user_id.as_ref(),
+            permission.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(PermissionStoreError::GrantNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving grants from PostgreSQL", skip_all)]
+    async fn get_grants(&self, user_id: &UserId) -> Result<Grants, PermissionStoreError> {
+        let roles = sqlx::query!(
+            r#"
+            SELECT role FROM user_roles WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(|row| Role::parse(&row.role))
+        .collect::<Result<_, _>>()
+        .map_err(PermissionStoreError::UnexpectedError)?;
+
+        let permissions = sqlx::query!(
+            r#"
+            SELECT permission FROM user_permissions WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(|row| Permission::parse(&row.permission))
+        .collect::<Result<_, _>>()
+        .map_err(PermissionStoreError::UnexpectedError)?;
+
+        Ok(Grants { roles, permissions })
+    }
+
+    #[tracing::instrument(name = "Removing grants from PostgreSQL", skip_all)]
+    async fn remove_grants(&mut self, user_id: &UserId) -> Result<(), PermissionStoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM user_roles WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM user_permissions WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PermissionStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{
+    data_stores::{RecoveryCodeStore, RecoveryCodeStoreError},
+    PasswordHasherType, RecoveryCode, UserId,
+};
+
+pub struct PostgresRecoveryCodeStore {
+    pool: PgPool,
+    password_hasher: PasswordHasherType,
+}
+
+impl PostgresRecoveryCodeStore {
+    pub fn new(pool: PgPool, password_hasher: PasswordHasherType) -> Self {
+        Self {
+            pool,
+            password_hasher,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RecoveryCodeStore for PostgresRecoveryCodeStore {
+    #[tracing::instrument(name = "Replacing recovery codes in PostgreSQL", skip_all)]
+    async fn replace_codes(
+        &mut self,
+        user_id: &UserId,
+        codes: Vec<RecoveryCode>,
+    ) -> Result<(), RecoveryCodeStoreError> {
+        let mut code_hashes = Vec::with_capacity(codes.len());
+        for code in codes {
+            let code_hash = self
+                .password_hasher
+                .hash(code.as_ref().to_owned())
+                .await
+                .map_err(RecoveryCodeStoreError::UnexpectedError)?;
+            code_hashes.push(code_hash.expose_secret().to_owned());
+        }
+        let ids: Vec<Uuid> = code_hashes.iter().map(|_| Uuid::new_v4()).collect();
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM recovery_codes WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (id, user_id, code_hash)
+            SELECT id, $2, code_hash FROM UNNEST($1::UUID[], $3::TEXT[]) AS codes(id, code_hash)
+            "#,
+            &ids,
+            user_id.as_ref(),
+            &code_hashes
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))
+    }
+
+    #[tracing::instrument(name = "Using recovery code in PostgreSQL", skip_all)]
+    async fn use_code(
+        &mut self,
+        user_id: &UserId,
+        code: &RecoveryCode,
+    ) -> Result<(), RecoveryCodeStoreError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, code_hash FROM recovery_codes WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        for row in rows {
+            let matches = self
+                .password_hasher
+                .verify(Secret::new(row.code_hash), code.as_ref().to_owned())
+                .await
+                .is_ok();
+            if !matches {
+                continue;
+            }
+
+            // a concurrent request may have used the same code in the meantime
+            let result = sqlx::query!(
+                r#"
+                DELETE FROM recovery_codes WHERE id = $1
+                "#,
+                row.id
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+            return match result.rows_affected() {
+                0 => Err(RecoveryCodeStoreError::InvalidRecoveryCode),
+                _ => Ok(()),
+            };
+        }
+        Err(RecoveryCodeStoreError::InvalidRecoveryCode)
+    }
+
+    #[tracing::instrument(name = "Counting recovery codes in PostgreSQL", skip_all)]
+    async fn count_codes(&self, user_id: &UserId) -> Result<usize, RecoveryCodeStoreError> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM recovery_codes WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        Ok(count as usize)
+    }
+}
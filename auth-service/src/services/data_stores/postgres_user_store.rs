@@ -16,23 +16,25 @@
 
 use crate::domain::{
     data_stores::{UserStore, UserStoreError},
-    Email, Password, User,
+    Email, ExistingPassword, Password, PasswordHasherType, TotpSecret, TwoFAMethod, User, UserId,
 };
-use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
-    PasswordVerifier, Version,
-};
-use color_eyre::eyre::{Context, Result};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
+use uuid::Uuid;
 
 pub struct PostgresUserStore {
     pool: PgPool,
+    password_hasher: PasswordHasherType,
 }
 
 impl PostgresUserStore {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(pool: PgPool, password_hasher: PasswordHasherType) -> Self {
+        Self {
+            pool,
+            password_hasher,
+        }
     }
 }
 
@@ -40,17 +42,25 @@ impl PostgresUserStore {
 impl UserStore for PostgresUserStore {
     #[tracing::instrument(name = "Adding user to PostgreSQL", skip_all)]
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        let password_hash = compute_password_hash(user.password.as_ref().to_owned())
+        let password_hash = self
+            .password_hasher
+            .hash(user.password.as_ref().to_owned())
             .await
             .map_err(UserStoreError::UnexpectedError)?;
 
         sqlx::query!(
             r#"
-            INSERT INTO USERS (email, password_hash, require_2fa) SELECT $1, $2, $3
+            INSERT INTO USERS (id, email, password_hash, two_fa_method, totp_secret, verified)
+            SELECT $1, $2, $3, $4, $5, $6
             "#,
+            user.id.as_ref(),
             user.email.as_ref().expose_secret(),
             &password_hash.expose_secret(),
-            user.require_2fa
+            user.two_fa_method.as_str(),
+            user.totp_secret
+                .as_ref()
+                .map(|secret| secret.as_ref().expose_secret().to_owned()),
+            user.verified
         )
         .execute(&self.pool)
         .await
@@ -61,85 +71,328 @@ impl UserStore for PostgresUserStore {
 
     #[tracing::instrument(name = "Retrieving user from PostgreSQL", skip_all)]
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
-        sqlx::query!(
+        sqlx::query_as!(
+            UserRow,
             r#"
-            SELECT email, password_hash, require_2fa FROM users WHERE email = $1
+            SELECT id, email, password_hash, two_fa_method, totp_secret, verified, purge_at
+            FROM users WHERE email = $1
             "#,
             email.as_ref().expose_secret()
         )
         .fetch_optional(&self.pool)
         .await
         .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
-        .map(|row| {
-            Ok(User {
-                email: Email::parse(Secret::new(row.email))
-                    .map_err(UserStoreError::UnexpectedError)?,
-                password: Password::parse(Secret::new(row.password_hash))
-                    .map_err(UserStoreError::UnexpectedError)?,
-                require_2fa: row.require_2fa,
-            })
-        })
         .ok_or(UserStoreError::UserNotFound)?
+        .try_into()
+        .map_err(UserStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Retrieving user by id from PostgreSQL", skip_all)]
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        sqlx::query_as!(
+            UserRow,
+            r#"
+            SELECT id, email, password_hash, two_fa_method, totp_secret, verified, purge_at
+            FROM users WHERE id = $1
+            "#,
+            id.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+        .ok_or(UserStoreError::UserNotFound)?
+        .try_into()
+        .map_err(UserStoreError::UnexpectedError)
     }
 
     #[tracing::instrument(name = "Validating user credentials in PostgreSQL", skip_all)]
     async fn validate_user(
         &self,
         email: &Email,
-        password: &Password,
+        password: &ExistingPassword,
     ) -> Result<(), UserStoreError> {
         let user = self.get_user(email).await?;
-        verify_password_hash(
-            user.password.as_ref().to_owned(),
-            password.as_ref().to_owned(),
+        let needs_rehash = self
+            .password_hasher
+            .verify_existing(user.password.as_ref(), password)
+            .await
+            .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        if needs_rehash {
+            // the login goes ahead either way, and the hash is upgraded at the next one instead
+            if let Err(e) = self
+                .upgrade_password_hash(&user, &password.normalized)
+                .await
+            {
+                tracing::error!("failed to upgrade password hash: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Storing TOTP secret in PostgreSQL", skip_all)]
+    async fn set_totp_secret(
+        &mut self,
+        id: &UserId,
+        secret: TotpSecret,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET totp_secret = $2, totp_last_step = NULL WHERE id = $1
+            "#,
+            id.as_ref(),
+            secret.as_ref().expose_secret()
         )
+        .execute(&self.pool)
         .await
-        .map_err(|_| UserStoreError::InvalidCredentials)
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Updating 2FA method in PostgreSQL", skip_all)]
+    async fn set_two_fa_method(
+        &mut self,
+        id: &UserId,
+        method: TwoFAMethod,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET two_fa_method = $2 WHERE id = $1
+            "#,
+            id.as_ref(),
+            method.as_str()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Disabling 2FA in PostgreSQL", skip_all)]
+    async fn disable_two_fa(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET two_fa_method = $2, totp_secret = NULL, totp_last_step = NULL
+            WHERE id = $1
+            "#,
+            id.as_ref(),
+            TwoFAMethod::None.as_str()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Recording TOTP step in PostgreSQL", skip_all)]
+    async fn use_totp_step(&mut self, id: &UserId, step: u64) -> Result<(), UserStoreError> {
+        let step = i64::try_from(step).map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+        // checked and recorded in one statement, so two requests can't both use the same step
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET totp_last_step = $2
+            WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            "#,
+            id.as_ref(),
+            step
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::InvalidCredentials);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Updating password in PostgreSQL", skip_all)]
+    async fn set_password(
+        &mut self,
+        id: &UserId,
+        password: Password,
+    ) -> Result<(), UserStoreError> {
+        let password_hash = self
+            .password_hasher
+            .hash(password.as_ref().to_owned())
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = $2 WHERE id = $1
+            "#,
+            id.as_ref(),
+            password_hash.expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Marking user as verified in PostgreSQL", skip_all)]
+    async fn set_verified(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET verified = TRUE WHERE id = $1
+            "#,
+            id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Marking user for deletion in PostgreSQL", skip_all)]
+    async fn mark_for_deletion(
+        &mut self,
+        id: &UserId,
+        purge_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET purge_at = $2 WHERE id = $1
+            "#,
+            id.as_ref(),
+            purge_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Restoring user in PostgreSQL", skip_all)]
+    async fn restore_user(&mut self, id: &UserId) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET purge_at = NULL WHERE id = $1
+            "#,
+            id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Deleting due users from PostgreSQL", skip_all)]
+    async fn delete_due_users(&mut self, now: DateTime<Utc>) -> Result<Vec<User>, UserStoreError> {
+        // a single statement, so an account restored in the meantime is never deleted
+        sqlx::query_as!(
+            UserRow,
+            r#"
+            DELETE FROM users WHERE purge_at <= $1
+            RETURNING id, email, password_hash, two_fa_method, totp_secret, verified, purge_at
+            "#,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(|row| row.try_into().map_err(UserStoreError::UnexpectedError))
+        .collect()
+    }
+
+    #[tracing::instrument(name = "Counting outdated password hashes in PostgreSQL", skip_all)]
+    async fn count_outdated_password_hashes(&self) -> Result<usize, UserStoreError> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM users WHERE NOT starts_with(password_hash, $1)
+            "#,
+            self.password_hasher.current_prefix()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        Ok(count as usize)
     }
 }
 
-#[tracing::instrument(name = "Computing password hash", skip_all)]
-async fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>> {
-    let current_span: tracing::Span = tracing::Span::current();
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
-            let password_hash = Argon2::new(
-                Algorithm::Argon2id,
-                Version::V0x13,
-                Params::new(15000, 2, 1, None)?,
-            )
-            .hash_password(password.expose_secret().as_bytes(), &salt)?
-            .to_string();
-
-            Ok(Secret::new(password_hash))
-        })
-    })
-    .await;
+impl PostgresUserStore {
+    #[tracing::instrument(name = "Upgrading password hash in PostgreSQL", skip_all)]
+    async fn upgrade_password_hash(&self, user: &User, password: &Password) -> Result<()> {
+        let password_hash = self
+            .password_hasher
+            .hash(password.as_ref().to_owned())
+            .await?;
+
+        // unless the password was changed in the meantime
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = $2 WHERE id = $1 AND password_hash = $3
+            "#,
+            user.id.as_ref(),
+            password_hash.expose_secret(),
+            user.password.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await?;
 
-    result?
+        if result.rows_affected() > 0 {
+            tracing::info!("upgraded password hash of user {}", user.id);
+        }
+        Ok(())
+    }
 }
 
-#[tracing::instrument(name = "Verify password hash", skip_all)]
-async fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<()> {
-    let current_span: tracing::Span = tracing::Span::current();
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let expected_password_hash: PasswordHash<'_> =
-                PasswordHash::new(expected_password_hash.expose_secret())?;
-
-            Argon2::default()
-                .verify_password(
-                    password_candidate.expose_secret().as_bytes(),
-                    &expected_password_hash,
-                )
-                .wrap_err("failed to verify password hash")
-        })
-    })
-    .await;
+struct UserRow {
+    id: Uuid,
+    email: String,
+    password_hash: String,
+    two_fa_method: String,
+    totp_secret: Option<String>,
+    verified: bool,
+    purge_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<UserRow> for User {
+    type Error = color_eyre::eyre::Report;
 
-    result?
+    fn try_from(row: UserRow) -> Result<Self, Self::Error> {
+        Ok(User {
+            id: row.id.into(),
+            email: Email::parse(Secret::new(row.email))?,
+            password: Password::parse(Secret::new(row.password_hash))?,
+            two_fa_method: TwoFAMethod::parse(&row.two_fa_method)?,
+            totp_secret: row
+                .totp_secret
+                .map(|secret| TotpSecret::parse(Secret::new(secret)))
+                .transpose()?,
+            verified: row.verified,
+            purge_at: row.purge_at,
+        })
+    }
 }
@@ -0,0 +1,170 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::domain::{
+    data_stores::{WebauthnCredentialStore, WebauthnCredentialStoreError},
+    CredentialId, UserId, WebauthnCredential,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use sqlx::PgPool;
+use uuid::Uuid;
+use webauthn_rs::prelude::Passkey;
+
+pub struct PostgresWebauthnCredentialStore {
+    pool: PgPool,
+}
+
+impl PostgresWebauthnCredentialStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebauthnCredentialStore for PostgresWebauthnCredentialStore {
+    #[tracing::instrument(name = "Adding WebAuthn credential to PostgreSQL", skip_all)]
+    async fn add_credential(
+        &mut self,
+        credential: WebauthnCredential,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        let passkey = serialize_passkey(&credential.passkey)?;
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO webauthn_credentials (id, user_id, passkey, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+            credential.id.as_ref(),
+            credential.user_id.as_ref(),
+            passkey,
+            credential.created_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| WebauthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebauthnCredentialStoreError::CredentialAlreadyExists);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving WebAuthn credential from PostgreSQL", skip_all)]
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<WebauthnCredential, WebauthnCredentialStoreError> {
+        sqlx::query!(
+            r#"
+            SELECT id, user_id, passkey, created_at
+            FROM webauthn_credentials WHERE id = $1
+            "#,
+            id.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| WebauthnCredentialStoreError::UnexpectedError(e.into()))?
+        .map(|row| to_credential(row.id, row.user_id, &row.passkey, row.created_at))
+        .ok_or(WebauthnCredentialStoreError::CredentialNotFound)?
+        .map_err(WebauthnCredentialStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(
+        name = "Retrieving user WebAuthn credentials from PostgreSQL",
+        skip_all
+    )]
+    async fn get_credentials(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<WebauthnCredential>, WebauthnCredentialStoreError> {
+        sqlx::query!(
+            r#"
+            SELECT id, user_id, passkey, created_at
+            FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at
+            "#,
+            user_id.as_ref()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| WebauthnCredentialStoreError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(|row| to_credential(row.id, row.user_id, &row.passkey, row.created_at))
+        .collect::<Result<_>>()
+        .map_err(WebauthnCredentialStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Updating WebAuthn passkey in PostgreSQL", skip_all)]
+    async fn update_passkey(
+        &mut self,
+        id: &CredentialId,
+        passkey: Passkey,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        let passkey = serialize_passkey(&passkey)?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE webauthn_credentials SET passkey = $2 WHERE id = $1
+            "#,
+            id.as_ref(),
+            passkey
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| WebauthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebauthnCredentialStoreError::CredentialNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Removing WebAuthn credentials from PostgreSQL", skip_all)]
+    async fn remove_credentials(
+        &mut self,
+        user_id: &UserId,
+    ) -> Result<(), WebauthnCredentialStoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM webauthn_credentials WHERE user_id = $1
+            "#,
+            user_id.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| WebauthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+}
+
+fn serialize_passkey(passkey: &Passkey) -> Result<String, WebauthnCredentialStoreError> {
+    serde_json::to_string(passkey)
+        .wrap_err("failed to serialize passkey")
+        .map_err(WebauthnCredentialStoreError::UnexpectedError)
+}
+
+fn to_credential(
+    id: Vec<u8>,
+    user_id: Uuid,
+    passkey: &str,
+    created_at: DateTime<Utc>,
+) -> Result<WebauthnCredential> {
+    Ok(WebauthnCredential {
+        id: CredentialId::parse(id)?,
+        user_id: user_id.into(),
+        passkey: serde_json::from_str(passkey).wrap_err("failed to deserialize passkey")?,
+        created_at,
+    })
+}
@@ -0,0 +1,137 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::{
+    domain::{
+        data_stores::{AuthorizationCodeStore, AuthorizationCodeStoreError},
+        AuthorizationCode, AuthorizationGrant, ClientId, Scopes, SessionId, UserId,
+    },
+    utils::authorization::AUTHORIZATION_CODE_TTL_SECONDS,
+};
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+
+pub struct RedisAuthorizationCodeStore {
+    conn: MultiplexedConnection,
+}
+
+impl RedisAuthorizationCodeStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthorizationCodeStore for RedisAuthorizationCodeStore {
+    #[tracing::instrument(name = "Storing authorization code in Redis", skip_all)]
+    async fn add_code(
+        &mut self,
+        code: AuthorizationCode,
+        grant: AuthorizationGrant,
+    ) -> Result<(), AuthorizationCodeStoreError> {
+        let serialized_grant = serde_json::to_string(&StoredGrant::from(&grant))
+            .wrap_err("failed to serialize authorization grant")
+            .map_err(AuthorizationCodeStoreError::UnexpectedError)?;
+
+        let mut conn = self.conn.clone();
+        let _: () = conn
+            .set_ex(
+                get_key(&code),
+                serialized_grant,
+                AUTHORIZATION_CODE_TTL_SECONDS as u64,
+            )
+            .await
+            .wrap_err("failed to set authorization code in Redis")
+            .map_err(AuthorizationCodeStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Taking authorization code from Redis", skip_all)]
+    async fn take_code(
+        &mut self,
+        code: &AuthorizationCode,
+    ) -> Result<AuthorizationGrant, AuthorizationCodeStoreError> {
+        let mut conn = self.conn.clone();
+        // GETDEL makes sure two token requests racing with the same code can't both succeed
+        let value: Option<String> = conn
+            .get_del(get_key(code))
+            .await
+            .wrap_err("failed to take authorization code from Redis")
+            .map_err(AuthorizationCodeStoreError::UnexpectedError)?;
+
+        let value = value.ok_or(AuthorizationCodeStoreError::CodeNotFound)?;
+        let stored: StoredGrant = serde_json::from_str(&value)
+            .wrap_err("failed to deserialize authorization grant")
+            .map_err(AuthorizationCodeStoreError::UnexpectedError)?;
+
+        stored
+            .try_into()
+            .map_err(AuthorizationCodeStoreError::UnexpectedError)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredGrant {
+    client_id: String,
+    redirect_uri: String,
+    user_id: String,
+    session_id: String,
+    scope: String,
+    code_challenge: String,
+    nonce: Option<String>,
+}
+
+impl From<&AuthorizationGrant> for StoredGrant {
+    fn from(grant: &AuthorizationGrant) -> Self {
+        Self {
+            client_id: grant.client_id.to_string(),
+            redirect_uri: grant.redirect_uri.clone(),
+            user_id: grant.user_id.to_string(),
+            session_id: grant.session_id.as_ref().to_owned(),
+            scope: grant.scopes.to_string(),
+            code_challenge: grant.code_challenge.clone(),
+            nonce: grant.nonce.clone(),
+        }
+    }
+}
+
+impl TryFrom<StoredGrant> for AuthorizationGrant {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(stored: StoredGrant) -> Result<Self, Self::Error> {
+        Ok(Self {
+            client_id: ClientId::parse(&stored.client_id)?,
+            redirect_uri: stored.redirect_uri,
+            user_id: UserId::parse(&stored.user_id)?,
+            session_id: SessionId::parse(stored.session_id)?,
+            scopes: Scopes::parse(&stored.scope)?,
+            code_challenge: stored.code_challenge,
+            nonce: stored.nonce,
+        })
+    }
+}
+
+const AUTHORIZATION_CODE_KEY_PREFIX: &str = "authorization_code:";
+
+fn get_key(code: &AuthorizationCode) -> String {
+    format!(
+        "{}{}",
+        AUTHORIZATION_CODE_KEY_PREFIX,
+        code.as_ref().expose_secret()
+    )
+}
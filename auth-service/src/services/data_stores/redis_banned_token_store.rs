@@ -19,8 +19,7 @@ use crate::{
     utils::auth::TOKEN_TTL_SECONDS,
 };
 use color_eyre::eyre::Context;
-use redis::{aio::MultiplexedConnection, AsyncCommands};
-use secrecy::{ExposeSecret, Secret};
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
 
 pub struct RedisBannedTokenStore {
     conn: MultiplexedConnection,
@@ -35,25 +34,29 @@ impl RedisBannedTokenStore {
 #[async_trait::async_trait]
 impl BannedTokenStore for RedisBannedTokenStore {
     #[tracing::instrument(name = "Storing banned JWT in Redis", skip_all)]
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
-        let token_key = get_key(token.expose_secret());
+    async fn add_token(&mut self, jti: String) -> Result<bool, BannedTokenStoreError> {
+        let token_key = get_key(&jti);
         let value = true;
         let ttl: u64 = TOKEN_TTL_SECONDS
             .try_into()
             .wrap_err("failed to cast TOKEN_TTL_SECONDS to u64")
             .map_err(BannedTokenStoreError::UnexpectedError)?;
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(ttl));
         let mut conn = self.conn.clone();
-        let _: () = conn
-            .set_ex(&token_key, value, ttl)
+        // NX answers nil instead of OK when the token was banned already
+        let newly_banned: Option<String> = conn
+            .set_options(&token_key, value, options)
             .await
             .wrap_err("failed to set banned token in Redis")
             .map_err(BannedTokenStoreError::UnexpectedError)?;
-        Ok(())
+        Ok(newly_banned.is_some())
     }
 
     #[tracing::instrument(name = "Checking for banned JWT in Redis", skip_all)]
-    async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError> {
-        let token_key = get_key(token.expose_secret());
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        let token_key = get_key(jti);
 
         let mut conn = self.conn.clone();
         let is_banned: bool = conn
@@ -68,6 +71,6 @@ impl BannedTokenStore for RedisBannedTokenStore {
 
 const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
 
-fn get_key(token: &str) -> String {
-    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, token)
+fn get_key(jti: &str) -> String {
+    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, jti)
 }
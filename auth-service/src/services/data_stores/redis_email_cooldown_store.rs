@@ -0,0 +1,68 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::{
+    domain::{
+        data_stores::{EmailCooldownStore, EmailCooldownStoreError},
+        Email,
+    },
+    utils::email_verification::VERIFICATION_EMAIL_COOLDOWN_SECONDS,
+};
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use secrecy::ExposeSecret;
+
+pub struct RedisEmailCooldownStore {
+    conn: MultiplexedConnection,
+}
+
+impl RedisEmailCooldownStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailCooldownStore for RedisEmailCooldownStore {
+    #[tracing::instrument(name = "Starting email cooldown in Redis", skip_all)]
+    async fn start_cooldown(&mut self, email: &Email) -> Result<(), EmailCooldownStoreError> {
+        // SET NX only succeeds for the first of two concurrent requests
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(VERIFICATION_EMAIL_COOLDOWN_SECONDS as u64));
+
+        let mut conn = self.conn.clone();
+        let started: Option<String> = conn
+            .set_options(get_key(email), true, options)
+            .await
+            .wrap_err("failed to set email cooldown in Redis")
+            .map_err(EmailCooldownStoreError::UnexpectedError)?;
+
+        match started {
+            Some(_) => Ok(()),
+            None => Err(EmailCooldownStoreError::CooldownActive),
+        }
+    }
+}
+
+const EMAIL_COOLDOWN_KEY_PREFIX: &str = "email_cooldown:";
+
+fn get_key(email: &Email) -> String {
+    format!(
+        "{}{}",
+        EMAIL_COOLDOWN_KEY_PREFIX,
+        email.as_ref().expose_secret()
+    )
+}
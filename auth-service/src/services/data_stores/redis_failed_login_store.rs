@@ -0,0 +1,174 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::{
+    domain::data_stores::{FailedLoginKey, FailedLoginStore, FailedLoginStoreError, FailedLogins},
+    utils::login_throttle::FAILED_LOGIN_TTL_SECONDS,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use secrecy::ExposeSecret;
+
+/// Counts the attempt, moves the last failure and the expiry on, and returns the record as it was
+/// before. It runs as one script so that every replica's attempts are counted before any of them
+/// is decided on.
+const RESERVE_ATTEMPT_SCRIPT: &str = r"
+local previous = redis.call('HMGET', KEYS[1], 'count', 'last_failure_at')
+redis.call('HINCRBY', KEYS[1], 'count', 1)
+redis.call('HSET', KEYS[1], 'last_failure_at', ARGV[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return previous
+";
+
+/// Takes one attempt back, and puts the last failure back to ARGV[2] if no other attempt has been
+/// counted since the one being refunded, which saw a count of ARGV[1].
+const REFUND_ATTEMPT_SCRIPT: &str = r"
+local count = tonumber(redis.call('HGET', KEYS[1], 'count'))
+if not count or count == 0 then
+    return 0
+end
+
+if count == tonumber(ARGV[1]) + 1 then
+    if ARGV[2] == '' then
+        redis.call('DEL', KEYS[1])
+        return 0
+    end
+    redis.call('HSET', KEYS[1], 'last_failure_at', ARGV[2])
+end
+redis.call('HINCRBY', KEYS[1], 'count', -1)
+return 0
+";
+
+pub struct RedisFailedLoginStore {
+    conn: MultiplexedConnection,
+    reserve_script: Script,
+    refund_script: Script,
+}
+
+impl RedisFailedLoginStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self {
+            conn,
+            reserve_script: Script::new(RESERVE_ATTEMPT_SCRIPT),
+            refund_script: Script::new(REFUND_ATTEMPT_SCRIPT),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FailedLoginStore for RedisFailedLoginStore {
+    #[tracing::instrument(name = "Retrieving failed logins from Redis", skip_all)]
+    async fn get_failures(
+        &self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError> {
+        let mut conn = self.conn.clone();
+        let (count, last_failure_at): (Option<u32>, Option<i64>) = conn
+            .hget(get_key(key), &[COUNT_FIELD, LAST_FAILURE_AT_FIELD])
+            .await
+            .wrap_err("failed to get failed logins from Redis")
+            .map_err(FailedLoginStoreError::UnexpectedError)?;
+
+        to_failed_logins(count, last_failure_at)
+    }
+
+    #[tracing::instrument(name = "Reserving login attempt in Redis", skip_all)]
+    async fn reserve_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+    ) -> Result<FailedLogins, FailedLoginStoreError> {
+        let mut conn = self.conn.clone();
+        let (count, last_failure_at): (Option<u32>, Option<i64>) = self
+            .reserve_script
+            .key(get_key(key))
+            .arg(Utc::now().timestamp())
+            .arg(FAILED_LOGIN_TTL_SECONDS)
+            .invoke_async(&mut conn)
+            .await
+            .wrap_err("failed to reserve login attempt in Redis")
+            .map_err(FailedLoginStoreError::UnexpectedError)?;
+
+        to_failed_logins(count, last_failure_at)
+    }
+
+    #[tracing::instrument(name = "Refunding login attempt in Redis", skip_all)]
+    async fn refund_attempt(
+        &mut self,
+        key: &FailedLoginKey,
+        previous: &FailedLogins,
+    ) -> Result<(), FailedLoginStoreError> {
+        let last_failure_at = previous
+            .last_failure_at
+            .map(|last_failure_at| last_failure_at.timestamp().to_string())
+            .unwrap_or_default();
+
+        let mut conn = self.conn.clone();
+        let _: () = self
+            .refund_script
+            .key(get_key(key))
+            .arg(previous.count)
+            .arg(last_failure_at)
+            .invoke_async(&mut conn)
+            .await
+            .wrap_err("failed to refund login attempt in Redis")
+            .map_err(FailedLoginStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Clearing failed logins from Redis", skip_all)]
+    async fn clear_failures(&mut self, key: &FailedLoginKey) -> Result<(), FailedLoginStoreError> {
+        let mut conn = self.conn.clone();
+        let _: () = conn
+            .del(get_key(key))
+            .await
+            .wrap_err("failed to delete failed logins from Redis")
+            .map_err(FailedLoginStoreError::UnexpectedError)?;
+        Ok(())
+    }
+}
+
+const FAILED_LOGIN_KEY_PREFIX: &str = "failed_login:";
+const COUNT_FIELD: &str = "count";
+const LAST_FAILURE_AT_FIELD: &str = "last_failure_at";
+
+fn to_failed_logins(
+    count: Option<u32>,
+    last_failure_at: Option<i64>,
+) -> Result<FailedLogins, FailedLoginStoreError> {
+    let last_failure_at = last_failure_at
+        .map(|timestamp| {
+            DateTime::from_timestamp(timestamp, 0)
+                .ok_or_else(|| eyre!("invalid failed login timestamp"))
+        })
+        .transpose()
+        .map_err(FailedLoginStoreError::UnexpectedError)?;
+
+    Ok(FailedLogins {
+        count: count.unwrap_or_default(),
+        last_failure_at,
+    })
+}
+
+fn get_key(key: &FailedLoginKey) -> String {
+    match key {
+        FailedLoginKey::Account(email) => format!(
+            "{}account:{}",
+            FAILED_LOGIN_KEY_PREFIX,
+            email.as_ref().expose_secret()
+        ),
+        FailedLoginKey::Ip(ip) => format!("{}ip:{}", FAILED_LOGIN_KEY_PREFIX, ip),
+    }
+}
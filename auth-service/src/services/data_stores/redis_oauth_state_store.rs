@@ -0,0 +1,118 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::{
+    domain::{
+        data_stores::{OAuthStateStore, OAuthStateStoreError},
+        OAuthFlow, OAuthState, PkceVerifier,
+    },
+    services::oidc_client::OAUTH_STATE_TTL_SECONDS,
+};
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+pub struct RedisOAuthStateStore {
+    conn: MultiplexedConnection,
+}
+
+impl RedisOAuthStateStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl OAuthStateStore for RedisOAuthStateStore {
+    #[tracing::instrument(name = "Storing OAuth state in Redis", skip_all)]
+    async fn add_flow(
+        &mut self,
+        state: OAuthState,
+        flow: OAuthFlow,
+    ) -> Result<(), OAuthStateStoreError> {
+        let serialized_flow = serde_json::to_string(&StoredFlow::from(&flow))
+            .wrap_err("failed to serialize OAuth flow")
+            .map_err(OAuthStateStoreError::UnexpectedError)?;
+
+        let mut conn = self.conn.clone();
+        let _: () = conn
+            .set_ex(
+                get_key(&state),
+                serialized_flow,
+                OAUTH_STATE_TTL_SECONDS as u64,
+            )
+            .await
+            .wrap_err("failed to set OAuth state in Redis")
+            .map_err(OAuthStateStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Taking OAuth state from Redis", skip_all)]
+    async fn take_flow(&mut self, state: &OAuthState) -> Result<OAuthFlow, OAuthStateStoreError> {
+        let mut conn = self.conn.clone();
+        // GETDEL makes sure a replayed callback can't claim the state a second time
+        let value: Option<String> = conn
+            .get_del(get_key(state))
+            .await
+            .wrap_err("failed to take OAuth state from Redis")
+            .map_err(OAuthStateStoreError::UnexpectedError)?;
+
+        let value = value.ok_or(OAuthStateStoreError::StateNotFound)?;
+        let stored: StoredFlow = serde_json::from_str(&value)
+            .wrap_err("failed to deserialize OAuth flow")
+            .map_err(OAuthStateStoreError::UnexpectedError)?;
+
+        stored
+            .try_into()
+            .map_err(OAuthStateStoreError::UnexpectedError)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredFlow {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+}
+
+impl From<&OAuthFlow> for StoredFlow {
+    fn from(flow: &OAuthFlow) -> Self {
+        Self {
+            provider: flow.provider.clone(),
+            code_verifier: flow.code_verifier.as_ref().expose_secret().to_owned(),
+            nonce: flow.nonce.clone(),
+        }
+    }
+}
+
+impl TryFrom<StoredFlow> for OAuthFlow {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(stored: StoredFlow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            provider: stored.provider,
+            code_verifier: PkceVerifier::parse(Secret::new(stored.code_verifier))?,
+            nonce: stored.nonce,
+        })
+    }
+}
+
+const OAUTH_STATE_KEY_PREFIX: &str = "oauth_state:";
+
+fn get_key(state: &OAuthState) -> String {
+    format!("{}{}", OAUTH_STATE_KEY_PREFIX, state.as_ref())
+}
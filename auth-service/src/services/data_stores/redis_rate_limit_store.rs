@@ -0,0 +1,91 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, Script};
+
+use crate::domain::data_stores::{RateLimit, RateLimitStore, RateLimitStoreError};
+
+/// Refills the bucket, takes a token if there is one, and returns the milliseconds until there
+/// is otherwise. It runs as one script so that replicas can't race each other for the last token,
+/// and reads the time from Redis so their clocks don't have to agree.
+const TAKE_TOKEN_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated_at) * refill_per_ms)
+
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / refill_per_ms)
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / refill_per_ms))
+return wait
+";
+
+pub struct RedisRateLimitStore {
+    conn: MultiplexedConnection,
+    script: Script,
+}
+
+impl RedisRateLimitStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self {
+            conn,
+            script: Script::new(TAKE_TOKEN_SCRIPT),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    #[tracing::instrument(name = "Taking rate limit token in Redis", skip_all)]
+    async fn take_token(
+        &mut self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<(), RateLimitStoreError> {
+        let mut conn = self.conn.clone();
+        let wait_ms: u64 = self
+            .script
+            .key(get_key(key))
+            .arg(limit.requests)
+            .arg(limit.refill_rate() / 1000.0)
+            .invoke_async(&mut conn)
+            .await
+            .wrap_err("failed to take rate limit token in Redis")
+            .map_err(RateLimitStoreError::UnexpectedError)?;
+
+        match wait_ms {
+            0 => Ok(()),
+            wait_ms => Err(RateLimitStoreError::LimitExceeded(wait_ms.div_ceil(1000))),
+        }
+    }
+}
+
+const RATE_LIMIT_KEY_PREFIX: &str = "rate_limit:";
+
+fn get_key(key: &str) -> String {
+    format!("{}{}", RATE_LIMIT_KEY_PREFIX, key)
+}
@@ -0,0 +1,208 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use crate::{
+    domain::{
+        data_stores::{
+            RefreshToken, RefreshTokenData, RefreshTokenFamilyId, RefreshTokenStore,
+            RefreshTokenStoreError,
+        },
+        SessionId, UserId,
+    },
+    utils::auth::REFRESH_TOKEN_TTL_SECONDS,
+};
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+
+/// Returns the stored token and flags it as used. It runs as one script so that two replicas
+/// refreshing with the same token can't both read it before either marks it.
+const USE_TOKEN_SCRIPT: &str = r"
+local value = redis.call('GET', KEYS[1])
+if not value then
+    return false
+end
+
+local data = cjson.decode(value)
+if not data.used then
+    data.used = true
+    redis.call('SET', KEYS[1], cjson.encode(data), 'KEEPTTL')
+end
+return value
+";
+
+pub struct RedisRefreshTokenStore {
+    conn: MultiplexedConnection,
+    use_script: Script,
+}
+
+impl RedisRefreshTokenStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self {
+            conn,
+            use_script: Script::new(USE_TOKEN_SCRIPT),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenStore for RedisRefreshTokenStore {
+    #[tracing::instrument(name = "Storing refresh token in Redis", skip_all)]
+    async fn add_token(
+        &mut self,
+        token: RefreshToken,
+        data: RefreshTokenData,
+    ) -> Result<(), RefreshTokenStoreError> {
+        let token_key = get_token_key(&token);
+        let family_key = get_family_key(&data.family_id);
+        let serialized_data = serialize(&data)?;
+        let ttl: u64 = REFRESH_TOKEN_TTL_SECONDS
+            .try_into()
+            .wrap_err("failed to cast REFRESH_TOKEN_TTL_SECONDS to u64")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+        let mut conn = self.conn.clone();
+        let _: () = redis::pipe()
+            .atomic()
+            .set_ex(&token_key, serialized_data, ttl)
+            .ignore()
+            .sadd(&family_key, &token_key)
+            .ignore()
+            .expire(&family_key, REFRESH_TOKEN_TTL_SECONDS)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .wrap_err("failed to set refresh token in Redis")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving refresh token from Redis", skip_all)]
+    async fn get_token(
+        &self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError> {
+        let token_key = get_token_key(token);
+
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .get(&token_key)
+            .await
+            .wrap_err("failed to get refresh token from Redis")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+        match value {
+            Some(value) => deserialize(&value),
+            None => Err(RefreshTokenStoreError::TokenNotFound),
+        }
+    }
+
+    #[tracing::instrument(name = "Using refresh token in Redis", skip_all)]
+    async fn use_token(
+        &mut self,
+        token: &RefreshToken,
+    ) -> Result<RefreshTokenData, RefreshTokenStoreError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = self
+            .use_script
+            .key(get_token_key(token))
+            .invoke_async(&mut conn)
+            .await
+            .wrap_err("failed to use refresh token in Redis")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+        match value {
+            Some(value) => deserialize(&value),
+            None => Err(RefreshTokenStoreError::TokenNotFound),
+        }
+    }
+
+    #[tracing::instrument(name = "Revoking refresh token family in Redis", skip_all)]
+    async fn revoke_family(
+        &mut self,
+        family_id: &RefreshTokenFamilyId,
+    ) -> Result<(), RefreshTokenStoreError> {
+        let family_key = get_family_key(family_id);
+
+        let mut conn = self.conn.clone();
+        let mut keys: Vec<String> = conn
+            .smembers(&family_key)
+            .await
+            .wrap_err("failed to get refresh token family from Redis")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+        keys.push(family_key);
+
+        let _: () = conn
+            .del(keys)
+            .await
+            .wrap_err("failed to delete refresh token family from Redis")
+            .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRefreshToken {
+    user_id: String,
+    session_id: String,
+    family_id: String,
+    used: bool,
+}
+
+fn serialize(data: &RefreshTokenData) -> Result<String, RefreshTokenStoreError> {
+    let stored = StoredRefreshToken {
+        user_id: data.user_id.to_string(),
+        session_id: data.session_id.as_ref().to_owned(),
+        family_id: data.family_id.as_ref().to_owned(),
+        used: data.used,
+    };
+    serde_json::to_string(&stored)
+        .wrap_err("failed to serialize refresh token")
+        .map_err(RefreshTokenStoreError::UnexpectedError)
+}
+
+fn deserialize(value: &str) -> Result<RefreshTokenData, RefreshTokenStoreError> {
+    let stored: StoredRefreshToken = serde_json::from_str(value)
+        .wrap_err("failed to deserialize refresh token")
+        .map_err(RefreshTokenStoreError::UnexpectedError)?;
+
+    Ok(RefreshTokenData {
+        user_id: UserId::parse(&stored.user_id).map_err(RefreshTokenStoreError::UnexpectedError)?,
+        session_id: SessionId::parse(stored.session_id)
+            .map_err(RefreshTokenStoreError::UnexpectedError)?,
+        family_id: RefreshTokenFamilyId::parse(stored.family_id)
+            .map_err(RefreshTokenStoreError::UnexpectedError)?,
+        used: stored.used,
+    })
+}
+
+const REFRESH_TOKEN_KEY_PREFIX: &str = "refresh_token:";
+const REFRESH_TOKEN_FAMILY_KEY_PREFIX: &str = "refresh_token_family:";
+
+fn get_token_key(token: &RefreshToken) -> String {
+    format!(
+        "{}{}",
+        REFRESH_TOKEN_KEY_PREFIX,
+        token.as_ref().expose_secret()
+    )
+}
+
+fn get_family_key(family_id: &RefreshTokenFamilyId) -> String {
+    format!("{}{}", REFRESH_TOKEN_FAMILY_KEY_PREFIX, family_id.as_ref())
+}
@@ -0,0 +1,259 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use crate::{
+    domain::{
+        data_stores::{SessionStore, SessionStoreError},
+        Session, SessionId, UserId,
+    },
+    utils::auth::REFRESH_TOKEN_TTL_SECONDS,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+
+/// Overwrites the session only if it still exists, and extends the index with it. It runs as one
+/// script so that a touch racing a logout can't bring the session back or re-add it to the index.
+const TOUCH_SESSION_SCRIPT: &str = r"
+if not redis.call('SET', KEYS[1], ARGV[1], 'XX', 'EX', ARGV[2]) then
+    return 0
+end
+redis.call('EXPIRE', KEYS[2], ARGV[2])
+return 1
+";
+
+pub struct RedisSessionStore {
+    conn: MultiplexedConnection,
+    touch_script: Script,
+}
+
+impl RedisSessionStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self {
+            conn,
+            touch_script: Script::new(TOUCH_SESSION_SCRIPT),
+        }
+    }
+
+    async fn save(&mut self, session: &Session) -> Result<(), SessionStoreError> {
+        let session_key = get_session_key(&session.id);
+        let user_key = get_user_sessions_key(&session.user_id);
+        let serialized_session = serialize(session)?;
+        let ttl = session_ttl()?;
+
+        let mut conn = self.conn.clone();
+        let _: () = redis::pipe()
+            .atomic()
+            .set_ex(&session_key, serialized_session, ttl)
+            .ignore()
+            .sadd(&user_key, session.id.as_ref())
+            .ignore()
+            .expire(&user_key, REFRESH_TOKEN_TTL_SECONDS)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .wrap_err("failed to set session in Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &SessionId) -> Result<Session, SessionStoreError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .get(get_session_key(id))
+            .await
+            .wrap_err("failed to get session from Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        match value {
+            Some(value) => deserialize(&value),
+            None => Err(SessionStoreError::SessionNotFound),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for RedisSessionStore {
+    #[tracing::instrument(name = "Storing session in Redis", skip_all)]
+    async fn add_session(&mut self, session: Session) -> Result<(), SessionStoreError> {
+        self.save(&session).await
+    }
+
+    #[tracing::instrument(name = "Touching session in Redis", skip_all)]
+    async fn touch_session(&mut self, id: &SessionId) -> Result<Session, SessionStoreError> {
+        let mut session = self.get_session(id).await?;
+        session.last_seen_at = Utc::now();
+
+        let mut conn = self.conn.clone();
+        let touched: bool = self
+            .touch_script
+            .key(get_session_key(id))
+            .key(get_user_sessions_key(&session.user_id))
+            .arg(serialize(&session)?)
+            .arg(session_ttl()?)
+            .invoke_async(&mut conn)
+            .await
+            .wrap_err("failed to touch session in Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        // removed since it was read
+        if !touched {
+            return Err(SessionStoreError::SessionNotFound);
+        }
+        Ok(session)
+    }
+
+    #[tracing::instrument(name = "Retrieving sessions from Redis", skip_all)]
+    async fn get_sessions(&self, user_id: &UserId) -> Result<Vec<Session>, SessionStoreError> {
+        let mut conn = self.conn.clone();
+        let ids: Vec<String> = conn
+            .smembers(get_user_sessions_key(user_id))
+            .await
+            .wrap_err("failed to get user sessions from Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        // the index outlives sessions that expired on their own, so skip ids that are gone
+        let mut sessions = vec![];
+        for id in ids {
+            let id = SessionId::parse(id).map_err(SessionStoreError::UnexpectedError)?;
+            match self.get_session(&id).await {
+                Ok(session) => sessions.push(session),
+                Err(SessionStoreError::SessionNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    #[tracing::instrument(name = "Removing session from Redis", skip_all)]
+    async fn remove_session(
+        &mut self,
+        user_id: &UserId,
+        id: &SessionId,
+    ) -> Result<(), SessionStoreError> {
+        let session = self.get_session(id).await?;
+        if session.user_id != *user_id {
+            return Err(SessionStoreError::SessionNotFound);
+        }
+
+        let mut conn = self.conn.clone();
+        let _: () = redis::pipe()
+            .atomic()
+            .del(get_session_key(id))
+            .ignore()
+            .srem(get_user_sessions_key(user_id), id.as_ref())
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .wrap_err("failed to delete session from Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Removing all sessions from Redis", skip_all)]
+    async fn remove_all_sessions(&mut self, user_id: &UserId) -> Result<(), SessionStoreError> {
+        let user_key = get_user_sessions_key(user_id);
+
+        let mut conn = self.conn.clone();
+        let ids: Vec<String> = conn
+            .smembers(&user_key)
+            .await
+            .wrap_err("failed to get user sessions from Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        let mut keys: Vec<String> = ids
+            .iter()
+            .map(|id| format!("{}{}", SESSION_KEY_PREFIX, id))
+            .collect();
+        keys.push(user_key);
+
+        let _: () = conn
+            .del(keys)
+            .await
+            .wrap_err("failed to delete user sessions from Redis")
+            .map_err(SessionStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    id: String,
+    user_id: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: i64,
+    last_seen_at: i64,
+}
+
+fn serialize(session: &Session) -> Result<String, SessionStoreError> {
+    let stored = StoredSession {
+        id: session.id.as_ref().to_owned(),
+        user_id: session.user_id.to_string(),
+        user_agent: session.user_agent.clone(),
+        ip: session.ip.clone(),
+        created_at: session.created_at.timestamp(),
+        last_seen_at: session.last_seen_at.timestamp(),
+    };
+    serde_json::to_string(&stored)
+        .wrap_err("failed to serialize session")
+        .map_err(SessionStoreError::UnexpectedError)
+}
+
+fn deserialize(value: &str) -> Result<Session, SessionStoreError> {
+    let stored: StoredSession = serde_json::from_str(value)
+        .wrap_err("failed to deserialize session")
+        .map_err(SessionStoreError::UnexpectedError)?;
+
+    Ok(Session {
+        id: SessionId::parse(stored.id).map_err(SessionStoreError::UnexpectedError)?,
+        user_id: UserId::parse(&stored.user_id).map_err(SessionStoreError::UnexpectedError)?,
+        user_agent: stored.user_agent,
+        ip: stored.ip,
+        created_at: from_timestamp(stored.created_at)?,
+        last_seen_at: from_timestamp(stored.last_seen_at)?,
+    })
+}
+
+fn session_ttl() -> Result<u64, SessionStoreError> {
+    REFRESH_TOKEN_TTL_SECONDS
+        .try_into()
+        .wrap_err("failed to cast REFRESH_TOKEN_TTL_SECONDS to u64")
+        .map_err(SessionStoreError::UnexpectedError)
+}
+
+fn from_timestamp(timestamp: i64) -> Result<DateTime<Utc>, SessionStoreError> {
+    DateTime::from_timestamp(timestamp, 0).ok_or(SessionStoreError::UnexpectedError(eyre!(
+        "invalid session timestamp: {}",
+        timestamp
+    )))
+}
+
+const SESSION_KEY_PREFIX: &str = "session:";
+const USER_SESSIONS_KEY_PREFIX: &str = "user_sessions:";
+
+fn get_session_key(id: &SessionId) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, id.as_ref())
+}
+
+fn get_user_sessions_key(user_id: &UserId) -> String {
+    format!("{}{}", USER_SESSIONS_KEY_PREFIX, user_id)
+}
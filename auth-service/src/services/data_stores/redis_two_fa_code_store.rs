@@ -15,11 +15,16 @@
 */
 
 use crate::domain::{
-    data_stores::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+    data_stores::{
+        LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, MAX_LIVE_TWO_FA_CODES,
+        MAX_TWO_FA_CODES_PER_WINDOW, MAX_TWO_FA_CODE_GUESSES, TWO_FA_CODE_TTL_SECONDS,
+        TWO_FA_CODE_WINDOW_SECONDS,
+    },
     Email,
 };
+use chrono::Utc;
 use color_eyre::eyre::Context;
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
@@ -42,43 +47,135 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        let key = get_key(&email);
+        let id = login_attempt_id.as_ref().expose_secret();
         let data = TwoFATuple(
-            login_attempt_id.as_ref().expose_secret().to_owned(),
+            email.as_ref().expose_secret().to_owned(),
             code.as_ref().expose_secret().to_owned(),
         );
         let serialized_data = serde_json::to_string(&data)
             .wrap_err("failed to serialize 2FA tuple")
             .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
+        // the window starts with the first code and isn't extended by later ones
+        let issued_key = get_issued_key(&email);
+        let window_options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(TWO_FA_CODE_WINDOW_SECONDS as u64));
+
         let mut conn = self.conn.clone();
-        let _: () = conn
-            .set_ex(&key, serialized_data, TEN_MINUTES_IN_SECONDS)
+        let (issued, ttl): (u32, i64) = redis::pipe()
+            .atomic()
+            .set_options(&issued_key, 0, window_options)
+            .ignore()
+            .incr(&issued_key, 1)
+            .ttl(&issued_key)
+            .query_async(&mut conn)
+            .await
+            .wrap_err("failed to count issued 2FA codes in Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        if issued > MAX_TWO_FA_CODES_PER_WINDOW {
+            return Err(TwoFACodeStoreError::TooManyCodes(ttl.max(1) as u64));
+        }
+
+        // the user's attempts are indexed by when they started, so that expired ones can be
+        // pruned and the oldest dropped once too many are live
+        let attempts_key = get_attempts_key(&email);
+        let now = Utc::now().timestamp_millis();
+        let (evicted,): (Vec<String>,) = redis::pipe()
+            .atomic()
+            .set_ex(get_key(id), serialized_data, TWO_FA_CODE_TTL_SECONDS as u64)
+            .ignore()
+            .zadd(&attempts_key, id, now)
+            .ignore()
+            .zrembyscore(&attempts_key, "-inf", now - TWO_FA_CODE_TTL_SECONDS * 1000)
+            .ignore()
+            .expire(&attempts_key, TWO_FA_CODE_TTL_SECONDS)
+            .ignore()
+            .zrevrange(&attempts_key, MAX_LIVE_TWO_FA_CODES as isize, -1)
+            .query_async(&mut conn)
             .await
             .wrap_err("failed to set 2FA code in Redis")
             .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
+        if !evicted.is_empty() {
+            let keys: Vec<String> = evicted
+                .iter()
+                .flat_map(|id| [get_key(id), get_guesses_key(id)])
+                .collect();
+            let _: () = redis::pipe()
+                .atomic()
+                .zrem(&attempts_key, &evicted)
+                .ignore()
+                .del(keys)
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .wrap_err("failed to drop oldest 2FA codes from Redis")
+                .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        }
+
         Ok(())
     }
 
     #[tracing::instrument(name = "Removing 2FA code from Redis", skip_all)]
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        let key = get_key(email);
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let id = login_attempt_id.as_ref().expose_secret();
+        let email = match self.get_code(login_attempt_id).await {
+            Ok((email, _)) => Some(email),
+            Err(TwoFACodeStoreError::LoginAttemptIdNotFound) => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(&[get_key(id), get_guesses_key(id)])
+            .ignore();
+        if let Some(email) = email {
+            pipe.zrem(get_attempts_key(&email), id).ignore();
+        }
+
         let mut conn = self.conn.clone();
-        let _: () = conn
-            .del(&key)
+        let _: () = pipe
+            .query_async(&mut conn)
             .await
             .wrap_err("failed to delete 2FA code from Redis")
             .map_err(TwoFACodeStoreError::UnexpectedError)?;
         Ok(())
     }
 
+    #[tracing::instrument(name = "Removing all 2FA codes of a user from Redis", skip_all)]
+    async fn remove_codes(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        let attempts_key = get_attempts_key(email);
+
+        let mut conn = self.conn.clone();
+        let ids: Vec<String> = conn
+            .zrange(&attempts_key, 0, -1)
+            .await
+            .wrap_err("failed to get 2FA login attempts from Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        let mut keys: Vec<String> = ids
+            .iter()
+            .flat_map(|id| [get_key(id), get_guesses_key(id)])
+            .collect();
+        keys.push(attempts_key);
+        let _: () = conn
+            .del(keys)
+            .await
+            .wrap_err("failed to delete 2FA codes from Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
     #[tracing::instrument(name = "Retrieving 2FA code from Redis", skip_all)]
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        let key = get_key(email);
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(Email, TwoFACode), TwoFACodeStoreError> {
+        let key = get_key(login_attempt_id.as_ref().expose_secret());
 
         let mut conn = self.conn.clone();
         match conn.get::<_, String>(&key).await {
@@ -87,24 +184,74 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
                     .wrap_err("failed to deserialize 2FA tuple")
                     .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
-                let login_attempt_id = LoginAttemptId::parse(Secret::new(data.0))
+                let email = Email::parse(Secret::new(data.0))
                     .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
                 let email_code = TwoFACode::parse(Secret::new(data.1))
                     .map_err(TwoFACodeStoreError::UnexpectedError)?;
-                Ok((login_attempt_id, email_code))
+                Ok((email, email_code))
             }
             Err(_) => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
         }
     }
+
+    #[tracing::instrument(name = "Recording wrong 2FA guess in Redis", skip_all)]
+    async fn record_wrong_guess(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<u32, TwoFACodeStoreError> {
+        let id = login_attempt_id.as_ref().expose_secret();
+        let guesses_key = get_guesses_key(id);
+
+        // counted atomically, so concurrent guesses can't slip past the limit
+        let mut conn = self.conn.clone();
+        let (code_exists, wrong_guesses): (bool, u32) = redis::pipe()
+            .atomic()
+            .exists(get_key(id))
+            .incr(&guesses_key, 1)
+            .expire(&guesses_key, TWO_FA_CODE_TTL_SECONDS)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .wrap_err("failed to count wrong 2FA guess in Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        if !code_exists {
+            return Err(TwoFACodeStoreError::LoginAttemptIdNotFound);
+        }
+
+        let guesses_left = MAX_TWO_FA_CODE_GUESSES.saturating_sub(wrong_guesses);
+        if guesses_left == 0 {
+            self.remove_code(login_attempt_id).await?;
+        }
+        Ok(guesses_left)
+    }
 }
 
+/// The user's email and the attempt's code.
 #[derive(Serialize, Deserialize)]
 struct TwoFATuple(pub String, pub String);
 
-const TEN_MINUTES_IN_SECONDS: u64 = 600;
 const TWO_FA_CODE_PREFIX: &str = "two_fa_code:";
+const TWO_FA_GUESSES_PREFIX: &str = "two_fa_guesses:";
+const TWO_FA_ATTEMPTS_PREFIX: &str = "two_fa_attempts:";
+const TWO_FA_ISSUED_PREFIX: &str = "two_fa_issued:";
+
+fn get_key(login_attempt_id: &str) -> String {
+    format!("{}{}", TWO_FA_CODE_PREFIX, login_attempt_id)
+}
+
+fn get_guesses_key(login_attempt_id: &str) -> String {
+    format!("{}{}", TWO_FA_GUESSES_PREFIX, login_attempt_id)
+}
+
+fn get_attempts_key(email: &Email) -> String {
+    format!(
+        "{}{}",
+        TWO_FA_ATTEMPTS_PREFIX,
+        email.as_ref().expose_secret()
+    )
+}
 
-fn get_key(email: &Email) -> String {
-    format!("{}{}", TWO_FA_CODE_PREFIX, email.as_ref().expose_secret())
+fn get_issued_key(email: &Email) -> String {
+    format!("{}{}", TWO_FA_ISSUED_PREFIX, email.as_ref().expose_secret())
 }
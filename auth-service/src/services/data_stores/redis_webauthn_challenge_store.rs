@@ -0,0 +1,155 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use crate::{
+    domain::{
+        data_stores::{WebauthnChallengeStore, WebauthnChallengeStoreError},
+        UserId, WebauthnCeremony, WebauthnChallenge,
+    },
+    utils::webauthn::WEBAUTHN_CHALLENGE_TTL_SECONDS,
+};
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::{
+    DiscoverableAuthentication, PasskeyAuthentication, PasskeyRegistration,
+};
+
+pub struct RedisWebauthnChallengeStore {
+    conn: MultiplexedConnection,
+}
+
+impl RedisWebauthnChallengeStore {
+    pub fn new(conn: MultiplexedConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebauthnChallengeStore for RedisWebauthnChallengeStore {
+    #[tracing::instrument(name = "Storing WebAuthn challenge in Redis", skip_all)]
+    async fn add_challenge(
+        &mut self,
+        challenge: WebauthnChallenge,
+        ceremony: WebauthnCeremony,
+    ) -> Result<(), WebauthnChallengeStoreError> {
+        let serialized_ceremony = serde_json::to_string(&StoredCeremony::from(ceremony))
+            .wrap_err("failed to serialize WebAuthn ceremony")
+            .map_err(WebauthnChallengeStoreError::UnexpectedError)?;
+
+        let mut conn = self.conn.clone();
+        let _: () = conn
+            .set_ex(
+                get_key(&challenge),
+                serialized_ceremony,
+                WEBAUTHN_CHALLENGE_TTL_SECONDS as u64,
+            )
+            .await
+            .wrap_err("failed to set WebAuthn challenge in Redis")
+            .map_err(WebauthnChallengeStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Taking WebAuthn challenge from Redis", skip_all)]
+    async fn take_challenge(
+        &mut self,
+        challenge: &WebauthnChallenge,
+    ) -> Result<WebauthnCeremony, WebauthnChallengeStoreError> {
+        let mut conn = self.conn.clone();
+        // GETDEL makes sure two concurrent answers can't both claim the challenge
+        let value: Option<String> = conn
+            .get_del(get_key(challenge))
+            .await
+            .wrap_err("failed to take WebAuthn challenge from Redis")
+            .map_err(WebauthnChallengeStoreError::UnexpectedError)?;
+
+        let value = value.ok_or(WebauthnChallengeStoreError::ChallengeNotFound)?;
+        let stored: StoredCeremony = serde_json::from_str(&value)
+            .wrap_err("failed to deserialize WebAuthn ceremony")
+            .map_err(WebauthnChallengeStoreError::UnexpectedError)?;
+
+        stored
+            .try_into()
+            .map_err(WebauthnChallengeStoreError::UnexpectedError)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum StoredCeremony {
+    Registration {
+        user_id: String,
+        state: PasskeyRegistration,
+    },
+    Authentication {
+        user_id: String,
+        state: PasskeyAuthentication,
+    },
+    DiscoverableAuthentication {
+        user_id: Option<String>,
+        state: DiscoverableAuthentication,
+    },
+}
+
+impl From<WebauthnCeremony> for StoredCeremony {
+    fn from(ceremony: WebauthnCeremony) -> Self {
+        match ceremony {
+            WebauthnCeremony::Registration { user_id, state } => Self::Registration {
+                user_id: user_id.to_string(),
+                state,
+            },
+            WebauthnCeremony::Authentication { user_id, state } => Self::Authentication {
+                user_id: user_id.to_string(),
+                state,
+            },
+            WebauthnCeremony::DiscoverableAuthentication { user_id, state } => {
+                Self::DiscoverableAuthentication {
+                    user_id: user_id.map(|id| id.to_string()),
+                    state,
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<StoredCeremony> for WebauthnCeremony {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(stored: StoredCeremony) -> Result<Self, Self::Error> {
+        Ok(match stored {
+            StoredCeremony::Registration { user_id, state } => Self::Registration {
+                user_id: UserId::parse(&user_id)?,
+                state,
+            },
+            StoredCeremony::Authentication { user_id, state } => Self::Authentication {
+                user_id: UserId::parse(&user_id)?,
+                state,
+            },
+            StoredCeremony::DiscoverableAuthentication { user_id, state } => {
+                Self::DiscoverableAuthentication {
+                    user_id: user_id.as_deref().map(UserId::parse).transpose()?,
+                    state,
+                }
+            }
+        })
+    }
+}
+
+const WEBAUTHN_CHALLENGE_KEY_PREFIX: &str = "webauthn_challenge:";
+
+fn get_key(challenge: &WebauthnChallenge) -> String {
+    format!("{}{}", WEBAUTHN_CHALLENGE_KEY_PREFIX, challenge.as_ref())
+}
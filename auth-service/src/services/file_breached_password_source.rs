@@ -0,0 +1,93 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Result};
+use std::{collections::HashMap, fs};
+
+use crate::domain::{BreachedPasswordSource, BREACHED_HASH_PREFIX_LENGTH};
+
+/// Breached passwords read from a file of SHA-1 hashes, one per line, optionally followed by
+/// `:COUNT` as in the Have I Been Pwned downloads. Meant for tests and offline deployments.
+#[derive(Default)]
+pub struct FileBreachedPasswordSource {
+    suffixes: HashMap<String, Vec<String>>,
+}
+
+impl FileBreachedPasswordSource {
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut suffixes: HashMap<String, Vec<String>> = HashMap::new();
+        for line in contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let hash = line.split(':').next().unwrap_or_default().to_uppercase();
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(eyre!("Not a SHA-1 hash: {}", hash));
+            }
+            let (prefix, suffix) = hash.split_at(BREACHED_HASH_PREFIX_LENGTH);
+            suffixes
+                .entry(prefix.to_owned())
+                .or_default()
+                .push(suffix.to_owned());
+        }
+        Ok(Self { suffixes })
+    }
+}
+
+#[async_trait::async_trait]
+impl BreachedPasswordSource for FileBreachedPasswordSource {
+    async fn hash_suffixes(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .suffixes
+            .get(&prefix.to_uppercase())
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn group_hashes_by_prefix() {
+        let source = FileBreachedPasswordSource::parse(
+            "AFC86521AEF28CC5ABEABE443FF1A826A4F70817:3\n\
+             afc860018a45c4d1def81644b54ab7f969b88d65\n\n",
+        )
+        .unwrap();
+
+        let mut suffixes = source.hash_suffixes("afc86").await.unwrap();
+        suffixes.sort();
+        assert_eq!(
+            suffixes,
+            vec![
+                "0018A45C4D1DEF81644B54AB7F969B88D65",
+                "521AEF28CC5ABEABE443FF1A826A4F70817"
+            ]
+        );
+        assert!(source.hash_suffixes("00000").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn reject_lines_that_are_not_hashes() {
+        assert!(FileBreachedPasswordSource::parse("password123").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::Result;
+use reqwest::{Client, Url};
+
+use crate::domain::BreachedPasswordSource;
+
+/// Looks passwords up with the Have I Been Pwned range API, which only ever sees the first few
+/// characters of a password's hash.
+pub struct HibpBreachedPasswordSource {
+    http_client: Client,
+    base_url: String,
+}
+
+impl HibpBreachedPasswordSource {
+    pub fn new(base_url: String, http_client: Client) -> Self {
+        Self {
+            http_client,
+            base_url,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BreachedPasswordSource for HibpBreachedPasswordSource {
+    #[tracing::instrument(name = "Fetching breached password hashes", skip_all)]
+    async fn hash_suffixes(&self, prefix: &str) -> Result<Vec<String>> {
+        let base = Url::parse(&self.base_url)?;
+        let url = base.join(&format!("/range/{}", prefix))?;
+
+        let body = self
+            .http_client
+            .get(url)
+            // pads every response to a similar size, so its length doesn't give the prefix away
+            .header(PADDING_HEADER, "true")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(parse_range(&body))
+    }
+}
+
+const PADDING_HEADER: &str = "Add-Padding";
+
+/// Reads `SUFFIX:COUNT` lines, leaving out the padding, which always has a count of 0.
+fn parse_range(body: &str) -> Vec<String> {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .filter(|(_, count)| *count != "0")
+        .map(|(suffix, _)| suffix.to_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{any, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn source(base_url: String) -> HibpBreachedPasswordSource {
+        HibpBreachedPasswordSource::new(base_url, Client::new())
+    }
+
+    #[tokio::test]
+    async fn hash_suffixes_asks_for_the_prefix_only() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/range/AFC86"))
+            .and(header(PADDING_HEADER, "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "521AEF28CC5ABEABE443FF1A826A4F70817:3\r\n\
+                 0018A45C4D1DEF81644B54AB7F969B88D65:0\r\n",
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let suffixes = source(mock_server.uri())
+            .hash_suffixes("AFC86")
+            .await
+            .unwrap();
+
+        assert_eq!(suffixes, vec!["521AEF28CC5ABEABE443FF1A826A4F70817"]);
+    }
+
+    #[tokio::test]
+    async fn hash_suffixes_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = source(mock_server.uri()).hash_suffixes("AFC86").await;
+
+        assert!(outcome.is_err());
+    }
+}
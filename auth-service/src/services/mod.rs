@@ -14,6 +14,12 @@
    limitations under the License.
 */
 
+pub mod account_purge;
+pub mod argon2_password_hasher;
 pub mod data_stores;
+pub mod file_breached_password_source;
+pub mod hibp_breached_password_source;
 pub mod mock_email_client;
+pub mod oidc_client;
+pub mod password_hash_report;
 pub mod postmark_email_client;
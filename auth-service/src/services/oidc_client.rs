@@ -0,0 +1,452 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Context, Report, Result};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::{header::ACCEPT, Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::{
+    domain::{OAuthFlow, OAuthProvider, OAuthProviders, OAuthState},
+    utils::constants::JWT_LEEWAY_SECONDS,
+};
+
+/// How long a user has to log in at the provider and come back to the callback.
+pub const OAUTH_STATE_TTL_SECONDS: i64 = 10 * 60;
+
+/// ID tokens have to be signed with the provider's private key. HMAC would make the client secret
+/// a signing key, and `none` no signature at all.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Logs users in at the configured OpenID Connect providers with the authorization code flow.
+pub struct OidcClient {
+    providers: OAuthProviders,
+    http_client: Client,
+}
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("Authorization code rejected")]
+    CodeRejected,
+    #[error("Invalid ID token")]
+    InvalidIdToken(#[source] Report),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+/// The claims of a validated ID token this service uses.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_email_verified")]
+    pub email_verified: bool,
+    pub nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<Secret<String>>,
+}
+
+impl OidcClient {
+    pub fn new(providers: OAuthProviders, http_client: Client) -> Self {
+        Self {
+            providers,
+            http_client,
+        }
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&OAuthProvider> {
+        self.providers.get(name)
+    }
+
+    /// Where to send the user to log in at the provider.
+    pub fn authorization_url(
+        &self,
+        provider: &OAuthProvider,
+        state: &OAuthState,
+        flow: &OAuthFlow,
+    ) -> Result<Url> {
+        Url::parse_with_params(
+            &provider.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", provider.client_id.as_str()),
+                ("redirect_uri", provider.redirect_uri.as_str()),
+                ("scope", provider.scopes.join(" ").as_str()),
+                ("state", state.as_ref()),
+                ("nonce", flow.nonce.as_str()),
+                ("code_challenge", flow.code_verifier.challenge().as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .wrap_err("invalid authorization endpoint")
+    }
+
+    /// Trades the authorization code the callback got for an ID token.
+    #[tracing::instrument(name = "Exchanging OAuth authorization code", skip_all)]
+    pub async fn exchange_code(
+        &self,
+        provider: &OAuthProvider,
+        code: &Secret<String>,
+        flow: &OAuthFlow,
+    ) -> Result<Secret<String>, OidcError> {
+        let response = self
+            .http_client
+            .post(&provider.token_endpoint)
+            .header(ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code.expose_secret().as_str()),
+                ("redirect_uri", provider.redirect_uri.as_str()),
+                ("client_id", provider.client_id.as_str()),
+                (
+                    "client_secret",
+                    provider.client_secret.expose_secret().as_str(),
+                ),
+                (
+                    "code_verifier",
+                    flow.code_verifier.as_ref().expose_secret().as_str(),
+                ),
+            ])
+            .send()
+            .await
+            .map_err(|e| OidcError::UnexpectedError(e.into()))?;
+
+        // an unknown, expired or already used code, or a verifier that doesn't match, is a 400
+        if response.status().is_client_error() {
+            tracing::debug!(status = %response.status(), "Token endpoint rejected the code");
+            return Err(OidcError::CodeRejected);
+        }
+
+        let tokens: TokenResponse = response
+            .error_for_status()
+            .map_err(|e| OidcError::UnexpectedError(e.into()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::UnexpectedError(e.into()))?;
+
+        tokens.id_token.ok_or(OidcError::UnexpectedError(eyre!(
+            "token response of {} has no ID token",
+            provider.name
+        )))
+    }
+
+    /// Checks the ID token's signature against the provider's published keys, and that it was
+    /// issued by the provider, for this client and for the login in `flow`.
+    #[tracing::instrument(name = "Validating ID token", skip_all)]
+    pub async fn validate_id_token(
+        &self,
+        provider: &OAuthProvider,
+        id_token: &Secret<String>,
+        flow: &OAuthFlow,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = decode_header(id_token.expose_secret())
+            .map_err(|e| OidcError::InvalidIdToken(e.into()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcError::InvalidIdToken(eyre!(
+                "ID token signed with {:?}",
+                header.alg
+            )));
+        }
+
+        let jwks = self.fetch_jwks(provider).await?;
+        // providers with a single key may leave the key id out
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or(OidcError::InvalidIdToken(eyre!(
+            "ID token signed with an unknown key"
+        )))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| OidcError::InvalidIdToken(e.into()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = *JWT_LEEWAY_SECONDS;
+
+        let claims = decode::<IdTokenClaims>(id_token.expose_secret(), &key, &validation)
+            .map_err(|e| OidcError::InvalidIdToken(e.into()))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(flow.nonce.as_str()) {
+            return Err(OidcError::InvalidIdToken(eyre!("ID token nonce mismatch")));
+        }
+        Ok(claims)
+    }
+
+    async fn fetch_jwks(&self, provider: &OAuthProvider) -> Result<JwkSet, OidcError> {
+        self.http_client
+            .get(&provider.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| OidcError::UnexpectedError(e.into()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::UnexpectedError(e.into()))
+    }
+}
+
+/// Some providers send `email_verified` as the string "true" rather than a boolean.
+fn deserialize_email_verified<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    Ok(match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(verified) => verified,
+        BoolOrString::String(verified) => verified.eq_ignore_ascii_case("true"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::signing::{Keyring, SigningKey};
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../../tests/fixtures/jwt_rsa_private_key.pem");
+    const CLIENT_ID: &str = "auth-service";
+
+    fn provider(base_url: &str) -> OAuthProvider {
+        serde_json::from_value(json!({
+            "name": "mock",
+            "issuer": base_url,
+            "client_id": CLIENT_ID,
+            "client_secret": "client-secret",
+            "authorization_endpoint": format!("{}/authorize", base_url),
+            "token_endpoint": format!("{}/token", base_url),
+            "jwks_uri": format!("{}/jwks", base_url),
+            "redirect_uri": "https://auth.example.com/oauth/mock/callback",
+        }))
+        .unwrap()
+    }
+
+    fn client(provider: &OAuthProvider) -> OidcClient {
+        OidcClient::new(
+            OAuthProviders::new(vec![provider.clone()]).unwrap(),
+            Client::new(),
+        )
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_pem("mock-key".to_owned(), Algorithm::RS256, RSA_PRIVATE_KEY).unwrap()
+    }
+
+    async fn serve_jwks(mock_server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(Keyring::new(signing_key(), 0).jwks()),
+            )
+            .mount(mock_server)
+            .await;
+    }
+
+    fn claims(provider: &OAuthProvider, flow: &OAuthFlow) -> Value {
+        let now = Utc::now().timestamp();
+        json!({
+            "iss": provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": "248289761001",
+            "email": "jane@example.com",
+            "email_verified": true,
+            "nonce": flow.nonce,
+            "iat": now,
+            "exp": now + 300,
+        })
+    }
+
+    #[test]
+    fn authorization_url_carries_state_nonce_and_challenge() {
+        let provider = provider("https://accounts.example.com");
+        let state = OAuthState::default();
+        let flow = OAuthFlow::new(provider.name.clone());
+
+        let url = client(&provider)
+            .authorization_url(&provider, &state, &flow)
+            .unwrap();
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+        assert_eq!(url.path(), "/authorize");
+        for (name, value) in [
+            ("response_type", "code"),
+            ("client_id", CLIENT_ID),
+            ("scope", "openid email"),
+            ("state", state.as_ref()),
+            ("nonce", flow.nonce.as_str()),
+            ("code_challenge", flow.code_verifier.challenge().as_str()),
+            ("code_challenge_method", "S256"),
+        ] {
+            assert!(
+                params.contains(&(name.to_owned(), value.to_owned())),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn exchange_code_sends_the_code_verifier() {
+        let mock_server = MockServer::start().await;
+        let provider = provider(&mock_server.uri());
+        let flow = OAuthFlow::new(provider.name.clone());
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("code=the-code"))
+            .and(body_string_contains(format!(
+                "code_verifier={}",
+                flow.code_verifier.as_ref().expose_secret()
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access",
+                "token_type": "Bearer",
+                "id_token": "id-token",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let id_token = client(&provider)
+            .exchange_code(&provider, &Secret::new("the-code".to_owned()), &flow)
+            .await
+            .unwrap();
+
+        assert_eq!(id_token.expose_secret(), "id-token");
+    }
+
+    #[tokio::test]
+    async fn exchange_code_fails_if_the_provider_rejects_the_code() {
+        let mock_server = MockServer::start().await;
+        let provider = provider(&mock_server.uri());
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(
+                ResponseTemplate::new(400).set_body_json(json!({ "error": "invalid_grant" })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client(&provider)
+            .exchange_code(
+                &provider,
+                &Secret::new("the-code".to_owned()),
+                &OAuthFlow::new(provider.name.clone()),
+            )
+            .await;
+
+        assert!(matches!(result, Err(OidcError::CodeRejected)));
+    }
+
+    #[tokio::test]
+    async fn validate_id_token_accepts_token_signed_by_the_provider() {
+        let mock_server = MockServer::start().await;
+        serve_jwks(&mock_server).await;
+        let provider = provider(&mock_server.uri());
+        let flow = OAuthFlow::new(provider.name.clone());
+        let id_token = signing_key().encode(&claims(&provider, &flow)).unwrap();
+
+        let claims = client(&provider)
+            .validate_id_token(&provider, &id_token, &flow)
+            .await
+            .unwrap();
+
+        assert_eq!(claims.sub, "248289761001");
+        assert_eq!(claims.email.as_deref(), Some("jane@example.com"));
+        assert!(claims.email_verified);
+    }
+
+    #[tokio::test]
+    async fn validate_id_token_rejects_tokens_for_other_logins_or_clients() {
+        let mock_server = MockServer::start().await;
+        serve_jwks(&mock_server).await;
+        let provider = provider(&mock_server.uri());
+        let flow = OAuthFlow::new(provider.name.clone());
+        let client = client(&provider);
+
+        let mut other_nonce = claims(&provider, &flow);
+        other_nonce["nonce"] = json!("another-login");
+        let mut other_audience = claims(&provider, &flow);
+        other_audience["aud"] = json!("another-client");
+        let mut other_issuer = claims(&provider, &flow);
+        other_issuer["iss"] = json!("https://evil.example.com");
+        let mut expired = claims(&provider, &flow);
+        expired["exp"] = json!(Utc::now().timestamp() - 3600);
+
+        for claims in [other_nonce, other_audience, other_issuer, expired] {
+            let id_token = signing_key().encode(&claims).unwrap();
+            let result = client.validate_id_token(&provider, &id_token, &flow).await;
+            assert!(matches!(result, Err(OidcError::InvalidIdToken(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_id_token_rejects_hmac_signed_tokens() {
+        let mock_server = MockServer::start().await;
+        serve_jwks(&mock_server).await;
+        let provider = provider(&mock_server.uri());
+        let flow = OAuthFlow::new(provider.name.clone());
+        let hmac_key = SigningKey::from_secret(
+            "mock-key".to_owned(),
+            Algorithm::HS256,
+            &provider.client_secret,
+        )
+        .unwrap();
+        let id_token = hmac_key.encode(&claims(&provider, &flow)).unwrap();
+
+        let result = client(&provider)
+            .validate_id_token(&provider, &id_token, &flow)
+            .await;
+
+        assert!(matches!(result, Err(OidcError::InvalidIdToken(_))));
+    }
+
+    #[test]
+    fn email_verified_may_be_a_string() {
+        let claims: IdTokenClaims =
+            serde_json::from_value(json!({ "sub": "1", "email_verified": "true" })).unwrap();
+        assert!(claims.email_verified);
+        let claims: IdTokenClaims = serde_json::from_value(json!({ "sub": "1" })).unwrap();
+        assert!(!claims.email_verified);
+    }
+}
@@ -0,0 +1,41 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::time::Duration;
+
+use crate::app_state::AppState;
+
+/// How often the number of users still on outdated password hashes is reported.
+pub const PASSWORD_HASH_REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Reports how many users have a password hash that will be upgraded at their next login, every
+/// `PASSWORD_HASH_REPORT_INTERVAL`, as the `outdated_password_hashes` gauge.
+pub async fn run_password_hash_report(state: AppState) {
+    let outdated_password_hashes = metrics::gauge!("outdated_password_hashes");
+    let mut interval = tokio::time::interval(PASSWORD_HASH_REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let count = state
+            .user_store
+            .read()
+            .await
+            .count_outdated_password_hashes()
+            .await;
+        match count {
+            Ok(count) => outdated_password_hashes.set(count as f64),
+            Err(e) => tracing::error!("failed to count outdated password hashes: {:?}", e),
+        }
+    }
+}
@@ -14,21 +14,76 @@
    limitations under the License.
 */
 
-use axum_extra::extract::cookie::{Cookie, SameSite};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
 use chrono::Utc;
 use color_eyre::eyre::{eyre, Context, ContextCompat, Report, Result};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
+use jsonwebtoken::{Algorithm, Validation};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{app_state::BannedTokenStoreType, domain::email::Email};
+use crate::{
+    app_state::{AppState, BannedTokenStoreType, RefreshTokenStoreType, SessionStoreType},
+    domain::{
+        data_stores::{RefreshToken, RefreshTokenData, RefreshTokenFamilyId, SessionStoreError},
+        error::AuthAPIError,
+        session::{Session, SessionId},
+        user::{User, UserId},
+        ClientId, Scopes, UserStoreError,
+    },
+};
 
-use super::constants::{JWT_COOKIE_NAME, JWT_SECRET};
+use super::{
+    client::ClientInfo,
+    constants::{
+        JWT_AUDIENCE, JWT_COOKIE_NAME, JWT_ISSUER, JWT_KEYRING, JWT_LEEWAY_SECONDS,
+        REFRESH_TOKEN_COOKIE_NAME,
+    },
+};
+
+#[tracing::instrument(name = "Create session", skip_all)]
+pub async fn create_session(
+    user_id: &UserId,
+    client: ClientInfo,
+    session_store: SessionStoreType,
+) -> Result<SessionId> {
+    let session = Session::new(*user_id, client.user_agent, client.ip);
+    let session_id = session.id.clone();
+
+    session_store
+        .write()
+        .await
+        .add_session(session)
+        .await
+        .wrap_err("failed to store session")?;
+
+    Ok(session_id)
+}
+
+/// Looks up what the user may do, as the scopes their tokens carry.
+#[tracing::instrument(name = "Get effective scopes", skip_all)]
+pub async fn effective_scopes(user_id: &UserId, state: &AppState) -> Result<Scopes> {
+    let grants = state
+        .permission_store
+        .read()
+        .await
+        .get_grants(user_id)
+        .await
+        .wrap_err("failed to get grants")?;
+
+    Ok(state.roles.effective_scopes(&grants))
+}
 
 #[tracing::instrument(name = "Generate auth cookie", skip_all)]
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>> {
-    let token = generate_auth_token(email)?;
+pub fn generate_auth_cookie(
+    user_id: &UserId,
+    session_id: &SessionId,
+    scopes: &Scopes,
+) -> Result<Cookie<'static>> {
+    let token = generate_auth_token(user_id, session_id, scopes)?;
     Ok(create_auth_cookie(token))
 }
 
@@ -50,13 +105,45 @@ fn create_auth_cookie(token: Secret<String>) -> Cookie<'static> {
 // }
 
 pub const TOKEN_TTL_SECONDS: i64 = 900;
+pub const REFRESH_TOKEN_TTL_SECONDS: i64 = 1_209_600;
 
 #[tracing::instrument(name = "Generate auth token", skip_all)]
-fn generate_auth_token(email: &Email) -> Result<Secret<String>> {
+fn generate_auth_token(
+    user_id: &UserId,
+    session_id: &SessionId,
+    scopes: &Scopes,
+) -> Result<Secret<String>> {
+    let scope = (!scopes.is_empty()).then(|| scopes.to_string());
+    let claims = new_claims(user_id.to_string(), Some(session_id), None, scope)?;
+    create_token(&claims)
+}
+
+/// Generates an access token for an OAuth client. Tokens from the authorization code grant act
+/// for the user in the login the code was issued from, those from the client credentials grant
+/// for the client itself, with the client id as subject and no session.
+#[tracing::instrument(name = "Generate access token", skip_all)]
+pub fn generate_access_token(
+    sub: String,
+    session_id: Option<&SessionId>,
+    client_id: &ClientId,
+    scopes: &Scopes,
+) -> Result<Secret<String>> {
+    let scope = (!scopes.is_empty()).then(|| scopes.to_string());
+    let claims = new_claims(sub, session_id, Some(client_id.to_string()), scope)?;
+    create_token(&claims)
+}
+
+fn new_claims(
+    sub: String,
+    session_id: Option<&SessionId>,
+    client_id: Option<String>,
+    scope: Option<String>,
+) -> Result<Claims> {
     let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
         .wrap_err("failed to create 10 minute time delta")?;
 
-    let exp = Utc::now()
+    let now = Utc::now();
+    let exp = now
         .checked_add_signed(delta)
         .ok_or(eyre!("failed to add 10 minutes to current time"))?
         .timestamp();
@@ -66,11 +153,59 @@ fn generate_auth_token(email: &Email) -> Result<Secret<String>> {
         exp
     ))?;
 
-    let sub = email.as_ref().expose_secret().to_owned();
+    let iat: usize = now.timestamp().try_into().wrap_err(format!(
+        "failed to cast iat time to usize. iat time: {}",
+        now.timestamp()
+    ))?;
+
+    Ok(Claims {
+        sub,
+        iss: JWT_ISSUER.to_owned(),
+        aud: JWT_AUDIENCE.to_owned(),
+        exp,
+        iat,
+        nbf: iat,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id.map(|session_id| session_id.as_ref().to_owned()),
+        client_id,
+        scope,
+    })
+}
 
-    let claims = Claims { sub, exp };
+#[tracing::instrument(name = "Generate refresh cookie", skip_all)]
+pub async fn generate_refresh_cookie(
+    user_id: &UserId,
+    session_id: &SessionId,
+    refresh_token_store: RefreshTokenStoreType,
+) -> Result<Cookie<'static>> {
+    let token = RefreshToken::default();
+    let data = RefreshTokenData::new(
+        *user_id,
+        session_id.clone(),
+        RefreshTokenFamilyId::default(),
+    );
 
-    create_token(&claims)
+    refresh_token_store
+        .write()
+        .await
+        .add_token(token.clone(), data)
+        .await
+        .wrap_err("failed to store refresh token")?;
+
+    Ok(create_refresh_cookie(&token))
+}
+
+#[tracing::instrument(name = "Create refresh cookie", skip_all)]
+pub fn create_refresh_cookie(token: &RefreshToken) -> Cookie<'static> {
+    Cookie::build((
+        REFRESH_TOKEN_COOKIE_NAME,
+        token.as_ref().expose_secret().to_owned(),
+    ))
+    .path("/")
+    .http_only(true)
+    .same_site(SameSite::Strict)
+    .max_age(time::Duration::seconds(REFRESH_TOKEN_TTL_SECONDS))
+    .build()
 }
 
 #[derive(Debug, Error)]
@@ -85,42 +220,140 @@ pub enum TokenValidationError {
 pub async fn validate_token(
     token: &Secret<String>,
     banned_token_store: BannedTokenStoreType,
+    session_store: SessionStoreType,
 ) -> Result<Claims, TokenValidationError> {
-    match banned_token_store.read().await.contains_token(token).await {
+    let claims = JWT_KEYRING
+        .decode::<Claims>(token, &token_validation())
+        .map_err(TokenValidationError::InvalidToken)?;
+
+    match banned_token_store
+        .read()
+        .await
+        .contains_token(&claims.jti)
+        .await
+    {
         Ok(value) => {
             if value {
+                return Err(TokenValidationError::InvalidToken(eyre!("token is banned")));
+            }
+        }
+        Err(e) => return Err(TokenValidationError::UnexpectedError(e.into())),
+    }
+
+    // only tokens issued to a client for itself come without a session
+    let Some(sid) = claims.sid.clone() else {
+        if claims.client_id.is_none() {
+            return Err(TokenValidationError::InvalidToken(eyre!(
+                "token has no session"
+            )));
+        }
+        return Ok(claims);
+    };
+    let session_id = SessionId::parse(sid)
+        .map_err(|e| TokenValidationError::InvalidToken(e.wrap_err("invalid session id")))?;
+
+    match session_store.write().await.touch_session(&session_id).await {
+        Ok(session) => {
+            if session.user_id.to_string() != claims.sub {
                 return Err(TokenValidationError::InvalidToken(eyre!(
-                    "token is banned"
+                    "session belongs to another user"
                 )));
             }
         }
+        Err(SessionStoreError::SessionNotFound) => {
+            return Err(TokenValidationError::InvalidToken(eyre!(
+                "session has been revoked"
+            )))
+        }
         Err(e) => return Err(TokenValidationError::UnexpectedError(e.into())),
     }
 
-    decode::<Claims>(
-        token.expose_secret(),
-        &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
-        &Validation::default(),
+    Ok(claims)
+}
+
+/// Validates the JWT cookie of a request that requires a logged in user.
+#[tracing::instrument(name = "Validate auth cookie", skip_all)]
+pub async fn validate_auth_cookie(
+    jar: &CookieJar,
+    state: &AppState,
+) -> Result<Claims, AuthAPIError> {
+    let cookie = jar.get(JWT_COOKIE_NAME).ok_or(AuthAPIError::MissingToken)?;
+    let token = Secret::new(cookie.value().to_owned());
+
+    match validate_token(
+        &token,
+        state.banned_token_store.clone(),
+        state.session_store.clone(),
     )
-    .map(|data| data.claims)
-    .map_err(|e| TokenValidationError::InvalidToken(e.into()))
+    .await
+    {
+        // a client acting for itself has no user to act as, and one acting for a user only
+        // gets what its scope allows, not the user's whole account
+        Ok(claims) if claims.sid.is_none() || claims.client_id.is_some() => {
+            Err(AuthAPIError::InvalidToken)
+        }
+        Ok(claims) => Ok(claims),
+        Err(TokenValidationError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(TokenValidationError::InvalidToken(_)) => Err(AuthAPIError::InvalidToken),
+    }
+}
+
+/// Loads the user the auth cookie was issued to.
+pub async fn current_user(jar: &CookieJar, state: &AppState) -> Result<User, AuthAPIError> {
+    let claims = validate_auth_cookie(jar, state).await?;
+    let user_id = claims.user_id()?;
+
+    match state.user_store.read().await.get_user_by_id(&user_id).await {
+        Ok(user) => Ok(user),
+        Err(UserStoreError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => Err(AuthAPIError::InvalidToken),
+    }
+}
+
+fn token_validation() -> Validation {
+    // The keyring pins the algorithm to the key that signed the token.
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[JWT_AUDIENCE.as_str()]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_nbf = true;
+    validation.leeway = *JWT_LEEWAY_SECONDS;
+    validation
 }
 
 #[tracing::instrument(name = "Create token", skip_all)]
 fn create_token(claims: &Claims) -> Result<Secret<String>> {
-    encode(
-        &jsonwebtoken::Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
-    )
-    .map(Secret::new)
-    .wrap_err("failed to create token")
+    JWT_KEYRING.encode(claims)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    pub iss: String,
+    pub aud: String,
     pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub jti: String,
+    /// The login the token was issued for, missing on tokens from the client credentials grant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// The OAuth client the token was issued to, missing on tokens from logging in directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl Claims {
+    pub fn user_id(&self) -> Result<UserId, AuthAPIError> {
+        UserId::parse(&self.sub).map_err(|_| AuthAPIError::InvalidToken)
+    }
+
+    pub fn session_id(&self) -> Result<SessionId, AuthAPIError> {
+        let sid = self.sid.clone().ok_or(AuthAPIError::InvalidToken)?;
+        SessionId::parse(sid).map_err(|_| AuthAPIError::InvalidToken)
+    }
 }
 
 #[cfg(test)]
@@ -128,14 +361,44 @@ mod tests {
     use std::sync::Arc;
     use tokio::sync::RwLock;
 
-    use crate::services::data_stores::HashsetBannedTokenStore;
+    use crate::services::data_stores::{HashmapSessionStore, HashsetBannedTokenStore};
 
     use super::*;
 
+    struct Stores {
+        banned_token_store: BannedTokenStoreType,
+        session_store: SessionStoreType,
+    }
+
+    impl Stores {
+        fn new() -> Self {
+            Self {
+                banned_token_store: Arc::new(RwLock::new(HashsetBannedTokenStore::default())),
+                session_store: Arc::new(RwLock::new(HashmapSessionStore::default())),
+            }
+        }
+
+        async fn session(&self, user_id: &UserId) -> SessionId {
+            create_session(user_id, ClientInfo::default(), self.session_store.clone())
+                .await
+                .unwrap()
+        }
+
+        async fn validate(&self, token: &Secret<String>) -> Result<Claims, TokenValidationError> {
+            validate_token(
+                token,
+                self.banned_token_store.clone(),
+                self.session_store.clone(),
+            )
+            .await
+        }
+    }
+
     #[tokio::test]
     async fn test_generate_auth_cookie() {
-        let email = Email::parse(Secret::new("ap@0xfrait.com".to_owned())).unwrap();
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let user_id = UserId::default();
+        let cookie =
+            generate_auth_cookie(&user_id, &SessionId::default(), &Scopes::default()).unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -145,18 +408,21 @@ mod tests {
 
     #[tokio::test]
     async fn test_generate_auth_token() {
-        let email = Email::parse(Secret::new("ap@0xfrait.com".to_owned())).unwrap();
-        let result = generate_auth_token(&email).unwrap();
+        let user_id = UserId::default();
+        let result =
+            generate_auth_token(&user_id, &SessionId::default(), &Scopes::default()).unwrap();
         assert_eq!(result.expose_secret().split('.').count(), 3);
     }
 
     #[tokio::test]
     async fn test_validate_token_with_valid_token() {
-        let email = Email::parse(Secret::new("ap@0xfrait.com".to_owned())).unwrap();
-        let token = generate_auth_token(&email).unwrap();
-        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
-        let result = validate_token(&token, banned_token_store).await.unwrap();
-        assert_eq!(result.sub, "ap@0xfrait.com");
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+        let token = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        let result = stores.validate(&token).await.unwrap();
+        assert_eq!(result.sub, user_id.to_string());
+        assert_eq!(result.sid.as_deref(), Some(session_id.as_ref()));
 
         let exp = Utc::now()
             .checked_add_signed(chrono::Duration::try_minutes(8).expect("valid duration"))
@@ -165,4 +431,139 @@ mod tests {
 
         assert!(result.exp > exp as usize)
     }
+
+    fn claims_for(user_id: &UserId, session_id: &SessionId) -> Claims {
+        let now = Utc::now().timestamp() as usize;
+        Claims {
+            sub: user_id.to_string(),
+            iss: JWT_ISSUER.to_owned(),
+            aud: JWT_AUDIENCE.to_owned(),
+            exp: now + TOKEN_TTL_SECONDS as usize,
+            iat: now,
+            nbf: now,
+            jti: uuid::Uuid::new_v4().to_string(),
+            sid: Some(session_id.as_ref().to_owned()),
+            client_id: None,
+            scope: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_auth_token_sets_registered_claims() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+
+        let first = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        let first = stores.validate(&first).await.unwrap();
+        assert_eq!(first.iss, *JWT_ISSUER);
+        assert_eq!(first.aud, *JWT_AUDIENCE);
+        assert_eq!(first.nbf, first.iat);
+        assert_eq!(first.exp, first.iat + TOKEN_TTL_SECONDS as usize);
+
+        let second = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        let second = stores.validate(&second).await.unwrap();
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[tokio::test]
+    async fn test_generate_auth_token_carries_scopes() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+
+        let scopes = Scopes::parse("roles:manage articles:write").unwrap();
+        let token = generate_auth_token(&user_id, &session_id, &scopes).unwrap();
+        let claims = stores.validate(&token).await.unwrap();
+        assert_eq!(claims.scope.as_deref(), Some("articles:write roles:manage"));
+
+        let token = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        assert_eq!(stores.validate(&token).await.unwrap().scope, None);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_banned_jti() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+        let token = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        let claims = stores.validate(&token).await.unwrap();
+
+        stores
+            .banned_token_store
+            .write()
+            .await
+            .add_token(claims.jti)
+            .await
+            .unwrap();
+
+        let result = stores.validate(&token).await;
+        assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_foreign_issuer_and_audience() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+
+        let claims = Claims {
+            iss: "another-issuer".to_owned(),
+            ..claims_for(&user_id, &session_id)
+        };
+        let token = create_token(&claims).unwrap();
+        assert!(stores.validate(&token).await.is_err());
+
+        let claims = Claims {
+            aud: "another-service".to_owned(),
+            ..claims_for(&user_id, &session_id)
+        };
+        let token = create_token(&claims).unwrap();
+        assert!(stores.validate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_token_before_nbf() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+        let claims = claims_for(&user_id, &session_id);
+        let claims = Claims {
+            nbf: claims.iat + *JWT_LEEWAY_SECONDS as usize + 60,
+            ..claims
+        };
+        let token = create_token(&claims).unwrap();
+        assert!(stores.validate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_revoked_session() {
+        let stores = Stores::new();
+        let user_id = UserId::default();
+        let session_id = stores.session(&user_id).await;
+        let token = generate_auth_token(&user_id, &session_id, &Scopes::default()).unwrap();
+        assert!(stores.validate(&token).await.is_ok());
+
+        stores
+            .session_store
+            .write()
+            .await
+            .remove_session(&user_id, &session_id)
+            .await
+            .unwrap();
+
+        let result = stores.validate(&token).await;
+        assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_another_users_session() {
+        let stores = Stores::new();
+        let session_id = stores.session(&UserId::default()).await;
+        let token =
+            generate_auth_token(&UserId::default(), &session_id, &Scopes::default()).unwrap();
+
+        let result = stores.validate(&token).await;
+        assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+    }
 }
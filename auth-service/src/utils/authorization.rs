@@ -0,0 +1,296 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Result};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        data_stores::OAuthClientStoreError, ClientId, ClientSecret, OAuthClient, OAuthError,
+        Scopes, User, EMAIL_SCOPE, OPENID_SCOPE,
+    },
+};
+
+use super::{
+    auth::TOKEN_TTL_SECONDS,
+    constants::{JWT_ISSUER, JWT_KEYRING},
+    signing::Keyring,
+};
+
+/// Codes are exchanged right after the redirect, so they only need to live long enough for that.
+pub const AUTHORIZATION_CODE_TTL_SECONDS: i64 = 60;
+
+/// Claims of the ID token issued along with an access token when the openid scope is granted
+/// (OpenID Connect Core section 2). Its audience is the client, so it can't be used to call APIs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+/// Clients check ID tokens with the key published in the JWKS, so they are only issued when tokens
+/// are signed with a private key. An HS256 signature could only be checked with the server secret.
+pub fn openid_enabled() -> bool {
+    JWT_KEYRING.active().has_public_key()
+}
+
+#[tracing::instrument(name = "Generate ID token", skip_all)]
+pub fn generate_id_token(
+    user: &User,
+    client_id: &ClientId,
+    scopes: &Scopes,
+    nonce: Option<String>,
+) -> Result<Secret<String>> {
+    let iat = Utc::now().timestamp();
+    let (email, email_verified) = if scopes.contains(EMAIL_SCOPE) {
+        (
+            Some(user.email.as_ref().expose_secret().to_owned()),
+            Some(user.verified),
+        )
+    } else {
+        (None, None)
+    };
+
+    let claims = IdTokenClaims {
+        iss: JWT_ISSUER.to_owned(),
+        sub: user.id.to_string(),
+        aud: client_id.to_string(),
+        exp: (iat + TOKEN_TTL_SECONDS) as usize,
+        iat: iat as usize,
+        nonce,
+        email,
+        email_verified,
+    };
+    sign_id_token(&JWT_KEYRING, &claims)
+}
+
+fn sign_id_token(keyring: &Keyring, claims: &IdTokenClaims) -> Result<Secret<String>> {
+    if !keyring.active().has_public_key() {
+        return Err(eyre!("ID tokens can't be signed with a shared secret"));
+    }
+    keyring.encode(claims)
+}
+
+/// Authenticates the client calling the token or introspection endpoint, with HTTP Basic or with
+/// the credentials in the request body (RFC 6749 section 2.3.1). Public clients only name
+/// themselves, which is all they can do.
+#[tracing::instrument(name = "Authenticate OAuth client", skip_all)]
+pub async fn authenticate_client(
+    headers: &HeaderMap,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+    state: &AppState,
+) -> Result<OAuthClient, OAuthError> {
+    let (client_id, client_secret) = match basic_credentials(headers)? {
+        Some(_) if client_secret.is_some() => {
+            return Err(OAuthError::InvalidRequest(
+                "use only one way to authenticate the client",
+            ))
+        }
+        Some((id, secret)) => (id, Some(secret)),
+        None => (
+            client_id.ok_or(OAuthError::InvalidRequest("client_id is missing"))?,
+            client_secret,
+        ),
+    };
+
+    let client_id = ClientId::parse(&client_id).map_err(|_| OAuthError::InvalidClient)?;
+    let client = match state
+        .oauth_client_store
+        .read()
+        .await
+        .get_client(&client_id)
+        .await
+    {
+        Ok(client) => client,
+        Err(OAuthClientStoreError::ClientNotFound) => return Err(OAuthError::InvalidClient),
+        Err(e) => return Err(OAuthError::UnexpectedError(e.into())),
+    };
+
+    match client_secret {
+        Some(secret) => {
+            let secret = ClientSecret::parse(secret).map_err(|_| OAuthError::InvalidClient)?;
+            if !client.verify_secret(&secret) {
+                return Err(OAuthError::InvalidClient);
+            }
+        }
+        None if client.is_confidential() => return Err(OAuthError::InvalidClient),
+        None => {}
+    }
+    Ok(client)
+}
+
+/// Authenticates a client that wants to introspect tokens. Only confidential clients can, since a
+/// public client's id is no secret.
+pub async fn authenticate_introspecting_client(
+    headers: &HeaderMap,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+    state: &AppState,
+) -> Result<OAuthClient, OAuthError> {
+    let client = authenticate_client(headers, client_id, client_secret, state).await?;
+    if !client.is_confidential() {
+        return Err(OAuthError::InvalidClient);
+    }
+    Ok(client)
+}
+
+/// Client ids and secrets are generated without characters that need form encoding, so the
+/// credentials are used as they are sent.
+fn basic_credentials(headers: &HeaderMap) -> Result<Option<(String, Secret<String>)>, OAuthError> {
+    let Some(value) = headers.get(AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let credentials = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(OAuthError::InvalidClient)?;
+    let (id, secret) = credentials
+        .split_once(':')
+        .ok_or(OAuthError::InvalidClient)?;
+    Ok(Some((id.to_owned(), Secret::new(secret.to_owned()))))
+}
+
+/// Checks the initial access token that `/oauth/register` is protected with (RFC 7591 section 3).
+/// Registration is closed when no token is configured.
+pub fn check_registration_token(headers: &HeaderMap, state: &AppState) -> Result<(), OAuthError> {
+    let expected = state
+        .client_registration_token
+        .as_ref()
+        .ok_or(OAuthError::InvalidToken)?;
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(OAuthError::InvalidToken)?;
+
+    if bool::from(
+        token
+            .trim()
+            .as_bytes()
+            .ct_eq(expected.expose_secret().as_bytes()),
+    ) {
+        Ok(())
+    } else {
+        Err(OAuthError::InvalidToken)
+    }
+}
+
+/// Scopes the client asked for, or all of its scopes when it didn't ask. A client never gets
+/// more than it was registered with.
+pub fn requested_scopes(scope: Option<&str>, client: &OAuthClient) -> Result<Scopes, OAuthError> {
+    let scopes = match scope {
+        Some(scope) => Scopes::parse(scope).map_err(|_| OAuthError::InvalidScope)?,
+        None => client.scopes.clone(),
+    };
+    if !scopes.is_subset(&client.scopes) {
+        return Err(OAuthError::InvalidScope);
+    }
+    if scopes.contains(OPENID_SCOPE) && !openid_enabled() {
+        return Err(OAuthError::InvalidScope);
+    }
+    Ok(scopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::signing::SigningKey;
+    use axum::http::HeaderValue;
+    use jsonwebtoken::Algorithm;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parse_basic_credentials() {
+        let encoded = STANDARD.encode("client:secret");
+        let (id, secret) = basic_credentials(&headers(&format!("Basic {}", encoded)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, "client");
+        assert_eq!(secret.expose_secret(), "secret");
+
+        assert!(basic_credentials(&HeaderMap::new()).unwrap().is_none());
+        assert!(basic_credentials(&headers("Bearer token")).is_err());
+        assert!(basic_credentials(&headers("Basic not-base64")).is_err());
+        let no_separator = STANDARD.encode("client");
+        assert!(basic_credentials(&headers(&format!("Basic {}", no_separator))).is_err());
+    }
+
+    #[test]
+    fn limit_requested_scopes_to_client() {
+        let (client, _) = OAuthClient::new(
+            "App".to_owned(),
+            vec![],
+            vec![crate::domain::GrantType::ClientCredentials],
+            Scopes::parse("orders:read orders:write").unwrap(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(requested_scopes(None, &client).unwrap(), client.scopes);
+        assert_eq!(
+            requested_scopes(Some("orders:read"), &client)
+                .unwrap()
+                .to_string(),
+            "orders:read"
+        );
+        assert!(requested_scopes(Some("orders:delete"), &client).is_err());
+    }
+
+    #[test]
+    fn sign_id_tokens_only_with_private_keys() {
+        let claims = IdTokenClaims {
+            iss: "https://auth.example.com".to_owned(),
+            sub: "user".to_owned(),
+            aud: "client".to_owned(),
+            exp: (Utc::now().timestamp() + 60) as usize,
+            iat: Utc::now().timestamp() as usize,
+            nonce: None,
+            email: None,
+            email_verified: None,
+        };
+
+        let pem = include_bytes!("../../tests/fixtures/jwt_ed25519_private_key.pem");
+        let key = SigningKey::from_pem("ed".to_owned(), Algorithm::EdDSA, pem).unwrap();
+        assert!(sign_id_token(&Keyring::new(key, 0), &claims).is_ok());
+
+        let secret = Secret::new("secret".to_owned());
+        let key = SigningKey::from_secret("hmac".to_owned(), Algorithm::HS256, &secret).unwrap();
+        assert!(sign_id_token(&Keyring::new(key, 0), &claims).is_err());
+    }
+}
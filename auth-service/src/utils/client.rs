@@ -0,0 +1,95 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use std::{convert::Infallible, net::SocketAddr};
+
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Describes the device a request came from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // X-Forwarded-For is set by whoever sent the request, so it is never trusted here. The
+        // Lambda entry point fills in the connection info from the API gateway instead.
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string());
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        Ok(Self { ip, user_agent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn client_info(request: Request<()>) -> ClientInfo {
+        let (mut parts, _) = request.into_parts();
+        ClientInfo::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_take_ip_from_connect_info() {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([198, 51, 100, 1], 443))));
+
+        assert_eq!(
+            client_info(request).await.ip,
+            Some("198.51.100.1".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn should_ignore_forwarded_for_header() {
+        let request = Request::builder()
+            .header("x-forwarded-for", "203.0.113.7, 198.51.100.1")
+            .body(())
+            .unwrap();
+
+        assert_eq!(client_info(request).await.ip, None);
+    }
+}
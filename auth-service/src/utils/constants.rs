@@ -15,12 +15,46 @@
 */
 
 use dotenvy::dotenv;
+use jsonwebtoken::Algorithm;
 use lazy_static::lazy_static;
 use secrecy::Secret;
-use std::env as std_env;
+use std::{env as std_env, fs, str::FromStr};
+
+use super::{
+    auth::TOKEN_TTL_SECONDS,
+    authorization::openid_enabled,
+    rate_limit::RateLimits,
+    signing::{Keyring, SigningKey},
+};
+use crate::domain::{
+    Normalization, OAuthProviders, PasswordPolicy, RateLimit, Roles, DEFAULT_MAX_PASSWORD_LENGTH,
+    DEFAULT_MIN_PASSWORD_LENGTH,
+};
+use crate::services::argon2_password_hasher::Argon2PasswordHasher;
 
 lazy_static! {
     pub static ref JWT_SECRET: Secret<String> = set_token();
+    pub static ref JWT_KEYRING: Keyring = set_keyring();
+    pub static ref JWT_ISSUER: String = set_jwt_issuer();
+    pub static ref JWT_AUDIENCE: String = set_jwt_audience();
+    pub static ref JWT_LEEWAY_SECONDS: u64 = set_jwt_leeway();
+    pub static ref TOTP_ISSUER: String = set_totp_issuer();
+    pub static ref WEBAUTHN_RP_ID: String = set_webauthn_rp_id();
+    pub static ref WEBAUTHN_RP_NAME: String = set_webauthn_rp_name();
+    pub static ref WEBAUTHN_ORIGIN: String = set_webauthn_origin();
+    pub static ref PASSWORD_RESET_URL: String = set_password_reset_url();
+    pub static ref EMAIL_VERIFICATION_URL: String = set_email_verification_url();
+    pub static ref ACCOUNT_DELETION_GRACE_PERIOD_SECONDS: i64 = set_account_deletion_grace_period();
+    pub static ref RATE_LIMITS: RateLimits = set_rate_limits();
+    pub static ref PASSWORD_POLICY: PasswordPolicy = set_password_policy();
+    pub static ref PASSWORD_BREACH_CHECK: bool = set_password_breach_check();
+    pub static ref BREACHED_PASSWORDS_PATH: Option<String> = set_breached_passwords_path();
+    pub static ref PASSWORD_HASHER: Argon2PasswordHasher = set_password_hasher();
+    pub static ref OAUTH_PROVIDERS: OAuthProviders = set_oauth_providers();
+    pub static ref AUTH_SERVICE_URL: String = set_auth_service_url();
+    pub static ref OAUTH_CLIENT_REGISTRATION_TOKEN: Option<Secret<String>> =
+        set_oauth_client_registration_token();
+    pub static ref ROLES: Roles = set_roles();
     pub static ref DATABASE_URL: Secret<String> = set_db_url();
     pub static ref REDIS_HOST_NAME: String = set_redis_host();
     pub static ref POSTMARK_AUTH_TOKEN: Secret<String> = set_postmark_auth_token();
@@ -35,6 +69,300 @@ fn set_token() -> Secret<String> {
     Secret::new(secret)
 }
 
+fn set_keyring() -> Keyring {
+    dotenv().ok();
+    // A retired key must outlive the tokens it signed, including the leeway given to their expiry.
+    let grace_period = TOKEN_TTL_SECONDS + *JWT_LEEWAY_SECONDS as i64;
+
+    if let Ok(path) = std_env::var(env::JWT_KEYRING_PATH_ENV_VAR) {
+        let json = fs::read_to_string(path).expect("JWT_KEYRING_PATH should be a readable file.");
+        return Keyring::from_json(&json, grace_period)
+            .expect("JWT_KEYRING_PATH should contain a valid keyring.");
+    }
+
+    let kid = std_env::var(env::JWT_KEY_ID_ENV_VAR).unwrap_or(DEFAULT_JWT_KEY_ID.to_owned());
+    let algorithm =
+        std_env::var(env::JWT_ALGORITHM_ENV_VAR).unwrap_or(DEFAULT_JWT_ALGORITHM.to_owned());
+    let algorithm = Algorithm::from_str(&algorithm)
+        .expect("JWT_ALGORITHM should be a supported JWT algorithm.");
+
+    let key = match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            SigningKey::from_secret(kid, algorithm, &JWT_SECRET)
+                .expect("JWT_SECRET should be a valid signing key.")
+        }
+        _ => {
+            let path = std_env::var(env::JWT_PRIVATE_KEY_PATH_ENV_VAR)
+                .expect("JWT_PRIVATE_KEY_PATH must be set for asymmetric JWT algorithms.");
+            let pem = fs::read(path).expect("JWT_PRIVATE_KEY_PATH should be a readable file.");
+            SigningKey::from_pem(kid, algorithm, &pem)
+                .expect("JWT_PRIVATE_KEY_PATH should contain a private key for JWT_ALGORITHM.")
+        }
+    };
+
+    Keyring::new(key, grace_period)
+}
+
+fn set_jwt_issuer() -> String {
+    dotenv().ok();
+    let issuer = std_env::var(env::JWT_ISSUER_ENV_VAR).unwrap_or(DEFAULT_JWT_ISSUER.to_owned());
+    if issuer.is_empty() {
+        panic!("JWT_ISSUER should not be empty.");
+    }
+    // ID tokens carry it, and OpenID Connect only accepts an https URL as their issuer
+    let is_https_url = reqwest::Url::parse(&issuer).is_ok_and(|url| url.scheme() == "https");
+    if openid_enabled() && !is_https_url {
+        panic!("JWT_ISSUER should be an https URL when tokens are signed with a private key.");
+    }
+    issuer
+}
+
+fn set_jwt_audience() -> String {
+    dotenv().ok();
+    let audience =
+        std_env::var(env::JWT_AUDIENCE_ENV_VAR).unwrap_or(DEFAULT_JWT_AUDIENCE.to_owned());
+    if audience.is_empty() {
+        panic!("JWT_AUDIENCE should not be empty.");
+    }
+    audience
+}
+
+fn set_jwt_leeway() -> u64 {
+    dotenv().ok();
+    std_env::var(env::JWT_LEEWAY_SECONDS_ENV_VAR)
+        .map(|leeway| {
+            leeway
+                .parse()
+                .expect("JWT_LEEWAY_SECONDS should be a number of seconds.")
+        })
+        .unwrap_or(DEFAULT_JWT_LEEWAY_SECONDS)
+}
+
+fn set_totp_issuer() -> String {
+    dotenv().ok();
+    let issuer = std_env::var(env::TOTP_ISSUER_ENV_VAR).unwrap_or(DEFAULT_TOTP_ISSUER.to_owned());
+    // authenticator apps split the otpauth label on ':'
+    if issuer.is_empty() || issuer.contains(':') {
+        panic!("TOTP_ISSUER should be a non-empty name without ':'.");
+    }
+    issuer
+}
+
+fn set_webauthn_rp_id() -> String {
+    dotenv().ok();
+    let rp_id =
+        std_env::var(env::WEBAUTHN_RP_ID_ENV_VAR).unwrap_or(DEFAULT_WEBAUTHN_RP_ID.to_owned());
+    if rp_id.is_empty() {
+        panic!("WEBAUTHN_RP_ID should not be empty.");
+    }
+    rp_id
+}
+
+fn set_webauthn_rp_name() -> String {
+    dotenv().ok();
+    std_env::var(env::WEBAUTHN_RP_NAME_ENV_VAR).unwrap_or(DEFAULT_WEBAUTHN_RP_NAME.to_owned())
+}
+
+fn set_webauthn_origin() -> String {
+    dotenv().ok();
+    let origin =
+        std_env::var(env::WEBAUTHN_ORIGIN_ENV_VAR).unwrap_or(DEFAULT_WEBAUTHN_ORIGIN.to_owned());
+    if origin.is_empty() {
+        panic!("WEBAUTHN_ORIGIN should not be empty.");
+    }
+    origin
+}
+
+fn set_password_reset_url() -> String {
+    dotenv().ok();
+    let url = std_env::var(env::PASSWORD_RESET_URL_ENV_VAR)
+        .unwrap_or(DEFAULT_PASSWORD_RESET_URL.to_owned());
+    if reqwest::Url::parse(&url).is_err() {
+        panic!("PASSWORD_RESET_URL should be an absolute URL.");
+    }
+    url
+}
+
+fn set_email_verification_url() -> String {
+    dotenv().ok();
+    let url = std_env::var(env::EMAIL_VERIFICATION_URL_ENV_VAR)
+        .unwrap_or(DEFAULT_EMAIL_VERIFICATION_URL.to_owned());
+    if reqwest::Url::parse(&url).is_err() {
+        panic!("EMAIL_VERIFICATION_URL should be an absolute URL.");
+    }
+    url
+}
+
+fn set_account_deletion_grace_period() -> i64 {
+    dotenv().ok();
+    let grace_period = std_env::var(env::ACCOUNT_DELETION_GRACE_PERIOD_SECONDS_ENV_VAR)
+        .map(|grace_period| {
+            grace_period
+                .parse()
+                .expect("ACCOUNT_DELETION_GRACE_PERIOD_SECONDS should be a number of seconds.")
+        })
+        .unwrap_or(DEFAULT_ACCOUNT_DELETION_GRACE_PERIOD_SECONDS);
+    if grace_period < 0 {
+        panic!("ACCOUNT_DELETION_GRACE_PERIOD_SECONDS should not be negative.");
+    }
+    grace_period
+}
+
+fn set_rate_limits() -> RateLimits {
+    dotenv().ok();
+    RateLimits {
+        signup: rate_limit_from_env(env::SIGNUP_RATE_LIMIT_ENV_VAR, DEFAULT_SIGNUP_RATE_LIMIT),
+        login: rate_limit_from_env(env::LOGIN_RATE_LIMIT_ENV_VAR, DEFAULT_LOGIN_RATE_LIMIT),
+        verify_2fa: rate_limit_from_env(
+            env::VERIFY_2FA_RATE_LIMIT_ENV_VAR,
+            DEFAULT_VERIFY_2FA_RATE_LIMIT,
+        ),
+        verify_token: rate_limit_from_env(
+            env::VERIFY_TOKEN_RATE_LIMIT_ENV_VAR,
+            DEFAULT_VERIFY_TOKEN_RATE_LIMIT,
+        ),
+        password_reset: rate_limit_from_env(
+            env::PASSWORD_RESET_RATE_LIMIT_ENV_VAR,
+            DEFAULT_PASSWORD_RESET_RATE_LIMIT,
+        ),
+    }
+}
+
+fn rate_limit_from_env(var: &str, default: RateLimit) -> RateLimit {
+    std_env::var(var)
+        .map(|limit| {
+            RateLimit::parse(&limit)
+                .unwrap_or_else(|_| panic!("{} should look like <requests>/<seconds>.", var))
+        })
+        .unwrap_or(default)
+}
+
+fn set_password_policy() -> PasswordPolicy {
+    dotenv().ok();
+    let min_length = std_env::var(env::PASSWORD_MIN_LENGTH_ENV_VAR)
+        .map(|length| {
+            length
+                .parse()
+                .expect("PASSWORD_MIN_LENGTH should be a number of characters.")
+        })
+        .unwrap_or(DEFAULT_MIN_PASSWORD_LENGTH);
+    if min_length < DEFAULT_MIN_PASSWORD_LENGTH {
+        panic!(
+            "PASSWORD_MIN_LENGTH should be at least {}.",
+            DEFAULT_MIN_PASSWORD_LENGTH
+        );
+    }
+    let max_length = std_env::var(env::PASSWORD_MAX_LENGTH_ENV_VAR)
+        .map(|length| {
+            length
+                .parse()
+                .expect("PASSWORD_MAX_LENGTH should be a number of characters.")
+        })
+        .unwrap_or(DEFAULT_MAX_PASSWORD_LENGTH);
+    if max_length < min_length {
+        panic!("PASSWORD_MAX_LENGTH should not be less than PASSWORD_MIN_LENGTH.");
+    }
+    let normalization = std_env::var(env::PASSWORD_NORMALIZATION_ENV_VAR)
+        .map(|normalization| {
+            Normalization::parse(&normalization)
+                .expect("PASSWORD_NORMALIZATION should be one of none, nfc or nfkc.")
+        })
+        .unwrap_or(Normalization::Nfkc);
+
+    PasswordPolicy {
+        min_length,
+        max_length,
+        normalization,
+        breached_passwords: None,
+    }
+}
+
+fn set_password_breach_check() -> bool {
+    dotenv().ok();
+    std_env::var(env::PASSWORD_BREACH_CHECK_ENV_VAR)
+        .map(|check| {
+            check
+                .parse()
+                .expect("PASSWORD_BREACH_CHECK should be true or false.")
+        })
+        .unwrap_or(false)
+}
+
+fn set_breached_passwords_path() -> Option<String> {
+    dotenv().ok();
+    std_env::var(env::BREACHED_PASSWORDS_PATH_ENV_VAR).ok()
+}
+
+fn set_password_hasher() -> Argon2PasswordHasher {
+    dotenv().ok();
+    let memory_kib = hash_param_from_env(
+        env::PASSWORD_HASH_MEMORY_KIB_ENV_VAR,
+        DEFAULT_PASSWORD_HASH_MEMORY_KIB,
+    );
+    let iterations = hash_param_from_env(
+        env::PASSWORD_HASH_ITERATIONS_ENV_VAR,
+        DEFAULT_PASSWORD_HASH_ITERATIONS,
+    );
+    let parallelism = hash_param_from_env(
+        env::PASSWORD_HASH_PARALLELISM_ENV_VAR,
+        DEFAULT_PASSWORD_HASH_PARALLELISM,
+    );
+    Argon2PasswordHasher::new(memory_kib, iterations, parallelism)
+        .expect("PASSWORD_HASH_* should be valid Argon2 parameters.")
+}
+
+fn hash_param_from_env(var: &str, default: u32) -> u32 {
+    std_env::var(var)
+        .map(|param| {
+            param
+                .parse()
+                .unwrap_or_else(|_| panic!("{} should be a positive number.", var))
+        })
+        .unwrap_or(default)
+}
+
+fn set_oauth_providers() -> OAuthProviders {
+    dotenv().ok();
+    match std_env::var(env::OAUTH_PROVIDERS_PATH_ENV_VAR) {
+        Ok(path) => {
+            let json =
+                fs::read_to_string(path).expect("OAUTH_PROVIDERS_PATH should be a readable file.");
+            OAuthProviders::from_json(&json)
+                .expect("OAUTH_PROVIDERS_PATH should contain valid OAuth providers.")
+        }
+        Err(_) => OAuthProviders::default(),
+    }
+}
+
+fn set_auth_service_url() -> String {
+    dotenv().ok();
+    let url =
+        std_env::var(env::AUTH_SERVICE_URL_ENV_VAR).unwrap_or(DEFAULT_AUTH_SERVICE_URL.to_owned());
+    if reqwest::Url::parse(&url).is_err() {
+        panic!("AUTH_SERVICE_URL should be an absolute URL.");
+    }
+    url.trim_end_matches('/').to_owned()
+}
+
+fn set_oauth_client_registration_token() -> Option<Secret<String>> {
+    dotenv().ok();
+    std_env::var(env::OAUTH_CLIENT_REGISTRATION_TOKEN_ENV_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Secret::new)
+}
+
+fn set_roles() -> Roles {
+    dotenv().ok();
+    match std_env::var(env::ROLES_PATH_ENV_VAR) {
+        Ok(path) => {
+            let json = fs::read_to_string(path).expect("ROLES_PATH should be a readable file.");
+            Roles::from_json(&json).expect("ROLES_PATH should contain valid roles.")
+        }
+        Err(_) => Roles::default(),
+    }
+}
+
 fn set_db_url() -> Secret<String> {
     dotenv().ok();
     Secret::new(std_env::var(env::DATABASE_URL_ENV_VAR).expect("DATABASE_URL must be set."))
@@ -54,16 +382,89 @@ fn set_postmark_auth_token() -> Secret<String> {
 
 pub mod env {
     pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
+    pub const JWT_ALGORITHM_ENV_VAR: &str = "JWT_ALGORITHM";
+    pub const JWT_PRIVATE_KEY_PATH_ENV_VAR: &str = "JWT_PRIVATE_KEY_PATH";
+    pub const JWT_KEY_ID_ENV_VAR: &str = "JWT_KEY_ID";
+    pub const JWT_KEYRING_PATH_ENV_VAR: &str = "JWT_KEYRING_PATH";
+    pub const JWT_ISSUER_ENV_VAR: &str = "JWT_ISSUER";
+    pub const JWT_AUDIENCE_ENV_VAR: &str = "JWT_AUDIENCE";
+    pub const JWT_LEEWAY_SECONDS_ENV_VAR: &str = "JWT_LEEWAY_SECONDS";
+    pub const TOTP_ISSUER_ENV_VAR: &str = "TOTP_ISSUER";
+    pub const WEBAUTHN_RP_ID_ENV_VAR: &str = "WEBAUTHN_RP_ID";
+    pub const WEBAUTHN_RP_NAME_ENV_VAR: &str = "WEBAUTHN_RP_NAME";
+    pub const WEBAUTHN_ORIGIN_ENV_VAR: &str = "WEBAUTHN_ORIGIN";
+    pub const PASSWORD_RESET_URL_ENV_VAR: &str = "PASSWORD_RESET_URL";
+    pub const EMAIL_VERIFICATION_URL_ENV_VAR: &str = "EMAIL_VERIFICATION_URL";
+    pub const ACCOUNT_DELETION_GRACE_PERIOD_SECONDS_ENV_VAR: &str =
+        "ACCOUNT_DELETION_GRACE_PERIOD_SECONDS";
+    pub const SIGNUP_RATE_LIMIT_ENV_VAR: &str = "SIGNUP_RATE_LIMIT";
+    pub const LOGIN_RATE_LIMIT_ENV_VAR: &str = "LOGIN_RATE_LIMIT";
+    pub const VERIFY_2FA_RATE_LIMIT_ENV_VAR: &str = "VERIFY_2FA_RATE_LIMIT";
+    pub const VERIFY_TOKEN_RATE_LIMIT_ENV_VAR: &str = "VERIFY_TOKEN_RATE_LIMIT";
+    pub const PASSWORD_RESET_RATE_LIMIT_ENV_VAR: &str = "PASSWORD_RESET_RATE_LIMIT";
+    pub const PASSWORD_MIN_LENGTH_ENV_VAR: &str = "PASSWORD_MIN_LENGTH";
+    pub const PASSWORD_MAX_LENGTH_ENV_VAR: &str = "PASSWORD_MAX_LENGTH";
+    pub const PASSWORD_NORMALIZATION_ENV_VAR: &str = "PASSWORD_NORMALIZATION";
+    pub const PASSWORD_BREACH_CHECK_ENV_VAR: &str = "PASSWORD_BREACH_CHECK";
+    pub const BREACHED_PASSWORDS_PATH_ENV_VAR: &str = "BREACHED_PASSWORDS_PATH";
+    pub const PASSWORD_HASH_MEMORY_KIB_ENV_VAR: &str = "PASSWORD_HASH_MEMORY_KIB";
+    pub const PASSWORD_HASH_ITERATIONS_ENV_VAR: &str = "PASSWORD_HASH_ITERATIONS";
+    pub const PASSWORD_HASH_PARALLELISM_ENV_VAR: &str = "PASSWORD_HASH_PARALLELISM";
+    pub const OAUTH_PROVIDERS_PATH_ENV_VAR: &str = "OAUTH_PROVIDERS_PATH";
+    pub const AUTH_SERVICE_URL_ENV_VAR: &str = "AUTH_SERVICE_URL";
+    pub const OAUTH_CLIENT_REGISTRATION_TOKEN_ENV_VAR: &str = "OAUTH_CLIENT_REGISTRATION_TOKEN";
+    pub const ROLES_PATH_ENV_VAR: &str = "ROLES_PATH";
     pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
     pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME";
     pub const POSTMARK_AUTH_TOKEN_ENV_VAR: &str = "POSTMARK_AUTH_TOKEN";
 }
 
 pub const JWT_COOKIE_NAME: &str = "jwt";
+pub const REFRESH_TOKEN_COOKIE_NAME: &str = "refresh_token";
+pub const OAUTH_STATE_COOKIE_NAME: &str = "oauth_state";
 pub const DEFAULT_REDIS_HOSTNAME: &str = "127.0.0.1";
+pub const DEFAULT_JWT_ALGORITHM: &str = "HS256";
+pub const DEFAULT_JWT_KEY_ID: &str = "primary";
+pub const DEFAULT_JWT_ISSUER: &str = "auth-service";
+pub const DEFAULT_JWT_AUDIENCE: &str = "app-service";
+pub const DEFAULT_JWT_LEEWAY_SECONDS: u64 = 60;
+pub const DEFAULT_TOTP_ISSUER: &str = "Outh";
+pub const DEFAULT_WEBAUTHN_RP_ID: &str = "localhost";
+pub const DEFAULT_WEBAUTHN_RP_NAME: &str = "Outh";
+pub const DEFAULT_WEBAUTHN_ORIGIN: &str = "http://localhost:42069";
+pub const DEFAULT_PASSWORD_RESET_URL: &str = "http://localhost:42069/reset-password";
+pub const DEFAULT_EMAIL_VERIFICATION_URL: &str = "http://localhost:42069/verify-email";
+pub const DEFAULT_AUTH_SERVICE_URL: &str = "http://localhost:42069";
+pub const DEFAULT_ACCOUNT_DELETION_GRACE_PERIOD_SECONDS: i64 = 30 * 24 * 60 * 60;
+pub const DEFAULT_SIGNUP_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 10,
+    per_seconds: 60,
+};
+pub const DEFAULT_LOGIN_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 20,
+    per_seconds: 60,
+};
+pub const DEFAULT_VERIFY_2FA_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 20,
+    per_seconds: 60,
+};
+// the app service checks a token on every request it serves
+pub const DEFAULT_VERIFY_TOKEN_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 300,
+    per_seconds: 60,
+};
+// every request can send an email
+pub const DEFAULT_PASSWORD_RESET_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 5,
+    per_seconds: 60,
+};
+pub const DEFAULT_PASSWORD_HASH_MEMORY_KIB: u32 = 15000;
+pub const DEFAULT_PASSWORD_HASH_ITERATIONS: u32 = 2;
+pub const DEFAULT_PASSWORD_HASH_PARALLELISM: u32 = 1;
 
 pub mod prod {
     pub const APP_ADDRESS: &str = "0.0.0.0:42069";
+    pub const METRICS_ADDRESS: &str = "0.0.0.0:42070";
     pub mod email_client {
         use std::time::Duration;
 
@@ -71,6 +472,17 @@ pub mod prod {
         pub const SENDER: &str = "code.ibra@gmail.com";
         pub const TIMEOUT: Duration = Duration::from_secs(10);
     }
+    pub mod breached_passwords {
+        use std::time::Duration;
+
+        pub const BASE_URL: &str = "https://api.pwnedpasswords.com";
+        pub const TIMEOUT: Duration = Duration::from_secs(5);
+    }
+    pub mod oauth {
+        use std::time::Duration;
+
+        pub const TIMEOUT: Duration = Duration::from_secs(10);
+    }
 }
 
 pub mod test {
@@ -81,4 +493,9 @@ pub mod test {
         pub const SENDER: &str = "test@email.com";
         pub const TIMEOUT: Duration = Duration::from_millis(200);
     }
+    pub mod oauth {
+        use std::time::Duration;
+
+        pub const TIMEOUT: Duration = Duration::from_millis(200);
+    }
 }
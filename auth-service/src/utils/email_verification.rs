@@ -0,0 +1,135 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::Utc;
+use color_eyre::eyre::{Context, Result};
+use jsonwebtoken::{Algorithm, Validation};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    auth::TokenValidationError,
+    constants::{EMAIL_VERIFICATION_URL, JWT_ISSUER, JWT_KEYRING, JWT_LEEWAY_SECONDS},
+};
+use crate::domain::UserId;
+
+pub const EMAIL_VERIFICATION_TOKEN_TTL_SECONDS: i64 = 86400;
+
+/// How long to wait before another verification email may go to the same address.
+pub const VERIFICATION_EMAIL_COOLDOWN_SECONDS: i64 = 60;
+
+/// Verification tokens are signed with the same keys as auth tokens, the audience keeps the two
+/// apart.
+const EMAIL_VERIFICATION_AUDIENCE: &str = "email-verification";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerificationClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+}
+
+#[tracing::instrument(name = "Generate email verification token", skip_all)]
+pub fn generate_email_verification_token(user_id: &UserId) -> Result<Secret<String>> {
+    let now = Utc::now().timestamp();
+    let iat: usize = now.try_into().wrap_err(format!(
+        "failed to cast iat time to usize. iat time: {}",
+        now
+    ))?;
+    let exp: usize = (now + EMAIL_VERIFICATION_TOKEN_TTL_SECONDS)
+        .try_into()
+        .wrap_err("failed to cast exp time to usize")?;
+
+    let claims = EmailVerificationClaims {
+        sub: user_id.to_string(),
+        iss: JWT_ISSUER.to_owned(),
+        aud: EMAIL_VERIFICATION_AUDIENCE.to_owned(),
+        exp,
+        iat,
+        nbf: iat,
+    };
+
+    JWT_KEYRING.encode(&claims)
+}
+
+/// The link mailed to the user, `EMAIL_VERIFICATION_URL` with the token as the `token` parameter.
+pub fn email_verification_link(token: &Secret<String>) -> Result<String> {
+    let mut url = Url::parse(&EMAIL_VERIFICATION_URL).wrap_err("invalid email verification URL")?;
+    url.query_pairs_mut()
+        .append_pair("token", token.expose_secret());
+    Ok(url.to_string())
+}
+
+/// Checks the signature and expiry of a verification token. Verifying twice is harmless, so
+/// unlike reset tokens these aren't used up.
+#[tracing::instrument(name = "Validate email verification token", skip_all)]
+pub fn validate_email_verification_token(
+    token: &Secret<String>,
+) -> Result<EmailVerificationClaims, TokenValidationError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[EMAIL_VERIFICATION_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_nbf = true;
+    validation.leeway = *JWT_LEEWAY_SECONDS;
+
+    JWT_KEYRING
+        .decode::<EmailVerificationClaims>(token, &validation)
+        .map_err(TokenValidationError::InvalidToken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{Scopes, SessionId},
+        utils::{auth::generate_auth_cookie, password_reset::generate_password_reset_token},
+    };
+
+    #[test]
+    fn accept_generated_token() {
+        let user_id = UserId::default();
+        let token = generate_email_verification_token(&user_id).unwrap();
+
+        let claims = validate_email_verification_token(&token).unwrap();
+        assert_eq!(claims.sub, user_id.to_string());
+    }
+
+    #[test]
+    fn reject_tokens_for_other_purposes() {
+        let user_id = UserId::default();
+        let cookie =
+            generate_auth_cookie(&user_id, &SessionId::default(), &Scopes::default()).unwrap();
+        let auth_token = Secret::new(cookie.value().to_owned());
+        let reset_token = generate_password_reset_token(&user_id).unwrap();
+
+        for token in [auth_token, reset_token] {
+            let result = validate_email_verification_token(&token);
+            assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+        }
+    }
+
+    #[test]
+    fn link_carries_token() {
+        let token = Secret::new("header.payload.signature".to_owned());
+        let link = email_verification_link(&token).unwrap();
+        assert!(link.starts_with(EMAIL_VERIFICATION_URL.as_str()));
+        assert!(link.ends_with("?token=header.payload.signature"));
+    }
+}
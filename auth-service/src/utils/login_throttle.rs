@@ -0,0 +1,225 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::{DateTime, Duration, Utc};
+
+use super::client::ClientInfo;
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, FailedLoginKey, FailedLogins},
+};
+
+/// Failed logins that don't slow down the next attempt on an account, enough for a few typos.
+pub const ACCOUNT_FREE_ATTEMPTS: u32 = 3;
+
+/// Failed logins after which an account is locked for `LOGIN_LOCKOUT_SECONDS`.
+pub const ACCOUNT_LOCKOUT_THRESHOLD: u32 = 10;
+
+/// Many users can share an address behind NAT, so an address gets more room than an account.
+pub const IP_FREE_ATTEMPTS: u32 = 20;
+pub const IP_LOCKOUT_THRESHOLD: u32 = 100;
+
+pub const LOGIN_LOCKOUT_SECONDS: i64 = 15 * 60;
+
+/// Failed logins are forgotten once this passes without another one.
+pub const FAILED_LOGIN_TTL_SECONDS: i64 = 60 * 60;
+
+/// The keys a login for `email` from `client` is counted against.
+pub fn failed_login_keys(email: &Email, client: &ClientInfo) -> Vec<FailedLoginKey> {
+    let mut keys = vec![FailedLoginKey::Account(email.clone())];
+    if let Some(ip) = &client.ip {
+        keys.push(FailedLoginKey::Ip(ip.clone()));
+    }
+    keys
+}
+
+/// How long after its last failure a key has to wait. Nothing for the first few failures, then
+/// doubling from one second, and the whole lockout once the key's threshold is reached.
+pub fn backoff(key: &FailedLoginKey, failures: u32) -> Duration {
+    let (free_attempts, threshold) = match key {
+        FailedLoginKey::Account(_) => (ACCOUNT_FREE_ATTEMPTS, ACCOUNT_LOCKOUT_THRESHOLD),
+        FailedLoginKey::Ip(_) => (IP_FREE_ATTEMPTS, IP_LOCKOUT_THRESHOLD),
+    };
+
+    let seconds = if failures >= threshold {
+        LOGIN_LOCKOUT_SECONDS
+    } else if failures < free_attempts {
+        0
+    } else {
+        2_i64
+            .saturating_pow(failures - free_attempts)
+            .min(LOGIN_LOCKOUT_SECONDS)
+    };
+    Duration::seconds(seconds)
+}
+
+/// Whole seconds until `key` may try again, if it has to wait at all.
+pub fn retry_after(
+    key: &FailedLoginKey,
+    failures: &FailedLogins,
+    now: DateTime<Utc>,
+) -> Option<u64> {
+    let last_failure_at = failures.last_failure_at?;
+    let wait = last_failure_at + backoff(key, failures.count) - now;
+    // rounded up, so a client that waits exactly as long as told isn't turned away again
+    let seconds = (wait.num_milliseconds() + 999) / 1000;
+    u64::try_from(seconds).ok().filter(|seconds| *seconds > 0)
+}
+
+/// Counts the attempt as failed against each of its keys before the password is checked, and
+/// turns it away while any of them is still waiting out a backoff. Parallel guesses are each
+/// counted before any is let through, so they can't slip past the backoff or the lockout together.
+/// Returns the records as they were, for `clear_failed_logins` or `refund_login_attempt`.
+#[tracing::instrument(name = "Reserve login attempt", skip_all)]
+pub async fn reserve_login_attempt(
+    keys: &[FailedLoginKey],
+    state: &AppState,
+) -> Result<Vec<FailedLogins>, AuthAPIError> {
+    let mut store = state.failed_login_store.write().await;
+    let now = Utc::now();
+
+    let mut previous = Vec::with_capacity(keys.len());
+    let mut longest_wait = None;
+    for key in keys {
+        let failures = store
+            .reserve_attempt(key)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        longest_wait = longest_wait.max(retry_after(key, &failures, now));
+        previous.push(failures);
+    }
+
+    let Some(seconds) = longest_wait else {
+        return Ok(previous);
+    };
+
+    // an attempt that is turned away doesn't count, or waiting as long as told wouldn't be enough
+    for (key, failures) in keys.iter().zip(&previous) {
+        store
+            .refund_attempt(key, failures)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+    Err(AuthAPIError::TooManyAttempts(seconds))
+}
+
+/// The attempt was counted when it was reserved, so a wrong password only has to be reported once
+/// it locks the account.
+pub fn report_failed_login(keys: &[FailedLoginKey], previous: &[FailedLogins]) {
+    for (key, failures) in keys.iter().zip(previous) {
+        if let FailedLoginKey::Account(_) = key {
+            if failures.count + 1 == ACCOUNT_LOCKOUT_THRESHOLD {
+                tracing::warn!("account locked after {} failed logins", failures.count + 1);
+            }
+        }
+    }
+}
+
+/// Takes back an attempt that couldn't be checked, e.g. because a store failed.
+#[tracing::instrument(name = "Refund login attempt", skip_all)]
+pub async fn refund_login_attempt(
+    keys: &[FailedLoginKey],
+    previous: &[FailedLogins],
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let mut store = state.failed_login_store.write().await;
+    for (key, failures) in keys.iter().zip(previous) {
+        store
+            .refund_attempt(key, failures)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+    Ok(())
+}
+
+/// Forgets the account's failures after a correct password. The address only gets this attempt
+/// back, so logging into an account of one's own doesn't reset the guesses against others.
+#[tracing::instrument(name = "Clear failed logins", skip_all)]
+pub async fn clear_failed_logins(
+    keys: &[FailedLoginKey],
+    previous: &[FailedLogins],
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let mut store = state.failed_login_store.write().await;
+    for (key, failures) in keys.iter().zip(previous) {
+        let result = match key {
+            FailedLoginKey::Account(_) => store.clear_failures(key).await,
+            FailedLoginKey::Ip(_) => store.refund_attempt(key, failures).await,
+        };
+        result.map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn account() -> FailedLoginKey {
+        FailedLoginKey::Account(
+            Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap(),
+        )
+    }
+
+    #[test]
+    fn backoff_starts_after_free_attempts_and_doubles() {
+        let key = account();
+        assert_eq!(backoff(&key, ACCOUNT_FREE_ATTEMPTS - 1), Duration::zero());
+        assert_eq!(backoff(&key, ACCOUNT_FREE_ATTEMPTS), Duration::seconds(1));
+        assert_eq!(
+            backoff(&key, ACCOUNT_FREE_ATTEMPTS + 1),
+            Duration::seconds(2)
+        );
+        assert_eq!(
+            backoff(&key, ACCOUNT_FREE_ATTEMPTS + 3),
+            Duration::seconds(8)
+        );
+    }
+
+    #[test]
+    fn lockout_applies_at_threshold() {
+        assert_eq!(
+            backoff(&account(), ACCOUNT_LOCKOUT_THRESHOLD),
+            Duration::seconds(LOGIN_LOCKOUT_SECONDS)
+        );
+
+        // an address is only slowed down well past the account threshold
+        let ip = FailedLoginKey::Ip("203.0.113.7".to_owned());
+        assert_eq!(backoff(&ip, ACCOUNT_LOCKOUT_THRESHOLD), Duration::zero());
+        assert_eq!(backoff(&ip, IP_FREE_ATTEMPTS), Duration::seconds(1));
+        // the doubling never waits longer than the lockout
+        assert_eq!(
+            backoff(&ip, IP_LOCKOUT_THRESHOLD - 1),
+            Duration::seconds(LOGIN_LOCKOUT_SECONDS)
+        );
+    }
+
+    #[test]
+    fn retry_after_counts_from_last_failure() {
+        let key = account();
+        let now = Utc::now();
+        let failures = FailedLogins {
+            count: ACCOUNT_FREE_ATTEMPTS + 2,
+            last_failure_at: Some(now - Duration::seconds(1)),
+        };
+        assert_eq!(retry_after(&key, &failures, now), Some(3));
+        assert_eq!(
+            retry_after(&key, &failures, now + Duration::seconds(3)),
+            None
+        );
+        assert_eq!(retry_after(&key, &FailedLogins::default(), now), None);
+    }
+}
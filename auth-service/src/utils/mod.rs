@@ -15,5 +15,15 @@
 */
 
 pub mod auth;
+pub mod authorization;
+pub mod client;
 pub mod constants;
+pub mod email_verification;
+pub mod login_throttle;
+pub mod password_reset;
+pub mod rate_limit;
+pub mod scope;
+pub mod signing;
+pub mod totp;
 pub mod tracing;
+pub mod webauthn;
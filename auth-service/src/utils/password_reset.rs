@@ -0,0 +1,170 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Context, Result};
+use jsonwebtoken::{Algorithm, Validation};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    auth::TokenValidationError,
+    constants::{JWT_ISSUER, JWT_KEYRING, JWT_LEEWAY_SECONDS, PASSWORD_RESET_URL},
+};
+use crate::{app_state::BannedTokenStoreType, domain::UserId};
+
+// Used reset tokens are banned for TOKEN_TTL_SECONDS, which has to outlast this plus the leeway.
+pub const PASSWORD_RESET_TOKEN_TTL_SECONDS: i64 = 600;
+
+/// Reset tokens are signed with the same keys as auth tokens, the audience keeps the two apart.
+const PASSWORD_RESET_AUDIENCE: &str = "password-reset";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub jti: String,
+}
+
+#[tracing::instrument(name = "Generate password reset token", skip_all)]
+pub fn generate_password_reset_token(user_id: &UserId) -> Result<Secret<String>> {
+    let now = Utc::now().timestamp();
+    let iat: usize = now.try_into().wrap_err(format!(
+        "failed to cast iat time to usize. iat time: {}",
+        now
+    ))?;
+    let exp: usize = (now + PASSWORD_RESET_TOKEN_TTL_SECONDS)
+        .try_into()
+        .wrap_err("failed to cast exp time to usize")?;
+
+    let claims = PasswordResetClaims {
+        sub: user_id.to_string(),
+        iss: JWT_ISSUER.to_owned(),
+        aud: PASSWORD_RESET_AUDIENCE.to_owned(),
+        exp,
+        iat,
+        nbf: iat,
+        jti: uuid::Uuid::new_v4().to_string(),
+    };
+
+    JWT_KEYRING.encode(&claims)
+}
+
+/// The link mailed to the user, `PASSWORD_RESET_URL` with the token as the `token` parameter.
+pub fn password_reset_link(token: &Secret<String>) -> Result<String> {
+    let mut url = Url::parse(&PASSWORD_RESET_URL).wrap_err("invalid password reset URL")?;
+    url.query_pairs_mut()
+        .append_pair("token", token.expose_secret());
+    Ok(url.to_string())
+}
+
+/// Checks the signature and expiry of a reset token, and that it hasn't been used yet.
+#[tracing::instrument(name = "Validate password reset token", skip_all)]
+pub async fn validate_password_reset_token(
+    token: &Secret<String>,
+    banned_token_store: BannedTokenStoreType,
+) -> Result<PasswordResetClaims, TokenValidationError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[PASSWORD_RESET_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_nbf = true;
+    validation.leeway = *JWT_LEEWAY_SECONDS;
+
+    let claims = JWT_KEYRING
+        .decode::<PasswordResetClaims>(token, &validation)
+        .map_err(TokenValidationError::InvalidToken)?;
+
+    let is_used = banned_token_store
+        .read()
+        .await
+        .contains_token(&claims.jti)
+        .await
+        .map_err(|e| TokenValidationError::UnexpectedError(e.into()))?;
+    if is_used {
+        return Err(TokenValidationError::InvalidToken(eyre!(
+            "password reset token was already used"
+        )));
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::{
+        domain::{Scopes, SessionId},
+        services::data_stores::HashsetBannedTokenStore,
+        utils::auth::{generate_auth_cookie, TOKEN_TTL_SECONDS},
+    };
+
+    fn banned_token_store() -> BannedTokenStoreType {
+        Arc::new(RwLock::new(HashsetBannedTokenStore::default()))
+    }
+
+    #[test]
+    fn used_tokens_stay_banned_until_they_expire() {
+        assert!(
+            PASSWORD_RESET_TOKEN_TTL_SECONDS + (*JWT_LEEWAY_SECONDS as i64) < TOKEN_TTL_SECONDS
+        );
+    }
+
+    #[tokio::test]
+    async fn accept_generated_token_until_used() {
+        let user_id = UserId::default();
+        let token = generate_password_reset_token(&user_id).unwrap();
+        let store = banned_token_store();
+
+        let claims = validate_password_reset_token(&token, store.clone())
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, user_id.to_string());
+
+        store.write().await.add_token(claims.jti).await.unwrap();
+        let result = validate_password_reset_token(&token, store).await;
+        assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn reject_auth_tokens() {
+        let cookie = generate_auth_cookie(
+            &UserId::default(),
+            &SessionId::default(),
+            &Scopes::default(),
+        )
+        .unwrap();
+        let token = Secret::new(cookie.value().to_owned());
+
+        let result = validate_password_reset_token(&token, banned_token_store()).await;
+        assert!(matches!(result, Err(TokenValidationError::InvalidToken(_))));
+    }
+
+    #[test]
+    fn link_carries_token() {
+        let token = Secret::new("header.payload.signature".to_owned());
+        let link = password_reset_link(&token).unwrap();
+        assert!(link.starts_with(PASSWORD_RESET_URL.as_str()));
+        assert!(link.ends_with("?token=header.payload.signature"));
+    }
+}
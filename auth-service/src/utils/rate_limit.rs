@@ -0,0 +1,218 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+use super::{
+    client::ClientInfo,
+    constants::{
+        DEFAULT_LOGIN_RATE_LIMIT, DEFAULT_PASSWORD_RESET_RATE_LIMIT, DEFAULT_SIGNUP_RATE_LIMIT,
+        DEFAULT_VERIFY_2FA_RATE_LIMIT, DEFAULT_VERIFY_TOKEN_RATE_LIMIT,
+    },
+};
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, RateLimit, RateLimitStoreError},
+};
+
+/// The bodies of rate limited routes are small JSON objects, anything bigger is turned away
+/// rather than buffered.
+const MAX_RATE_LIMITED_BODY_BYTES: usize = 64 * 1024;
+
+/// Limits for the routes worth guessing at or flooding. Each one applies to the client's address
+/// and, for routes that take one, to the submitted email separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    pub signup: RateLimit,
+    pub login: RateLimit,
+    pub verify_2fa: RateLimit,
+    pub verify_token: RateLimit,
+    pub password_reset: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            signup: DEFAULT_SIGNUP_RATE_LIMIT,
+            login: DEFAULT_LOGIN_RATE_LIMIT,
+            verify_2fa: DEFAULT_VERIFY_2FA_RATE_LIMIT,
+            verify_token: DEFAULT_VERIFY_TOKEN_RATE_LIMIT,
+            password_reset: DEFAULT_PASSWORD_RESET_RATE_LIMIT,
+        }
+    }
+}
+
+impl RateLimits {
+    /// The limit for a path, and the name its buckets are kept under.
+    pub fn for_path(&self, path: &str) -> Option<(&'static str, RateLimit)> {
+        match path {
+            "/signup" => Some(("signup", self.signup)),
+            "/login" => Some(("login", self.login)),
+            // the other ways in take credentials like a login does
+            "/account/restore" => Some(("restore_account", self.login)),
+            "/change-password" => Some(("change_password", self.login)),
+            "/webauthn/login/start" => Some(("webauthn_login_start", self.login)),
+            "/webauthn/login/finish" => Some(("webauthn_login_finish", self.login)),
+            "/oauth/token" => Some(("oauth_token", self.login)),
+            "/verify-2fa" => Some(("verify_2fa", self.verify_2fa)),
+            "/verify-token" => Some(("verify_token", self.verify_token)),
+            // resource servers check tokens here as often as on /verify-token
+            "/oauth/introspect" => Some(("oauth_introspect", self.verify_token)),
+            "/password-reset/request" => Some(("password_reset_request", self.password_reset)),
+            "/password-reset/confirm" => Some(("password_reset_confirm", self.password_reset)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SubmittedEmail {
+    email: Option<String>,
+}
+
+/// Turns requests to rate limited routes away with 429 once any of their buckets is empty.
+/// Other routes pass straight through.
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthAPIError> {
+    let Some((route, limit)) = state.rate_limits.for_path(request.uri().path()) else {
+        return Ok(next.run(request).await);
+    };
+
+    // the email is read from the body, which then has to be put back for the handler
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_RATE_LIMITED_BODY_BYTES)
+        .await
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let keys = rate_limit_keys(route, &client, submitted_email(&body));
+    check_rate_limits(&keys, &limit, &state).await?;
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// The buckets a request to `route` is counted against.
+fn rate_limit_keys(route: &str, client: &ClientInfo, email: Option<Email>) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(ip) = &client.ip {
+        keys.push(format!("{}:ip:{}", route, ip));
+    }
+    if let Some(email) = email {
+        // addresses differing only in case reach the same inbox
+        let email = email.as_ref().expose_secret().to_lowercase();
+        keys.push(format!("{}:email:{}", route, email));
+    }
+    keys
+}
+
+#[tracing::instrument(name = "Check rate limits", skip_all)]
+async fn check_rate_limits(
+    keys: &[String],
+    limit: &RateLimit,
+    state: &AppState,
+) -> Result<(), AuthAPIError> {
+    let mut store = state.rate_limit_store.write().await;
+
+    // every bucket is charged, so a client can't save its address's tokens behind an empty
+    // email bucket
+    let mut longest_wait = None;
+    for key in keys {
+        match store.take_token(key, limit).await {
+            Ok(()) => {}
+            Err(RateLimitStoreError::LimitExceeded(seconds)) => {
+                longest_wait = longest_wait.max(Some(seconds));
+            }
+            Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+        }
+    }
+
+    match longest_wait {
+        Some(seconds) => Err(AuthAPIError::TooManyRequests(seconds)),
+        None => Ok(()),
+    }
+}
+
+/// The email of a JSON body, if it has a valid one. Malformed bodies are left for the handler
+/// to reject.
+fn submitted_email(body: &[u8]) -> Option<Email> {
+    let email = serde_json::from_slice::<SubmittedEmail>(body).ok()?.email?;
+    Email::parse(Secret::new(email)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_rate_limited_paths() {
+        let limits = RateLimits::default();
+        assert_eq!(limits.for_path("/login"), Some(("login", limits.login)));
+        assert_eq!(
+            limits.for_path("/verify-token"),
+            Some(("verify_token", limits.verify_token))
+        );
+        assert_eq!(
+            limits.for_path("/account/restore"),
+            Some(("restore_account", limits.login))
+        );
+        assert_eq!(
+            limits.for_path("/change-password"),
+            Some(("change_password", limits.login))
+        );
+        assert_eq!(
+            limits.for_path("/oauth/introspect"),
+            Some(("oauth_introspect", limits.verify_token))
+        );
+        assert_eq!(
+            limits.for_path("/password-reset/confirm"),
+            Some(("password_reset_confirm", limits.password_reset))
+        );
+        assert_eq!(limits.for_path("/logout"), None);
+    }
+
+    #[test]
+    fn key_by_address_and_email() {
+        let client = ClientInfo {
+            ip: Some("203.0.113.7".to_owned()),
+            user_agent: None,
+        };
+        let email = submitted_email(br#"{"email": "Ibrahim@Umbrella.corp", "password": "x"}"#);
+        assert_eq!(
+            rate_limit_keys("login", &client, email),
+            vec![
+                "login:ip:203.0.113.7".to_owned(),
+                "login:email:ibrahim@umbrella.corp".to_owned()
+            ]
+        );
+
+        assert_eq!(submitted_email(br#"{"token": "abc"}"#), None);
+        assert_eq!(submitted_email(br#"{"email": 42}"#), None);
+        assert_eq!(submitted_email(b"not json"), None);
+        assert_eq!(
+            rate_limit_keys("signup", &ClientInfo::default(), None),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use std::marker::PhantomData;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Scopes, MANAGE_ROLES_SCOPE},
+};
+
+use super::{
+    auth::{validate_token, Claims, TokenValidationError},
+    constants::JWT_COOKIE_NAME,
+};
+
+/// A scope that routes can require, named by a type so it shows in the handler's signature.
+pub trait RequiredScope {
+    const SCOPE: &'static str;
+}
+
+/// Scope of the admin API, which grants and revokes roles and permissions.
+pub struct ManageRoles;
+
+impl RequiredScope for ManageRoles {
+    const SCOPE: &'static str = MANAGE_ROLES_SCOPE;
+}
+
+/// Extractor that only lets a request through when its token carries the scope `S` names. The
+/// token is taken from a Bearer authorization header, as services and OAuth clients send it, or
+/// else from the auth cookie of a logged in user.
+pub struct RequireScope<S>(pub Claims, PhantomData<S>);
+
+#[async_trait]
+impl<S> FromRequestParts<AppState> for RequireScope<S>
+where
+    S: RequiredScope,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers)
+            .or_else(|| {
+                CookieJar::from_headers(&parts.headers)
+                    .get(JWT_COOKIE_NAME)
+                    .map(|cookie| Secret::new(cookie.value().to_owned()))
+            })
+            .ok_or(AuthAPIError::MissingToken)?;
+
+        let claims = match validate_token(
+            &token,
+            state.banned_token_store.clone(),
+            state.session_store.clone(),
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err(TokenValidationError::InvalidToken(_)) => return Err(AuthAPIError::InvalidToken),
+            Err(TokenValidationError::UnexpectedError(e)) => {
+                return Err(AuthAPIError::UnexpectedError(e))
+            }
+        };
+
+        let scopes = Scopes::parse(claims.scope.as_deref().unwrap_or_default())
+            .map_err(|_| AuthAPIError::InvalidToken)?;
+        if !scopes.contains(S::SCOPE) {
+            return Err(AuthAPIError::InsufficientScope);
+        }
+
+        Ok(Self(claims, PhantomData))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Secret::new(token.trim().to_owned()))
+}
@@ -0,0 +1,571 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Context, Result};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use secrecy::{ExposeSecret, Secret};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fs, path::PathBuf, str::FromStr};
+
+/// A key used to sign and verify JWTs, identified by the `kid` header of the tokens it signs.
+/// HMAC keys are shared secrets and are never published, while RSA and Ed25519 keys expose their
+/// public half as a JWK for downstream verification.
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    pub fn from_secret(kid: String, algorithm: Algorithm, secret: &Secret<String>) -> Result<Self> {
+        if !matches!(
+            algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(eyre!("{:?} cannot be used with a shared secret", algorithm));
+        }
+
+        let secret = secret.expose_secret().as_bytes();
+        Ok(Self {
+            kid,
+            algorithm,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        })
+    }
+
+    pub fn from_pem(kid: String, algorithm: Algorithm, pem: &[u8]) -> Result<Self> {
+        let parsed = pem::parse(pem).wrap_err("failed to parse private key PEM")?;
+
+        let (encoding_key, parameters) = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                let key_pair = match parsed.tag() {
+                    "PRIVATE KEY" => RsaKeyPair::from_pkcs8(parsed.contents()),
+                    "RSA PRIVATE KEY" => RsaKeyPair::from_der(parsed.contents()),
+                    tag => return Err(eyre!("unexpected PEM block for an RSA key: {}", tag)),
+                }
+                .map_err(|e| eyre!("invalid RSA private key: {}", e))?;
+
+                let components = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+                let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(components.n),
+                    e: URL_SAFE_NO_PAD.encode(components.e),
+                });
+
+                (
+                    EncodingKey::from_rsa_pem(pem).wrap_err("invalid RSA private key")?,
+                    parameters,
+                )
+            }
+            Algorithm::EdDSA => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(parsed.contents())
+                    .map_err(|e| eyre!("invalid Ed25519 private key: {}", e))?;
+
+                let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+                });
+
+                (
+                    EncodingKey::from_ed_pem(pem).wrap_err("invalid Ed25519 private key")?,
+                    parameters,
+                )
+            }
+            _ => {
+                return Err(eyre!(
+                    "{:?} is not supported for private key signing",
+                    algorithm
+                ))
+            }
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(
+                    KeyAlgorithm::from_str(&format!("{:?}", algorithm))
+                        .wrap_err("failed to map signing algorithm to a JWK algorithm")?,
+                ),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: parameters,
+        };
+
+        // Verify with the published key rather than the private one, so a mistake in the JWK
+        // surfaces here instead of in every downstream service.
+        let decoding_key = DecodingKey::from_jwk(&jwk).wrap_err("failed to build decoding key")?;
+
+        Ok(Self {
+            kid,
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk: Some(jwk),
+        })
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Whether tokens signed with this key can be checked by others, with the key published in the
+    /// JWKS. Tokens signed with a shared secret can only be checked by this service.
+    pub fn has_public_key(&self) -> bool {
+        self.jwk.is_some()
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<Secret<String>> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
+
+        encode(&header, claims, &self.encoding_key)
+            .map(Secret::new)
+            .wrap_err("failed to create token")
+    }
+
+    /// Checks the signature and claims of `token`. Only this key's algorithm is accepted,
+    /// whatever `validation` says.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &Secret<String>,
+        validation: &Validation,
+    ) -> Result<T, jsonwebtoken::errors::Error> {
+        let mut validation = validation.clone();
+        validation.algorithms = vec![self.algorithm];
+
+        decode::<T>(token.expose_secret(), &self.decoding_key, &validation).map(|data| data.claims)
+    }
+}
+
+struct RetiredKey {
+    key: SigningKey,
+    accepted_until: i64,
+}
+
+/// Every key a token may have been signed with. New tokens are always signed with the active key,
+/// and a retired key keeps verifying tokens for `grace_period` seconds after it was retired, which
+/// is long enough for everything it signed to expire.
+pub struct Keyring {
+    active: SigningKey,
+    retired: Vec<RetiredKey>,
+    grace_period: i64,
+}
+
+impl Keyring {
+    pub fn new(active: SigningKey, grace_period: i64) -> Self {
+        Self {
+            active,
+            retired: vec![],
+            grace_period,
+        }
+    }
+
+    pub fn with_retired_key(mut self, key: SigningKey, retired_at: i64) -> Result<Self> {
+        let kid = key.kid();
+        if self.active.kid() == kid || self.retired.iter().any(|retired| retired.key.kid() == kid) {
+            return Err(eyre!("duplicate signing key id: {}", key.kid()));
+        }
+
+        self.retired.push(RetiredKey {
+            key,
+            accepted_until: retired_at + self.grace_period,
+        });
+        Ok(self)
+    }
+
+    /// Builds a keyring from a JSON document listing every key. Exactly one key must be active,
+    /// the rest carry the unix time they were retired at.
+    pub fn from_json(json: &str, grace_period: i64) -> Result<Self> {
+        let config: KeyringConfig =
+            serde_json::from_str(json).wrap_err("failed to parse keyring")?;
+
+        let mut active = None;
+        let mut retired = vec![];
+
+        for key in config.keys {
+            let retired_at = key.retired_at;
+            let signing_key = key.into_signing_key()?;
+            match retired_at {
+                Some(retired_at) => retired.push((signing_key, retired_at)),
+                None if active.is_none() => active = Some(signing_key),
+                None => return Err(eyre!("keyring has more than one active key")),
+            }
+        }
+
+        let active = active.ok_or(eyre!("keyring has no active key"))?;
+        retired.into_iter().try_fold(
+            Self::new(active, grace_period),
+            |keyring, (key, retired_at)| keyring.with_retired_key(key, retired_at),
+        )
+    }
+
+    pub fn active(&self) -> &SigningKey {
+        &self.active
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<Secret<String>> {
+        self.active.encode(claims)
+    }
+
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &Secret<String>,
+        validation: &Validation,
+    ) -> Result<T> {
+        let header = decode_header(token.expose_secret()).wrap_err("malformed token header")?;
+
+        // Tokens issued before key ids were introduced can only have come from the active key.
+        let key = match header.kid {
+            Some(kid) => self
+                .get(&kid)
+                .ok_or(eyre!("token signed with unknown key: {}", kid))?,
+            None => &self.active,
+        };
+
+        Ok(key.decode(token, validation)?)
+    }
+
+    /// The public keys downstream services should trust. HMAC keys are never included, so this is
+    /// empty when signing with a shared secret.
+    pub fn jwks(&self) -> JwkSet {
+        let retired = self.accepted_retired_keys().map(|retired| &retired.key);
+        JwkSet {
+            keys: std::iter::once(&self.active)
+                .chain(retired)
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+
+    fn get(&self, kid: &str) -> Option<&SigningKey> {
+        if self.active.kid() == kid {
+            return Some(&self.active);
+        }
+
+        self.accepted_retired_keys()
+            .find(|retired| retired.key.kid() == kid)
+            .map(|retired| &retired.key)
+    }
+
+    fn accepted_retired_keys(&self) -> impl Iterator<Item = &RetiredKey> {
+        let now = Utc::now().timestamp();
+        self.retired
+            .iter()
+            .filter(move |retired| retired.accepted_until > now)
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyringConfig {
+    keys: Vec<KeyConfig>,
+}
+
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String,
+    algorithm: Algorithm,
+    secret: Option<Secret<String>>,
+    private_key_path: Option<PathBuf>,
+    retired_at: Option<i64>,
+}
+
+impl KeyConfig {
+    fn into_signing_key(self) -> Result<SigningKey> {
+        match (self.secret, self.private_key_path) {
+            (Some(secret), None) => SigningKey::from_secret(self.kid, self.algorithm, &secret),
+            (None, Some(path)) => {
+                let pem = fs::read(&path)
+                    .wrap_err(format!("failed to read private key {}", path.display()))?;
+                SigningKey::from_pem(self.kid, self.algorithm, &pem)
+            }
+            _ => Err(eyre!(
+                "signing key {} needs exactly one of secret or private_key_path",
+                self.kid
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../../tests/fixtures/jwt_rsa_private_key.pem");
+    const ED25519_PRIVATE_KEY: &[u8] =
+        include_bytes!("../../tests/fixtures/jwt_ed25519_private_key.pem");
+    const GRACE_PERIOD: i64 = 900;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> TestClaims {
+        TestClaims {
+            sub: "ap@0xfrait.com".to_owned(),
+            exp: (Utc::now().timestamp() + 60) as usize,
+        }
+    }
+
+    fn hmac_key(kid: &str, secret: &str) -> SigningKey {
+        SigningKey::from_secret(
+            kid.to_owned(),
+            Algorithm::HS256,
+            &Secret::new(secret.to_owned()),
+        )
+        .unwrap()
+    }
+
+    fn assert_verifiable_with_published_key(key: SigningKey) {
+        let keyring = Keyring::new(key, GRACE_PERIOD);
+        let token = keyring.encode(&claims()).unwrap();
+
+        let jwks = keyring.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        let jwk = jwks.find(keyring.active().kid()).unwrap();
+        let public_key = DecodingKey::from_jwk(jwk).unwrap();
+
+        let decoded = decode::<TestClaims>(
+            token.expose_secret(),
+            &public_key,
+            &Validation::new(keyring.active().algorithm()),
+        )
+        .unwrap();
+        assert_eq!(decoded.claims, claims());
+        assert_eq!(decoded.header.alg, keyring.active().algorithm());
+    }
+
+    #[test]
+    fn hmac_key_round_trips_and_publishes_nothing() {
+        let keyring = Keyring::new(hmac_key("primary", "secret"), GRACE_PERIOD);
+        let token = keyring.encode(&claims()).unwrap();
+        assert_eq!(
+            keyring
+                .decode::<TestClaims>(&token, &Validation::default())
+                .unwrap(),
+            claims()
+        );
+        assert!(keyring.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn tokens_carry_the_active_kid() {
+        let keyring = Keyring::new(hmac_key("2024-11", "secret"), GRACE_PERIOD);
+        let token = keyring.encode(&claims()).unwrap();
+        let header = decode_header(token.expose_secret()).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("2024-11"));
+    }
+
+    #[test]
+    fn rsa_tokens_verify_with_published_jwk() {
+        let key = SigningKey::from_pem("rsa".to_owned(), Algorithm::RS256, RSA_PRIVATE_KEY);
+        assert_verifiable_with_published_key(key.unwrap());
+
+        let key = SigningKey::from_pem("rsa".to_owned(), Algorithm::PS512, RSA_PRIVATE_KEY);
+        assert_verifiable_with_published_key(key.unwrap());
+    }
+
+    #[test]
+    fn ed25519_tokens_verify_with_published_jwk() {
+        let key = SigningKey::from_pem("ed".to_owned(), Algorithm::EdDSA, ED25519_PRIVATE_KEY);
+        assert_verifiable_with_published_key(key.unwrap());
+    }
+
+    #[test]
+    fn only_private_keys_have_a_public_key() {
+        let key = SigningKey::from_pem("ed".to_owned(), Algorithm::EdDSA, ED25519_PRIVATE_KEY);
+        assert!(key.unwrap().has_public_key());
+        assert!(!hmac_key("hmac", "secret").has_public_key());
+    }
+
+    #[test]
+    fn reject_tokens_signed_by_another_key() {
+        let rsa = SigningKey::from_pem("primary".to_owned(), Algorithm::RS256, RSA_PRIVATE_KEY);
+        let rsa = Keyring::new(rsa.unwrap(), GRACE_PERIOD);
+        let hmac = Keyring::new(hmac_key("primary", "secret"), GRACE_PERIOD);
+
+        let token = hmac.encode(&claims()).unwrap();
+        assert!(rsa
+            .decode::<TestClaims>(&token, &Validation::default())
+            .is_err());
+
+        let token = rsa.encode(&claims()).unwrap();
+        assert!(hmac
+            .decode::<TestClaims>(&token, &Validation::default())
+            .is_err());
+    }
+
+    #[test]
+    fn reject_mismatched_key_material() {
+        let kid = || "primary".to_owned();
+        assert!(SigningKey::from_pem(kid(), Algorithm::EdDSA, RSA_PRIVATE_KEY).is_err());
+        assert!(SigningKey::from_pem(kid(), Algorithm::RS256, ED25519_PRIVATE_KEY).is_err());
+        assert!(SigningKey::from_pem(kid(), Algorithm::HS256, RSA_PRIVATE_KEY).is_err());
+        assert!(SigningKey::from_pem(kid(), Algorithm::ES256, RSA_PRIVATE_KEY).is_err());
+        assert!(SigningKey::from_secret(
+            kid(),
+            Algorithm::RS256,
+            &Secret::new("secret".to_owned())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn retired_key_is_accepted_during_grace_period() {
+        let old = Keyring::new(hmac_key("old", "old-secret"), GRACE_PERIOD);
+        let token = old.encode(&claims()).unwrap();
+
+        let rotated = Keyring::new(hmac_key("new", "new-secret"), GRACE_PERIOD)
+            .with_retired_key(hmac_key("old", "old-secret"), Utc::now().timestamp())
+            .unwrap();
+        assert_eq!(
+            rotated
+                .decode::<TestClaims>(&token, &Validation::default())
+                .unwrap(),
+            claims()
+        );
+
+        let token = rotated.encode(&claims()).unwrap();
+        let header = decode_header(token.expose_secret()).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn retired_key_is_rejected_after_grace_period() {
+        let old = Keyring::new(hmac_key("old", "old-secret"), GRACE_PERIOD);
+        let token = old.encode(&claims()).unwrap();
+
+        let retired_at = Utc::now().timestamp() - GRACE_PERIOD - 1;
+        let rotated = Keyring::new(hmac_key("new", "new-secret"), GRACE_PERIOD)
+            .with_retired_key(hmac_key("old", "old-secret"), retired_at)
+            .unwrap();
+        assert!(rotated
+            .decode::<TestClaims>(&token, &Validation::default())
+            .is_err());
+    }
+
+    #[test]
+    fn reject_unknown_kid() {
+        let other = Keyring::new(hmac_key("other", "secret"), GRACE_PERIOD);
+        let token = other.encode(&claims()).unwrap();
+
+        let keyring = Keyring::new(hmac_key("primary", "secret"), GRACE_PERIOD);
+        assert!(keyring
+            .decode::<TestClaims>(&token, &Validation::default())
+            .is_err());
+    }
+
+    #[test]
+    fn reject_duplicate_kids() {
+        let keyring = Keyring::new(hmac_key("primary", "secret"), GRACE_PERIOD)
+            .with_retired_key(hmac_key("primary", "other"), Utc::now().timestamp());
+        assert!(keyring.is_err());
+    }
+
+    #[test]
+    fn jwks_lists_retired_keys_until_grace_period_ends() {
+        let now = Utc::now().timestamp();
+        let rsa = SigningKey::from_pem("rsa".to_owned(), Algorithm::RS256, RSA_PRIVATE_KEY);
+        let ed = SigningKey::from_pem("ed".to_owned(), Algorithm::EdDSA, ED25519_PRIVATE_KEY);
+        let expired = SigningKey::from_pem("old".to_owned(), Algorithm::PS256, RSA_PRIVATE_KEY);
+
+        let keyring = Keyring::new(ed.unwrap(), GRACE_PERIOD)
+            .with_retired_key(rsa.unwrap(), now)
+            .unwrap()
+            .with_retired_key(expired.unwrap(), now - GRACE_PERIOD - 1)
+            .unwrap();
+
+        let jwks = keyring.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        assert!(jwks.find("ed").is_some());
+        assert!(jwks.find("rsa").is_some());
+        assert!(jwks.find("old").is_none());
+    }
+
+    #[test]
+    fn load_keyring_from_json() {
+        let json = serde_json::json!({
+            "keys": [
+                {
+                    "kid": "2024-11",
+                    "algorithm": "RS256",
+                    "private_key_path": concat!(
+                        env!("CARGO_MANIFEST_DIR"),
+                        "/tests/fixtures/jwt_rsa_private_key.pem"
+                    ),
+                },
+                {
+                    "kid": "2024-10",
+                    "algorithm": "HS256",
+                    "secret": "old-secret",
+                    "retired_at": Utc::now().timestamp(),
+                },
+            ]
+        });
+
+        let keyring = Keyring::from_json(&json.to_string(), GRACE_PERIOD).unwrap();
+        assert_eq!(keyring.active().kid(), "2024-11");
+        assert_eq!(keyring.active().algorithm(), Algorithm::RS256);
+
+        let old = Keyring::new(hmac_key("2024-10", "old-secret"), GRACE_PERIOD);
+        let token = old.encode(&claims()).unwrap();
+        assert!(keyring
+            .decode::<TestClaims>(&token, &Validation::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn reject_keyring_without_exactly_one_active_key() {
+        let no_active = serde_json::json!({
+            "keys": [{ "kid": "a", "algorithm": "HS256", "secret": "s", "retired_at": 0 }]
+        });
+        assert!(Keyring::from_json(&no_active.to_string(), GRACE_PERIOD).is_err());
+
+        let two_active = serde_json::json!({
+            "keys": [
+                { "kid": "a", "algorithm": "HS256", "secret": "s" },
+                { "kid": "b", "algorithm": "HS256", "secret": "s" },
+            ]
+        });
+        assert!(Keyring::from_json(&two_active.to_string(), GRACE_PERIOD).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use color_eyre::eyre::{eyre, Result};
+use secrecy::{ExposeSecret, Secret};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, TOTP};
+
+use super::constants::TOTP_ISSUER;
+use crate::domain::{Email, TotpSecret};
+
+// The parameters every mainstream authenticator app assumes for otpauth URIs.
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// Codes one step either side of now are accepted to absorb clock drift on the user's device.
+const TOTP_SKEW_STEPS: u8 = 1;
+
+/// Builds the `otpauth://` URI an authenticator app scans to enroll the secret.
+pub fn provisioning_uri(secret: &TotpSecret, email: &Email) -> Result<String> {
+    Ok(totp(secret, email.as_ref().expose_secret().to_owned())?.get_url())
+}
+
+/// Returns the time step the code belongs to, which the caller has to record through
+/// `UserStore::use_totp_step` so that the code can't be accepted a second time.
+#[tracing::instrument(name = "Verify TOTP code", skip_all)]
+pub fn verify_totp_code(secret: &TotpSecret, code: &Secret<String>) -> Result<Option<u64>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    verify_totp_code_at(secret, code, now)
+}
+
+fn verify_totp_code_at(
+    secret: &TotpSecret,
+    code: &Secret<String>,
+    time: u64,
+) -> Result<Option<u64>> {
+    let code = code.expose_secret();
+    if code.len() != TOTP_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let totp = totp(secret, String::new())?;
+    let current_step = time / TOTP_STEP_SECONDS;
+    let skew = u64::from(TOTP_SKEW_STEPS);
+    // latest step first, so that a code is never taken for an earlier step it also happens to match
+    Ok((current_step.saturating_sub(skew)..=current_step + skew)
+        .rev()
+        .find(|step| {
+            totp.generate(step * TOTP_STEP_SECONDS)
+                .as_bytes()
+                .ct_eq(code.as_bytes())
+                .into()
+        }))
+}
+
+fn totp(secret: &TotpSecret, account_name: String) -> Result<TOTP> {
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW_STEPS,
+        TOTP_STEP_SECONDS,
+        secret.to_bytes()?,
+        Some(TOTP_ISSUER.to_owned()),
+        account_name,
+    )
+    .map_err(|e| eyre!("failed to build TOTP: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_at(secret: &TotpSecret, time: u64) -> Secret<String> {
+        Secret::new(totp(secret, String::new()).unwrap().generate(time))
+    }
+
+    #[test]
+    fn accept_code_for_current_step() {
+        let secret = TotpSecret::default();
+        let now = 1_700_000_000;
+        assert_eq!(
+            verify_totp_code_at(&secret, &code_at(&secret, now), now).unwrap(),
+            Some(now / TOTP_STEP_SECONDS)
+        );
+    }
+
+    #[test]
+    fn accept_codes_within_skew_window() {
+        let secret = TotpSecret::default();
+        let now = 1_700_000_000;
+        let previous = code_at(&secret, now - TOTP_STEP_SECONDS);
+        let next = code_at(&secret, now + TOTP_STEP_SECONDS);
+        let step = now / TOTP_STEP_SECONDS;
+        assert_eq!(
+            verify_totp_code_at(&secret, &previous, now).unwrap(),
+            Some(step - 1)
+        );
+        assert_eq!(
+            verify_totp_code_at(&secret, &next, now).unwrap(),
+            Some(step + 1)
+        );
+    }
+
+    #[test]
+    fn reject_codes_outside_skew_window() {
+        let secret = TotpSecret::default();
+        let now = 1_700_000_000;
+        let stale = code_at(&secret, now - 3 * TOTP_STEP_SECONDS);
+        assert_eq!(verify_totp_code_at(&secret, &stale, now).unwrap(), None);
+    }
+
+    #[test]
+    fn reject_malformed_codes() {
+        let secret = TotpSecret::default();
+        for code in ["", "12345", "1234567", "12a456"] {
+            let code = Secret::new(code.to_owned());
+            assert_eq!(
+                verify_totp_code_at(&secret, &code, 1_700_000_000).unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn provisioning_uri_carries_secret_issuer_and_account() {
+        let secret = TotpSecret::default();
+        let email = Email::parse(Secret::new("ibrahim@umbrella.corp".to_owned())).unwrap();
+        let uri = provisioning_uri(&secret, &email).unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(&format!("secret={}", secret.as_ref().expose_secret())));
+        assert!(uri.contains(&format!("issuer={}", *TOTP_ISSUER)));
+        assert!(uri.contains("ibrahim%40umbrella.corp"));
+    }
+}
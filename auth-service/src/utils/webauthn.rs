@@ -0,0 +1,59 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+//! The relying party for passkeys, built on webauthn-rs. Passkeys are registered without
+//! attestation because we only care that the same authenticator comes back, not which vendor
+//! made it.
+
+use color_eyre::eyre::{eyre, Result};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::time::Duration;
+use webauthn_rs::prelude::{Url, Webauthn, WebauthnBuilder};
+
+use super::constants::{WEBAUTHN_ORIGIN, WEBAUTHN_RP_ID, WEBAUTHN_RP_NAME};
+use crate::domain::WebauthnChallenge;
+
+/// How long a registration or login ceremony may take before its challenge expires.
+pub const WEBAUTHN_CHALLENGE_TTL_SECONDS: i64 = 300;
+
+lazy_static! {
+    pub static ref WEBAUTHN: Webauthn = build_webauthn();
+}
+
+fn build_webauthn() -> Webauthn {
+    let origin = Url::parse(&WEBAUTHN_ORIGIN).expect("WEBAUTHN_ORIGIN should be an absolute URL.");
+    WebauthnBuilder::new(&WEBAUTHN_RP_ID, &origin)
+        .and_then(|builder| {
+            builder
+                .rp_name(&WEBAUTHN_RP_NAME)
+                .timeout(Duration::from_secs(WEBAUTHN_CHALLENGE_TTL_SECONDS as u64))
+                .build()
+        })
+        .expect("WEBAUTHN_ORIGIN should be served from WEBAUTHN_RP_ID.")
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    challenge: String,
+}
+
+/// Returns the challenge a ceremony answers, to look up the state it was started with.
+/// webauthn-rs checks everything else in the client data once it has that state.
+pub fn answered_challenge(client_data_json: &[u8]) -> Result<WebauthnChallenge> {
+    let client_data: CollectedClientData = serde_json::from_slice(client_data_json)
+        .map_err(|e| eyre!("Invalid client data: {}", e))?;
+    WebauthnChallenge::parse(client_data.challenge)
+}
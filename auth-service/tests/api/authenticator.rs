@@ -0,0 +1,171 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::utils::constants::{WEBAUTHN_ORIGIN, WEBAUTHN_RP_ID};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use rand::RngCore;
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// A passkey authenticator with a P-256 key that answers WebAuthn ceremonies the way a browser
+/// would hand them to the server.
+pub struct SoftwareAuthenticator {
+    key_pair: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    user_handle: Option<Vec<u8>>,
+    pub sign_count: u32,
+    pub origin: String,
+}
+
+impl SoftwareAuthenticator {
+    pub fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+            .expect("failed to generate key");
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .expect("failed to load key");
+
+        let mut credential_id = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut credential_id);
+
+        Self {
+            key_pair,
+            credential_id,
+            user_handle: None,
+            sign_count: 0,
+            origin: WEBAUTHN_ORIGIN.to_owned(),
+        }
+    }
+
+    pub fn credential_id(&self) -> &[u8] {
+        &self.credential_id
+    }
+
+    /// Creates the credential, like `navigator.credentials.create()`.
+    pub fn register(&mut self, options: &CreationChallengeResponse) -> serde_json::Value {
+        let options = &options.public_key;
+        self.user_handle = Some(options.user.id.to_vec());
+
+        let public_key = self.key_pair.public_key().as_ref();
+        let cose_key = Value::Map(vec![
+            (Value::Integer(1.into()), Value::Integer(2.into())),
+            (Value::Integer(3.into()), Value::Integer((-7).into())),
+            (Value::Integer((-1).into()), Value::Integer(1.into())),
+            (
+                Value::Integer((-2).into()),
+                Value::Bytes(public_key[1..33].to_vec()),
+            ),
+            (
+                Value::Integer((-3).into()),
+                Value::Bytes(public_key[33..].to_vec()),
+            ),
+        ]);
+
+        let mut auth_data = self.authenticator_data(
+            &options.rp.id,
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA,
+        );
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::into_writer(&cose_key, &mut auth_data).expect("failed to encode COSE key");
+
+        let attestation = Value::Map(vec![
+            (
+                Value::Text("fmt".to_owned()),
+                Value::Text("none".to_owned()),
+            ),
+            (Value::Text("attStmt".to_owned()), Value::Map(vec![])),
+            (Value::Text("authData".to_owned()), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = vec![];
+        ciborium::into_writer(&attestation, &mut attestation_object)
+            .expect("failed to encode attestation object");
+
+        let client_data = self.client_data("webauthn.create", &options.challenge);
+        serde_json::json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "rawId": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+            },
+        })
+    }
+
+    /// Signs an assertion with the next counter value, like `navigator.credentials.get()`.
+    pub fn login(&mut self, options: &RequestChallengeResponse) -> serde_json::Value {
+        let options = &options.public_key;
+        self.sign_count += 1;
+
+        let auth_data =
+            self.authenticator_data(&options.rp_id, FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+        let client_data = self.client_data("webauthn.get", &options.challenge);
+        let mut message = auth_data.clone();
+        message.extend_from_slice(digest(&SHA256, &client_data).as_ref());
+        let signature = self
+            .key_pair
+            .sign(&SystemRandom::new(), &message)
+            .expect("failed to sign assertion");
+
+        serde_json::json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "rawId": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                "userHandle": self.user_handle.as_ref().map(|handle| URL_SAFE_NO_PAD.encode(handle)),
+            },
+        })
+    }
+
+    fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+        assert_eq!(rp_id, WEBAUTHN_RP_ID.as_str());
+        let mut data = digest(&SHA256, rp_id.as_bytes()).as_ref().to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    fn client_data(&self, ceremony_type: &str, challenge: &[u8]) -> Vec<u8> {
+        serde_json::json!({
+            "type": ceremony_type,
+            "challenge": URL_SAFE_NO_PAD.encode(challenge),
+            "origin": self.origin,
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
+    }
+}
+
+impl Default for SoftwareAuthenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
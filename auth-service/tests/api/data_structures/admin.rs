@@ -0,0 +1,300 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use auth_service::{
+    domain::{Email, UserId},
+    routes::{DeleteAccountResponse, GrantsResponse, RegisterClientResponse, TokenResponse},
+    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    ErrorResponse,
+};
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde_json::json;
+
+use super::helpers::{
+    get_random_email, TestApp, EDITOR_PERMISSION, EDITOR_ROLE, REGISTRATION_TOKEN,
+};
+
+/// Gets a token with the scope to manage roles from a client registered for it, the way the
+/// first admin is appointed.
+async fn admin_token(app: &TestApp) -> String {
+    let response = app
+        .register_client(
+            &json!({
+                "client_name": "Admin console",
+                "grant_types": ["client_credentials"],
+                "scope": "roles:manage",
+            }),
+            REGISTRATION_TOKEN,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let client = response
+        .json::<RegisterClientResponse>()
+        .await
+        .expect("could not deserialize response body to RegisterClientResponse");
+
+    let response = app
+        .token(
+            &[("grant_type", "client_credentials")],
+            Some((&client.client_id, client.client_secret.as_deref().unwrap())),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<TokenResponse>()
+        .await
+        .expect("could not deserialize response body to TokenResponse")
+        .access_token
+}
+
+/// Signs a user up and returns their id and email.
+async fn signup(app: &TestApp) -> (String, String) {
+    let email = get_random_email();
+    let response = app
+        .signup(&json!({
+            "email": email,
+            "password": "notSoSecure",
+            "require2FA": false,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let user = app
+        .user_store
+        .read()
+        .await
+        .get_user(&Email::parse(Secret::new(email.clone())).unwrap())
+        .await
+        .unwrap();
+    (user.id.to_string(), email)
+}
+
+/// Logs the user in and returns the scope their new token carries.
+async fn login(app: &TestApp, email: &str) -> Option<String> {
+    let response = app
+        .login(&json!({ "email": email, "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    token_scope(app, &response).await
+}
+
+async fn token_scope(app: &TestApp, response: &reqwest::Response) -> Option<String> {
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    validate_token(
+        &Secret::new(auth_cookie.value().to_owned()),
+        app.banned_token_store.clone(),
+        app.session_store.clone(),
+    )
+    .await
+    .expect("auth cookie should hold a valid token")
+    .scope
+}
+
+async fn grants(app: &TestApp, user_id: &str, token: &str) -> GrantsResponse {
+    let response = app.get_grants(user_id, Some(token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<GrantsResponse>()
+        .await
+        .expect("could not deserialize response body to GrantsResponse")
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_require_token_with_manage_roles_scope() {
+    let app = TestApp::new().await;
+    let (user_id, email) = signup(&app).await;
+
+    let response = app.get_grants(&user_id, None).await;
+    assert_error(response, 400, "Missing auth token").await;
+
+    let response = app.get_grants(&user_id, Some("invalid")).await;
+    assert_error(response, 401, "Invalid auth token").await;
+
+    // a logged in user without the scope is sent away, with the cookie as with a Bearer token
+    assert_eq!(login(&app, &email).await, None);
+    let response = app.get_grants(&user_id, None).await;
+    assert_error(response, 403, "Insufficient scope").await;
+    let response = app
+        .grant(&user_id, &format!("roles/{}", EDITOR_ROLE), None)
+        .await;
+    assert_error(response, 403, "Insufficient scope").await;
+}
+
+#[tokio::test]
+async fn should_grant_roles_and_permissions_carried_in_tokens() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (user_id, email) = signup(&app).await;
+
+    let role = format!("roles/{}", EDITOR_ROLE);
+    for grant in [role.as_str(), "permissions/reports:read", role.as_str()] {
+        let response = app.grant(&user_id, grant, Some(&token)).await;
+        assert_eq!(response.status().as_u16(), 204);
+    }
+
+    let grants = grants(&app, &user_id, &token).await;
+    assert_eq!(grants.roles, vec![EDITOR_ROLE]);
+    assert_eq!(grants.permissions, vec!["reports:read"]);
+    let scope = format!("{} reports:read", EDITOR_PERMISSION);
+    assert_eq!(grants.scope, scope);
+
+    assert_eq!(login(&app, &email).await, Some(scope));
+}
+
+#[tokio::test]
+async fn should_carry_changed_grants_in_refreshed_tokens() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (user_id, email) = signup(&app).await;
+    assert_eq!(login(&app, &email).await, None);
+
+    let response = app
+        .grant(&user_id, "permissions/reports:read", Some(&token))
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        token_scope(&app, &response).await.as_deref(),
+        Some("reports:read")
+    );
+}
+
+#[tokio::test]
+async fn should_revoke_roles_and_permissions() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (user_id, email) = signup(&app).await;
+
+    let role = format!("roles/{}", EDITOR_ROLE);
+    for grant in [role.as_str(), "permissions/reports:read"] {
+        let response = app.grant(&user_id, grant, Some(&token)).await;
+        assert_eq!(response.status().as_u16(), 204);
+        let response = app.revoke(&user_id, grant, Some(&token)).await;
+        assert_eq!(response.status().as_u16(), 204);
+
+        let response = app.revoke(&user_id, grant, Some(&token)).await;
+        assert_error(response, 404, "Grant not found").await;
+    }
+
+    let grants = grants(&app, &user_id, &token).await;
+    assert!(grants.roles.is_empty());
+    assert!(grants.permissions.is_empty());
+    assert_eq!(login(&app, &email).await, None);
+}
+
+#[tokio::test]
+async fn should_reject_unknown_roles_invalid_permissions_and_users() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (user_id, _) = signup(&app).await;
+
+    let response = app.grant(&user_id, "roles/viewer", Some(&token)).await;
+    assert_error(response, 400, "Unknown role").await;
+
+    let response = app
+        .grant(&user_id, "permissions/reports%22read", Some(&token))
+        .await;
+    assert_error(response, 400, "Invalid permission").await;
+
+    let unknown_user = UserId::default().to_string();
+    let response = app
+        .grant(&unknown_user, "permissions/reports:read", Some(&token))
+        .await;
+    assert_error(response, 404, "User not found").await;
+
+    let response = app.get_grants("not-a-user-id", Some(&token)).await;
+    assert_error(response, 404, "User not found").await;
+}
+
+#[tokio::test]
+async fn should_let_admins_manage_roles_when_logged_in() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (admin_id, admin_email) = signup(&app).await;
+    let (user_id, _) = signup(&app).await;
+
+    let response = app.grant(&admin_id, "roles/admin", Some(&token)).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(
+        login(&app, &admin_email).await.as_deref(),
+        Some("roles:manage")
+    );
+
+    // the admin's auth cookie is enough from here on
+    let response = app
+        .grant(&user_id, &format!("roles/{}", EDITOR_ROLE), None)
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+    let response = app.get_grants(&user_id, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_remove_grants_of_purged_accounts() {
+    let app = TestApp::new().await;
+    let token = admin_token(&app).await;
+    let (user_id, email) = signup(&app).await;
+
+    let response = app
+        .grant(&user_id, "permissions/reports:read", Some(&token))
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    assert_eq!(login(&app, &email).await.as_deref(), Some("reports:read"));
+    let response = app
+        .delete_account(&json!({ "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let purge_at = response
+        .json::<DeleteAccountResponse>()
+        .await
+        .expect("could not deserialize response body to DeleteAccountResponse")
+        .purge_at;
+    let purge_at = DateTime::parse_from_rfc3339(&purge_at).expect("purgeAt is not RFC 3339");
+    assert_eq!(
+        app.purge_deleted_accounts(purge_at.with_timezone(&Utc))
+            .await,
+        1
+    );
+
+    let grants = app
+        .app_state
+        .permission_store
+        .read()
+        .await
+        .get_grants(&UserId::parse(&user_id).unwrap())
+        .await
+        .unwrap();
+    assert!(grants.roles.is_empty() && grants.permissions.is_empty());
+}
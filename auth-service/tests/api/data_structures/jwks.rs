@@ -0,0 +1,32 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use super::helpers::TestApp;
+use jsonwebtoken::jwk::JwkSet;
+
+#[tokio::test]
+async fn ds_jwks_is_empty_for_shared_secret_signing() {
+    let app = TestApp::new().await;
+
+    let response = app.get_jwks().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let jwks = response
+        .json::<JwkSet>()
+        .await
+        .expect("Could not deserialize response body to JwkSet");
+    assert!(jwks.keys.is_empty());
+}
@@ -15,7 +15,10 @@
 */
 
 use auth_service::{
-    domain::Email, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME, ErrorResponse,
+    domain::{LoginAttemptId, UserId},
+    routes::TwoFactorAuthResponse,
+    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    ErrorResponse,
 };
 use secrecy::{ExposeSecret, Secret};
 use wiremock::{
@@ -83,14 +86,11 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
     let two_fa_code_store = app.two_fa_code_store.read().await;
 
     let code_tuple = two_fa_code_store
-        .get_code(&Email::parse(Secret::new(random_email)).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(json_body.login_attempt_id)).unwrap())
         .await
         .expect("Failed to get 2FA code");
 
-    assert_eq!(
-        code_tuple.0.as_ref().expose_secret(),
-        &json_body.login_attempt_id
-    );
+    assert_eq!(code_tuple.0.as_ref().expose_secret(), &random_email);
 }
 
 #[tokio::test]
@@ -227,3 +227,179 @@ async fn should_return_422_if_malformed_credentials() {
         );
     }
 }
+
+#[tokio::test]
+async fn should_return_429_with_retry_after_once_failures_pile_up() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecret",
+        "require2FA": false
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "inCoRrecT",
+    });
+    for _ in 0..3 {
+        let response = app.login(&wrong_login).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // even the right password is turned away until the backoff is over
+    let response = app
+        .login(&serde_json::json!({
+            "email": random_email,
+            "password": "notSoSecret",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response
+        .headers()
+        .get("retry-after")
+        .expect("No Retry-After header found")
+        .to_str()
+        .unwrap()
+        .parse()
+        .expect("Retry-After is not a number of seconds");
+    assert!(retry_after >= 1);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("failed to deserialize response into error")
+            .error,
+        "Too many attempts".to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_throttle_parallel_failures() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecret",
+        "require2FA": false
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // sent together, the guesses can't all get past the check before any of them has failed
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "inCoRrecT",
+    });
+    let responses = tokio::join!(
+        app.login(&wrong_login),
+        app.login(&wrong_login),
+        app.login(&wrong_login),
+        app.login(&wrong_login),
+        app.login(&wrong_login),
+        app.login(&wrong_login),
+    );
+    let statuses = [
+        responses.0.status().as_u16(),
+        responses.1.status().as_u16(),
+        responses.2.status().as_u16(),
+        responses.3.status().as_u16(),
+        responses.4.status().as_u16(),
+        responses.5.status().as_u16(),
+    ];
+    assert_eq!(statuses.iter().filter(|status| **status == 401).count(), 3);
+    assert_eq!(statuses.iter().filter(|status| **status == 429).count(), 3);
+}
+
+#[tokio::test]
+async fn should_forget_failures_after_successful_login() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecret",
+        "require2FA": false
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "inCoRrecT",
+    });
+    let right_login = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecret",
+    });
+    for _ in 0..2 {
+        for _ in 0..2 {
+            let response = app.login(&wrong_login).await;
+            assert_eq!(response.status().as_u16(), 401);
+        }
+        let response = app.login(&right_login).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn ds_should_issue_jwt_with_user_id_as_subject() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure1",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure1",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    let token = Secret::new(auth_cookie.value().to_owned());
+    let claims = validate_token(
+        &token,
+        app.banned_token_store.clone(),
+        app.session_store.clone(),
+    )
+    .await
+    .expect("failed to validate token");
+
+    assert!(UserId::parse(&claims.sub).is_ok());
+    assert!(!claims.sub.contains(&random_email));
+}
+
+#[tokio::test]
+async fn should_accept_password_set_before_normalization() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    // NFKC turns the ligatures into "fi" and "fl", but the hash was made from them as typed
+    app.add_verified_user(&random_email, "ﬁreﬂy$3curedZ").await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "ﬁreﬂy$3curedZ",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // the hash is replaced with one of the normalized password, which both forms match
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "firefly$3curedZ",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
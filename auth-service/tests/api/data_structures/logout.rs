@@ -14,7 +14,10 @@
    limitations under the License.
 */
 
-use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use auth_service::{
+    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    ErrorResponse,
+};
 use reqwest::Url;
 use secrecy::Secret;
 
@@ -49,6 +52,13 @@ async fn should_return_200_if_valid_jwt_cookie() {
     assert!(!auth_cookie.value().is_empty());
 
     let token = Secret::new(auth_cookie.value().to_owned());
+    let claims = validate_token(
+        &token,
+        app.banned_token_store.clone(),
+        app.session_store.clone(),
+    )
+    .await
+    .expect("failed to validate token");
     let response = app.logout().await;
     assert_eq!(response.status().as_u16(), 200);
 
@@ -60,7 +70,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let banned_token_store = app.banned_token_store.read().await;
     let contains_token = banned_token_store
-        .contains_token(&token)
+        .contains_token(&claims.jti)
         .await
         .expect("failed to check if token is banned");
     assert!(contains_token);
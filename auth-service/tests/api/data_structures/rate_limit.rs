@@ -0,0 +1,125 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{domain::RateLimit, utils::rate_limit::RateLimits, ErrorResponse};
+
+use super::helpers::{get_random_email, TestApp};
+
+const LIMIT: RateLimit = RateLimit {
+    requests: 2,
+    per_seconds: 60,
+};
+
+#[tokio::test]
+async fn should_return_429_once_route_limit_is_used_up() {
+    let app = TestApp::with_rate_limits(RateLimits {
+        login: LIMIT,
+        ..RateLimits::default()
+    })
+    .await;
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "notSoSecure",
+    });
+
+    for _ in 0..LIMIT.requests {
+        let response = app.login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response
+        .headers()
+        .get("retry-after")
+        .expect("No Retry-After header found")
+        .to_str()
+        .unwrap()
+        .parse()
+        .expect("Retry-After is not a number of seconds");
+    // a token comes back every 30 seconds
+    assert!(retry_after > 0 && retry_after <= 30);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        "Too many requests".to_owned()
+    );
+
+    // the address is limited on /login, whichever account it tries
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn should_limit_routes_separately() {
+    let app = TestApp::with_rate_limits(RateLimits {
+        verify_token: LIMIT,
+        ..RateLimits::default()
+    })
+    .await;
+    let verify_token_body = serde_json::json!({ "token": "invalid_token" });
+
+    for _ in 0..LIMIT.requests {
+        let response = app.verify_token(&verify_token_body).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = app.verify_token(&verify_token_body).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn should_limit_password_reset_requests() {
+    let app = TestApp::with_rate_limits(RateLimits {
+        password_reset: LIMIT,
+        ..RateLimits::default()
+    })
+    .await;
+
+    // nobody has these addresses, so no email goes out, but each request still counts
+    for _ in 0..LIMIT.requests {
+        let response = app
+            .request_password_reset(&serde_json::json!({ "email": get_random_email() }))
+            .await;
+        assert_eq!(response.status().as_u16(), 202);
+    }
+    let response = app
+        .request_password_reset(&serde_json::json!({ "email": get_random_email() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    // confirming a reset has buckets of its own
+    let response = app
+        .confirm_password_reset(&serde_json::json!({
+            "token": "invalid_token",
+            "password": "newPassword",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,267 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use auth_service::{
+    utils::constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    ErrorResponse,
+};
+use reqwest::Url;
+
+use super::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_return_200_and_rotate_refresh_token() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let refresh_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found");
+    assert!(!refresh_cookie.value().is_empty());
+    assert!(refresh_cookie.http_only());
+    let old_refresh_token = refresh_cookie.value().to_owned();
+
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+
+    let refresh_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found");
+    assert_ne!(refresh_cookie.value(), old_refresh_token);
+
+    let verify_token_body = serde_json::json!({
+        "token": auth_cookie.value(),
+    });
+    let response = app.verify_token(&verify_token_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_refresh_cookie_missing() {
+    let app = TestApp::new().await;
+
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        "Missing auth token".to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_return_401_if_invalid_refresh_token() {
+    let app = TestApp::new().await;
+    let test_cases = ["invalid", &"a".repeat(64)];
+
+    for test_case in test_cases {
+        app.cookie_jar.add_cookie_str(
+            &format!(
+                "{}={}; HttpOnly; SameSite=Strict; Path=/",
+                REFRESH_TOKEN_COOKIE_NAME, test_case
+            ),
+            &Url::parse("http://127.0.0.1").expect("failed to parse url"),
+        );
+
+        let response = app.refresh().await;
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "Failed for input: {:?}",
+            test_case
+        );
+        assert_eq!(
+            response
+                .json::<ErrorResponse>()
+                .await
+                .expect("could not deserialize response body to ErrorResponse")
+                .error,
+            "Invalid auth token".to_owned()
+        );
+    }
+}
+
+#[tokio::test]
+async fn should_revoke_token_family_if_refresh_token_reused() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let stolen_refresh_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found")
+        .value()
+        .to_owned();
+
+    // the legitimate client rotates its token
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let current_refresh_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found")
+        .value()
+        .to_owned();
+
+    // the old token is replayed
+    let url = Url::parse("http://127.0.0.1").expect("failed to parse url");
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Strict; Path=/",
+            REFRESH_TOKEN_COOKIE_NAME, stolen_refresh_token
+        ),
+        &url,
+    );
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // the whole family is revoked, including the latest token
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Strict; Path=/",
+            REFRESH_TOKEN_COOKIE_NAME, current_refresh_token
+        ),
+        &url,
+    );
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_rotate_refresh_token_once_if_used_concurrently() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let refresh_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found")
+        .value()
+        .to_owned();
+
+    // both requests carry the same token, so only one of them may rotate it
+    let client = reqwest::Client::new();
+    let refresh = || {
+        client
+            .post(format!("{}/refresh", &app.address))
+            .header(
+                reqwest::header::COOKIE,
+                format!("{}={}", REFRESH_TOKEN_COOKIE_NAME, refresh_token),
+            )
+            .send()
+    };
+    let (first, second) = tokio::join!(refresh(), refresh());
+    let mut statuses = [
+        first.expect("refresh failed").status().as_u16(),
+        second.expect("refresh failed").status().as_u16(),
+    ];
+    statuses.sort();
+    assert_eq!(statuses, [200, 401]);
+}
+
+#[tokio::test]
+async fn should_revoke_refresh_token_on_logout() {
+    let app = TestApp::new().await;
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let refresh_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found")
+        .value()
+        .to_owned();
+
+    let response = app.logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let refresh_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found");
+    assert!(refresh_cookie.value().is_empty());
+
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Strict; Path=/",
+            REFRESH_TOKEN_COOKIE_NAME, refresh_token
+        ),
+        &Url::parse("http://127.0.0.1").expect("failed to parse url"),
+    );
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,186 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    routes::ListSessionsResponse,
+    utils::constants::{JWT_COOKIE_NAME, REFRESH_TOKEN_COOKIE_NAME},
+    ErrorResponse,
+};
+use reqwest::Url;
+
+use super::helpers::{get_random_email, TestApp, USER_AGENT};
+
+async fn signup_and_login(app: &TestApp) -> (String, reqwest::Response) {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login(app, &random_email).await;
+    (random_email, response)
+}
+
+async fn login(app: &TestApp, email: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+}
+
+fn auth_token(response: &reqwest::Response) -> String {
+    response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned()
+}
+
+async fn list_sessions(app: &TestApp) -> ListSessionsResponse {
+    let response = app.list_sessions().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<ListSessionsResponse>()
+        .await
+        .expect("Could not deserialize response body to ListSessionsResponse")
+}
+
+#[tokio::test]
+async fn should_list_current_session() {
+    let app = TestApp::new().await;
+    signup_and_login(&app).await;
+
+    let sessions = list_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 1);
+    let session = &sessions[0];
+    assert!(session.current);
+    assert_eq!(session.user_agent.as_deref(), Some(USER_AGENT));
+    assert_eq!(session.ip.as_deref(), Some("127.0.0.1"));
+    assert!(!session.created_at.is_empty());
+    assert!(!session.last_seen_at.is_empty());
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let app = TestApp::new().await;
+    let response = app.list_sessions().await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Missing auth token".to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_reject_tokens_of_revoked_session() {
+    let app = TestApp::new().await;
+    let (email, response) = signup_and_login(&app).await;
+    let first_token = auth_token(&response);
+    let response = login(&app, &email).await;
+    let second_token = auth_token(&response);
+
+    let sessions = list_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 2);
+    let other_session = sessions
+        .iter()
+        .find(|session| !session.current)
+        .expect("No other session found");
+
+    let response = app.revoke_session(&other_session.id).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app
+        .verify_token(&serde_json::json!({ "token": first_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .verify_token(&serde_json::json!({ "token": second_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let sessions = list_sessions(&app).await.sessions;
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+}
+
+#[tokio::test]
+async fn should_return_404_if_session_not_found() {
+    let app = TestApp::new().await;
+    signup_and_login(&app).await;
+
+    let response = app.revoke_session(&uuid::Uuid::new_v4().to_string()).await;
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Session not found".to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_revoke_all_sessions() {
+    let app = TestApp::new().await;
+    let (email, response) = signup_and_login(&app).await;
+    let first_token = auth_token(&response);
+    let response = login(&app, &email).await;
+    let second_token = auth_token(&response);
+    let refresh_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == REFRESH_TOKEN_COOKIE_NAME)
+        .expect("No refresh cookie found")
+        .value()
+        .to_owned();
+
+    let response = app.revoke_all_sessions().await;
+    assert_eq!(response.status().as_u16(), 204);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(auth_cookie.value().is_empty());
+
+    for token in [first_token, second_token] {
+        let response = app
+            .verify_token(&serde_json::json!({ "token": token }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // the revoked session can no longer be refreshed either
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Strict; Path=/",
+            REFRESH_TOKEN_COOKIE_NAME, refresh_token
+        ),
+        &Url::parse("http://127.0.0.1").expect("failed to parse url"),
+    );
+    let response = app.refresh().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -14,8 +14,12 @@
    limitations under the License.
 */
 
-use super::helpers::{get_random_email, TestApp};
-use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use super::helpers::{get_random_email, TestApp, REGISTRATION_TOKEN};
+use auth_service::{
+    routes::{IntrospectionResponse, RegisterClientResponse},
+    utils::constants::JWT_COOKIE_NAME,
+    ErrorResponse,
+};
 
 #[tokio::test]
 async fn should_return_200_for_valid_token() {
@@ -130,3 +134,147 @@ async fn should_return_422_if_malformed_input() {
         assert_eq!(response.status().as_u16(), 422);
     }
 }
+
+/// Logs in a new user and returns the auth token.
+async fn login(app: &TestApp) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    auth_cookie.value().to_owned()
+}
+
+async fn introspection_response(response: reqwest::Response) -> IntrospectionResponse {
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<IntrospectionResponse>()
+        .await
+        .expect("could not deserialize response body to IntrospectionResponse")
+}
+
+async fn register_service(app: &TestApp) -> RegisterClientResponse {
+    let service_body = serde_json::json!({
+        "client_name": "App service",
+        "grant_types": ["client_credentials"],
+        "token_endpoint_auth_method": "client_secret_post",
+    });
+    let response = app.register_client(&service_body, REGISTRATION_TOKEN).await;
+    assert_eq!(response.status().as_u16(), 201);
+    response
+        .json::<RegisterClientResponse>()
+        .await
+        .expect("could not deserialize response body to RegisterClientResponse")
+}
+
+async fn assert_invalid_client(response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        "Invalid client credentials".to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_return_introspection_for_valid_token() {
+    let app = TestApp::new().await;
+    let token = login(&app).await;
+    let service = register_service(&app).await;
+
+    let verify_token_body = serde_json::json!({
+        "token": token,
+        "introspect": true,
+        "client_id": service.client_id,
+        "client_secret": service.client_secret,
+    });
+    let response = app.verify_token(&verify_token_body).await;
+    let introspection = introspection_response(response).await;
+    assert!(introspection.active);
+    assert!(introspection.sub.is_some());
+    assert!(introspection.sid.is_some());
+    assert!(introspection.exp.unwrap() > introspection.iat.unwrap());
+}
+
+#[tokio::test]
+async fn should_return_inactive_introspection_for_invalid_token() {
+    let app = TestApp::new().await;
+    let token = login(&app).await;
+    let service = register_service(&app).await;
+    let response = app.logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for token in [token.as_str(), "invalid_token"] {
+        let verify_token_body = serde_json::json!({
+            "token": token,
+            "introspect": true,
+            "client_id": service.client_id,
+            "client_secret": service.client_secret,
+        });
+        let response = app.verify_token(&verify_token_body).await;
+        let body = response.text().await.unwrap();
+        assert_eq!(body, r#"{"active":false}"#);
+    }
+}
+
+#[tokio::test]
+async fn should_require_client_authentication_to_introspect() {
+    let app = TestApp::new().await;
+    let token = login(&app).await;
+
+    // anyone holding a token could otherwise find out whose it is
+    let verify_token_body = serde_json::json!({
+        "token": token,
+        "introspect": true,
+    });
+    let response = app.verify_token(&verify_token_body).await;
+    assert_invalid_client(response).await;
+
+    // without introspection the bare check stays open
+    let verify_token_body = serde_json::json!({ "token": token });
+    let response = app.verify_token(&verify_token_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_authenticate_calling_service() {
+    let app = TestApp::new().await;
+    let token = login(&app).await;
+    let service = register_service(&app).await;
+
+    let verify_token_body = serde_json::json!({
+        "token": token,
+        "introspect": true,
+        "client_id": service.client_id,
+        "client_secret": service.client_secret,
+    });
+    let response = app.verify_token(&verify_token_body).await;
+    assert!(introspection_response(response).await.active);
+
+    let verify_token_body = serde_json::json!({
+        "token": token,
+        "introspect": true,
+        "client_id": service.client_id,
+        "client_secret": "wrong-secret",
+    });
+    let response = app.verify_token(&verify_token_body).await;
+    assert_invalid_client(response).await;
+}
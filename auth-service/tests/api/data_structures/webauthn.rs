@@ -0,0 +1,343 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::LoginAttemptId, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME,
+    ErrorResponse,
+};
+use secrecy::{ExposeSecret, Secret};
+use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use crate::authenticator::SoftwareAuthenticator;
+
+async fn signup_and_login(app: &TestApp) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    random_email
+}
+
+async fn registration_credential(
+    app: &TestApp,
+    authenticator: &mut SoftwareAuthenticator,
+) -> serde_json::Value {
+    let response = app.webauthn_register_start().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let options = response
+        .json::<CreationChallengeResponse>()
+        .await
+        .expect("could not deserialize response body to CreationChallengeResponse");
+
+    authenticator.register(&options)
+}
+
+async fn register(app: &TestApp, authenticator: &mut SoftwareAuthenticator) -> reqwest::Response {
+    let credential = registration_credential(app, authenticator).await;
+    app.webauthn_register_finish(&serde_json::json!({
+        "credential": credential,
+        "password": "notSoSecure",
+    }))
+    .await
+}
+
+async fn emailed_code(app: &TestApp, login_attempt_id: &str) -> String {
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&login_attempt_id)
+        .await
+        .unwrap();
+    code_tuple.1.as_ref().expose_secret().to_owned()
+}
+
+async fn login_options(app: &TestApp, email: Option<&str>) -> RequestChallengeResponse {
+    let body = match email {
+        Some(email) => serde_json::json!({ "email": email }),
+        None => serde_json::json!({}),
+    };
+    let response = app.webauthn_login_start(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<RequestChallengeResponse>()
+        .await
+        .expect("could not deserialize response body to RequestChallengeResponse")
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[tokio::test]
+async fn should_login_with_registered_passkey() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let options = login_options(&app, Some(&email)).await;
+    assert_eq!(options.public_key.allow_credentials.len(), 1);
+    assert_eq!(
+        options.public_key.allow_credentials[0].id.as_slice(),
+        authenticator.credential_id()
+    );
+
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    let response = app
+        .verify_token(&serde_json::json!({ "token": auth_cookie.value() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_login_with_discoverable_passkey_without_email() {
+    let app = TestApp::new().await;
+    signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let options = login_options(&app, None).await;
+    assert!(options.public_key.allow_credentials.is_empty());
+
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_not_reveal_unknown_email() {
+    let app = TestApp::new().await;
+    let options = login_options(&app, Some(&get_random_email())).await;
+    assert!(options.public_key.allow_credentials.is_empty());
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let app = TestApp::new().await;
+    let response = app.webauthn_register_start().await;
+    assert_error(response, 400, "Missing auth token").await;
+}
+
+#[tokio::test]
+async fn should_return_409_if_passkey_already_registered() {
+    let app = TestApp::new().await;
+    signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = register(&app, &mut authenticator).await;
+    assert_error(response, 409, "Passkey already registered").await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_challenge_is_replayed() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let options = login_options(&app, Some(&email)).await;
+    let assertion = authenticator.login(&options);
+    let response = app.webauthn_login_finish(&assertion).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.webauthn_login_finish(&assertion).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_sign_count_goes_backwards() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let options = login_options(&app, Some(&email)).await;
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // a cloned authenticator reuses a counter value the server has already seen
+    authenticator.sign_count -= 1;
+    let options = login_options(&app, Some(&email)).await;
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_origin_does_not_match() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    authenticator.origin = "https://evil.example".to_owned();
+    let options = login_options(&app, Some(&email)).await;
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_passkey_belongs_to_another_account() {
+    let app = TestApp::new().await;
+    signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let response = register(&app, &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let other_email = signup_and_login(&app).await;
+    let options = login_options(&app, Some(&other_email)).await;
+    let response = app
+        .webauthn_login_finish(&authenticator.login(&options))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_password_is_incorrect_at_registration() {
+    let app = TestApp::new().await;
+    let email = signup_and_login(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    let credential = registration_credential(&app, &mut authenticator).await;
+
+    // a stolen cookie alone can't add a passkey to the account
+    let response = app
+        .webauthn_register_finish(&serde_json::json!({
+            "credential": credential,
+            "password": "wrongPassword",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let options = login_options(&app, Some(&email)).await;
+    assert!(options.public_key.allow_credentials.is_empty());
+}
+
+#[tokio::test]
+async fn should_require_second_factor_to_register_passkey() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": true,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    // the login code, then the one that confirms the registration
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    let response = app
+        .verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": emailed_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut authenticator = SoftwareAuthenticator::new();
+    let credential = registration_credential(&app, &mut authenticator).await;
+    let response = app
+        .webauthn_register_finish(&serde_json::json!({
+            "credential": credential,
+            "password": "notSoSecure",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+
+    // the same credential is sent again along with the code
+    let response = app
+        .webauthn_register_finish(&serde_json::json!({
+            "credential": credential,
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": emailed_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let options = login_options(&app, Some(&email)).await;
+    assert_eq!(options.public_key.allow_credentials.len(), 1);
+}
@@ -14,5 +14,7 @@
    limitations under the License.
 */
 
+pub mod authenticator;
 pub mod data_structures;
+pub mod oidc_provider;
 pub mod postgres;
@@ -0,0 +1,174 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::OAuthProvider,
+    utils::signing::{Keyring, SigningKey},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::Algorithm;
+use reqwest::{header::LOCATION, Url};
+use ring::digest::{digest, SHA256};
+use secrecy::ExposeSecret;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Match, Mock, MockServer, Request, ResponseTemplate,
+};
+
+pub const MOCK_PROVIDER: &str = "mock";
+pub const MOCK_CLIENT_ID: &str = "auth-service";
+
+const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../fixtures/jwt_rsa_private_key.pem");
+
+/// An OpenID Connect provider that logs in whoever a test asks it to, with ID tokens signed by a
+/// key only the test knows.
+pub struct MockOidcProvider {
+    pub server: MockServer,
+    signing_key: SigningKey,
+}
+
+/// The parameters of the authorization request the app redirected the browser with.
+pub struct AuthorizationRequest {
+    pub client_id: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+}
+
+impl AuthorizationRequest {
+    pub fn from_redirect(response: &reqwest::Response) -> Self {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .expect("no Location header")
+            .to_str()
+            .expect("Location header is not a string");
+        let url = Url::parse(location).expect("Location header is not a URL");
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_else(|| panic!("authorization request has no {}", name))
+        };
+
+        Self {
+            client_id: param("client_id"),
+            state: param("state"),
+            nonce: param("nonce"),
+            code_challenge: param("code_challenge"),
+            code_challenge_method: param("code_challenge_method"),
+        }
+    }
+}
+
+impl MockOidcProvider {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let jwks = Keyring::new(signing_key(), 0).jwks();
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+            .mount(&server)
+            .await;
+
+        Self {
+            server,
+            signing_key: signing_key(),
+        }
+    }
+
+    pub fn config(&self) -> OAuthProvider {
+        let base_url = self.server.uri();
+        serde_json::from_value(json!({
+            "name": MOCK_PROVIDER,
+            "issuer": base_url,
+            "client_id": MOCK_CLIENT_ID,
+            "client_secret": "client-secret",
+            "authorization_endpoint": format!("{}/authorize", base_url),
+            "token_endpoint": format!("{}/token", base_url),
+            "jwks_uri": format!("{}/jwks", base_url),
+            "redirect_uri": "http://localhost/oauth/mock/callback",
+        }))
+        .expect("invalid provider config")
+    }
+
+    /// Logs the user in as `claims` and returns the code the provider redirects back with. The
+    /// token endpoint hands out the ID token once, and only with the verifier of the request's
+    /// PKCE challenge. `claims` can override the standard claims the token gets.
+    pub async fn authorize(&self, request: &AuthorizationRequest, claims: Value) -> String {
+        let now = Utc::now().timestamp();
+        let mut id_token_claims = json!({
+            "iss": self.server.uri(),
+            "aud": MOCK_CLIENT_ID,
+            "nonce": request.nonce,
+            "iat": now,
+            "exp": now + 300,
+        });
+        for (name, value) in claims.as_object().expect("claims should be an object") {
+            id_token_claims[name] = value.clone();
+        }
+        let id_token = self
+            .signing_key
+            .encode(&id_token_claims)
+            .expect("failed to sign ID token");
+
+        let code = Uuid::new_v4().to_string();
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains(format!("code={}", code)))
+            .and(CodeVerifierMatches(request.code_challenge.clone()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access-token",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "id_token": id_token.expose_secret(),
+            })))
+            .up_to_n_times(1)
+            .mount(&self.server)
+            .await;
+
+        code
+    }
+}
+
+fn signing_key() -> SigningKey {
+    SigningKey::from_pem("mock-key".to_owned(), Algorithm::RS256, RSA_PRIVATE_KEY)
+        .expect("failed to load signing key")
+}
+
+/// Matches token requests whose `code_verifier` hashes to the S256 challenge.
+struct CodeVerifierMatches(String);
+
+impl Match for CodeVerifierMatches {
+    fn matches(&self, request: &Request) -> bool {
+        let Ok(body) = std::str::from_utf8(&request.body) else {
+            return false;
+        };
+        let mut form = Url::parse("http://localhost/").unwrap();
+        form.set_query(Some(body));
+        let verifier = form
+            .query_pairs()
+            .find(|(name, _)| name == "code_verifier")
+            .map(|(_, value)| value.into_owned());
+
+        verifier.is_some_and(|verifier| {
+            URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes())) == self.0
+        })
+    }
+}
@@ -0,0 +1,358 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::{Email, LoginAttemptId, UserStoreError},
+    routes::{DeleteAccountResponse, TwoFactorAuthResponse},
+    utils::constants::{ACCOUNT_DELETION_GRACE_PERIOD_SECONDS, JWT_COOKIE_NAME},
+    ErrorResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup(app: &TestApp, require_2fa: bool) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": require_2fa,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    random_email
+}
+
+/// Logs in and returns the auth token, which also replaces the one in the cookie jar.
+async fn login(app: &TestApp, email: &str) -> String {
+    let response = login_response(app, email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+    token
+}
+
+async fn login_response(app: &TestApp, email: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    app.login(&login_body).await
+}
+
+async fn two_fa_code(app: &TestApp, login_attempt_id: &str) -> String {
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap())
+        .await
+        .unwrap();
+    code_tuple.1.as_ref().expose_secret().to_owned()
+}
+
+async fn mount_email_server(app: &TestApp, expected_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn token_is_valid(app: &TestApp, token: &str) -> bool {
+    let response = app
+        .verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    response.status().as_u16() == 200
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+/// Deletes the logged in user's account and returns when it will be purged.
+async fn delete_account(app: &TestApp) -> DateTime<Utc> {
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let response_body = response
+        .json::<DeleteAccountResponse>()
+        .await
+        .expect("could not deserialize response body to DeleteAccountResponse");
+    DateTime::parse_from_rfc3339(&response_body.purge_at)
+        .expect("purgeAt is not RFC 3339")
+        .with_timezone(&Utc)
+}
+
+#[api_test]
+async fn should_schedule_deletion_and_log_out_everywhere() {
+    let email = signup(&app, false).await;
+    let other_token = login(&app, &email).await;
+    let current_token = login(&app, &email).await;
+    mount_email_server(&app, 1).await;
+
+    let before = Utc::now();
+    let purge_at = delete_account(&app).await;
+    let grace_period = Duration::seconds(*ACCOUNT_DELETION_GRACE_PERIOD_SECONDS);
+    assert!(purge_at >= before + grace_period - Duration::seconds(1));
+    assert!(purge_at <= Utc::now() + grace_period);
+
+    assert!(!token_is_valid(&app, &current_token).await);
+    assert!(!token_is_valid(&app, &other_token).await);
+
+    let response = login_response(&app, &email).await;
+    assert_error(response, 403, "Account pending deletion").await;
+}
+
+#[api_test]
+async fn should_restore_account_during_grace_period() {
+    let email = signup(&app, false).await;
+    login(&app, &email).await;
+    mount_email_server(&app, 1).await;
+    let purge_at = delete_account(&app).await;
+
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    login(&app, &email).await;
+    assert_eq!(app.purge_deleted_accounts(purge_at).await, 0);
+}
+
+#[api_test]
+async fn should_purge_account_after_grace_period() {
+    let email = signup(&app, false).await;
+    login(&app, &email).await;
+    mount_email_server(&app, 1).await;
+    let purge_at = delete_account(&app).await;
+
+    // nothing happens before the grace period is over
+    let purged = app
+        .purge_deleted_accounts(purge_at - Duration::seconds(1))
+        .await;
+    assert_eq!(purged, 0);
+
+    assert_eq!(app.purge_deleted_accounts(purge_at).await, 1);
+    let result = app
+        .user_store
+        .read()
+        .await
+        .get_user(&Email::parse(Secret::new(email.clone())).unwrap())
+        .await;
+    assert_eq!(result, Err(UserStoreError::UserNotFound));
+
+    let response = login_response(&app, &email).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_require_2fa_when_enabled() {
+    let email = signup(&app, true).await;
+    // the login code, the deletion code and the deletion notice
+    mount_email_server(&app, 3).await;
+
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    let response = app
+        .verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(response_body.two_fa_method, "email".to_owned());
+
+    let response = app
+        .delete_account(&serde_json::json!({
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": "000000",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let response = app
+        .delete_account(&serde_json::json!({
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[api_test]
+async fn should_require_2fa_to_restore_account() {
+    let email = signup(&app, true).await;
+    // the login code, the deletion code, the deletion notice and the restore code
+    mount_email_server(&app, 4).await;
+
+    let response = login_response(&app, &email).await;
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    let response = app
+        .verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    let response = app
+        .delete_account(&serde_json::json!({
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // the password alone doesn't bring the account back
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": "000000",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+    let response = login_response(&app, &email).await;
+    assert_error(response, 403, "Account pending deletion").await;
+
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_throttle_failed_restore_attempts() {
+    let email = signup(&app, false).await;
+    login(&app, &email).await;
+    mount_email_server(&app, 1).await;
+    delete_account(&app).await;
+
+    let wrong_restore = serde_json::json!({
+        "email": email,
+        "password": "wrongPassword",
+    });
+    for _ in 0..3 {
+        let response = app.restore_account(&wrong_restore).await;
+        assert_error(response, 401, "Incorrect credentials").await;
+    }
+
+    let response = app
+        .restore_account(&serde_json::json!({
+            "email": email,
+            "password": "notSoSecure",
+        }))
+        .await;
+    assert_error(response, 429, "Too many attempts").await;
+}
+
+#[api_test]
+async fn should_return_401_if_password_is_incorrect() {
+    let email = signup(&app, false).await;
+    login(&app, &email).await;
+    mount_email_server(&app, 0).await;
+
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "wrongPassword" }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    login(&app, &email).await;
+}
+
+#[api_test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let response = app
+        .delete_account(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    assert_error(response, 400, "Missing auth token").await;
+}
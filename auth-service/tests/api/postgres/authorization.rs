@@ -0,0 +1,484 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::PkceVerifier,
+    routes::{IntrospectionResponse, OpenIdConfiguration, RegisterClientResponse, TokenResponse},
+    utils::constants::JWT_COOKIE_NAME,
+    OAuthErrorResponse,
+};
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde_json::json;
+use std::collections::HashMap;
+
+use super::helpers::{get_random_email, TestApp, REGISTRATION_TOKEN};
+use test_helpers::api_test;
+
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+async fn register_client(app: &TestApp, body: serde_json::Value) -> RegisterClientResponse {
+    let response = app.register_client(&body, REGISTRATION_TOKEN).await;
+    assert_eq!(response.status().as_u16(), 201);
+    response
+        .json::<RegisterClientResponse>()
+        .await
+        .expect("could not deserialize response body to RegisterClientResponse")
+}
+
+/// A public client, such as a single page app, that logs users in with the code grant.
+async fn register_public_client(app: &TestApp) -> RegisterClientResponse {
+    register_client(
+        app,
+        json!({
+            "client_name": "Web app",
+            "redirect_uris": [REDIRECT_URI],
+            "scope": "openid email",
+            "token_endpoint_auth_method": "none",
+        }),
+    )
+    .await
+}
+
+/// A confidential client, such as a backend service, that acts for itself.
+async fn register_service_client(app: &TestApp) -> RegisterClientResponse {
+    register_client(
+        app,
+        json!({
+            "client_name": "Orders service",
+            "grant_types": ["client_credentials"],
+            "scope": "orders:read orders:write",
+        }),
+    )
+    .await
+}
+
+async fn login(app: &TestApp) {
+    let email = get_random_email();
+    let response = app
+        .signup(&json!({
+            "email": email,
+            "password": "notSoSecure",
+            "require2FA": false,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response = app
+        .login(&json!({ "email": email, "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+fn authorize_query(client_id: &str, verifier: &PkceVerifier) -> Vec<(&'static str, String)> {
+    vec![
+        ("response_type", "code".to_owned()),
+        ("client_id", client_id.to_owned()),
+        ("redirect_uri", REDIRECT_URI.to_owned()),
+        ("scope", "email".to_owned()),
+        ("state", "client-state".to_owned()),
+        ("nonce", "client-nonce".to_owned()),
+        ("code_challenge", verifier.challenge()),
+        ("code_challenge_method", "S256".to_owned()),
+    ]
+}
+
+/// The query parameters of the redirect the response sends the browser to.
+fn redirect_params(response: &reqwest::Response) -> (Url, HashMap<String, String>) {
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("location")
+        .expect("No location header found")
+        .to_str()
+        .unwrap();
+    let url = Url::parse(location).expect("location is not an absolute URL");
+    let params = url.query_pairs().into_owned().collect();
+    (url, params)
+}
+
+/// Runs the browser half of the code flow for a logged in user and returns the code.
+async fn authorize(app: &TestApp, client_id: &str, verifier: &PkceVerifier) -> String {
+    let response = app.authorize(&authorize_query(client_id, verifier)).await;
+    let (url, params) = redirect_params(&response);
+    assert!(url.as_str().starts_with(REDIRECT_URI));
+    assert_eq!(params["state"], "client-state");
+    params["code"].clone()
+}
+
+async fn exchange_code(
+    app: &TestApp,
+    client_id: &str,
+    code: &str,
+    verifier: &PkceVerifier,
+) -> reqwest::Response {
+    app.token(
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", verifier.as_ref().expose_secret()),
+            ("client_id", client_id),
+        ],
+        None,
+    )
+    .await
+}
+
+async fn token_response(response: reqwest::Response) -> TokenResponse {
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    response
+        .json::<TokenResponse>()
+        .await
+        .expect("could not deserialize response body to TokenResponse")
+}
+
+async fn introspect(
+    app: &TestApp,
+    client: &RegisterClientResponse,
+    token: &str,
+) -> IntrospectionResponse {
+    let response = app
+        .introspect(&[
+            ("token", token),
+            ("client_id", client.client_id.as_str()),
+            ("client_secret", client.client_secret.as_deref().unwrap()),
+        ])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<IntrospectionResponse>()
+        .await
+        .expect("could not deserialize response body to IntrospectionResponse")
+}
+
+async fn assert_oauth_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<OAuthErrorResponse>()
+            .await
+            .expect("could not deserialize response body to OAuthErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_publish_openid_configuration() {
+    let response = app.get_openid_configuration().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let configuration = response
+        .json::<OpenIdConfiguration>()
+        .await
+        .expect("could not deserialize response body to OpenIdConfiguration");
+    assert!(configuration.token_endpoint.ends_with("/oauth/token"));
+    assert!(configuration.jwks_uri.ends_with("/.well-known/jwks.json"));
+    assert_eq!(configuration.code_challenge_methods_supported, vec!["S256"]);
+    // tests sign with a shared secret, which clients can't check ID tokens with
+    assert!(!configuration
+        .scopes_supported
+        .contains(&"openid".to_owned()));
+    assert!(configuration
+        .id_token_signing_alg_values_supported
+        .is_empty());
+}
+
+#[api_test]
+async fn should_register_clients_with_registration_token() {
+    let response = app
+        .register_client(&json!({ "client_name": "App" }), "wrong-token")
+        .await;
+    assert_oauth_error(response, 401, "invalid_token").await;
+
+    let client = register_public_client(&app).await;
+    assert!(client.client_secret.is_none());
+    assert_eq!(client.grant_types, vec!["authorization_code"]);
+    assert_eq!(client.scope, "email openid");
+
+    let client = register_service_client(&app).await;
+    assert!(client.client_secret.is_some());
+    assert_eq!(client.token_endpoint_auth_method, "client_secret_basic");
+}
+
+#[api_test]
+async fn should_reject_invalid_client_metadata() {
+    let response = app
+        .register_client(
+            &json!({ "client_name": "App", "redirect_uris": ["http://app.example.com/callback"] }),
+            REGISTRATION_TOKEN,
+        )
+        .await;
+    assert_oauth_error(response, 400, "invalid_redirect_uri").await;
+
+    let response = app
+        .register_client(
+            &json!({
+                "client_name": "App",
+                "grant_types": ["client_credentials"],
+                "token_endpoint_auth_method": "none",
+            }),
+            REGISTRATION_TOKEN,
+        )
+        .await;
+    assert_oauth_error(response, 400, "invalid_client_metadata").await;
+
+    let response = app
+        .register_client(
+            &json!({ "client_name": "App", "grant_types": ["password"] }),
+            REGISTRATION_TOKEN,
+        )
+        .await;
+    assert_oauth_error(response, 400, "invalid_client_metadata").await;
+}
+
+#[api_test]
+async fn should_send_user_to_login_page_before_authorizing() {
+    let client = register_public_client(&app).await;
+    let verifier = PkceVerifier::default();
+    let query = authorize_query(&client.client_id, &verifier);
+
+    let response = app.authorize(&query).await;
+    let (url, params) = redirect_params(&response);
+    assert_eq!(url.path(), "/");
+    assert!(params["return_to"].starts_with("/oauth/authorize?"));
+
+    let mut query = query;
+    query.push(("prompt", "none".to_owned()));
+    let response = app.authorize(&query).await;
+    let (_, params) = redirect_params(&response);
+    assert_eq!(params["error"], "login_required");
+    assert_eq!(params["state"], "client-state");
+}
+
+#[api_test]
+async fn should_not_redirect_to_unregistered_redirect_uri() {
+    let client = register_public_client(&app).await;
+    login(&app).await;
+
+    let mut query = authorize_query(&client.client_id, &PkceVerifier::default());
+    query[2].1 = "https://attacker.example.com/callback".to_owned();
+    let response = app.authorize(&query).await;
+    assert_oauth_error(response, 400, "invalid_request").await;
+}
+
+#[api_test]
+async fn should_require_pkce() {
+    let client = register_public_client(&app).await;
+    login(&app).await;
+
+    let query: Vec<_> = authorize_query(&client.client_id, &PkceVerifier::default())
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with("code_challenge"))
+        .collect();
+    let response = app.authorize(&query).await;
+    let (_, params) = redirect_params(&response);
+    assert_eq!(params["error"], "invalid_request");
+    assert!(!params.contains_key("code"));
+}
+
+#[api_test]
+async fn should_exchange_code_for_access_token() {
+    let client = register_public_client(&app).await;
+    let service = register_service_client(&app).await;
+    login(&app).await;
+    let verifier = PkceVerifier::default();
+
+    let code = authorize(&app, &client.client_id, &verifier).await;
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    let tokens = token_response(response).await;
+    assert_eq!(tokens.token_type, "Bearer");
+    assert_eq!(tokens.scope.as_deref(), Some("email"));
+    assert!(tokens.id_token.is_none());
+
+    let introspection = introspect(&app, &service, &tokens.access_token).await;
+    assert!(introspection.active);
+    assert_eq!(introspection.client_id, Some(client.client_id));
+    assert_eq!(introspection.scope.as_deref(), Some("email"));
+    assert!(introspection.sid.is_some());
+}
+
+#[api_test]
+async fn should_only_issue_permissions_granted_to_the_user() {
+    let client = register_client(
+        &app,
+        json!({
+            "client_name": "Admin console",
+            "redirect_uris": [REDIRECT_URI],
+            "scope": "email roles:manage",
+            "token_endpoint_auth_method": "none",
+        }),
+    )
+    .await;
+    login(&app).await;
+    let verifier = PkceVerifier::default();
+
+    // the client may ask for the scope, but the user hasn't been granted it
+    let mut query = authorize_query(&client.client_id, &verifier);
+    for (key, value) in query.iter_mut() {
+        if *key == "scope" {
+            *value = "email roles:manage".to_owned();
+        }
+    }
+    let response = app.authorize(&query).await;
+    let (_, params) = redirect_params(&response);
+    let response = exchange_code(&app, &client.client_id, &params["code"], &verifier).await;
+    let tokens = token_response(response).await;
+    assert_eq!(tokens.scope.as_deref(), Some("email"));
+}
+
+#[api_test]
+async fn should_refuse_openid_scope_without_private_signing_key() {
+    let client = register_public_client(&app).await;
+    login(&app).await;
+
+    let mut query = authorize_query(&client.client_id, &PkceVerifier::default());
+    for (key, value) in query.iter_mut() {
+        if *key == "scope" {
+            *value = "openid email".to_owned();
+        }
+    }
+    let response = app.authorize(&query).await;
+    let (_, params) = redirect_params(&response);
+    assert_eq!(params["error"], "invalid_scope");
+    assert!(!params.contains_key("code"));
+}
+
+#[api_test]
+async fn should_reject_reused_code_and_wrong_verifier() {
+    let client = register_public_client(&app).await;
+    login(&app).await;
+    let verifier = PkceVerifier::default();
+
+    let code = authorize(&app, &client.client_id, &verifier).await;
+    let response = exchange_code(&app, &client.client_id, &code, &PkceVerifier::default()).await;
+    assert_oauth_error(response, 400, "invalid_grant").await;
+    // a failed exchange uses the code up too
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    assert_oauth_error(response, 400, "invalid_grant").await;
+
+    let code = authorize(&app, &client.client_id, &verifier).await;
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    token_response(response).await;
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    assert_oauth_error(response, 400, "invalid_grant").await;
+}
+
+#[api_test]
+async fn should_deactivate_tokens_when_user_logs_out() {
+    let client = register_public_client(&app).await;
+    let service = register_service_client(&app).await;
+    login(&app).await;
+    let verifier = PkceVerifier::default();
+
+    let code = authorize(&app, &client.client_id, &verifier).await;
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    let tokens = token_response(response).await;
+
+    let response = app.logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let introspection = introspect(&app, &service, &tokens.access_token).await;
+    assert!(!introspection.active);
+    assert!(introspection.sub.is_none());
+}
+
+#[api_test]
+async fn should_not_accept_client_access_token_as_auth_cookie() {
+    let client = register_public_client(&app).await;
+    login(&app).await;
+    let verifier = PkceVerifier::default();
+
+    let code = authorize(&app, &client.client_id, &verifier).await;
+    let response = exchange_code(&app, &client.client_id, &code, &verifier).await;
+    let tokens = token_response(response).await;
+
+    // the token carries the user's session, but was issued to the client, not the browser
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Lax; Secure; Path=/",
+            JWT_COOKIE_NAME, tokens.access_token
+        ),
+        &Url::parse("http://127.0.0.1").expect("failed to parse url"),
+    );
+    let response = app.list_sessions().await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[api_test]
+async fn should_issue_client_credentials_tokens() {
+    let service = register_service_client(&app).await;
+    let client_secret = service.client_secret.clone().unwrap();
+
+    let response = app
+        .token(
+            &[
+                ("grant_type", "client_credentials"),
+                ("scope", "orders:read"),
+            ],
+            Some((&service.client_id, &client_secret)),
+        )
+        .await;
+    let tokens = token_response(response).await;
+    assert_eq!(tokens.scope.as_deref(), Some("orders:read"));
+    assert!(tokens.id_token.is_none());
+
+    let introspection = introspect(&app, &service, &tokens.access_token).await;
+    assert!(introspection.active);
+    assert_eq!(introspection.sub.as_ref(), Some(&service.client_id));
+    assert!(introspection.sid.is_none());
+
+    let response = app
+        .token(
+            &[
+                ("grant_type", "client_credentials"),
+                ("scope", "orders:delete"),
+            ],
+            Some((&service.client_id, &client_secret)),
+        )
+        .await;
+    assert_oauth_error(response, 400, "invalid_scope").await;
+}
+
+#[api_test]
+async fn should_reject_unauthenticated_clients() {
+    let client = register_public_client(&app).await;
+    let service = register_service_client(&app).await;
+
+    let response = app
+        .token(
+            &[("grant_type", "client_credentials")],
+            Some((&service.client_id, "wrong-secret")),
+        )
+        .await;
+    assert_oauth_error(response, 401, "invalid_client").await;
+
+    // public clients can't use the grant, however they name themselves
+    let response = app
+        .token(
+            &[
+                ("grant_type", "client_credentials"),
+                ("client_id", client.client_id.as_str()),
+            ],
+            None,
+        )
+        .await;
+    assert_oauth_error(response, 400, "unauthorized_client").await;
+
+    let response = app
+        .introspect(&[("token", "token"), ("client_id", client.client_id.as_str())])
+        .await;
+    assert_oauth_error(response, 401, "invalid_client").await;
+}
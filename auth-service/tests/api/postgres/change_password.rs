@@ -0,0 +1,217 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use reqwest::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup(app: &TestApp) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    random_email
+}
+
+/// Logs in and returns the auth token, which also replaces the one in the cookie jar.
+async fn login(app: &TestApp, email: &str, password: &str) -> String {
+    let response = login_response(app, email, password).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+    token
+}
+
+async fn login_response(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": password,
+    });
+    app.login(&login_body).await
+}
+
+async fn mount_email_server(app: &TestApp, expected_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn token_is_valid(app: &TestApp, token: &str) -> bool {
+    let response = app
+        .verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    response.status().as_u16() == 200
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_change_password_and_notify_user() {
+    let email = signup(&app).await;
+    let other_token = login(&app, &email, "notSoSecure").await;
+    let current_token = login(&app, &email, "notSoSecure").await;
+    mount_email_server(&app, 1).await;
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "n3wP4ssword",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // other devices stay logged in unless asked otherwise
+    assert!(token_is_valid(&app, &current_token).await);
+    assert!(token_is_valid(&app, &other_token).await);
+
+    let response = login_response(&app, &email, "notSoSecure").await;
+    assert_error(response, 401, "Incorrect credentials").await;
+    login(&app, &email, "n3wP4ssword").await;
+}
+
+#[api_test]
+async fn should_revoke_other_sessions_when_asked() {
+    let email = signup(&app).await;
+    let other_token = login(&app, &email, "notSoSecure").await;
+    let current_token = login(&app, &email, "notSoSecure").await;
+    mount_email_server(&app, 1).await;
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "n3wP4ssword",
+            "revokeOtherSessions": true,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert!(token_is_valid(&app, &current_token).await);
+    assert!(!token_is_valid(&app, &other_token).await);
+}
+
+#[api_test]
+async fn should_return_401_if_current_password_is_incorrect() {
+    let email = signup(&app).await;
+    login(&app, &email, "notSoSecure").await;
+    mount_email_server(&app, 0).await;
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "wrongPassword",
+            "newPassword": "n3wP4ssword",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    login(&app, &email, "notSoSecure").await;
+}
+
+#[api_test]
+async fn should_throttle_failed_current_password_attempts() {
+    let email = signup(&app).await;
+    login(&app, &email, "notSoSecure").await;
+    mount_email_server(&app, 0).await;
+
+    // a stolen cookie mustn't be a way around the login throttle
+    for _ in 0..3 {
+        let response = app
+            .change_password(&serde_json::json!({
+                "currentPassword": "wrongPassword",
+                "newPassword": "n3wP4ssword",
+            }))
+            .await;
+        assert_error(response, 401, "Incorrect credentials").await;
+    }
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "n3wP4ssword",
+        }))
+        .await;
+    assert_error(response, 429, "Too many attempts").await;
+}
+
+#[api_test]
+async fn should_return_400_if_new_password_is_invalid() {
+    let email = signup(&app).await;
+    login(&app, &email, "notSoSecure").await;
+    mount_email_server(&app, 0).await;
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "short",
+        }))
+        .await;
+    assert_error(response, 400, "Password must be at least 8 characters").await;
+}
+
+#[api_test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "n3wP4ssword",
+        }))
+        .await;
+    assert_error(response, 400, "Missing auth token").await;
+}
+
+#[api_test]
+async fn should_return_401_if_invalid_token() {
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}=invalid; HttpOnly; SameSite=Lax; Secure; Path=/",
+            JWT_COOKIE_NAME
+        ),
+        &Url::parse("http://127.0.0.1").expect("failed to parse url"),
+    );
+
+    let response = app
+        .change_password(&serde_json::json!({
+            "currentPassword": "notSoSecure",
+            "newPassword": "n3wP4ssword",
+        }))
+        .await;
+    assert_error(response, 401, "Invalid auth token").await;
+}
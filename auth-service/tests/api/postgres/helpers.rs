@@ -15,14 +15,34 @@
 */
 
 use auth_service::{
-    app_state::{AppState, BannedTokenStoreType, TwoFACodeStoreType},
-    domain::Email,
+    app_state::{
+        AppState, BannedTokenStoreType, RefreshTokenStoreType, SessionStoreType,
+        TwoFACodeStoreType, UserStoreType,
+    },
+    domain::{
+        Email, OAuthProviders, Password, PasswordPolicy, RoleDefinition, Roles, TwoFAMethod, User,
+    },
     get_postgres_pool, get_redis_client,
-    services::data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore},
+    services::account_purge::purge_deleted_accounts,
+    services::argon2_password_hasher::Argon2PasswordHasher,
+    services::data_stores::{
+        HashmapFailedLoginStore, HashmapRateLimitStore, PostgresExternalIdentityStore,
+        PostgresOAuthClientStore, PostgresPermissionStore, PostgresRecoveryCodeStore,
+        PostgresUserStore, PostgresWebauthnCredentialStore, RedisAuthorizationCodeStore,
+        RedisBannedTokenStore, RedisEmailCooldownStore, RedisOAuthStateStore,
+        RedisRefreshTokenStore, RedisSessionStore, RedisTwoFACodeStore,
+        RedisWebauthnChallengeStore,
+    },
+    services::file_breached_password_source::FileBreachedPasswordSource,
+    services::oidc_client::OidcClient,
     services::postmark_email_client::PostmarkEmailClient,
-    utils::constants::{test, DATABASE_URL, DEFAULT_REDIS_HOSTNAME},
+    utils::{
+        constants::{test, DATABASE_URL, DEFAULT_REDIS_HOSTNAME},
+        rate_limit::RateLimits,
+    },
     Application,
 };
+use chrono::{DateTime, Utc};
 use core::panic;
 use reqwest::{cookie::Jar, Client};
 use secrecy::{ExposeSecret, Secret};
@@ -36,15 +56,22 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 use wiremock::MockServer;
 
+use crate::oidc_provider::MockOidcProvider;
+
 pub struct TestApp {
     pub address: String,
     pub http_client: reqwest::Client,
     pub cookie_jar: Arc<Jar>,
     pub banned_token_store: BannedTokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
+    pub refresh_token_store: RefreshTokenStoreType,
+    pub session_store: SessionStoreType,
+    pub user_store: UserStoreType,
+    pub app_state: AppState,
     pub db_name: String,
     pub clean_up_called: bool,
     pub email_server: MockServer,
+    pub oidc_provider: MockOidcProvider,
 }
 
 impl TestApp {
@@ -53,21 +80,82 @@ impl TestApp {
         let pg_pool = configure_postgresql(&db_name).await;
         let redis_connection = configure_redis().await;
 
-        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
+        let password_hasher = Arc::new(Argon2PasswordHasher::default());
+        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(
+            pg_pool.clone(),
+            password_hasher.clone(),
+        )));
         let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(
             redis_connection.clone(),
         )));
-        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_connection)));
+        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+            redis_connection.clone(),
+        )));
+        let refresh_token_store = Arc::new(RwLock::new(RedisRefreshTokenStore::new(
+            redis_connection.clone(),
+        )));
+        let session_store = Arc::new(RwLock::new(RedisSessionStore::new(
+            redis_connection.clone(),
+        )));
+        let recovery_code_store = Arc::new(RwLock::new(PostgresRecoveryCodeStore::new(
+            pg_pool.clone(),
+            password_hasher,
+        )));
+        let webauthn_credential_store = Arc::new(RwLock::new(
+            PostgresWebauthnCredentialStore::new(pg_pool.clone()),
+        ));
+        let external_identity_store = Arc::new(RwLock::new(PostgresExternalIdentityStore::new(
+            pg_pool.clone(),
+        )));
+        let oauth_client_store =
+            Arc::new(RwLock::new(PostgresOAuthClientStore::new(pg_pool.clone())));
+        let permission_store = Arc::new(RwLock::new(PostgresPermissionStore::new(pg_pool)));
+        let webauthn_challenge_store = Arc::new(RwLock::new(RedisWebauthnChallengeStore::new(
+            redis_connection.clone(),
+        )));
+        let email_cooldown_store = Arc::new(RwLock::new(RedisEmailCooldownStore::new(
+            redis_connection.clone(),
+        )));
+        let oauth_state_store = Arc::new(RwLock::new(RedisOAuthStateStore::new(
+            redis_connection.clone(),
+        )));
+        let authorization_code_store = Arc::new(RwLock::new(RedisAuthorizationCodeStore::new(
+            redis_connection,
+        )));
+        // every test logs in from 127.0.0.1, so a count shared through Redis would let the failed
+        // logins of one test throttle the others, and the same goes for rate limits
+        let failed_login_store = Arc::new(RwLock::new(HashmapFailedLoginStore::default()));
+        let rate_limit_store = Arc::new(RwLock::new(HashmapRateLimitStore::default()));
         let email_server = MockServer::start().await;
         let base_url = email_server.uri();
         let email_client = Arc::new(configure_postmark_email_client(base_url));
+        let oidc_provider = MockOidcProvider::start().await;
+        let oidc_client = Arc::new(configure_oidc_client(&oidc_provider));
         let app_state = AppState::new(
-            user_store,
+            user_store.clone(),
             banned_token_store.clone(),
             two_fa_code_store.clone(),
+            refresh_token_store.clone(),
+            session_store.clone(),
+            recovery_code_store,
+            webauthn_credential_store,
+            webauthn_challenge_store,
+            email_cooldown_store,
+            failed_login_store,
+            rate_limit_store,
+            oauth_state_store,
+            external_identity_store,
+            oauth_client_store,
+            authorization_code_store,
+            permission_store,
+            RateLimits::default(),
+            configure_password_policy(),
+            Arc::new(configure_roles()),
+            Some(Secret::new(REGISTRATION_TOKEN.to_owned())),
             email_client,
+            oidc_client,
         );
-        let app = Application::build(app_state, test::APP_ADDRESS)
+        let app = Application::build(app_state.clone(), test::APP_ADDRESS)
             .await
             .expect("failed to build service");
 
@@ -80,6 +168,9 @@ impl TestApp {
         let cookie_jar = Arc::new(Jar::default());
         let http_client = reqwest::Client::builder()
             .cookie_provider(cookie_jar.clone())
+            .user_agent(USER_AGENT)
+            // OAuth logins redirect to the provider, whose mock is driven by the tests instead
+            .redirect(reqwest::redirect::Policy::none())
             .build()
             .unwrap();
 
@@ -89,38 +180,117 @@ impl TestApp {
             cookie_jar,
             banned_token_store,
             two_fa_code_store,
+            refresh_token_store,
+            session_store,
+            user_store,
+            app_state,
             db_name,
             clean_up_called: false,
             email_server,
+            oidc_provider,
         }
     }
 
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/", &self.address))
+            .get(format!("{}/", &self.address))
             .send()
             .await
             .expect("failed to execute request")
     }
 
+    pub async fn get_jwks(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/.well-known/jwks.json", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Signs up and marks the new account as verified, since most tests start from a user that
+    /// can log in. `signup_unverified` leaves the account as signup does.
     pub async fn signup<SignupRequest>(&self, body: &SignupRequest) -> reqwest::Response
+    where
+        SignupRequest: serde::Serialize,
+    {
+        let response = self.signup_unverified(body).await;
+        if response.status() == reqwest::StatusCode::CREATED {
+            let body = serde_json::to_value(body).expect("signup body is not JSON");
+            let email = body["email"].as_str().expect("signup body has no email");
+            self.mark_verified(email).await;
+        }
+        response
+    }
+
+    pub async fn signup_unverified<SignupRequest>(&self, body: &SignupRequest) -> reqwest::Response
     where
         SignupRequest: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/signup", &self.address))
+            .post(format!("{}/signup", &self.address))
             .json(body)
             .send()
             .await
             .expect("signup failed")
     }
 
+    async fn mark_verified(&self, email: &str) {
+        let email = Email::parse(Secret::new(email.to_owned())).expect("invalid email");
+        let mut user_store = self.user_store.write().await;
+        let user = user_store
+            .get_user(&email)
+            .await
+            .expect("signed up user not found");
+        user_store
+            .set_verified(&user.id)
+            .await
+            .expect("failed to mark user as verified");
+    }
+
+    /// Adds a verified user with the password stored exactly as given, like one set before
+    /// passwords were normalized.
+    pub async fn add_verified_user(&self, email: &str, password: &str) {
+        let email = Email::parse(Secret::new(email.to_owned())).expect("invalid email");
+        let password = Password::parse(Secret::new(password.to_owned())).expect("invalid password");
+        let user = User::new(email, password, TwoFAMethod::None);
+        let mut user_store = self.user_store.write().await;
+        user_store
+            .add_user(user.clone())
+            .await
+            .expect("failed to add user");
+        user_store
+            .set_verified(&user.id)
+            .await
+            .expect("failed to mark user as verified");
+    }
+
+    pub async fn verify_email(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/verify-email", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("email verification failed")
+    }
+
+    pub async fn resend_verification_email<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/verify-email/resend", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("verification email resend failed")
+    }
+
     pub async fn login<LoginRequest>(&self, body: &LoginRequest) -> reqwest::Response
     where
         LoginRequest: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .json(body)
             .send()
             .await
@@ -129,7 +299,7 @@ impl TestApp {
 
     pub async fn logout(&self) -> reqwest::Response {
         self.http_client
-            .post(&format!("{}/logout", &self.address))
+            .post(format!("{}/logout", &self.address))
             .send()
             .await
             .expect("logout failed")
@@ -140,25 +310,365 @@ impl TestApp {
         Request: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/verify-2fa", &self.address))
+            .post(format!("{}/verify-2fa", &self.address))
             .json(body)
             .send()
             .await
             .expect("2fa verification failed")
     }
 
+    pub async fn refresh(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/refresh", &self.address))
+            .send()
+            .await
+            .expect("refresh failed")
+    }
+
     pub async fn verify_token<Request>(&self, body: &Request) -> reqwest::Response
     where
         Request: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/verify-token", &self.address))
+            .post(format!("{}/verify-token", &self.address))
             .json(body)
             .send()
             .await
             .expect("token verification failed")
     }
 
+    pub async fn request_password_reset<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/password-reset/request", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("password reset request failed")
+    }
+
+    pub async fn confirm_password_reset<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/password-reset/confirm", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("password reset confirmation failed")
+    }
+
+    pub async fn change_password<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/change-password", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("password change failed")
+    }
+
+    pub async fn delete_account<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .delete(format!("{}/account", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("account deletion failed")
+    }
+
+    pub async fn restore_account<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/account/restore", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("account restore failed")
+    }
+
+    /// Runs the account purge job once, as if it were `now`.
+    pub async fn purge_deleted_accounts(&self, now: DateTime<Utc>) -> usize {
+        purge_deleted_accounts(&self.app_state, now)
+            .await
+            .expect("account purge failed")
+    }
+
+    pub async fn enable_2fa<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/enable-2fa", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("enabling 2FA failed")
+    }
+
+    pub async fn disable_2fa<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/disable-2fa", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("disabling 2FA failed")
+    }
+
+    pub async fn enroll_totp(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/enroll-totp", &self.address))
+            .send()
+            .await
+            .expect("TOTP enrollment failed")
+    }
+
+    pub async fn confirm_totp<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/confirm-totp", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("TOTP confirmation failed")
+    }
+
+    pub async fn list_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/sessions", &self.address))
+            .send()
+            .await
+            .expect("listing sessions failed")
+    }
+
+    pub async fn revoke_session(&self, id: &str) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/sessions/{}", &self.address, id))
+            .send()
+            .await
+            .expect("revoking session failed")
+    }
+
+    pub async fn regenerate_recovery_codes<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/recovery-codes", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("recovery code regeneration failed")
+    }
+
+    pub async fn count_recovery_codes(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/recovery-codes", &self.address))
+            .send()
+            .await
+            .expect("recovery code count failed")
+    }
+
+    pub async fn webauthn_register_start(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/webauthn/register/start", &self.address))
+            .send()
+            .await
+            .expect("WebAuthn registration start failed")
+    }
+
+    pub async fn webauthn_register_finish<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/register/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("WebAuthn registration finish failed")
+    }
+
+    pub async fn webauthn_login_start<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/start", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("WebAuthn login start failed")
+    }
+
+    pub async fn webauthn_login_finish<Request>(&self, body: &Request) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("WebAuthn login finish failed")
+    }
+
+    pub async fn oauth_start(&self, provider: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/oauth/{}/start", &self.address, provider))
+            .send()
+            .await
+            .expect("OAuth login start failed")
+    }
+
+    pub async fn oauth_callback<Query>(&self, provider: &str, query: &Query) -> reqwest::Response
+    where
+        Query: serde::Serialize,
+    {
+        self.http_client
+            .get(format!("{}/oauth/{}/callback", &self.address, provider))
+            .query(query)
+            .send()
+            .await
+            .expect("OAuth callback failed")
+    }
+
+    pub async fn authorize<Query>(&self, query: &Query) -> reqwest::Response
+    where
+        Query: serde::Serialize,
+    {
+        self.http_client
+            .get(format!("{}/oauth/authorize", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("authorization request failed")
+    }
+
+    /// Calls the token endpoint, with the client credentials in a Basic header if given, and
+    /// otherwise only what the form holds.
+    pub async fn token<Form>(
+        &self,
+        form: &Form,
+        basic_credentials: Option<(&str, &str)>,
+    ) -> reqwest::Response
+    where
+        Form: serde::Serialize,
+    {
+        let mut request = self
+            .http_client
+            .post(format!("{}/oauth/token", &self.address))
+            .form(form);
+        if let Some((client_id, client_secret)) = basic_credentials {
+            request = request.basic_auth(client_id, Some(client_secret));
+        }
+        request.send().await.expect("token request failed")
+    }
+
+    pub async fn introspect<Form>(&self, form: &Form) -> reqwest::Response
+    where
+        Form: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/oauth/introspect", &self.address))
+            .form(form)
+            .send()
+            .await
+            .expect("introspection failed")
+    }
+
+    pub async fn register_client<Request>(
+        &self,
+        body: &Request,
+        registration_token: &str,
+    ) -> reqwest::Response
+    where
+        Request: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/oauth/register", &self.address))
+            .bearer_auth(registration_token)
+            .json(body)
+            .send()
+            .await
+            .expect("client registration failed")
+    }
+
+    pub async fn get_openid_configuration(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn revoke_all_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/sessions", &self.address))
+            .send()
+            .await
+            .expect("revoking sessions failed")
+    }
+
+    /// Calls the admin API with `token` as Bearer token if given, and otherwise with the auth
+    /// cookie of whoever is logged in.
+    pub async fn get_grants(&self, user_id: &str, token: Option<&str>) -> reqwest::Response {
+        let url = format!("{}/admin/users/{}/grants", &self.address, user_id);
+        self.admin_request(self.http_client.get(url), token).await
+    }
+
+    /// Grants a role or permission, with `grant` being e.g. `roles/editor`.
+    pub async fn grant(
+        &self,
+        user_id: &str,
+        grant: &str,
+        token: Option<&str>,
+    ) -> reqwest::Response {
+        let url = format!("{}/admin/users/{}/{}", &self.address, user_id, grant);
+        self.admin_request(self.http_client.put(url), token).await
+    }
+
+    pub async fn revoke(
+        &self,
+        user_id: &str,
+        grant: &str,
+        token: Option<&str>,
+    ) -> reqwest::Response {
+        let url = format!("{}/admin/users/{}/{}", &self.address, user_id, grant);
+        self.admin_request(self.http_client.delete(url), token)
+            .await
+    }
+
+    async fn admin_request(
+        &self,
+        request: reqwest::RequestBuilder,
+        token: Option<&str>,
+    ) -> reqwest::Response {
+        let request = match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        request.send().await.expect("admin request failed")
+    }
+
     pub async fn clean_up(&mut self) {
         if self.clean_up_called {
             return;
@@ -176,6 +686,12 @@ impl Drop for TestApp {
     }
 }
 
+pub const USER_AGENT: &str = "auth-service-tests";
+/// Initial access token the test app accepts on `/oauth/register`.
+pub const REGISTRATION_TOKEN: &str = "test-registration-token";
+pub const EDITOR_ROLE: &str = "editor";
+pub const EDITOR_PERMISSION: &str = "articles:write";
+
 pub fn get_random_email() -> String {
     format!("{}@umbrella.corp", Uuid::new_v4())
 }
@@ -273,3 +789,32 @@ fn configure_postmark_email_client(base_url: String) -> PostmarkEmailClient {
 
     PostmarkEmailClient::new(base_url, sender, postmark_auth_token, http_client)
 }
+
+fn configure_oidc_client(oidc_provider: &MockOidcProvider) -> OidcClient {
+    let http_client = Client::builder()
+        .timeout(test::oauth::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    let providers =
+        OAuthProviders::new(vec![oidc_provider.config()]).expect("Failed to load OAuth providers");
+    OidcClient::new(providers, http_client)
+}
+
+/// The default policy, checking breached passwords against a local list instead of the
+/// Have I Been Pwned API.
+/// Roles the tests can grant, besides the admin role.
+fn configure_roles() -> Roles {
+    Roles::new(vec![RoleDefinition {
+        name: EDITOR_ROLE.to_owned(),
+        permissions: vec![EDITOR_PERMISSION.to_owned()],
+    }])
+    .expect("test roles should be valid")
+}
+
+fn configure_password_policy() -> PasswordPolicy {
+    let breached_passwords =
+        FileBreachedPasswordSource::parse(include_str!("../../fixtures/breached_passwords.txt"))
+            .expect("Failed to parse breached passwords");
+    PasswordPolicy::default().with_breached_passwords(Arc::new(breached_passwords))
+}
@@ -13,10 +13,24 @@
    See the License for the specific language governing permissions and
    limitations under the License.
 */
+pub mod account;
+pub mod admin;
+pub mod authorization;
+pub mod change_password;
 pub mod helpers;
+pub mod jwks;
 pub mod login;
 pub mod logout;
+pub mod oauth;
+pub mod password_reset;
+pub mod recovery_codes;
+pub mod refresh;
 pub mod root;
+pub mod sessions;
 pub mod signup;
+pub mod totp;
+pub mod two_fa;
 pub mod verify_2fa;
+pub mod verify_email;
 pub mod verify_token;
+pub mod webauthn;
@@ -0,0 +1,313 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::Email,
+    routes::TwoFactorAuthResponse,
+    utils::constants::{JWT_COOKIE_NAME, OAUTH_STATE_COOKIE_NAME},
+    ErrorResponse,
+};
+use secrecy::Secret;
+use serde_json::json;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use crate::oidc_provider::{AuthorizationRequest, MOCK_CLIENT_ID, MOCK_PROVIDER};
+use test_helpers::api_test;
+
+async fn start_login(app: &TestApp) -> AuthorizationRequest {
+    let response = app.oauth_start(MOCK_PROVIDER).await;
+    assert_eq!(response.status().as_u16(), 303);
+    AuthorizationRequest::from_redirect(&response)
+}
+
+/// Logs in at the mock provider as the account with `claims` and follows its redirect back.
+async fn login_with_provider(app: &TestApp, claims: serde_json::Value) -> reqwest::Response {
+    let request = start_login(app).await;
+    let code = app.oidc_provider.authorize(&request, claims).await;
+    app.oauth_callback(
+        MOCK_PROVIDER,
+        &[("code", code.as_str()), ("state", request.state.as_str())],
+    )
+    .await
+}
+
+async fn assert_logged_in(app: &TestApp, response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    let response = app
+        .verify_token(&json!({ "token": auth_cookie.value() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_redirect_to_provider_with_state_and_pkce_challenge() {
+    let response = app.oauth_start(MOCK_PROVIDER).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let state_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == OAUTH_STATE_COOKIE_NAME)
+        .expect("No OAuth state cookie found");
+    assert!(state_cookie.http_only());
+
+    let request = AuthorizationRequest::from_redirect(&response);
+    assert_eq!(request.client_id, MOCK_CLIENT_ID);
+    assert_eq!(request.state, state_cookie.value());
+    assert_eq!(request.code_challenge_method, "S256");
+    assert!(!request.code_challenge.is_empty());
+    assert!(!request.nonce.is_empty());
+}
+
+#[api_test]
+async fn should_return_404_for_unknown_provider() {
+    let response = app.oauth_start("unknown").await;
+    assert_error(response, 404, "OAuth provider not found").await;
+}
+
+#[api_test]
+async fn should_create_verified_user_on_first_login() {
+    let email = get_random_email();
+
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "new-user", "email": email, "email_verified": true }),
+    )
+    .await;
+    assert_logged_in(&app, response).await;
+
+    let email = Email::parse(Secret::new(email)).unwrap();
+    let user = app.user_store.read().await.get_user(&email).await.unwrap();
+    assert!(user.verified);
+}
+
+#[api_test]
+async fn should_link_existing_user_by_verified_email() {
+    let email = get_random_email();
+    let signup_body = json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "existing-user", "email": email, "email_verified": true }),
+    )
+    .await;
+    assert_logged_in(&app, response).await;
+
+    // the link is kept, so the account can change its address at the provider
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "existing-user", "email": get_random_email(), "email_verified": false }),
+    )
+    .await;
+    assert_logged_in(&app, response).await;
+
+    // and the password keeps working
+    let response = app
+        .login(&json!({ "email": email, "password": "notSoSecure" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_replace_password_of_unverified_user_when_linking() {
+    let email = get_random_email();
+    let signup_body = json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup_unverified(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "squatted", "email": email, "email_verified": true }),
+    )
+    .await;
+    assert_logged_in(&app, response).await;
+
+    let response = app
+        .login(&json!({ "email": email, "password": "notSoSecure" }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_403_if_email_not_verified_by_provider() {
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "unverified", "email": get_random_email(), "email_verified": false }),
+    )
+    .await;
+    assert_error(response, 403, "Email not verified").await;
+}
+
+#[api_test]
+async fn should_return_206_if_2fa_enabled() {
+    let email = get_random_email();
+    let signup_body = json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": true,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = login_with_provider(
+        &app,
+        json!({ "sub": "two-factor", "email": email, "email_verified": true }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let json_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(json_body.message, "2FA required".to_owned());
+}
+
+#[api_test]
+async fn should_return_401_if_state_is_replayed() {
+    let request = start_login(&app).await;
+    let code = app
+        .oidc_provider
+        .authorize(
+            &request,
+            json!({ "sub": "replay", "email": get_random_email(), "email_verified": true }),
+        )
+        .await;
+    let query = [("code", code.as_str()), ("state", request.state.as_str())];
+
+    let response = app.oauth_callback(MOCK_PROVIDER, &query).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.oauth_callback(MOCK_PROVIDER, &query).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_state_was_issued_to_another_browser() {
+    // the attacker starts a login in their own browser and sends the victim the callback link
+    let attacker = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = attacker
+        .get(format!("{}/oauth/{}/start", &app.address, MOCK_PROVIDER))
+        .send()
+        .await
+        .unwrap();
+    let request = AuthorizationRequest::from_redirect(&response);
+    let code = app
+        .oidc_provider
+        .authorize(
+            &request,
+            json!({ "sub": "attacker", "email": get_random_email(), "email_verified": true }),
+        )
+        .await;
+
+    let response = app
+        .oauth_callback(
+            MOCK_PROVIDER,
+            &[("code", code.as_str()), ("state", request.state.as_str())],
+        )
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_id_token_is_for_another_client() {
+    let response = login_with_provider(
+        &app,
+        json!({
+            "sub": "other-client",
+            "email": get_random_email(),
+            "email_verified": true,
+            "aud": "another-client",
+        }),
+    )
+    .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_code_verifier_does_not_match() {
+    let mut request = start_login(&app).await;
+    let state = request.state.clone();
+    // the provider only redeems the code with the verifier of another challenge
+    request.code_challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_owned();
+    let code = app
+        .oidc_provider
+        .authorize(
+            &request,
+            json!({ "sub": "pkce", "email": get_random_email(), "email_verified": true }),
+        )
+        .await;
+
+    let response = app
+        .oauth_callback(
+            MOCK_PROVIDER,
+            &[("code", code.as_str()), ("state", state.as_str())],
+        )
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_provider_returns_an_error() {
+    let request = start_login(&app).await;
+
+    let response = app
+        .oauth_callback(
+            MOCK_PROVIDER,
+            &[
+                ("error", "access_denied"),
+                ("state", request.state.as_str()),
+            ],
+        )
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
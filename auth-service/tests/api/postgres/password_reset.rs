@@ -0,0 +1,219 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use std::time::Duration;
+
+use auth_service::{
+    routes::PasswordResetResponse, utils::constants::JWT_COOKIE_NAME, ErrorResponse,
+};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup_and_login(app: &TestApp) -> (String, String) {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login(app, &random_email, "notSoSecure").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    (random_email, auth_token)
+}
+
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": password,
+    });
+    app.login(&login_body).await
+}
+
+async fn request_reset(app: &TestApp, email: &str) {
+    let response = app
+        .request_password_reset(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    response
+        .json::<PasswordResetResponse>()
+        .await
+        .expect("could not deserialize response body to PasswordResetResponse");
+}
+
+/// The reset email is sent in the background, so wait for it to reach the mock server.
+async fn emailed_reset_token(app: &TestApp) -> String {
+    for _ in 0..50 {
+        let requests = app
+            .email_server
+            .received_requests()
+            .await
+            .expect("request recording is disabled");
+        // signup sends a verification email as well
+        let reset_email = requests
+            .iter()
+            .map(|request| {
+                serde_json::from_slice::<serde_json::Value>(&request.body)
+                    .expect("email body is not JSON")
+            })
+            .find(|body| body["Subject"] == "Reset your password");
+        if let Some(body) = reset_email {
+            let text = body["TextBody"].as_str().expect("email has no TextBody");
+            let (_, token) = text.split_once("token=").expect("no token in reset link");
+            return token.to_owned();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("password reset email was never sent");
+}
+
+async fn confirm_reset(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    app.confirm_password_reset(&serde_json::json!({
+        "token": token,
+        "password": password,
+    }))
+    .await
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_reset_password_and_revoke_existing_tokens() {
+    let (email, auth_token) = signup_and_login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    request_reset(&app, &email).await;
+    let token = emailed_reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, "n3wP4ssword").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .verify_token(&serde_json::json!({ "token": auth_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = login(&app, &email, "notSoSecure").await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let response = login(&app, &email, "n3wP4ssword").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_not_reveal_unknown_emails() {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    request_reset(&app, &get_random_email()).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+#[api_test]
+async fn should_return_400_if_email_is_malformed() {
+    let response = app
+        .request_password_reset(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_error(response, 400, "Invalid credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_token_is_reused() {
+    let (email, _) = signup_and_login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    request_reset(&app, &email).await;
+    let token = emailed_reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, "n3wP4ssword").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = confirm_reset(&app, &token, "an0therP4ssword").await;
+    assert_error(response, 401, "Invalid auth token").await;
+
+    let response = login(&app, &email, "n3wP4ssword").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_return_401_if_token_is_invalid() {
+    let (_, auth_token) = signup_and_login(&app).await;
+
+    // auth tokens are signed with the same key, but aren't meant for resets
+    for token in ["not-a-token", auth_token.as_str()] {
+        let response = confirm_reset(&app, token, "n3wP4ssword").await;
+        assert_error(response, 401, "Invalid auth token").await;
+    }
+}
+
+#[api_test]
+async fn should_return_400_if_new_password_is_invalid() {
+    let (email, _) = signup_and_login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    request_reset(&app, &email).await;
+    let token = emailed_reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, "short").await;
+    assert_error(response, 400, "Password must be at least 8 characters").await;
+
+    // the token wasn't used up by the rejected attempt
+    let response = confirm_reset(&app, &token, "n3wP4ssword").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,233 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::LoginAttemptId,
+    routes::{
+        RecoveryCodeCountResponse, RecoveryCodesResponse, SignupResponse, TwoFactorAuthResponse,
+    },
+    ErrorResponse,
+};
+use secrecy::{ExposeSecret, Secret};
+use test_helpers::api_test;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+
+async fn signup_with_2fa(app: &TestApp) -> (String, Vec<String>) {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": true,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let recovery_codes = response
+        .json::<SignupResponse>()
+        .await
+        .expect("could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("no recovery codes in signup response");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    (random_email, recovery_codes)
+}
+
+async fn login_with_recovery_code(
+    app: &TestApp,
+    email: &str,
+    recovery_code: &str,
+) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+
+    let request_body = serde_json::json!({
+        "email": email,
+        "loginAttemptId": response_body.login_attempt_id,
+        "2FACode": recovery_code,
+    });
+    app.verify_2fa(&request_body).await
+}
+
+async fn emailed_code(app: &TestApp, login_attempt_id: &str) -> String {
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&login_attempt_id)
+        .await
+        .unwrap();
+    code_tuple.1.as_ref().expose_secret().to_owned()
+}
+
+async fn start_regeneration(app: &TestApp) -> String {
+    let response = app.regenerate_recovery_codes(&serde_json::json!({})).await;
+    assert_eq!(response.status().as_u16(), 206);
+    response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id
+}
+
+async fn remaining_codes(app: &TestApp) -> usize {
+    let response = app.count_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<RecoveryCodeCountResponse>()
+        .await
+        .expect("could not deserialize response body to RecoveryCodeCountResponse")
+        .remaining
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_accept_each_recovery_code_once() {
+    let (email, recovery_codes) = signup_with_2fa(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // codes are accepted in whatever case the user types them
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[0].to_uppercase()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(remaining_codes(&app).await, 9);
+
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[0]).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_401_if_recovery_code_is_incorrect() {
+    let (email, _) = signup_with_2fa(&app).await;
+
+    let response = login_with_recovery_code(&app, &email, "abcde-fghjk").await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_replace_recovery_codes_when_regenerated() {
+    let (email, recovery_codes) = signup_with_2fa(&app).await;
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login_attempt_id = start_regeneration(&app).await;
+    let response = app
+        .regenerate_recovery_codes(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": emailed_code(&app, &login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_codes = response
+        .json::<RecoveryCodesResponse>()
+        .await
+        .expect("could not deserialize response body to RecoveryCodesResponse")
+        .recovery_codes;
+    assert_eq!(new_codes.len(), 10);
+    assert_eq!(remaining_codes(&app).await, 10);
+
+    // codes left from the previous set no longer work
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[1]).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let response = login_with_recovery_code(&app, &email, &new_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_require_second_factor_to_regenerate_recovery_codes() {
+    let (email, recovery_codes) = signup_with_2fa(&app).await;
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // a stolen cookie alone can't replace the codes
+    let login_attempt_id = start_regeneration(&app).await;
+    let response = app
+        .regenerate_recovery_codes(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": "000000",
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+    assert_eq!(remaining_codes(&app).await, 9);
+
+    let response = login_with_recovery_code(&app, &email, &recovery_codes[1]).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_return_409_if_2fa_not_enabled() {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response_body = response
+        .json::<SignupResponse>()
+        .await
+        .expect("could not deserialize response body to SignupResponse");
+    assert!(response_body.recovery_codes.is_none());
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.regenerate_recovery_codes(&serde_json::json!({})).await;
+    assert_error(response, 409, "2FA not enabled").await;
+    assert_eq!(remaining_codes(&app).await, 0);
+}
+
+#[api_test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let response = app.regenerate_recovery_codes(&serde_json::json!({})).await;
+    assert_error(response, 400, "Missing auth token").await;
+
+    let response = app.count_recovery_codes().await;
+    assert_error(response, 400, "Missing auth token").await;
+}
@@ -0,0 +1,385 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::LoginAttemptId,
+    routes::{ConfirmTotpResponse, EnrollTotpResponse, TwoFactorAuthResponse},
+    utils::constants::JWT_COOKIE_NAME,
+    ErrorResponse,
+};
+use secrecy::ExposeSecret;
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup_and_login(app: &TestApp) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    random_email
+}
+
+async fn enroll(app: &TestApp) -> EnrollTotpResponse {
+    let response = app.enroll_totp().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<EnrollTotpResponse>()
+        .await
+        .expect("could not deserialize response body to EnrollTotpResponse")
+}
+
+async fn two_fa_response(response: reqwest::Response) -> TwoFactorAuthResponse {
+    assert_eq!(response.status().as_u16(), 206);
+    response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse")
+}
+
+async fn emailed_code(app: &TestApp, login_attempt_id: &str) -> String {
+    let login_attempt_id =
+        LoginAttemptId::parse(secrecy::Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&login_attempt_id)
+        .await
+        .unwrap();
+    code_tuple.1.as_ref().expose_secret().to_owned()
+}
+
+fn totp(secret: &str) -> TOTP {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .expect("invalid TOTP secret");
+    TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, None, String::new()).expect("failed to build TOTP")
+}
+
+fn current_code(secret: &str) -> String {
+    totp(secret)
+        .generate_current()
+        .expect("failed to generate TOTP code")
+}
+
+/// The code for the next time step, which is still accepted to absorb clock drift.
+fn next_code(secret: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before the epoch")
+        .as_secs();
+    totp(secret).generate(now + 30)
+}
+
+fn wrong_code(secret: &str) -> String {
+    let code: u32 = current_code(secret).parse().unwrap();
+    format!("{:06}", (code + 500_000) % 1_000_000)
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_return_otpauth_uri_and_secret() {
+    let email = signup_and_login(&app).await;
+
+    let enrollment = enroll(&app).await;
+    assert!(!enrollment.secret.is_empty());
+    assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+    assert!(enrollment
+        .otpauth_uri
+        .contains(&format!("secret={}", enrollment.secret)));
+    assert!(enrollment.otpauth_uri.contains(&email.replace('@', "%40")));
+}
+
+#[api_test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let response = app.enroll_totp().await;
+    assert_error(response, 400, "Missing auth token").await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": "123456",
+        }))
+        .await;
+    assert_error(response, 400, "Missing auth token").await;
+}
+
+#[api_test]
+async fn should_return_404_if_enrollment_not_started() {
+    signup_and_login(&app).await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": "123456",
+        }))
+        .await;
+    assert_error(response, 404, "TOTP enrollment not found").await;
+}
+
+#[api_test]
+async fn should_return_401_if_incorrect_code() {
+    signup_and_login(&app).await;
+    let enrollment = enroll(&app).await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": wrong_code(&enrollment.secret),
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    // a failed confirmation leaves TOTP disabled, so enrollment can start over
+    let response = app.enroll_totp().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_return_401_if_incorrect_password() {
+    signup_and_login(&app).await;
+    let enrollment = enroll(&app).await;
+
+    // the session alone isn't enough to bind an authenticator app
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "wrongPassword",
+            "2FACode": current_code(&enrollment.secret),
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_return_409_if_totp_already_enabled() {
+    signup_and_login(&app).await;
+    let enrollment = enroll(&app).await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.enroll_totp().await;
+    assert_error(response, 409, "TOTP already enabled").await;
+}
+
+#[api_test]
+async fn should_require_totp_code_at_login_once_confirmed() {
+    let email = signup_and_login(&app).await;
+    let enrollment = enroll(&app).await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    // 2FA was off before, so turning it on hands out recovery codes
+    let response_body = response
+        .json::<ConfirmTotpResponse>()
+        .await
+        .expect("could not deserialize response body to ConfirmTotpResponse");
+    assert_eq!(
+        response_body.recovery_codes.map(|codes| codes.len()),
+        Some(10)
+    );
+
+    // the code comes from the authenticator app, nothing is mailed
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(response_body.two_fa_method, "totp".to_owned());
+
+    let request_body = serde_json::json!({
+        "email": email,
+        "loginAttemptId": response_body.login_attempt_id,
+        "2FACode": wrong_code(&enrollment.secret),
+    });
+    let response = app.verify_2fa(&request_body).await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    // the code that confirmed the enrollment has been used up
+    let request_body = serde_json::json!({
+        "email": email,
+        "loginAttemptId": response_body.login_attempt_id,
+        "2FACode": next_code(&enrollment.secret),
+    });
+    let response = app.verify_2fa(&request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+}
+
+#[api_test]
+async fn should_require_current_second_factor_if_2fa_enabled() {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": true,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    // the login code, then the one that confirms the switch
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let response_body = two_fa_response(app.login(&login_body).await).await;
+    let request_body = serde_json::json!({
+        "email": email,
+        "loginAttemptId": response_body.login_attempt_id,
+        "2FACode": emailed_code(&app, &response_body.login_attempt_id).await,
+    });
+    let response = app.verify_2fa(&request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // a hijacked session can't swap in its own authenticator app without the emailed code
+    let enrollment = enroll(&app).await;
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+        }))
+        .await;
+    let response_body = two_fa_response(response).await;
+    assert_eq!(response_body.two_fa_method, "email".to_owned());
+    let code = emailed_code(&app, &response_body.login_attempt_id).await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+            "loginAttemptId": response_body.login_attempt_id,
+            "current2FACode": format!("{:06}", (code.parse::<u32>().unwrap() + 500_000) % 1_000_000),
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+            "loginAttemptId": response_body.login_attempt_id,
+            "current2FACode": code,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    // users switching over from emailed codes keep their recovery codes
+    let response_body = response
+        .json::<ConfirmTotpResponse>()
+        .await
+        .expect("could not deserialize response body to ConfirmTotpResponse");
+    assert!(response_body.recovery_codes.is_none());
+
+    let response = app.login(&login_body).await;
+    assert_eq!(two_fa_response(response).await.two_fa_method, "totp");
+}
+
+#[api_test]
+async fn should_reject_a_totp_code_used_before() {
+    let email = signup_and_login(&app).await;
+    let enrollment = enroll(&app).await;
+    let response = app
+        .confirm_totp(&serde_json::json!({
+            "password": "notSoSecure",
+            "2FACode": current_code(&enrollment.secret),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    let code = next_code(&enrollment.secret);
+    for status in [200, 401] {
+        let response_body = two_fa_response(app.login(&login_body).await).await;
+        let request_body = serde_json::json!({
+            "email": email,
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": code,
+        });
+        let response = app.verify_2fa(&request_body).await;
+        assert_eq!(response.status().as_u16(), status);
+    }
+}
@@ -0,0 +1,227 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{
+    domain::{LoginAttemptId, RECOVERY_CODE_COUNT},
+    routes::{RecoveryCodeCountResponse, RecoveryCodesResponse, TwoFactorAuthResponse},
+    ErrorResponse,
+};
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup(app: &TestApp, require_2fa: bool) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": require_2fa,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    random_email
+}
+
+async fn login_response(app: &TestApp, email: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+    });
+    app.login(&login_body).await
+}
+
+/// Logs in a user with emailed 2FA codes, through `/verify-2fa`.
+async fn login_with_2fa(app: &TestApp, email: &str) {
+    let response = login_response(app, email).await;
+    let response_body = two_fa_response(response).await;
+    let response = app
+        .verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn two_fa_response(response: reqwest::Response) -> TwoFactorAuthResponse {
+    assert_eq!(response.status().as_u16(), 206);
+    response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse")
+}
+
+async fn two_fa_code(app: &TestApp, login_attempt_id: &str) -> String {
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap())
+        .await
+        .unwrap();
+    code_tuple.1.as_ref().expose_secret().to_owned()
+}
+
+fn wrong_code(code: &str) -> String {
+    let code: u32 = code.parse().unwrap();
+    format!("{:06}", (code + 500_000) % 1_000_000)
+}
+
+async fn mount_email_server(app: &TestApp, expected_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_enable_2fa_after_emailed_code_is_confirmed() {
+    let email = signup(&app, false).await;
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // the code that turns 2FA on, then the one for the next login
+    mount_email_server(&app, 2).await;
+
+    let response = app
+        .enable_2fa(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    let response_body = two_fa_response(response).await;
+    assert_eq!(response_body.two_fa_method, "email".to_owned());
+    let code = two_fa_code(&app, &response_body.login_attempt_id).await;
+
+    let response = app
+        .enable_2fa(&serde_json::json!({
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": wrong_code(&code),
+        }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    // 2FA stays off until the code is confirmed
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .enable_2fa(&serde_json::json!({
+            "password": "notSoSecure",
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": code,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response_body = response
+        .json::<RecoveryCodesResponse>()
+        .await
+        .expect("could not deserialize response body to RecoveryCodesResponse");
+    assert_eq!(response_body.recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+    let response = login_response(&app, &email).await;
+    assert_eq!(two_fa_response(response).await.two_fa_method, "email");
+}
+
+#[api_test]
+async fn should_return_401_if_incorrect_password() {
+    let email = signup(&app, false).await;
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // no code is mailed before the password checks out
+    mount_email_server(&app, 0).await;
+
+    let response = app
+        .enable_2fa(&serde_json::json!({ "password": "wrongPassword" }))
+        .await;
+    assert_error(response, 401, "Incorrect credentials").await;
+}
+
+#[api_test]
+async fn should_disable_2fa_after_fresh_check() {
+    let email = signup(&app, true).await;
+    // the login code, then the one that turns 2FA off
+    mount_email_server(&app, 2).await;
+    login_with_2fa(&app, &email).await;
+
+    let response = app.disable_2fa(&serde_json::json!({})).await;
+    let response_body = two_fa_response(response).await;
+
+    let response = app
+        .disable_2fa(&serde_json::json!({
+            "loginAttemptId": response_body.login_attempt_id,
+            "2FACode": two_fa_code(&app, &response_body.login_attempt_id).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.count_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response_body = response
+        .json::<RecoveryCodeCountResponse>()
+        .await
+        .expect("could not deserialize response body to RecoveryCodeCountResponse");
+    assert_eq!(response_body.remaining, 0);
+
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_return_409_if_2fa_already_in_requested_state() {
+    let email = signup(&app, false).await;
+    let response = login_response(&app, &email).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.disable_2fa(&serde_json::json!({})).await;
+    assert_error(response, 409, "2FA not enabled").await;
+
+    let email = signup(&app, true).await;
+    mount_email_server(&app, 1).await;
+    login_with_2fa(&app, &email).await;
+
+    let response = app
+        .enable_2fa(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    assert_error(response, 409, "2FA already enabled").await;
+}
+
+#[api_test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let response = app
+        .enable_2fa(&serde_json::json!({ "password": "notSoSecure" }))
+        .await;
+    assert_error(response, 400, "Missing auth token").await;
+
+    let response = app.disable_2fa(&serde_json::json!({})).await;
+    assert_error(response, 400, "Missing auth token").await;
+}
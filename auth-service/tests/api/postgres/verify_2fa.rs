@@ -16,7 +16,10 @@
 
 use super::helpers::{get_random_email, TestApp};
 use auth_service::{
-    domain::{Email, LoginAttemptId, TwoFACode},
+    domain::{
+        LoginAttemptId, TwoFACode, MAX_LIVE_TWO_FA_CODES, MAX_TWO_FA_CODES_PER_WINDOW,
+        MAX_TWO_FA_CODE_GUESSES,
+    },
     routes::TwoFactorAuthResponse,
     utils::constants::JWT_COOKIE_NAME,
     ErrorResponse,
@@ -60,13 +63,14 @@ async fn should_return_200_if_correct_code() {
         .expect("could not deserialize response body to TwoFactorAuthResponse");
     assert_eq!(response_body.message, "2FA required".to_owned());
     assert!(!response_body.login_attempt_id.is_empty());
+    assert_eq!(response_body.two_fa_method, "email".to_owned());
 
     let login_attempt_id = response_body.login_attempt_id;
     let code_tuple = app
         .two_fa_code_store
         .read()
         .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
         .await
         .unwrap();
     let code = code_tuple.1.as_ref().expose_secret();
@@ -118,14 +122,11 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
     let two_fa_code_store = app.two_fa_code_store.read().await;
 
     let code_tuple = two_fa_code_store
-        .get_code(&Email::parse(Secret::new(random_email)).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(json_body.login_attempt_id)).unwrap())
         .await
         .expect("Failed to get 2FA code");
 
-    assert_eq!(
-        code_tuple.0.as_ref().expose_secret(),
-        &json_body.login_attempt_id
-    );
+    assert_eq!(code_tuple.0.as_ref().expose_secret(), &random_email);
 }
 
 #[api_test]
@@ -219,7 +220,7 @@ async fn should_return_401_if_incorrect_credentials() {
         .two_fa_code_store
         .read()
         .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
         .await
         .unwrap();
     let two_fa_code = code_tuple.1.as_ref();
@@ -287,7 +288,7 @@ async fn should_return_401_if_old_code() {
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .expect(2)
+        .expect(1 + MAX_LIVE_TWO_FA_CODES as u64)
         .mount(&app.email_server)
         .await;
 
@@ -311,14 +312,16 @@ async fn should_return_401_if_old_code() {
         .two_fa_code_store
         .read()
         .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
         .await
         .unwrap();
 
     let code = code_tuple.1.as_ref();
-    // 2nd login call
-    let response = app.login(&login_body).await;
-    assert_eq!(response.status().as_u16(), 206);
+    // the first attempt is dropped once enough later ones are in progress
+    for _ in 0..MAX_LIVE_TWO_FA_CODES {
+        let response = app.login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 206);
+    }
     // 2FA attempt with old login_attempt_id and code
     let request_body = serde_json::json!({
         "email": random_email,
@@ -330,6 +333,62 @@ async fn should_return_401_if_old_code() {
     assert_eq!(response.status().as_u16(), 401);
 }
 
+#[api_test]
+async fn should_return_200_for_each_concurrent_attempt() {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": true
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // e.g. one login on a laptop and another on a phone
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure"
+    });
+    let mut login_attempt_ids = Vec::new();
+    for _ in 0..2 {
+        let response = app.login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 206);
+        let login_attempt_id = response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("could not deserialize response body to TwoFactorAuthResponse")
+            .login_attempt_id;
+        login_attempt_ids.push(login_attempt_id);
+    }
+
+    // finishing the later attempt leaves the earlier one usable
+    for login_attempt_id in login_attempt_ids.into_iter().rev() {
+        let code_tuple = app
+            .two_fa_code_store
+            .read()
+            .await
+            .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(code_tuple.0.as_ref().expose_secret(), &random_email);
+
+        let request_body = serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code_tuple.1.as_ref().expose_secret(),
+        });
+        let response = app.verify_2fa(&request_body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
 #[api_test]
 async fn should_return_401_if_same_code_twice() {
     //let app = TestApp::new().await;
@@ -369,7 +428,7 @@ async fn should_return_401_if_same_code_twice() {
         .two_fa_code_store
         .read()
         .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
         .await
         .unwrap();
 
@@ -432,3 +491,108 @@ async fn should_return_422_if_malformed_input() {
         );
     }
 }
+
+#[api_test]
+async fn should_invalidate_code_after_too_many_wrong_guesses() {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": true
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure"
+    });
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let code_tuple = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
+        .await
+        .unwrap();
+    let code = code_tuple.1.as_ref().expose_secret().to_owned();
+    let wrong_code = format!(
+        "{:06}",
+        (code.parse::<u32>().unwrap() + 500_000) % 1_000_000
+    );
+
+    for _ in 0..MAX_TWO_FA_CODE_GUESSES {
+        let response = app
+            .verify_2fa(&serde_json::json!({
+                "email": random_email,
+                "loginAttemptId": login_attempt_id,
+                "2FACode": wrong_code,
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // the right code no longer helps once the attempt has been guessed at too often
+    let response = app
+        .verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        "Incorrect credentials".to_owned()
+    );
+}
+
+#[api_test]
+async fn should_return_429_if_too_many_codes_issued() {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": true
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(MAX_TWO_FA_CODES_PER_WINDOW as u64)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure"
+    });
+    for _ in 0..MAX_TWO_FA_CODES_PER_WINDOW {
+        let response = app.login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 206);
+    }
+
+    let response = app.login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("retry-after"));
+}
@@ -0,0 +1,172 @@
+/*
+   Copyright 2024 Ibrahim Mbaziira
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+use auth_service::{routes::VerifyEmailResponse, utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use super::helpers::{get_random_email, TestApp};
+use test_helpers::api_test;
+
+async fn signup_unverified(app: &TestApp) -> String {
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup_unverified(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    random_email
+}
+
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": password,
+    });
+    app.login(&login_body).await
+}
+
+/// Signup sends the verification email before it responds.
+async fn emailed_verification_token(app: &TestApp) -> String {
+    let requests = app
+        .email_server
+        .received_requests()
+        .await
+        .expect("request recording is disabled");
+    let request = requests.last().expect("verification email was never sent");
+    let body: serde_json::Value =
+        serde_json::from_slice(&request.body).expect("email body is not JSON");
+    let text = body["TextBody"].as_str().expect("email has no TextBody");
+    let (_, token) = text
+        .split_once("token=")
+        .expect("no token in verification link");
+    token.to_owned()
+}
+
+async fn assert_error(response: reqwest::Response, status: u16, error: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("could not deserialize response body to ErrorResponse")
+            .error,
+        error.to_owned()
+    );
+}
+
+#[api_test]
+async fn should_refuse_login_until_email_is_verified() {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let email = signup_unverified(&app).await;
+
+    let response = login(&app, &email, "notSoSecure").await;
+    assert_error(response, 403, "Email not verified").await;
+
+    // a wrong password is refused as usual, without mentioning verification
+    let response = login(&app, &email, "wrongPassword").await;
+    assert_error(response, 401, "Incorrect credentials").await;
+
+    let token = emailed_verification_token(&app).await;
+    let response = app.verify_email(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<VerifyEmailResponse>()
+        .await
+        .expect("could not deserialize response body to VerifyEmailResponse");
+
+    let response = login(&app, &email, "notSoSecure").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // following the link again does no harm
+    let response = app.verify_email(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[api_test]
+async fn should_return_401_if_token_is_invalid() {
+    let response = app.verify_email("not-a-token").await;
+    assert_error(response, 401, "Invalid auth token").await;
+}
+
+#[api_test]
+async fn should_not_accept_auth_tokens_as_verification_tokens() {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "notSoSecure",
+        "require2FA": false,
+    });
+    let response = app.signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login(&app, &email, "notSoSecure").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let response = app.verify_email(&auth_token).await;
+    assert_error(response, 401, "Invalid auth token").await;
+}
+
+#[api_test]
+async fn should_return_429_if_resent_during_cooldown() {
+    let email = signup_unverified(&app).await;
+
+    let response = app
+        .resend_verification_email(&serde_json::json!({ "email": email }))
+        .await;
+    assert_error(response, 429, "Verification email sent recently").await;
+}
+
+#[api_test]
+async fn should_not_reveal_unknown_emails() {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let request_body = serde_json::json!({ "email": get_random_email() });
+    let response = app.resend_verification_email(&request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // unknown addresses are put on the same cooldown as real ones
+    let response = app.resend_verification_email(&request_body).await;
+    assert_error(response, 429, "Verification email sent recently").await;
+}
+
+#[api_test]
+async fn should_return_400_if_email_is_malformed() {
+    let response = app
+        .resend_verification_email(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_error(response, 400, "Invalid credentials").await;
+}